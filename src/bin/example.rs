@@ -4,13 +4,7 @@ use vmgc::*;
 // Holds the heap and the stack.
 struct VM {
     heap: Heap,
-    stack: GlobalHandle<Stack>,
-}
-
-#[derive(Default)]
-struct Stack {
-    pending_result: HeapHandle<()>,
-    values: List<()>,
+    stack: GlobalHandle<ValueStack>,
 }
 
 // type DynamicHeapHandle = HeapHandle<()>;
@@ -20,22 +14,11 @@ struct Stack {
 //     _phantom: PhantomData<ValueType>,
 // }
 
-impl HostObject for Stack {
-    const TYPE_ID: ObjectType = ObjectType::Host;
-}
-
-impl Traceable for Stack {
-    fn trace(&mut self, visitor: &mut ObjectVisitor) {
-        self.values.trace(visitor);
-        self.pending_result.trace(visitor);
-    }
-}
-
 fn init() -> VM {
     let heap = Heap::new(1000).unwrap();
     let stack = {
         let scope = HandleScope::new(&heap);
-        GlobalHandle::from(scope.create::<Stack>().unwrap())
+        GlobalHandle::from(scope.create::<ValueStack>().unwrap())
     };
     VM { stack, heap }
 }
@@ -59,51 +42,44 @@ fn main() {
     // push two numbers on the stack
     {
         let scope = HandleScope::new(&vm.heap);
-        let stack = scope.as_mut(&vm.stack);
-        stack.values.push(scope.create_num(1.0).into());
-        stack.values.push(scope.create_num(2.0).into());
+        let mut stack = scope.as_mut(&vm.stack);
+        stack.push(scope.create_num(1.0).into());
+        stack.push(scope.create_num(2.0).into());
     }
     vm.heap.collect().ok();
 
     // call the add function
     {
         let scope = HandleScope::new(&vm.heap);
-        let stack = scope.as_mut(&vm.stack);
-
-        num_add(&vm, &stack.values[..], &mut stack.pending_result).ok();
-
-        stack.values.truncate(0);
-        stack.values.push(stack.pending_result.take());
+        let mut stack = scope.as_mut(&vm.stack);
+        stack.call(2, |args, out| num_add(&vm, args, out)).ok();
     }
 
     vm.heap.collect().ok();
     // expect a single number on the stack.
     {
         let scope = HandleScope::new(&vm.heap);
-        let stack = scope.as_mut(&vm.stack);
+        let mut stack = scope.as_mut(&vm.stack);
 
-        let result: f64 = stack.values[0].ptr().try_into().unwrap();
+        let result: f64 = stack.pop(&scope).unwrap().try_into().unwrap();
         println!("1 + 2 = {}", result);
+        stack.push(scope.create_num(result).into());
     }
 
     vm.heap.collect().ok();
     // call is_nan function
     {
         let scope = HandleScope::new(&vm.heap);
-        let stack = scope.as_mut(&vm.stack);
-
-        num_is_nan(&vm, &stack.values[..], &mut stack.pending_result).ok();
-
-        stack.values.truncate(0);
-        stack.values.push(stack.pending_result.take());
+        let mut stack = scope.as_mut(&vm.stack);
+        stack.call(1, |args, out| num_is_nan(&vm, args, out)).ok();
     }
 
     // expect a single bool (false) on the stack.
     {
         let scope = HandleScope::new(&vm.heap);
-        let stack = scope.as_mut(&vm.stack);
+        let mut stack = scope.as_mut(&vm.stack);
 
-        let result: bool = stack.values[0].ptr().try_into().unwrap();
+        let result: bool = stack.pop(&scope).unwrap().try_into().unwrap();
         println!("3.is_nan = {}", result);
     }
 }
@@ -0,0 +1,161 @@
+use std::alloc::Layout;
+
+use crate::display::ValueFormatter;
+use crate::gc_string::GcString;
+use crate::object::*;
+use crate::typed_array::write_elements;
+
+// Variable length objects (ByteArray and GcString) are an empty struct at
+// the start of their ControlBlocks payload, followed by their length and
+// then their bytes.  Like every payload they never move, only their headers
+// do, so references to the bytes stay valid across collections.  Moving the
+// struct by value would lose the bytes, so it's private to the heap.
+pub(crate) trait TrailingBytes: HostObject + Sized {}
+
+const LEN_SIZE: usize = std::mem::size_of::<usize>();
+
+// The payload layout for `len` bytes, or None if too large for one.
+pub(crate) fn layout_for(len: usize) -> Option<Layout> {
+    let size = LEN_SIZE.checked_add(len)?;
+    Layout::from_size_align(size, std::mem::align_of::<usize>()).ok()
+}
+
+// Writes a T of `len` zeroes into `block`, which must be layout_for(len).
+pub(crate) fn init<T: TrailingBytes>(block: *mut u8, len: usize) -> *mut T {
+    unsafe {
+        *(block as *mut usize) = len;
+        block.add(LEN_SIZE).write_bytes(0, len);
+    }
+    block as *mut T
+}
+
+// The layout of `payload`'s block if it's a ByteArray or GcString, whose
+// bytes Layout::for_value doesn't see.
+pub(crate) fn block_layout(payload: &dyn Traceable) -> Option<Layout> {
+    let any = payload.as_any();
+    if !any.is::<ByteArray>() && !any.is::<GcString>() {
+        return None;
+    }
+    let len = unsafe { *(payload as *const dyn Traceable as *const usize) };
+    layout_for(len)
+}
+
+fn bytes_ptr<T: TrailingBytes>(payload: &T) -> *mut u8 {
+    unsafe { (payload as *const T as *mut u8).add(LEN_SIZE) }
+}
+
+pub(crate) fn bytes_of<T: TrailingBytes>(payload: &T) -> &[u8] {
+    let len = unsafe { *(payload as *const T as *const usize) };
+    unsafe { std::slice::from_raw_parts(bytes_ptr(payload), len) }
+}
+
+pub(crate) fn bytes_of_mut<T: TrailingBytes>(payload: &mut T) -> &mut [u8] {
+    let len = bytes_of(payload).len();
+    unsafe { std::slice::from_raw_parts_mut(bytes_ptr(payload), len) }
+}
+
+/// A fixed length buffer of bytes stored in one block with its length,
+/// rather than behind a second pointer like TypedArray<u8>.  Made by
+/// HandleScope::byte_array, and only ever used in place.
+pub struct ByteArray {
+    _in_place: [usize; 0],
+}
+
+impl TrailingBytes for ByteArray {}
+
+impl ByteArray {
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        bytes_of(self)
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        bytes_of_mut(self)
+    }
+
+    /// Copies `bytes` in starting at `offset`.  Panics if they don't fit.
+    pub fn copy_from(&mut self, offset: usize, bytes: &[u8]) {
+        self.as_mut_slice()[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Fills `bytes` with the bytes starting at `offset`.  Panics if there
+    /// aren't enough.
+    pub fn copy_to(&self, offset: usize, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.as_slice()[offset..offset + bytes.len()]);
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.as_slice().to_vec()
+    }
+}
+
+impl HostObject for ByteArray {}
+
+impl Traceable for ByteArray {
+    fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        write_elements(formatter, self.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+
+    #[test]
+    fn byte_array_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let empty = scope.byte_array(0).unwrap();
+        assert!(empty.as_ref().is_empty());
+        let array = scope.byte_array_from(&[1, 2, 3, 4, 5]).unwrap();
+        let used_bytes = heap.used_bytes();
+        {
+            let inner = HandleScope::new(&heap);
+            inner.byte_array(1000).unwrap();
+        }
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), used_bytes);
+        assert_eq!(array.as_ref().len(), 5);
+        assert_eq!(array.as_ref().as_slice(), &[1, 2, 3, 4, 5]);
+
+        array.as_mut().copy_from(1, &[20, 30]);
+        array.as_mut().as_mut_slice()[4] = 50;
+        let mut out = [0; 3];
+        array.as_ref().copy_to(2, &mut out);
+        assert_eq!(out, [30, 4, 50]);
+        heap.collect().unwrap();
+        assert_eq!(array.as_ref().to_vec(), vec![1, 20, 30, 4, 50]);
+        assert_eq!(array.display(&scope), "[1, 20, 30, 4, 50]");
+
+        // The bytes never move, so a slice held across a collection still
+        // refers to the array.
+        let zeroes = scope.byte_array(100).unwrap();
+        let mut payload = zeroes.as_mut();
+        let slice = payload.as_mut_slice();
+        assert!(slice.iter().all(|byte| *byte == 0));
+        heap.collect().unwrap();
+        slice[0] = 42;
+        assert_eq!(zeroes.as_ref().as_slice()[0], 42);
+        assert_eq!(heap.used_bytes() - used_bytes, HOST_OBJECT_ALLOC_SIZE);
+        assert!(layout_for(usize::MAX).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn byte_array_bounds_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let array = scope.byte_array(4).unwrap();
+        array.as_mut().copy_from(2, &[1, 2, 3]);
+    }
+}
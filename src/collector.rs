@@ -0,0 +1,317 @@
+use crate::object::HeapHandle;
+use crate::pointer::*;
+use crate::space::*;
+use crate::types::*;
+use std::rc::Rc;
+
+/// Owns the memory objects live in and decides where live objects go during
+/// a collection.  Tracing, rooting and finalization are shared by the Heap,
+/// a strategy only manages placement.  Custom strategies are installed with
+/// Heap::with_strategy.
+pub trait CollectorStrategy {
+    /// The total memory budget, including any reserve the collector needs.
+    fn size_in_bytes(&self) -> usize;
+
+    /// How many bytes of objects fit in a budget of `size_in_bytes`.
+    fn capacity_for(&self, size_in_bytes: usize) -> usize;
+
+    fn used_bytes(&self) -> usize;
+
+    fn free_bytes(&self) -> usize;
+
+    fn alloc(&mut self, size: usize) -> Result<*mut u8, GCError>;
+
+    /// Gives back an allocation known to be dead before the next collection.
+    /// Collectors may ignore this, the memory is reclaimed by the next
+    /// collection either way.
+    fn unalloc(&mut self, _addr: *mut u8, _size: usize) {}
+
+    /// Called before tracing.  `size_in_bytes` is the budget the heap should
+    /// have once the collection is done.  The heap may allocate, collect and
+    /// prepare again before tracing, so this (and prepare_minor_collection)
+    /// must replace anything an earlier prepare left.
+    fn prepare_collection(&mut self, size_in_bytes: usize) -> Result<(), GCError>;
+
+    /// Called instead of prepare_collection before a minor collection, which
+    /// only moves young objects (see is_young) and treats the rest as live.
+    /// Returns false if this strategy has no young generation, or can't
+    /// promote all of it now, and needs a full collection instead.
+    fn prepare_minor_collection(&mut self) -> bool {
+        false
+    }
+
+    /// Whether the object is in the young generation, only asked during a
+    /// minor collection.
+    fn is_young(&self, _header: &mut ObjectHeader) -> bool {
+        true
+    }
+
+    /// Whether a collection can be spread over several Heap::collect_step
+    /// calls, with the mutator allocating in between.  The strategy must
+    /// keep allocating from the spaces being collected until
+    /// finish_collection, and relocate must not reuse their memory.
+    fn supports_incremental(&self) -> bool {
+        false
+    }
+
+    /// The barrier shared by this strategy's spaces (see
+    /// Space::with_barrier).  It lets the last step of an incremental
+    /// collection trace again only the objects written since they were
+    /// traced, rather than every live object.
+    fn write_barrier(&self) -> Option<&WriteBarrier> {
+        None
+    }
+
+    /// Whether a minor collection must trace this old object for pointers
+    /// into the young generation.  Strategies which track writes (e.g. with
+    /// a CardTable) can rule out objects which haven't been written to.
+    fn is_remembered(&self, _header: &mut ObjectHeader) -> bool {
+        true
+    }
+
+    /// Picks where a reachable object should live after this collection.
+    /// Returning the object's own header leaves it in place.
+    fn relocate(&mut self, header: &mut ObjectHeader) -> HeaderPtr;
+
+    /// Called after tracing with every surviving object, at its new address.
+    fn finish_collection(&mut self, survivors: &[HeapHandle<()>]);
+
+    /// Picks an address for each of `survivors`, which are sorted by
+    /// address, to slide them together after a collection (see
+    /// Heap::compact).  Each must be at or below the object's current
+    /// address.  finish_collection is called again once they have moved.
+    /// None means the strategy doesn't compact this way, e.g. because its
+    /// collections already do.
+    fn plan_compaction(&mut self, _survivors: &[HeapHandle<()>]) -> Option<Vec<HeaderPtr>> {
+        None
+    }
+
+    /// The words generated code bumps to allocate inline, if this strategy
+    /// allocates from a single bump region.
+    #[cfg(feature = "jit")]
+    fn bump_pointer(&mut self) -> Option<BumpPointer> {
+        None
+    }
+}
+
+/// Addresses of the allocation top and limit words.  Generated code may
+/// allocate `size` bytes by advancing `*top` as long as it stays at or
+/// below `*limit`, and must call back into the Heap otherwise.  Both words
+/// hold addresses and stay at the same place for the life of the Heap,
+/// though their values change whenever Rust code allocates or collects.
+#[cfg(feature = "jit")]
+#[derive(Debug, Clone, Copy)]
+pub struct BumpPointer {
+    pub top: *mut usize,
+    pub limit: *const usize,
+}
+
+/// Cheney-style copying between two equally sized semispaces.  Only half of
+/// the budget is usable for allocation, unless to-space is allocated on
+/// demand.
+pub struct SemiSpaceCollector {
+    space: Space,
+    to_space: Option<Space>,
+    // Whether the whole budget is from-space, rather than half of it.
+    to_space_on_demand: bool,
+    barrier: Rc<WriteBarrier>,
+}
+
+impl SemiSpaceCollector {
+    pub fn new(size_in_bytes: usize) -> Result<SemiSpaceCollector, GCError> {
+        let barrier = Rc::new(WriteBarrier::default());
+        Ok(SemiSpaceCollector {
+            space: Space::with_barrier(size_in_bytes / 2, &barrier)?,
+            to_space: None,
+            to_space_on_demand: false,
+            barrier,
+        })
+    }
+
+    /// Uses all of `size_in_bytes` for allocation, allocating an equally
+    /// large to-space only for the length of each collection.  Peak memory
+    /// use is the same as SemiSpaceCollector::new(size_in_bytes * 2).
+    pub fn with_to_space_on_demand(size_in_bytes: usize) -> Result<SemiSpaceCollector, GCError> {
+        let barrier = Rc::new(WriteBarrier::default());
+        Ok(SemiSpaceCollector {
+            space: Space::with_barrier(size_in_bytes, &barrier)?,
+            to_space: None,
+            to_space_on_demand: true,
+            barrier,
+        })
+    }
+}
+
+impl CollectorStrategy for SemiSpaceCollector {
+    fn size_in_bytes(&self) -> usize {
+        if self.to_space_on_demand {
+            return self.space.size_in_bytes;
+        }
+        self.space.size_in_bytes * 2
+    }
+
+    fn capacity_for(&self, size_in_bytes: usize) -> usize {
+        if self.to_space_on_demand {
+            return size_in_bytes;
+        }
+        size_in_bytes / 2
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.space.used_bytes()
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.space.free_bytes()
+    }
+
+    fn alloc(&mut self, size: usize) -> Result<*mut u8, GCError> {
+        self.space.alloc(size)
+    }
+
+    fn unalloc(&mut self, addr: *mut u8, size: usize) {
+        self.space.unalloc(addr, size);
+    }
+
+    fn prepare_collection(&mut self, size_in_bytes: usize) -> Result<(), GCError> {
+        let to_space = Space::with_barrier(self.capacity_for(size_in_bytes), &self.barrier)?;
+        self.to_space = Some(to_space);
+        Ok(())
+    }
+
+    // New objects go to from-space, which is only replaced once finished.
+    fn supports_incremental(&self) -> bool {
+        true
+    }
+
+    fn write_barrier(&self) -> Option<&WriteBarrier> {
+        Some(&self.barrier)
+    }
+
+    fn relocate(&mut self, header: &mut ObjectHeader) -> HeaderPtr {
+        let to_space = self.to_space.as_mut().unwrap();
+        // Everything live fit in from-space, so it fits in to-space.
+        HeaderPtr::new(to_space.alloc(header.alloc_size()).unwrap())
+    }
+
+    fn finish_collection(&mut self, _survivors: &[HeapHandle<()>]) {
+        // Assigned in place so addresses from bump_pointer stay valid.
+        self.space = self.to_space.take().unwrap();
+    }
+
+    #[cfg(feature = "jit")]
+    fn bump_pointer(&mut self) -> Option<BumpPointer> {
+        Some(self.space.bump_pointer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HeapConfig;
+    use crate::heap::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Delegates to a semispace, counting how many objects it relocates.
+    // While `fragmented`, allocations fail as if the free bytes were too
+    // scattered to use, until the next collection.
+    struct CountingStrategy {
+        inner: SemiSpaceCollector,
+        relocated: Rc<Cell<usize>>,
+        fragmented: bool,
+    }
+
+    impl CollectorStrategy for CountingStrategy {
+        fn size_in_bytes(&self) -> usize {
+            self.inner.size_in_bytes()
+        }
+
+        fn capacity_for(&self, size_in_bytes: usize) -> usize {
+            self.inner.capacity_for(size_in_bytes)
+        }
+
+        fn used_bytes(&self) -> usize {
+            self.inner.used_bytes()
+        }
+
+        fn free_bytes(&self) -> usize {
+            self.inner.free_bytes()
+        }
+
+        fn alloc(&mut self, size: usize) -> Result<*mut u8, GCError> {
+            if self.fragmented {
+                return Err(GCError::NoSpace);
+            }
+            self.inner.alloc(size)
+        }
+
+        fn prepare_collection(&mut self, size_in_bytes: usize) -> Result<(), GCError> {
+            self.inner.prepare_collection(size_in_bytes)
+        }
+
+        fn relocate(&mut self, header: &mut ObjectHeader) -> HeaderPtr {
+            self.relocated.set(self.relocated.get() + 1);
+            self.inner.relocate(header)
+        }
+
+        fn finish_collection(&mut self, survivors: &[HeapHandle<()>]) {
+            self.fragmented = false;
+            self.inner.finish_collection(survivors)
+        }
+    }
+
+    #[test]
+    fn custom_strategy_test() {
+        let relocated = Rc::new(Cell::new(0));
+        let strategy = CountingStrategy {
+            inner: SemiSpaceCollector::new(1000).unwrap(),
+            relocated: relocated.clone(),
+            fragmented: false,
+        };
+        let heap = Heap::with_strategy(HeapConfig::fixed(1000), Box::new(strategy)).unwrap();
+        let scope = HandleScope::new(&heap);
+        let kept = scope.str("kept").unwrap();
+        {
+            let inner_scope = HandleScope::new(&heap);
+            inner_scope.str("dropped").unwrap();
+        }
+        heap.collect().unwrap();
+        assert_eq!(relocated.get(), 1);
+        assert_eq!(*kept.as_ref(), "kept");
+    }
+
+    #[test]
+    fn to_space_on_demand_test() {
+        let heap = Heap::with_config(HeapConfig {
+            to_space_on_demand: true,
+            ..HeapConfig::fixed(1000)
+        })
+        .unwrap();
+        assert_eq!(heap.free_bytes(), 1000);
+        let scope = HandleScope::new(&heap);
+        // More live objects than half the budget would hold.
+        let count = 500 / HOST_OBJECT_ALLOC_SIZE + 1;
+        let strings: Vec<_> = (0..count).map(|_| scope.str("kept").unwrap()).collect();
+        heap.collect().unwrap();
+        assert_eq!(heap.size_in_bytes(), 1000);
+        assert!(heap.used_bytes() > 500);
+        assert!(strings.iter().all(|string| *string.as_ref() == "kept"));
+    }
+
+    #[test]
+    fn alloc_retry_test() {
+        let relocated = Rc::new(Cell::new(0));
+        let strategy = CountingStrategy {
+            inner: SemiSpaceCollector::new(1000).unwrap(),
+            relocated: relocated.clone(),
+            fragmented: true,
+        };
+        let heap = Heap::with_strategy(HeapConfig::fixed(1000), Box::new(strategy)).unwrap();
+        let scope = HandleScope::new(&heap);
+        // The first attempt fails, the collection before the retry succeeds.
+        let string = scope.str("retried").unwrap();
+        assert_eq!(*string.as_ref(), "retried");
+        assert_eq!(relocated.get(), 0);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::hash::HashAlgorithm;
+use crate::heap::Heap;
+use crate::types::*;
+
+type OomCallback = Box<dyn FnMut(&Heap, usize) -> bool>;
+
+/// What an allocation does when the heap is still full after collecting,
+/// see Heap::set_oom_policy.
+#[derive(Default)]
+pub enum OomPolicy {
+    // The allocation fails with GCError::NoSpace.
+    #[default]
+    ReturnError,
+    // Called with the heap and the bytes requested, e.g. to drop caches
+    // holding heap objects.  Returning true collects and retries the
+    // allocation, calling back again if it still doesn't fit.  Returning
+    // false, or true without the collection freeing anything, fails with
+    // GCError::NoSpace.
+    Callback(OomCallback),
+    // Prints a report of the heap's usage and aborts the process.
+    Abort,
+}
+
+impl std::fmt::Debug for OomPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OomPolicy::ReturnError => f.write_str("ReturnError"),
+            OomPolicy::Callback(_) => f.write_str("Callback(..)"),
+            OomPolicy::Abort => f.write_str("Abort"),
+        }
+    }
+}
+
+/// How short of memory the system is, reported by the embedder with
+/// Heap::notify_memory_pressure, e.g. on a mobile OS's low-memory warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryPressure {
+    #[default]
+    None,
+    Moderate,
+    Critical,
+}
+
+impl MemoryPressure {
+    // Under pressure the heap grows in smaller steps.
+    pub(crate) fn growth_factor(self, growth_factor: f64) -> f64 {
+        match self {
+            MemoryPressure::None => growth_factor,
+            MemoryPressure::Moderate => 1.0 + (growth_factor - 1.0) / 2.0,
+            MemoryPressure::Critical => 1.0 + (growth_factor - 1.0) / 4.0,
+        }
+    }
+}
+
+/// The algorithm used to reclaim memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollectorKind {
+    // Copies live objects between two semispaces, only half the heap size
+    // is usable unless HeapConfig::to_space_on_demand is set.
+    SemiSpace,
+    // Immix-style mark-region collection in 32KB blocks, which only moves
+    // objects out of fragmented blocks.  Sizes are rounded up to whole
+    // blocks.
+    Immix,
+    // Allocates into a nursery which minor collections promote from into a
+    // semispace old generation, see Heap::collect_minor.  An eighth of the
+    // budget is the nursery, half the rest is usable by the old generation.
+    Generational,
+    // Marks and sweeps in place, objects never move, for embedders holding
+    // raw object addresses.  The whole budget is usable, but the heap can't
+    // be resized.
+    MarkSweep,
+}
+
+/// Sizing policy for a Heap.  Sizes are the total budget for the heap, which
+/// the SemiSpace collector splits evenly between its two semispaces unless
+/// to_space_on_demand is set.
+#[derive(Debug, Clone)]
+pub struct HeapConfig {
+    pub initial_size_in_bytes: usize,
+    // The heap never shrinks below min_size_in_bytes.
+    pub min_size_in_bytes: usize,
+    // The heap never grows beyond max_size_in_bytes.
+    pub max_size_in_bytes: usize,
+    // Multiplier applied to the current size when the heap grows, which it
+    // does when an allocation still doesn't fit after a collection.
+    pub growth_factor: f64,
+    // After a collection leaving less than this fraction of the heap in use,
+    // the heap shrinks by growth_factor, handing memory back to the system.
+    // 0.0 never shrinks.
+    pub shrink_threshold: f64,
+    pub collector: CollectorKind,
+    // The SemiSpace collector normally keeps half the size in reserve for
+    // to-space.  When set, the whole size is usable and to-space is only
+    // allocated while collecting, so memory use briefly doubles then.
+    pub to_space_on_demand: bool,
+    // The Generational collector promotes objects into the old generation
+    // once they have survived this many minor collections, keeping younger
+    // survivors in the nursery.  Must be at least 1, which promotes every
+    // survivor.
+    pub promotion_age: u8,
+    // When set, objects held by a UniqueHandle are finalized as soon as the
+    // handle drops, rather than at the next collection.
+    pub eager_reclamation: bool,
+    // Keys the hashing of heap values, see Heap::hash_state.  None picks a
+    // random seed, set it to make hashes reproducible in tests.
+    pub hash_seed: Option<u64>,
+    // SipHash by default, FxHash trades collision resistance for speed.
+    pub hash_algorithm: HashAlgorithm,
+    // When set, the same sequence of allocations lays out and hashes objects
+    // identically on every run, for record/replay debuggers and differential
+    // tests.  The hash seed defaults to 0 rather than random.
+    pub deterministic: bool,
+    // When set, dead objects are queued rather than finalized during
+    // collections (or when their scope drops), and their finalizers only
+    // run from Heap::run_finalizers, at a point of the embedder's choosing.
+    pub deferred_finalization: bool,
+    // When set, every allocation and every new LocalHandle collects first,
+    // so an object the embedder forgot to root is moved or freed at once
+    // rather than by some rare later collection.  Very slow, for tests,
+    // and Immix heaps fill sooner as each collection leaves lines part
+    // used.  Defaults to whether the VMGC_GC_STRESS environment variable is
+    // set.
+    pub gc_stress: bool,
+}
+
+fn gc_stress_from_env() -> bool {
+    std::env::var_os("VMGC_GC_STRESS").is_some()
+}
+
+impl HeapConfig {
+    /// A heap which stays at exactly `size_in_bytes`, as made by Heap::new.
+    pub fn fixed(size_in_bytes: usize) -> HeapConfig {
+        HeapConfig {
+            initial_size_in_bytes: size_in_bytes,
+            min_size_in_bytes: size_in_bytes,
+            max_size_in_bytes: size_in_bytes,
+            growth_factor: 1.0,
+            shrink_threshold: 0.0,
+            collector: CollectorKind::SemiSpace,
+            to_space_on_demand: false,
+            promotion_age: 1,
+            eager_reclamation: false,
+            hash_seed: None,
+            hash_algorithm: HashAlgorithm::SipHash,
+            deterministic: false,
+            deferred_finalization: false,
+            gc_stress: gc_stress_from_env(),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), GCError> {
+        let sizes_ordered = self.min_size_in_bytes <= self.initial_size_in_bytes
+            && self.initial_size_in_bytes <= self.max_size_in_bytes;
+        let shrink_valid = (0.0..1.0).contains(&self.shrink_threshold);
+        if !sizes_ordered
+            || !shrink_valid
+            || self.promotion_age == 0
+            || self.growth_factor.is_nan()
+            || self.growth_factor < 1.0
+        {
+            return Err(GCError::InvalidConfig);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn allows_size(&self, size_in_bytes: usize) -> bool {
+        self.min_size_in_bytes <= size_in_bytes && size_in_bytes <= self.max_size_in_bytes
+    }
+}
+
+impl Default for HeapConfig {
+    fn default() -> Self {
+        HeapConfig {
+            initial_size_in_bytes: 1 << 20,
+            min_size_in_bytes: 1 << 16,
+            max_size_in_bytes: 1 << 30,
+            growth_factor: 2.0,
+            shrink_threshold: 0.25,
+            collector: CollectorKind::SemiSpace,
+            to_space_on_demand: false,
+            promotion_age: 1,
+            eager_reclamation: false,
+            hash_seed: None,
+            hash_algorithm: HashAlgorithm::SipHash,
+            deterministic: false,
+            deferred_finalization: false,
+            gc_stress: gc_stress_from_env(),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use std::collections::HashMap;
+
+// Small payloads are carved out of slabs in multiples of GRANULE bytes,
+// which is also the largest alignment a slab serves.
+const SLAB_SIZE: usize = 64 * 1024;
+const GRANULE: usize = 16;
+const MAX_SLAB_PAYLOAD: usize = 1024;
+
+// The size of a slab block for `layout` in granules, or None if it must
+// come from the global allocator.
+fn granules_for(layout: Layout) -> Option<usize> {
+    if layout.size() > MAX_SLAB_PAYLOAD || layout.align() > GRANULE {
+        return None;
+    }
+    Some(layout.size().div_ceil(GRANULE).max(1))
+}
+
+/// Memory for host objects' payloads, the T each TraceableObject points to.
+/// Payloads never move, only their headers do.  Small payloads come from
+/// slabs with a free list per size, so allocating and finalizing objects
+/// rarely reaches the global allocator, and everything left is freed at
+/// once when the heap is dropped.
+pub(crate) struct ControlBlocks {
+    slabs: Vec<*mut u8>,
+    // The unused end of the newest slab.
+    next: *mut u8,
+    limit: *mut u8,
+    // Freed blocks, indexed by their size in granules.
+    free_lists: Vec<Vec<*mut u8>>,
+    // Payloads too large or too aligned for a slab, by address.
+    large: HashMap<usize, Layout>,
+}
+
+impl Default for ControlBlocks {
+    fn default() -> Self {
+        ControlBlocks {
+            slabs: vec![],
+            next: std::ptr::null_mut(),
+            limit: std::ptr::null_mut(),
+            free_lists: vec![],
+            large: HashMap::new(),
+        }
+    }
+}
+
+impl ControlBlocks {
+    fn slab_layout() -> Layout {
+        Layout::from_size_align(SLAB_SIZE, GRANULE).unwrap()
+    }
+
+    /// Moves `value` into a new block.
+    pub(crate) fn alloc<T>(&mut self, value: T) -> *mut T {
+        let ptr = self.alloc_layout(Layout::new::<T>()) as *mut T;
+        unsafe {
+            ptr.write(value);
+        }
+        ptr
+    }
+
+    pub(crate) fn alloc_layout(&mut self, layout: Layout) -> *mut u8 {
+        let granules = match granules_for(layout) {
+            Some(granules) => granules,
+            None => return self.alloc_large(layout),
+        };
+        if let Some(ptr) = self.free_lists.get_mut(granules).and_then(Vec::pop) {
+            return ptr;
+        }
+        let size = granules * GRANULE;
+        if (self.limit as usize) - (self.next as usize) < size {
+            // The rest of the old slab is left unused.
+            let slab = unsafe { alloc(Self::slab_layout()) };
+            if slab.is_null() {
+                handle_alloc_error(Self::slab_layout());
+            }
+            self.slabs.push(slab);
+            self.next = slab;
+            self.limit = unsafe { slab.add(SLAB_SIZE) };
+        }
+        let ptr = self.next;
+        self.next = unsafe { ptr.add(size) };
+        ptr
+    }
+
+    fn alloc_large(&mut self, layout: Layout) -> *mut u8 {
+        // Zero sized allocations aren't allowed, even over-aligned ones.
+        let layout = Layout::from_size_align(layout.size().max(1), layout.align()).unwrap();
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        self.large.insert(ptr as usize, layout);
+        ptr
+    }
+
+    /// Gives back a block whose payload has already been dropped.
+    pub(crate) fn free(&mut self, ptr: *mut u8, layout: Layout) {
+        match granules_for(layout) {
+            Some(granules) => {
+                if self.free_lists.len() <= granules {
+                    self.free_lists.resize_with(granules + 1, Vec::new);
+                }
+                self.free_lists[granules].push(ptr);
+            }
+            None => {
+                let layout = self.large.remove(&(ptr as usize)).unwrap();
+                unsafe { dealloc(ptr, layout) };
+            }
+        }
+    }
+}
+
+impl Drop for ControlBlocks {
+    // Payloads still live when the heap is dropped are freed without being
+    // dropped, as objects are never finalized once their heap is gone.
+    fn drop(&mut self) {
+        for slab in self.slabs.drain(..) {
+            unsafe { dealloc(slab, Self::slab_layout()) };
+        }
+        for (addr, layout) in self.large.drain() {
+            unsafe { dealloc(addr as *mut u8, layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_test() {
+        let mut blocks = ControlBlocks::default();
+        let first = blocks.alloc(1u64);
+        let second = blocks.alloc([2u8; 20]);
+        assert_eq!(second as usize - first as usize, GRANULE);
+        blocks.free(first as *mut u8, Layout::new::<u64>());
+        // A freed block is reused by the next payload of its size.
+        assert_eq!(blocks.alloc(3u32) as *mut u8, first as *mut u8);
+
+        let large = blocks.alloc([4u8; 2000]);
+        assert_eq!(unsafe { (*large)[1999] }, 4);
+        blocks.free(large as *mut u8, Layout::new::<[u8; 2000]>());
+        assert!(blocks.large.is_empty());
+        assert_eq!(blocks.slabs.len(), 1);
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::hash::Hash;
+
+use crate::heap::{HandleScope, LocalHandle};
+use crate::object::*;
+use crate::types::GCError;
+
+/// Moves a Rust value into the heap, e.g. `vec![1.0, 2.0].into_heap(&scope)`.
+pub trait IntoHeap {
+    // The type of the handle produced, e.g. List<f64> for Vec<f64>.
+    type Output;
+
+    fn into_heap<'a>(
+        self,
+        scope: &'a HandleScope,
+    ) -> Result<LocalHandle<'a, Self::Output>, GCError>;
+}
+
+/// Copies a heap value out into a Rust value, failing with
+/// GCError::TypeError if the value has the wrong type.
+pub trait TryFromHeap: Sized {
+    // The type of the handle read from, e.g. List<f64> for Vec<f64>.
+    type Source;
+
+    fn try_from_heap(scope: &HandleScope, handle: &HeapHandle<()>) -> Result<Self, GCError>;
+}
+
+impl IntoHeap for f64 {
+    type Output = f64;
+
+    fn into_heap<'a>(self, scope: &'a HandleScope) -> Result<LocalHandle<'a, f64>, GCError> {
+        Ok(scope.create_num(self))
+    }
+}
+
+impl IntoHeap for i32 {
+    type Output = f64;
+
+    fn into_heap<'a>(self, scope: &'a HandleScope) -> Result<LocalHandle<'a, f64>, GCError> {
+        Ok(scope.create_num(self.into()))
+    }
+}
+
+impl IntoHeap for u32 {
+    type Output = f64;
+
+    fn into_heap<'a>(self, scope: &'a HandleScope) -> Result<LocalHandle<'a, f64>, GCError> {
+        Ok(scope.create_num(self.into()))
+    }
+}
+
+impl IntoHeap for bool {
+    type Output = bool;
+
+    fn into_heap<'a>(self, scope: &'a HandleScope) -> Result<LocalHandle<'a, bool>, GCError> {
+        Ok(scope.create_bool(self))
+    }
+}
+
+impl IntoHeap for String {
+    type Output = String;
+
+    fn into_heap<'a>(self, scope: &'a HandleScope) -> Result<LocalHandle<'a, String>, GCError> {
+        scope.take(self)
+    }
+}
+
+impl IntoHeap for &str {
+    type Output = String;
+
+    fn into_heap<'a>(self, scope: &'a HandleScope) -> Result<LocalHandle<'a, String>, GCError> {
+        scope.str(self)
+    }
+}
+
+impl<T: IntoHeap> IntoHeap for Vec<T>
+where
+    T::Output: 'static,
+{
+    type Output = List<T::Output>;
+
+    fn into_heap<'a>(
+        self,
+        scope: &'a HandleScope,
+    ) -> Result<LocalHandle<'a, Self::Output>, GCError> {
+        // The list is allocated first so that each element is rooted by it
+        // before the next allocation can trigger a collection.
+        let list = scope.create::<List<T::Output>>()?;
+        for value in self {
+            let element = value.into_heap(scope)?;
+            list.as_mut().append(element.into());
+        }
+        Ok(list)
+    }
+}
+
+impl<K: IntoHeap, V: IntoHeap> IntoHeap for HashMap<K, V>
+where
+    HeapHandle<K::Output>: Eq + Hash,
+    K::Output: 'static,
+    V::Output: 'static,
+{
+    type Output = Map<K::Output, V::Output>;
+
+    fn into_heap<'a>(
+        self,
+        scope: &'a HandleScope,
+    ) -> Result<LocalHandle<'a, Self::Output>, GCError> {
+        let map = scope.create_map::<K::Output, V::Output>()?;
+        for (key, value) in self {
+            let key = key.into_heap(scope)?;
+            let value = value.into_heap(scope)?;
+            map.as_mut().insert(key.into(), value.into());
+        }
+        Ok(map)
+    }
+}
+
+impl TryFromHeap for f64 {
+    type Source = f64;
+
+    fn try_from_heap(_scope: &HandleScope, handle: &HeapHandle<()>) -> Result<f64, GCError> {
+        handle.ptr().try_into()
+    }
+}
+
+macro_rules! impl_try_from_heap_integer {
+    ($($int:ty),*) => {
+        $(
+            impl TryFromHeap for $int {
+                type Source = f64;
+
+                fn try_from_heap(_scope: &HandleScope, handle: &HeapHandle<()>) -> Result<$int, GCError> {
+                    handle.ptr().try_into()
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from_heap_integer!(i32, i64, u32, usize);
+
+impl TryFromHeap for bool {
+    type Source = bool;
+
+    fn try_from_heap(_scope: &HandleScope, handle: &HeapHandle<()>) -> Result<bool, GCError> {
+        handle.ptr().try_into()
+    }
+}
+
+impl TryFromHeap for String {
+    type Source = String;
+
+    fn try_from_heap(_scope: &HandleScope, handle: &HeapHandle<()>) -> Result<String, GCError> {
+        if let Some(small) = handle.ptr().as_small_str() {
+            return Ok(small.to_string());
+        }
+        handle
+            .try_as_ref::<String>()
+            .cloned()
+            .ok_or(GCError::TypeError)
+    }
+}
+
+impl<T: TryFromHeap> TryFromHeap for Vec<T>
+where
+    T::Source: 'static,
+{
+    type Source = List<T::Source>;
+
+    fn try_from_heap(scope: &HandleScope, handle: &HeapHandle<()>) -> Result<Vec<T>, GCError> {
+        // Untyped lists are accepted as long as every element converts.
+        if let Some(list) = handle.try_as_ref::<List<()>>() {
+            return list
+                .iter()
+                .map(|element| T::try_from_heap(scope, element))
+                .collect();
+        }
+        let list = handle
+            .try_as_ref::<List<T::Source>>()
+            .ok_or(GCError::TypeError)?;
+        list.iter()
+            .map(|element| T::try_from_heap(scope, &element.erase_type()))
+            .collect()
+    }
+}
+
+impl<K, V> TryFromHeap for HashMap<K, V>
+where
+    K: TryFromHeap + Eq + Hash,
+    V: TryFromHeap,
+    K::Source: 'static,
+    V::Source: 'static,
+{
+    type Source = Map<K::Source, V::Source>;
+
+    fn try_from_heap(scope: &HandleScope, handle: &HeapHandle<()>) -> Result<Self, GCError> {
+        if let Some(map) = handle.try_as_ref::<Map<(), ()>>() {
+            return map
+                .iter()
+                .map(|(key, value)| {
+                    Ok((
+                        K::try_from_heap(scope, key)?,
+                        V::try_from_heap(scope, value)?,
+                    ))
+                })
+                .collect();
+        }
+        handle
+            .try_as_ref::<Map<K::Source, V::Source>>()
+            .ok_or(GCError::TypeError)?
+            .iter()
+            .map(|(key, value)| {
+                Ok((
+                    K::try_from_heap(scope, &key.erase_type())?,
+                    V::try_from_heap(scope, &value.erase_type())?,
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::Heap;
+
+    #[test]
+    fn round_trip_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+
+        let list = vec![1.0, 2.0, 3.0].into_heap(&scope).unwrap();
+        heap.collect().unwrap();
+        let values: Vec<f64> = list.try_from_heap(&scope).unwrap();
+        assert_eq!(values, vec![1.0, 2.0, 3.0]);
+        let wrong: Result<Vec<String>, GCError> = list.try_from_heap(&scope);
+        assert!(wrong.is_err());
+
+        let nested = vec![vec!["a"], vec!["b", "c"]].into_heap(&scope).unwrap();
+        heap.collect().unwrap();
+        let values: Vec<Vec<String>> = nested.try_from_heap(&scope).unwrap();
+        assert_eq!(values, vec![vec!["a"], vec!["b", "c"]]);
+
+        let mut map = HashMap::new();
+        map.insert("one".to_string(), 1.0);
+        map.insert("two".to_string(), 2.0);
+        let handle = map.clone().into_heap(&scope).unwrap();
+        heap.collect().unwrap();
+        let values: HashMap<String, f64> = handle.try_from_heap(&scope).unwrap();
+        assert_eq!(values, map);
+    }
+
+    #[test]
+    fn untyped_list_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<()>>().unwrap();
+        list.as_mut().push(scope.create_num(1.0).into());
+        list.as_mut().push(scope.create_bool(true).into());
+        let args: HeapHandle<()> = list.erase_type().into();
+        let result: Result<Vec<f64>, GCError> = args.try_from_heap(&scope);
+        assert!(matches!(result, Err(GCError::TypeError)));
+        let first: f64 = args.try_as_ref::<List<()>>().unwrap()[0]
+            .try_from_heap(&scope)
+            .unwrap();
+        assert_eq!(first, 1.0);
+    }
+}
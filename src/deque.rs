@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+
+use crate::display::ValueFormatter;
+use crate::object::*;
+
+/// A double-ended queue of values in a ring buffer, e.g. for a scheduler's
+/// run queue, so it can live in the heap rather than behind globals.  Make
+/// one with HandleScope::create.
+pub struct Deque<T> {
+    values: VecDeque<HeapHandle<T>>,
+}
+
+impl<T> Default for Deque<T> {
+    fn default() -> Self {
+        Deque {
+            values: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: 'static> HostObject for Deque<T> {}
+
+impl<T: 'static> Traceable for Deque<T> {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        for value in self.values.iter() {
+            value.trace(visitor);
+        }
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_list(self.iter())
+    }
+}
+
+impl<T> Deque<T> {
+    pub fn push_front(&mut self, value: HeapHandle<T>) {
+        self.values.push_front(value);
+    }
+
+    pub fn push_back(&mut self, value: HeapHandle<T>) {
+        self.values.push_back(value);
+    }
+
+    pub fn pop_front(&mut self) -> Option<HeapHandle<T>> {
+        self.values.pop_front()
+    }
+
+    pub fn pop_back(&mut self) -> Option<HeapHandle<T>> {
+        self.values.pop_back()
+    }
+
+    pub fn front(&self) -> Option<&HeapHandle<T>> {
+        self.values.front()
+    }
+
+    pub fn back(&self) -> Option<&HeapHandle<T>> {
+        self.values.back()
+    }
+
+    /// The value `index` places from the front.
+    pub fn get(&self, index: usize) -> Option<&HeapHandle<T>> {
+        self.values.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// From front to back.
+    pub fn iter(&self) -> impl Iterator<Item = &HeapHandle<T>> {
+        self.values.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+
+    #[test]
+    fn deque_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let deque = scope.create::<Deque<String>>().unwrap();
+        let str = |text: &str| -> HeapHandle<String> { scope.str(text).unwrap().into() };
+        deque.as_mut().push_back(str("b"));
+        deque.as_mut().push_front(str("a"));
+        deque.as_mut().push_back(str("c"));
+        heap.collect().unwrap();
+        assert_eq!(deque.display(&scope), "[\"a\", \"b\", \"c\"]");
+        assert_eq!(deque.as_ref().len(), 3);
+        assert_eq!(deque.as_ref().get(1).unwrap().as_ref(), "b");
+
+        // Wrapping around the ring buffer.
+        for round in 0..20 {
+            let front = deque.as_mut().pop_front().unwrap();
+            deque.as_mut().push_back(front);
+            if round % 5 == 0 {
+                heap.collect().unwrap();
+            }
+        }
+        assert_eq!(deque.as_ref().front().unwrap().as_ref(), "c");
+        assert_eq!(deque.as_mut().pop_back().unwrap().as_ref(), "b");
+        assert_eq!(deque.as_mut().pop_front().unwrap().as_ref(), "c");
+        assert_eq!(deque.as_ref().back().unwrap().as_ref(), "a");
+
+        // Popped values are no longer kept alive.
+        let used_bytes = heap.used_bytes();
+        {
+            let inner = HandleScope::new(&heap);
+            deque
+                .as_mut()
+                .push_back(inner.str("garbage").unwrap().into());
+            deque.as_mut().pop_back();
+        }
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), used_bytes);
+        deque.as_mut().clear();
+        assert!(deque.as_ref().is_empty());
+        assert!(deque.as_mut().pop_front().is_none());
+    }
+}
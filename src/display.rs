@@ -0,0 +1,207 @@
+use std::convert::TryInto;
+use std::fmt::{self, Write};
+
+use crate::heap::HandleScope;
+use crate::object::*;
+use crate::pointer::*;
+
+// Containers nested deeper than this are elided as "...".
+const MAX_DEPTH: usize = 16;
+
+/// Renders heap values as text for printing and debug logging.  Host objects
+/// describe themselves through Traceable::object_fmt.
+pub struct ValueFormatter<'a> {
+    scope: &'a HandleScope<'a>,
+    out: String,
+    // Addresses of the objects currently being written, used to cut cycles.
+    in_progress: Vec<*mut u8>,
+}
+
+impl<'a> ValueFormatter<'a> {
+    pub(crate) fn new(scope: &'a HandleScope<'a>) -> ValueFormatter<'a> {
+        ValueFormatter {
+            scope,
+            out: String::new(),
+            in_progress: vec![],
+        }
+    }
+
+    pub(crate) fn finish(self) -> String {
+        self.out
+    }
+
+    /// The scope the value is being displayed from, for host objects which
+    /// need handles to describe themselves.
+    pub fn scope(&self) -> &'a HandleScope<'a> {
+        self.scope
+    }
+
+    pub fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.out.write_str(text)
+    }
+
+    pub fn write_quoted(&mut self, text: &str) -> fmt::Result {
+        write!(self.out, "{:?}", text)
+    }
+
+    pub fn write_value<T>(&mut self, handle: &HeapHandle<T>) -> fmt::Result {
+        self.write_ptr(handle.ptr())
+    }
+
+    pub fn write_list<'h, T: 'h, I>(&mut self, elements: I) -> fmt::Result
+    where
+        I: IntoIterator<Item = &'h HeapHandle<T>>,
+    {
+        self.write_str("[")?;
+        for (index, element) in elements.into_iter().enumerate() {
+            if index > 0 {
+                self.write_str(", ")?;
+            }
+            self.write_value(element)?;
+        }
+        self.write_str("]")
+    }
+
+    pub fn write_set<'h, T: 'h, I>(&mut self, elements: I) -> fmt::Result
+    where
+        I: IntoIterator<Item = &'h HeapHandle<T>>,
+    {
+        self.write_str("{")?;
+        for (index, element) in elements.into_iter().enumerate() {
+            if index > 0 {
+                self.write_str(", ")?;
+            }
+            self.write_value(element)?;
+        }
+        self.write_str("}")
+    }
+
+    pub fn write_map<'h, K: 'h, V: 'h, I>(&mut self, entries: I) -> fmt::Result
+    where
+        I: IntoIterator<Item = (&'h HeapHandle<K>, &'h HeapHandle<V>)>,
+    {
+        self.write_str("{")?;
+        for (index, (key, value)) in entries.into_iter().enumerate() {
+            if index > 0 {
+                self.write_str(", ")?;
+            }
+            self.write_value(key)?;
+            self.write_str(": ")?;
+            self.write_value(value)?;
+        }
+        self.write_str("}")
+    }
+
+    pub(crate) fn write_ptr(&mut self, ptr: TaggedPtr) -> fmt::Result {
+        if ptr.is_num() {
+            let value: f64 = ptr.try_into().unwrap();
+            return self.write_str(&format_num(value));
+        }
+        if ptr.is_undefined() {
+            return self.write_str("undefined");
+        }
+        if let Some(small) = ptr.as_small_str() {
+            return self.write_quoted(&small);
+        }
+        if ptr.is_int() {
+            let value: i32 = ptr.try_into().unwrap();
+            return self.write_str(&value.to_string());
+        }
+        if ptr.is_bool() {
+            return self.write_str(if ptr.is_true_singleton() {
+                "true"
+            } else {
+                "false"
+            });
+        }
+        let object_ptr: ObjectPtr = match ptr.try_into() {
+            Ok(object_ptr) => object_ptr,
+            Err(_) => return self.write_str("null"),
+        };
+        if self.in_progress.contains(&object_ptr.addr()) {
+            return self.write_str("<cycle>");
+        }
+        if self.in_progress.len() >= MAX_DEPTH {
+            return self.write_str("...");
+        }
+        self.in_progress.push(object_ptr.addr());
+        let object = TraceableObject::load(object_ptr);
+        let result = object.as_traceable().object_fmt(self);
+        self.in_progress.pop();
+        result
+    }
+}
+
+// Matches C's "%.14g", which avoids printing binary float noise like
+// 0.30000000000000004.
+fn format_num(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "infinity" } else { "-infinity" }.to_string();
+    }
+    if value == 0.0 {
+        return if value.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+    let scientific = format!("{:.13e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if !(-4..14).contains(&exponent) {
+        let mantissa = trim_fraction(mantissa);
+        let sign = if exponent < 0 { '-' } else { '+' };
+        return format!("{}e{}{:02}", mantissa, sign, exponent.abs());
+    }
+    let decimals = (13 - exponent).max(0) as usize;
+    trim_fraction(&format!("{:.*}", decimals, value)).to_string()
+}
+
+fn trim_fraction(text: &str) -> &str {
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+
+    #[test]
+    fn format_num_test() {
+        assert_eq!(format_num(1.0), "1");
+        assert_eq!(format_num(-2.5), "-2.5");
+        assert_eq!(format_num(0.1 + 0.2), "0.3");
+        assert_eq!(format_num(1e20), "1e+20");
+        assert_eq!(format_num(0.00001), "1e-05");
+        assert_eq!(format_num(123456789.0), "123456789");
+        assert_eq!(format_num(f64::NAN), "nan");
+        assert_eq!(format_num(f64::NEG_INFINITY), "-infinity");
+    }
+
+    #[test]
+    fn display_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        assert_eq!(scope.create_num(3.0).display(&scope), "3");
+        assert_eq!(scope.create_bool(false).display(&scope), "false");
+        assert_eq!(scope.create_null().display(&scope), "null");
+        assert_eq!(scope.str("a\"b").unwrap().display(&scope), "\"a\\\"b\"");
+
+        let list = scope.create::<List<()>>().unwrap();
+        list.as_mut().push(scope.create_num(1.0).into());
+        list.as_mut().push(scope.str("two").unwrap().into());
+        assert_eq!(list.display(&scope), "[1, \"two\"]");
+
+        // Lists containing themselves don't recurse forever.
+        list.as_mut().push(list.clone().into());
+        assert_eq!(list.display(&scope), "[1, \"two\", <cycle>]");
+
+        let map = scope.create::<Map<String, f64>>().unwrap();
+        map.as_mut()
+            .insert(scope.str("a").unwrap().into(), 1.0.into());
+        assert_eq!(map.display(&scope), "{\"a\": 1}");
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::{self, Write};
+
+use crate::snapshot::SnapshotNode;
+
+// Strings longer than this are cut short in labels, to keep graphs legible.
+const MAX_STRING_LABEL: usize = 32;
+
+// Writes `roots` and `nodes` as a Graphviz digraph, with a "roots" node
+// referring to each of `roots` (indices into `nodes`).  Objects are labeled
+// by their type name, strings by their text, then their size in bytes.
+pub(crate) fn write_dot(
+    roots: &[usize],
+    nodes: &[SnapshotNode],
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    writer.write_all(b"digraph heap {\n  node [shape=box];\n")?;
+    writer.write_all(b"  roots [label=\"(GC roots)\", shape=ellipse];\n")?;
+    for (index, node) in nodes.iter().enumerate() {
+        let name = if node.is_string {
+            let mut end = node.name.len().min(MAX_STRING_LABEL);
+            while !node.name.is_char_boundary(end) {
+                end -= 1;
+            }
+            let ellipsis = if end < node.name.len() { "..." } else { "" };
+            format!("\"{}{}\"", &node.name[..end], ellipsis)
+        } else {
+            node.name.clone()
+        };
+        write!(writer, "  n{} [label=", index)?;
+        write_dot_string(&format!("{}\n{} bytes", name, node.self_size), writer)?;
+        writer.write_all(b"];\n")?;
+    }
+    for root in roots {
+        writeln!(writer, "  roots -> n{};", root)?;
+    }
+    for (index, node) in nodes.iter().enumerate() {
+        for to in node.edges.iter() {
+            writeln!(writer, "  n{} -> n{};", index, to)?;
+        }
+    }
+    writer.write_all(b"}\n")
+}
+
+fn write_dot_string(string: &str, writer: &mut dyn Write) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => writer.write_all(b"\\\"")?,
+            '\\' => writer.write_all(b"\\\\")?,
+            '\n' => writer.write_all(b"\\n")?,
+            c => write!(writer, "{}", c)?,
+        }
+    }
+    writer.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heap::*;
+    use crate::object::*;
+
+    #[test]
+    fn write_dot_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        {
+            let inner = HandleScope::new(&heap);
+            list.as_mut().push(inner.str("first").unwrap().into());
+            list.as_mut().push(
+                inner
+                    .str("a \"quoted\" string long enough to cut")
+                    .unwrap()
+                    .into(),
+            );
+            inner.str("garbage").unwrap();
+        }
+        let mut out = vec![];
+        heap.write_dot(&mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+
+        assert!(dot.starts_with("digraph heap {\n"));
+        assert!(dot.ends_with("}\n"));
+        // The list and its two strings, the garbage collected first.
+        assert_eq!(dot.matches(" [label=").count(), 4);
+        assert!(!dot.contains("garbage"));
+        let list_label = format!("[label=\"{}\\n", std::any::type_name::<List<String>>());
+        assert!(dot.contains(&list_label));
+        assert!(dot.contains(r#"[label="\"first\"\n"#));
+        assert!(dot.contains(r#"[label="\"a \"quoted\" string long enough to...\"\n"#));
+        assert_eq!(dot.matches("roots -> ").count(), 1);
+        assert_eq!(dot.matches(" -> ").count(), 3);
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::pointer::HeaderFlags;
+
+const MAGIC: &[u8; 8] = b"VMGCDUMP";
+const VERSION: u32 = 1;
+
+/// A heap written by Heap::dump and loaded with HeapDump::read, e.g. from a
+/// deployed VM, for finding out offline what filled it or keeps an object
+/// alive.  Objects are numbered by their index in objects().
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeapDump {
+    types: Vec<String>,
+    type_indices: HashMap<String, usize>,
+    roots: Vec<usize>,
+    objects: Vec<DumpedObject>,
+}
+
+/// An object in a HeapDump, from its header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpedObject {
+    pub(crate) type_index: usize,
+    // Where it was in the dumped process, to match against crash reports.
+    pub address: u64,
+    // The object in the heap, header included.
+    pub heap_size: usize,
+    // Its payload outside the heap.
+    pub payload_size: usize,
+    pub identity_hash: u32,
+    pub user_tag: u32,
+    pub flags: HeaderFlags,
+    // Indices of the objects it refers to.
+    pub references: Vec<usize>,
+}
+
+impl HeapDump {
+    pub(crate) fn new(roots: Vec<usize>) -> HeapDump {
+        HeapDump {
+            types: vec![],
+            type_indices: HashMap::new(),
+            roots,
+            objects: vec![],
+        }
+    }
+
+    pub(crate) fn type_index(&mut self, type_name: &str) -> usize {
+        if let Some(index) = self.type_indices.get(type_name) {
+            return *index;
+        }
+        self.types.push(type_name.to_string());
+        self.type_indices
+            .insert(type_name.to_string(), self.types.len() - 1);
+        self.types.len() - 1
+    }
+
+    pub(crate) fn push(&mut self, object: DumpedObject) {
+        self.objects.push(object);
+    }
+
+    /// The objects the roots refer to.
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    pub fn objects(&self) -> &[DumpedObject] {
+        &self.objects
+    }
+
+    /// The Rust type name of object `index`.
+    pub fn type_name(&self, index: usize) -> &str {
+        &self.types[self.objects[index].type_index]
+    }
+
+    pub fn objects_of_type<'a>(&'a self, type_name: &'a str) -> impl Iterator<Item = usize> + 'a {
+        (0..self.objects.len()).filter(move |index| self.type_name(*index) == type_name)
+    }
+
+    /// The objects referring to object `index`.
+    pub fn referrers(&self, index: usize) -> Vec<usize> {
+        (0..self.objects.len())
+            .filter(|referrer| self.objects[*referrer].references.contains(&index))
+            .collect()
+    }
+
+    /// The shortest chain of references from a root to object `index`,
+    /// starting with the object a root refers to and ending with `index`, or
+    /// None if it's garbage the heap hadn't collected yet.
+    pub fn path_from_root(&self, index: usize) -> Option<Vec<usize>> {
+        let mut previous: Vec<Option<usize>> = vec![None; self.objects.len()];
+        let mut seen = vec![false; self.objects.len()];
+        let mut queue = VecDeque::new();
+        for root in self.roots.iter() {
+            if !seen[*root] {
+                seen[*root] = true;
+                queue.push_back(*root);
+            }
+        }
+        while let Some(current) = queue.pop_front() {
+            if current == index {
+                let mut path = vec![current];
+                while let Some(referrer) = previous[*path.last().unwrap()] {
+                    path.push(referrer);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for reference in self.objects[current].references.iter() {
+                if !seen[*reference] {
+                    seen[*reference] = true;
+                    previous[*reference] = Some(current);
+                    queue.push_back(*reference);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+        write_len(writer, self.types.len())?;
+        for type_name in self.types.iter() {
+            write_len(writer, type_name.len())?;
+            writer.write_all(type_name.as_bytes())?;
+        }
+        write_indices(writer, &self.roots)?;
+        write_len(writer, self.objects.len())?;
+        for object in self.objects.iter() {
+            write_len(writer, object.type_index)?;
+            writer.write_all(&object.address.to_le_bytes())?;
+            writer.write_all(&(object.heap_size as u64).to_le_bytes())?;
+            writer.write_all(&(object.payload_size as u64).to_le_bytes())?;
+            write_u32(writer, object.identity_hash)?;
+            write_u32(writer, object.user_tag)?;
+            writer.write_all(&[object.flags.bits(), object.flags.age()])?;
+            write_indices(writer, &object.references)?;
+        }
+        Ok(())
+    }
+
+    /// Loads a dump written by Heap::dump, failing with
+    /// io::ErrorKind::InvalidData if it's malformed.
+    pub fn read(mut reader: impl Read) -> io::Result<HeapDump> {
+        let reader: &mut dyn Read = &mut reader;
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(reader)? != VERSION {
+            return Err(invalid_data("not a vmgc heap dump"));
+        }
+        let type_count = read_len(reader)?;
+        let mut types = Vec::new();
+        for _ in 0..type_count {
+            let len = read_len(reader)?;
+            // As with read_indices, len only bounds the read.
+            let mut bytes = Vec::new();
+            reader.take(len as u64).read_to_end(&mut bytes)?;
+            if bytes.len() != len {
+                return Err(invalid_data("truncated type name"));
+            }
+            types.push(String::from_utf8(bytes).map_err(|_| invalid_data("type name"))?);
+        }
+        let roots = read_indices(reader)?;
+        let object_count = read_len(reader)?;
+        let mut objects = Vec::new();
+        for _ in 0..object_count {
+            let type_index = read_len(reader)?;
+            let address = read_u64(reader)?;
+            let heap_size = read_u64(reader)? as usize;
+            let payload_size = read_u64(reader)? as usize;
+            let identity_hash = read_u32(reader)?;
+            let user_tag = read_u32(reader)?;
+            let mut flags = [0; 2];
+            reader.read_exact(&mut flags)?;
+            if type_index >= types.len() {
+                return Err(invalid_data("type index out of range"));
+            }
+            objects.push(DumpedObject {
+                type_index,
+                address,
+                heap_size,
+                payload_size,
+                identity_hash,
+                user_tag,
+                flags: HeaderFlags::from_bits(flags[0], flags[1]),
+                references: read_indices(reader)?,
+            });
+        }
+        let in_range = |index: &usize| *index < objects.len();
+        let references = objects.iter().flat_map(|object| object.references.iter());
+        if !roots.iter().chain(references).all(in_range) {
+            return Err(invalid_data("object index out of range"));
+        }
+        let type_indices = types
+            .iter()
+            .enumerate()
+            .map(|(index, type_name)| (type_name.clone(), index))
+            .collect();
+        Ok(HeapDump {
+            types,
+            type_indices,
+            roots,
+            objects,
+        })
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Numbers are little endian, counts and indices 32 bits.
+fn write_u32(writer: &mut dyn Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_len(writer: &mut dyn Write, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len).map_err(|_| invalid_data("too large to dump"))?;
+    write_u32(writer, len)
+}
+
+fn write_indices(writer: &mut dyn Write, indices: &[usize]) -> io::Result<()> {
+    write_len(writer, indices.len())?;
+    for index in indices {
+        write_len(writer, *index)?;
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut dyn Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_len(reader: &mut dyn Read) -> io::Result<usize> {
+    read_u32(reader).map(|len| len as usize)
+}
+
+fn read_indices(reader: &mut dyn Read) -> io::Result<Vec<usize>> {
+    let len = read_len(reader)?;
+    // Not trusting len to preallocate, the dump may be truncated.
+    let mut indices = Vec::new();
+    for _ in 0..len {
+        indices.push(read_len(reader)?);
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+    use crate::object::*;
+    use crate::pointer::ObjectPtr;
+    use std::convert::TryInto;
+
+    #[test]
+    fn dump_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        let tagged = scope.take_tagged(String::from("tagged"), 7).unwrap();
+        list.as_mut().push(tagged.into());
+        {
+            // Only the list keeps "held" alive.
+            let inner = HandleScope::new(&heap);
+            list.as_mut().push(inner.str("held").unwrap().into());
+            inner.str("garbage").unwrap();
+        }
+        let mut out = vec![];
+        heap.dump(&mut out).unwrap();
+        let dump = HeapDump::read(out.as_slice()).unwrap();
+
+        // Garbage isn't collected first, but nothing reaches it.
+        assert_eq!(dump.objects().len(), 4);
+        let string_name = std::any::type_name::<String>();
+        let strings: Vec<usize> = dump.objects_of_type(string_name).collect();
+        assert_eq!(strings.len(), 3);
+        let list_index = dump
+            .objects_of_type(std::any::type_name::<List<String>>())
+            .next()
+            .unwrap();
+        let list_object = &dump.objects()[list_index];
+        let list_ptr: ObjectPtr = list.ptr_for_test().try_into().unwrap();
+        assert_eq!(list_object.address, list_ptr.addr() as u64);
+        assert_eq!(list_object.references.len(), 2);
+        let tagged_index = list_object.references[0];
+        assert_eq!(dump.objects()[tagged_index].user_tag, 7);
+        assert_eq!(dump.referrers(tagged_index), vec![list_index]);
+        let held_index = list_object.references[1];
+        assert_eq!(
+            dump.path_from_root(held_index),
+            Some(vec![list_index, held_index])
+        );
+        let garbage = strings
+            .iter()
+            .find(|index| dump.path_from_root(**index).is_none());
+        assert!(garbage.is_some());
+        assert!(dump.objects().iter().all(|object| object.heap_size > 0));
+
+        // Round trips, and rejects what isn't a whole dump.
+        let mut rewritten = vec![];
+        dump.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten, out);
+        assert!(HeapDump::read(&out[..out.len() - 1]).is_err());
+        let error = HeapDump::read(&b"not a dump at all"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // A huge type name length in a truncated dump fails without
+        // allocating for it.
+        let mut truncated = MAGIC.to_vec();
+        truncated.extend_from_slice(&VERSION.to_le_bytes());
+        truncated.extend_from_slice(&1u32.to_le_bytes());
+        truncated.extend_from_slice(&u32::MAX.to_le_bytes());
+        truncated.extend_from_slice(b"short");
+        let error = HeapDump::read(truncated.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
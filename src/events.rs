@@ -0,0 +1,47 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Notifications about collector activity, delivered to receivers returned by
+/// Heap::subscribe_events.  Events are plain data so they can be consumed on
+/// another thread without running code on the VM thread.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GcEvent {
+    CollectionStarted {
+        used_bytes: usize,
+    },
+    CollectionFinished {
+        used_bytes_before: usize,
+        used_bytes_after: usize,
+        objects_finalized: usize,
+    },
+    FinalizersRun {
+        count: usize,
+    },
+    // An allocation did not fit in the space and forced a collection.
+    LimitCrossed {
+        requested_bytes: usize,
+        used_bytes: usize,
+        limit_bytes: usize,
+    },
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct EventSinks {
+    senders: Vec<Sender<GcEvent>>,
+}
+
+impl EventSinks {
+    pub fn subscribe(&mut self) -> Receiver<GcEvent> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    pub fn send(&mut self, event: GcEvent) {
+        if self.senders.is_empty() {
+            return;
+        }
+        // Receivers which have been dropped are forgotten.
+        self.senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
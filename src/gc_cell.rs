@@ -0,0 +1,77 @@
+use crate::display::ValueFormatter;
+use crate::heap::{HandleScope, LocalHandle};
+use crate::object::*;
+
+/// A heap object holding a single value, e.g. a closure's upvalue or a
+/// hosted language's mutable box.  Starts out null; make one with
+/// HandleScope::create.  set takes the cell mutably, so it's reached
+/// through LocalHandle::as_mut, whose write barrier keeps an old cell's
+/// young value alive.
+#[derive(Default)]
+pub struct GcCell {
+    value: HeapHandle<()>,
+}
+
+impl GcCell {
+    pub fn get<'a>(&self, scope: &'a HandleScope) -> LocalHandle<'a, ()> {
+        scope.from_heap(&self.value)
+    }
+
+    pub fn set<T>(&mut self, value: HeapHandle<T>) {
+        self.value = value.erase_type();
+    }
+
+    /// Empties the cell, returning what it held.
+    pub fn take<'a>(&mut self, scope: &'a HandleScope) -> LocalHandle<'a, ()> {
+        scope.from_heap(&self.value.take())
+    }
+}
+
+impl HostObject for GcCell {}
+
+impl Traceable for GcCell {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        self.value.trace(visitor);
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_str("cell(")?;
+        formatter.write_value(&self.value)?;
+        formatter.write_str(")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CollectorKind, HeapConfig};
+    use crate::heap::*;
+
+    #[test]
+    fn gc_cell_test() {
+        let heap = Heap::with_config(HeapConfig {
+            collector: CollectorKind::Generational,
+            ..HeapConfig::fixed(1 << 16)
+        })
+        .unwrap();
+        let scope = HandleScope::new(&heap);
+        let cell = scope.create::<GcCell>().unwrap();
+        assert!(cell.as_ref().get(&scope).is_null());
+        // Promote the cell, so only the barrier keeps its new value alive.
+        heap.collect().unwrap();
+        {
+            let inner = HandleScope::new(&heap);
+            cell.as_mut().set(inner.str("young").unwrap().into());
+        }
+        heap.collect_minor().unwrap();
+        heap.collect_minor().unwrap();
+        let value = cell.as_ref().get(&scope);
+        assert_eq!(value.try_as_ref::<String>().unwrap(), "young");
+        assert_eq!(cell.display(&scope), "cell(\"young\")");
+
+        cell.as_mut().set(scope.create_num(1.0).into());
+        assert_eq!(cell.display(&scope), "cell(1)");
+        assert!(cell.as_mut().take(&scope).is_num());
+        assert!(cell.as_ref().get(&scope).is_null());
+    }
+}
@@ -0,0 +1,105 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::byte_array::{bytes_of, TrailingBytes};
+use crate::display::ValueFormatter;
+use crate::object::*;
+use crate::pointer::*;
+
+/// An immutable string whose UTF-8 bytes are stored in one block with its
+/// length, so unlike a String object it needs no second allocation or
+/// finalizer.  Made by HandleScope::gc_str, and only ever used in place.
+/// Hashes and compares like String and SharedStr.
+pub struct GcString {
+    _in_place: [usize; 0],
+}
+
+impl TrailingBytes for GcString {}
+
+impl GcString {
+    pub fn as_str(&self) -> &str {
+        // Only ever filled from a str, see HandleScope::gc_str.
+        unsafe { std::str::from_utf8_unchecked(bytes_of(self)) }
+    }
+
+    /// The length in bytes.
+    pub fn len(&self) -> usize {
+        self.as_str().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl std::ops::Deref for GcString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl HostObject for GcString {}
+
+impl Traceable for GcString {
+    fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+
+    fn object_hash(&self, _ptr: ObjectPtr, mut state: &mut dyn Hasher) {
+        self.as_str().hash(&mut state);
+    }
+
+    fn object_eq(&self, _lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> bool {
+        object_str(rhs_object_ptr) == Some(self.as_str())
+    }
+
+    fn object_cmp(&self, _lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> Option<Ordering> {
+        str_cmp(self.as_str(), rhs_object_ptr)
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_quoted(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+
+    #[test]
+    fn gc_string_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let text = scope.gc_str("héllo").unwrap();
+        let used_bytes = heap.used_bytes();
+        {
+            let inner = HandleScope::new(&heap);
+            inner.gc_str("garbage").unwrap();
+        }
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), used_bytes);
+        assert_eq!(text.as_ref().as_str(), "héllo");
+        assert_eq!(text.as_ref().len(), 6);
+        assert!(scope.gc_str("").unwrap().as_ref().is_empty());
+        assert_eq!(text.display(&scope), "\"héllo\"");
+
+        // Interchangeable with the other string types.
+        let string = scope.str("héllo").unwrap().erase_type();
+        let shared = scope.shared_str("héllo").unwrap().erase_type();
+        let text = text.erase_type();
+        let ptr = |handle: &LocalHandle<'_, ()>| handle.ptr_for_test();
+        assert!(ptr(&text) == ptr(&string) && ptr(&string) == ptr(&text));
+        assert!(ptr(&text) == ptr(&shared) && ptr(&shared) == ptr(&text));
+        let hash = |handle: &LocalHandle<'_, ()>| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            ptr(handle).hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&text), hash(&string));
+        let other = scope.gc_str("world").unwrap().erase_type();
+        assert_eq!(ptr(&text).compare(&ptr(&other)), Some(Ordering::Less));
+        assert_eq!(ptr(&string).compare(&ptr(&other)), Some(Ordering::Less));
+        assert_eq!(text.with_str(str::len), Some(6));
+    }
+}
@@ -0,0 +1,407 @@
+#[cfg(feature = "jit")]
+use crate::collector::BumpPointer;
+use crate::collector::CollectorStrategy;
+use crate::object::HeapHandle;
+use crate::pointer::*;
+use crate::space::*;
+use crate::types::*;
+use std::rc::Rc;
+
+// The nursery's share of the budget, the rest is split between the old
+// generation's two semispaces.
+const NURSERY_FRACTION: usize = 8;
+
+fn nursery_size_for(size_in_bytes: usize) -> usize {
+    size_in_bytes / NURSERY_FRACTION
+}
+
+fn old_size_for(size_in_bytes: usize) -> usize {
+    (size_in_bytes - nursery_size_for(size_in_bytes)) / 2
+}
+
+/// Allocates into a nursery, which minor collections (Heap::collect_minor)
+/// empty by promoting its survivors into the old generation.  Full
+/// collections copy everything into a fresh old semispace.
+///
+/// A minor collection treats every old object as live, and traces those in
+/// cards of the old generation's CardTable which have been written to since
+/// the last collection for references into the nursery.
+///
+/// By default every survivor of a minor collection is promoted.  With a
+/// higher promotion age, younger survivors are copied to a fresh nursery
+/// instead, so short lived objects which happened to be live at one
+/// collection don't fill the old generation.
+pub struct GenerationalCollector {
+    nursery: Space,
+    old: Space,
+    // Set while a full collection is copying into a fresh old generation,
+    // or a minor one is keeping some survivors young.
+    to_space: Option<Space>,
+    next_nursery: Option<Space>,
+    minor: bool,
+    promotion_age: u8,
+    // Whether the current minor collection has kept any survivors young.
+    kept_young: bool,
+    // Shared by all of this heap's spaces.
+    barrier: Rc<WriteBarrier>,
+}
+
+impl GenerationalCollector {
+    pub fn new(size_in_bytes: usize) -> Result<GenerationalCollector, GCError> {
+        Self::with_promotion_age(size_in_bytes, 1)
+    }
+
+    /// Promotes objects once they have survived `promotion_age` minor
+    /// collections, see HeapConfig::promotion_age.
+    pub fn with_promotion_age(
+        size_in_bytes: usize,
+        promotion_age: u8,
+    ) -> Result<GenerationalCollector, GCError> {
+        if promotion_age == 0 {
+            return Err(GCError::InvalidConfig);
+        }
+        let barrier = Rc::new(WriteBarrier::default());
+        Ok(GenerationalCollector {
+            nursery: Space::with_barrier(nursery_size_for(size_in_bytes), &barrier)?,
+            old: Space::with_card_table(old_size_for(size_in_bytes), &barrier)?,
+            to_space: None,
+            next_nursery: None,
+            minor: false,
+            promotion_age,
+            kept_young: false,
+            barrier,
+        })
+    }
+}
+
+impl CollectorStrategy for GenerationalCollector {
+    fn size_in_bytes(&self) -> usize {
+        self.nursery.size_in_bytes + self.old.size_in_bytes * 2
+    }
+
+    // Everything must fit in the old generation after a full collection.
+    fn capacity_for(&self, size_in_bytes: usize) -> usize {
+        old_size_for(size_in_bytes)
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.nursery.used_bytes() + self.old.used_bytes()
+    }
+
+    // Only the nursery is allocated from.
+    fn free_bytes(&self) -> usize {
+        self.nursery.free_bytes()
+    }
+
+    fn alloc(&mut self, size: usize) -> Result<*mut u8, GCError> {
+        self.nursery.alloc(size)
+    }
+
+    fn unalloc(&mut self, addr: *mut u8, size: usize) {
+        self.nursery.unalloc(addr, size);
+    }
+
+    fn prepare_collection(&mut self, size_in_bytes: usize) -> Result<(), GCError> {
+        let to_space = Space::with_card_table(old_size_for(size_in_bytes), &self.barrier)?;
+        self.next_nursery = Some(Space::with_barrier(
+            nursery_size_for(size_in_bytes),
+            &self.barrier,
+        )?);
+        self.to_space = Some(to_space);
+        self.minor = false;
+        Ok(())
+    }
+
+    fn prepare_minor_collection(&mut self) -> bool {
+        // Promotes into old, not a to-space left by a full collection's
+        // prepare.
+        self.to_space = None;
+        self.next_nursery = None;
+        // Promoting could fail part way if the old generation can't take the
+        // whole nursery, so collect everything instead.
+        if self.old.free_bytes() < self.nursery.used_bytes() {
+            return false;
+        }
+        if self.promotion_age > 1 {
+            match Space::with_barrier(self.nursery.size_in_bytes, &self.barrier) {
+                Ok(next_nursery) => self.next_nursery = Some(next_nursery),
+                Err(_) => return false,
+            }
+        }
+        self.minor = true;
+        true
+    }
+
+    fn write_barrier(&self) -> Option<&WriteBarrier> {
+        Some(&self.barrier)
+    }
+
+    fn is_young(&self, header: &mut ObjectHeader) -> bool {
+        self.nursery.contains(header.as_ptr().addr())
+    }
+
+    fn is_remembered(&self, header: &mut ObjectHeader) -> bool {
+        let card_table = self.old.card_table().unwrap();
+        card_table.is_dirty(header.as_ptr().addr())
+    }
+
+    fn relocate(&mut self, header: &mut ObjectHeader) -> HeaderPtr {
+        let size = header.alloc_size();
+        // The age counts copies so far, this is one more survival.
+        if self.minor && header.age() + 1 < self.promotion_age {
+            self.kept_young = true;
+            let next_nursery = self.next_nursery.as_mut().unwrap();
+            // Everything young fits in a nursery of the same size.
+            return HeaderPtr::new(next_nursery.alloc(size).unwrap());
+        }
+        let space = match self.to_space.as_mut() {
+            Some(to_space) => to_space,
+            None => &mut self.old,
+        };
+        // Checked to fit by prepare_collection or prepare_minor_collection.
+        let addr = space.alloc(size).unwrap();
+        if self.minor && self.promotion_age > 1 {
+            // The promoted object may point to one kept young.
+            write_barrier(addr);
+        }
+        HeaderPtr::new(addr)
+    }
+
+    fn finish_collection(&mut self, _survivors: &[HeapHandle<()>]) {
+        // Both assigned in place so addresses from bump_pointer stay valid.
+        if self.minor {
+            match self.next_nursery.take() {
+                Some(next_nursery) => self.nursery = next_nursery,
+                None => self.nursery.reset(),
+            }
+            // Once everything young was promoted no old object points into
+            // the nursery any more.  Otherwise the cards stay dirty.
+            if !self.kept_young {
+                self.old.card_table().unwrap().clear();
+            }
+        } else {
+            self.old = self.to_space.take().unwrap();
+            self.nursery = self.next_nursery.take().unwrap();
+        }
+        if self.barrier.has_open_writes() {
+            self.old.card_table().unwrap().dirty_all();
+        }
+        self.minor = false;
+        self.kept_young = false;
+    }
+
+    #[cfg(feature = "jit")]
+    fn bump_pointer(&mut self) -> Option<BumpPointer> {
+        Some(self.nursery.bump_pointer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::config::{CollectorKind, HeapConfig};
+    use crate::heap::*;
+    use crate::object::*;
+
+    fn generational_heap(size_in_bytes: usize) -> Heap {
+        Heap::with_config(HeapConfig {
+            collector: CollectorKind::Generational,
+            ..HeapConfig::fixed(size_in_bytes)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn minor_collection_test() {
+        let heap = generational_heap(1 << 16);
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        {
+            let inner = HandleScope::new(&heap);
+            for _ in 0..10 {
+                inner.str("temporary").unwrap();
+            }
+        }
+        heap.collect_minor().unwrap();
+        // Only the list survived, promoted to the old generation.
+        let promoted = heap.used_bytes();
+        assert_eq!(heap.free_bytes(), heap.size_in_bytes() / 8);
+
+        // An old object pointing into the nursery keeps its target alive.
+        list.as_mut().push(scope.str("young").unwrap().into());
+        {
+            let inner = HandleScope::new(&heap);
+            inner.str("temporary").unwrap();
+        }
+        heap.collect_minor().unwrap();
+        assert_eq!(heap.used_bytes(), promoted * 2);
+        assert_eq!(list.as_ref()[0].as_ref(), "young");
+
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), promoted * 2);
+        assert_eq!(list.as_ref()[0].as_ref(), "young");
+    }
+
+    #[test]
+    fn nursery_overflow_test() {
+        let heap = generational_heap(1 << 16);
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        // Filling the nursery many times over promotes through minor
+        // collections, falling back to full ones as the old space fills.
+        for index in 0..2000 {
+            let inner = HandleScope::new(&heap);
+            let string = inner.str("kept").unwrap();
+            if index % 10 == 0 {
+                list.as_mut().push(string.into());
+            }
+        }
+        assert_eq!(list.as_ref().len(), 200);
+        assert!(list.as_ref().iter().all(|string| string.as_ref() == "kept"));
+    }
+
+    #[test]
+    fn promotion_age_test() {
+        let heap = Heap::with_config(HeapConfig {
+            collector: CollectorKind::Generational,
+            promotion_age: 2,
+            ..HeapConfig::fixed(1 << 16)
+        })
+        .unwrap();
+        let nursery_size = heap.size_in_bytes() / 8;
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        heap.collect_minor().unwrap();
+        // Survived once, so still young.
+        assert_eq!(list.ptr_for_test().header().unwrap().age(), 1);
+        assert!(heap.free_bytes() < nursery_size);
+
+        list.as_mut().push(scope.str("young").unwrap().into());
+        heap.collect_minor().unwrap();
+        // The list was promoted, leaving only the string in the nursery.
+        assert_eq!(list.ptr_for_test().header().unwrap().age(), 2);
+        assert!(heap.free_bytes() < nursery_size);
+        // The promoted list is remembered, keeping the string alive.
+        heap.collect_minor().unwrap();
+        assert_eq!(heap.free_bytes(), nursery_size);
+        assert_eq!(list.as_ref()[0].as_ref(), "young");
+        heap.collect().unwrap();
+        assert_eq!(list.as_ref()[0].as_ref(), "young");
+    }
+
+    #[test]
+    fn store_after_minor_collection_test() {
+        let heap = generational_heap(1 << 16);
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        heap.collect_minor().unwrap();
+        // Fill the nursery so allocating the argument below collects, after
+        // as_mut was taken but before the push.
+        while heap.free_bytes() >= HOST_OBJECT_ALLOC_SIZE {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+        }
+        {
+            let inner = HandleScope::new(&heap);
+            list.as_mut().push(inner.str("young").unwrap().into());
+        }
+        assert!(heap.free_bytes() > HOST_OBJECT_ALLOC_SIZE);
+        heap.collect_minor().unwrap();
+        heap.verify();
+        assert_eq!(list.as_ref()[0].as_ref(), "young");
+
+        // Likewise for stores through an ObjectMut held across collections.
+        let mut payload = list.as_mut();
+        heap.collect().unwrap();
+        heap.collect_minor().unwrap();
+        payload.push(scope.str("later").unwrap().into());
+        heap.collect_minor().unwrap();
+        std::mem::drop(payload);
+        heap.collect_minor().unwrap();
+        heap.verify();
+        assert_eq!(list.as_ref()[1].as_ref(), "later");
+    }
+
+    #[derive(Default)]
+    struct TraceCounter {
+        traces: Rc<Cell<usize>>,
+        children: Vec<HeapHandle<String>>,
+    }
+
+    impl HostObject for TraceCounter {}
+
+    impl Traceable for TraceCounter {
+        fn trace(&mut self, visitor: &mut ObjectVisitor) {
+            self.traces.set(self.traces.get() + 1);
+            for child in self.children.iter() {
+                child.trace(visitor);
+            }
+        }
+    }
+
+    #[test]
+    fn card_table_test() {
+        let heap = generational_heap(1 << 16);
+        let scope = HandleScope::new(&heap);
+        let traces = Rc::new(Cell::new(0));
+        let list = scope.create::<List<TraceCounter>>().unwrap();
+        let counter = TraceCounter {
+            traces: traces.clone(),
+            children: vec![],
+        };
+        list.as_mut().push(scope.take(counter).unwrap().into());
+        heap.collect_minor().unwrap();
+        // Debug builds trace every object again to verify the heap.
+        let verify_traces = cfg!(debug_assertions) as usize;
+        // Promoted, and not written since, so not traced again.
+        traces.set(0);
+        heap.collect_minor().unwrap();
+        assert_eq!(traces.get(), verify_traces);
+
+        // A write through a member handle dirties the counter's card.
+        {
+            let inner = HandleScope::new(&heap);
+            let young = inner.str("young").unwrap();
+            list.as_ref()[0].as_mut().children.push(young.into());
+        }
+        traces.set(0);
+        heap.collect_minor().unwrap();
+        assert_eq!(traces.get(), 1 + verify_traces);
+        assert_eq!(list.as_ref()[0].as_ref().children[0].as_ref(), "young");
+    }
+
+    #[test]
+    fn open_write_in_other_heap_test() {
+        let heap = generational_heap(1 << 16);
+        let scope = HandleScope::new(&heap);
+        let traces = Rc::new(Cell::new(0));
+        let counter = TraceCounter {
+            traces: traces.clone(),
+            children: vec![],
+        };
+        let counter = scope.take(counter).unwrap();
+        heap.collect_minor().unwrap();
+        let verify_traces = cfg!(debug_assertions) as usize;
+
+        // Another heap's ObjectMut doesn't leave this heap's cards dirty.
+        let other = generational_heap(1 << 16);
+        let other_scope = HandleScope::new(&other);
+        let other_list = other_scope.create::<List<String>>().unwrap();
+        let other_payload = other_list.as_mut();
+        traces.set(0);
+        heap.collect_minor().unwrap();
+        heap.collect_minor().unwrap();
+        assert_eq!(traces.get(), 2 * verify_traces);
+        std::mem::drop(other_payload);
+
+        // Its own does.
+        let payload = counter.as_mut();
+        heap.collect_minor().unwrap();
+        traces.set(0);
+        heap.collect_minor().unwrap();
+        assert_eq!(traces.get(), 1 + verify_traces);
+        std::mem::drop(payload);
+    }
+}
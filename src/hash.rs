@@ -0,0 +1,257 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::convert::TryInto;
+use std::hash::{BuildHasher, Hasher};
+
+/// The algorithm used to hash heap values, see HeapConfig::hash_algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HashAlgorithm {
+    // SipHash-1-3, as used by std.  Resists inputs crafted to collide.
+    #[default]
+    SipHash,
+    // The multiply-rotate hash from Firefox and rustc.  Much faster, but
+    // only suitable when keys aren't attacker controlled.
+    FxHash,
+}
+
+/// Builds HeapHashers keyed by a heap's hash seed, see Heap::hash_state.
+/// Hashes of heap values only agree between hashers from the same seed, so
+/// script input can't be crafted to collide without knowing the seed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeededState {
+    algorithm: HashAlgorithm,
+    k0: u64,
+    k1: u64,
+}
+
+impl SeededState {
+    pub fn with_seed(seed: u64) -> SeededState {
+        SeededState {
+            algorithm: HashAlgorithm::SipHash,
+            k0: seed,
+            k1: seed.rotate_left(32) ^ 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn with_algorithm(self, algorithm: HashAlgorithm) -> SeededState {
+        SeededState { algorithm, ..self }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub(crate) fn random() -> SeededState {
+        SeededState::with_seed(RandomState::new().build_hasher().finish())
+    }
+}
+
+thread_local! {
+    // The state of the heap whose HandleScope::create is running, if any.
+    static CREATING_STATE: Cell<Option<SeededState>> = const { Cell::new(None) };
+}
+
+// Runs `create` with SeededState::default() returning `state`.
+pub(crate) fn with_default_state<R>(state: SeededState, create: impl FnOnce() -> R) -> R {
+    let previous = CREATING_STATE.with(|cell| cell.replace(Some(state)));
+    let result = create();
+    CREATING_STATE.with(|cell| cell.set(previous));
+    result
+}
+
+/// Maps made by HandleScope::create hash with their heap's state, maps made
+/// without a heap get a random SipHash seed.
+impl Default for SeededState {
+    fn default() -> Self {
+        CREATING_STATE
+            .with(Cell::get)
+            .unwrap_or_else(SeededState::random)
+    }
+}
+
+impl BuildHasher for SeededState {
+    type Hasher = HeapHasher;
+
+    fn build_hasher(&self) -> HeapHasher {
+        match self.algorithm {
+            HashAlgorithm::SipHash => {
+                HeapHasher(HasherKind::Sip(SipHasher13::new(self.k0, self.k1)))
+            }
+            HashAlgorithm::FxHash => HeapHasher(HasherKind::Fx(FxHasher { hash: self.k0 })),
+        }
+    }
+}
+
+/// Hashes heap values with the algorithm picked by a SeededState.
+#[derive(Debug, Clone)]
+pub struct HeapHasher(HasherKind);
+
+#[derive(Debug, Clone)]
+enum HasherKind {
+    Sip(SipHasher13),
+    Fx(FxHasher),
+}
+
+impl Hasher for HeapHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        match &mut self.0 {
+            HasherKind::Sip(hasher) => hasher.write(bytes),
+            HasherKind::Fx(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        match &mut self.0 {
+            HasherKind::Sip(hasher) => hasher.write_u64(value),
+            HasherKind::Fx(hasher) => hasher.write_u64(value),
+        }
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        match &self.0 {
+            HasherKind::Sip(hasher) => hasher.finish(),
+            HasherKind::Fx(hasher) => hasher.finish(),
+        }
+    }
+}
+
+/// SipHash-1-3, the algorithm behind std's DefaultHasher, with caller
+/// supplied keys.
+#[derive(Debug, Clone)]
+struct SipHasher13 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    // Bytes written since the last full word, little endian.
+    tail: u64,
+    tail_len: usize,
+    length: usize,
+}
+
+impl SipHasher13 {
+    fn new(k0: u64, k1: u64) -> SipHasher13 {
+        SipHasher13 {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            tail_len: 0,
+            length: 0,
+        }
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.round();
+        self.v0 ^= word;
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.tail |= (*byte as u64) << (8 * self.tail_len);
+            self.tail_len += 1;
+            if self.tail_len == 8 {
+                self.compress(self.tail);
+                self.tail = 0;
+                self.tail_len = 0;
+            }
+        }
+        self.length += bytes.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        state.compress(((self.length as u64 & 0xff) << 56) | self.tail);
+        state.v2 ^= 0xff;
+        state.round();
+        state.round();
+        state.round();
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+const FX_SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// FxHash, which mixes in a word at a time with a rotate and multiply.
+#[derive(Debug, Clone)]
+struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    fn add_word(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(FX_SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.add_word(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        for byte in chunks.remainder() {
+            self.add_word(*byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.add_word(value);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hash;
+
+    #[test]
+    fn matches_default_hasher_test() {
+        // DefaultHasher::new() is SipHash-1-3 with zero keys.
+        for text in ["", "a", "seven b", "eight by", "a longer string of text"] {
+            let mut expected = DefaultHasher::new();
+            text.hash(&mut expected);
+            let mut actual = SipHasher13::new(0, 0);
+            text.hash(&mut actual);
+            assert_eq!(actual.finish(), expected.finish());
+        }
+    }
+
+    #[test]
+    fn seeded_test() {
+        let a = SeededState::with_seed(1);
+        let b = SeededState::with_seed(2);
+        assert_eq!(a.hash_one("key"), a.hash_one("key"));
+        assert_ne!(a.hash_one("key"), b.hash_one("key"));
+
+        let fx = a.with_algorithm(HashAlgorithm::FxHash);
+        assert_eq!(fx.hash_one("key"), fx.hash_one("key"));
+        assert_ne!(fx.hash_one("key"), fx.hash_one("kez"));
+    }
+}
@@ -1,63 +1,807 @@
-use std::cell::RefCell;
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
 use std::marker::PhantomData;
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::byte_array::{self, ByteArray, TrailingBytes};
+use crate::collector::*;
+use crate::config::*;
+use crate::control_blocks::*;
+use crate::convert::*;
+use crate::display::*;
+use crate::dot;
+use crate::dump::{DumpedObject, HeapDump};
+use crate::events::*;
+use crate::gc_string::GcString;
+use crate::generational::*;
+use crate::hash::*;
+use crate::immix::*;
+use crate::intern::*;
+use crate::mark_sweep::*;
 use crate::object::*;
+use crate::ordered_map::OrderedMap;
 use crate::pointer::*;
-use crate::space::*;
+use crate::profile::{AllocationProfile, AllocationProfiler};
+use crate::rope::Rope;
+use crate::shared_str::*;
+use crate::snapshot::{self, SnapshotNode};
+use crate::space::WriteBarrier;
+use crate::type_registry;
+use crate::typed_array::*;
 use crate::types::*;
+use crate::weak_set::{WeakEntries, WeakSet};
+
+type PostGcTask = Box<dyn FnOnce(&Heap)>;
+type MoveCallback = Rc<RefCell<dyn FnMut(*mut u8, *mut u8)>>;
+type WatermarkCallback = Rc<RefCell<dyn FnMut(&Heap, usize)>>;
+type GcCallback = Box<dyn FnMut(&Heap)>;
+
+struct Watermark {
+    fraction: f64,
+    // Cleared when the watermark fires, set again once usage drops back
+    // below it, so it fires once per crossing.
+    armed: bool,
+    callback: WatermarkCallback,
+}
+
+/// When a callback added with Heap::add_gc_callback runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcPhase {
+    // Before tracing, once the collector is ready to collect.
+    Begin,
+    // Once the collection has finished, after finalizers and post-GC tasks.
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Collection {
+    Full,
+    // Only young objects, see Heap::collect_minor.
+    Minor,
+    // Full, then sliding the survivors together, see Heap::compact.
+    Compacting,
+}
+
+// A collection started by Heap::collect_step which hasn't finished.
+#[derive(Default)]
+struct IncrementalCollection {
+    queue: VecDeque<ObjectPtr>,
+    // The objects traced so far, which the mutator may have given new
+    // references since, so finishing traces them again.  None while the
+    // collector's WriteBarrier logs the objects written to instead.
+    traced: Option<Vec<ObjectPtr>>,
+    panicked: bool,
+}
+
+struct PatchSite {
+    object: HeapHandle<()>,
+    on_move: MoveCallback,
+}
+
+struct WeakTarget {
+    // None once the object has been collected.
+    object: Option<HeapHandle<()>>,
+    on_collect: Option<PostGcTask>,
+}
+
+struct EphemeronTarget {
+    // The key and value, None once the key has been collected.
+    entry: Option<(HeapHandle<()>, HeapHandle<()>)>,
+}
+
+/// Counts of the handles rooting (or watching) objects, see
+/// Heap::handle_stats.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HandleStats {
+    // Local handles in each live HandleScope, outermost first.
+    pub local_handles: Vec<usize>,
+    // Live GlobalHandles and PersistentHandles.
+    pub global_handles: usize,
+    // Live WeakHandles, including those whose object has been collected.
+    pub weak_handles: usize,
+}
+
+/// The objects of one type in the heap, see Heap::usage_by_type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeUsage {
+    pub type_name: &'static str,
+    pub count: usize,
+    // The objects in the heap, headers included.
+    pub bytes: usize,
+    // Their payloads outside the heap.
+    pub payload_bytes: usize,
+}
+
+/// What a collection did, returned by Heap::collect and the other ways of
+/// collecting.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CollectionStats {
+    pub used_bytes_before: usize,
+    pub used_bytes_after: usize,
+    // Survivors copied to a new address, none for collectors which don't
+    // move objects.
+    pub objects_copied: usize,
+    // Dead objects, queued rather than finalized if finalization is
+    // deferred, see HeapConfig::deferred_finalization.
+    pub objects_finalized: usize,
+    // WeakHandles whose object was collected.
+    pub weak_handles_cleared: usize,
+    // Including running finalizers and post-GC tasks.  For an incremental
+    // collection, only the last step.
+    pub duration: Duration,
+}
+
+impl CollectionStats {
+    // The stats of this collection followed by `later`, e.g. when collect
+    // collects again to shrink the heap.
+    fn then(self, later: CollectionStats) -> CollectionStats {
+        CollectionStats {
+            used_bytes_before: self.used_bytes_before,
+            used_bytes_after: later.used_bytes_after,
+            objects_copied: self.objects_copied + later.objects_copied,
+            objects_finalized: self.objects_finalized + later.objects_finalized,
+            weak_handles_cleared: self.weak_handles_cleared + later.weak_handles_cleared,
+            duration: self.duration + later.duration,
+        }
+    }
+}
+
+/// Identifies a callback added with Heap::register_patch_site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchSiteId(usize);
 
 struct HeapInner {
     // TODO: Add more generations.
-    space: Space,
-    scopes: Vec<Vec<HeapHandle<()>>>,
+    collector: Box<dyn CollectorStrategy>,
+    // Indexed by HandleScope::index, None once a scope dropped out of order
+    // until those above it are gone too, see remove_scope.
+    scopes: Vec<Option<Vec<HeapHandle<()>>>>,
+    // Indices in scopes of the live SealedHandleScopes, innermost last.
+    sealed: Vec<usize>,
     globals: Vec<Option<HeapHandle<()>>>,
+    // Indices of the None entries in globals, reused before growing it.
+    free_globals: Vec<usize>,
+    // Targets of WeakHandles, not traced.  None once dropped.
+    weak_handles: Vec<Option<WeakTarget>>,
+    // Indices of the None entries in weak_handles, reused before growing it.
+    free_weak_handles: Vec<usize>,
+    // Entries of Ephemerons, not traced as roots, see trace_ephemerons.
+    // None once dropped.
+    ephemerons: Vec<Option<EphemeronTarget>>,
+    // Live WeakSets, not traced, and their payloads to prune after each
+    // collection.  Payloads in ControlBlocks don't move.
+    weak_sets: Vec<(HeapHandle<()>, *mut dyn WeakEntries)>,
+    named_roots: BTreeMap<String, HeapHandle<()>>,
+    // Objects being moved into the heap by Heap::emplace, whose handles are
+    // roots until they're there in case allocating their space collects.
+    unplaced: Vec<*mut dyn Traceable>,
+    interned: InternTable,
+    symbols: InternTable<Symbol>,
+    payloads: PayloadCache,
+    control_blocks: ControlBlocks,
+    hash_state: SeededState,
+    // Numbers objects in allocation order for their identity hashes.
+    next_identity_hash: u32,
     weaks: Vec<HeapHandle<()>>,
+    // Dead objects waiting for Heap::run_finalizers.
+    finalization_queue: Vec<TraceableObject>,
+    post_gc_tasks: Vec<PostGcTask>,
+    oom_policy: OomPolicy,
+    watermarks: Vec<Watermark>,
+    memory_pressure: MemoryPressure,
+    // Set during a collection forced by HeapConfig::gc_stress.
+    stressing: bool,
+    gc_callbacks: Vec<(GcPhase, GcCallback)>,
+    patch_sites: Vec<Option<PatchSite>>,
+    incremental: Option<IncrementalCollection>,
+    events: EventSinks,
+    profiler: Option<AllocationProfiler>,
+    config: HeapConfig,
 }
 
+// Every host object is a header followed by a pointer to its payload.
+pub(crate) const HOST_OBJECT_ALLOC_SIZE: usize =
+    alloc_size_for(std::mem::size_of::<TraceableObject>());
+
+// How much collect_with_deadline traces between looks at the clock.
+const DEADLINE_STEP_BYTES: usize = 64 * HOST_OBJECT_ALLOC_SIZE;
+
 impl HeapInner {
-    fn new(space: Space) -> HeapInner {
+    // Allocation here never collects, see Heap::reserve.
+    fn alloc_host_object(&mut self, object_type: ObjectType) -> Result<ObjectPtr, GCError> {
+        let object_size = std::mem::size_of::<TraceableObject>();
+        let header = ObjectHeader::new(self.collector.as_mut(), object_size, object_type)?;
+        header.set_identity_hash(self.take_identity_hash());
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(object_type, header.alloc_size());
+        }
+        Ok(header.as_ptr().to_object_ptr())
+    }
+
+    fn init_host_object<T: HostObject>(&mut self, object_ptr: ObjectPtr, object: T) {
+        let payload: *mut dyn Traceable = self.control_blocks.alloc(object);
+        TraceableObject::new(payload).store(object_ptr);
+        self.weaks.push(HeapHandle::new(object_ptr.into()));
+    }
+
+    // `layout` must be byte_array::layout_for(len).
+    fn init_bytes_object<T: TrailingBytes>(
+        &mut self,
+        object_ptr: ObjectPtr,
+        len: usize,
+        layout: Layout,
+    ) {
+        let block = self.control_blocks.alloc_layout(layout);
+        let payload: *mut dyn Traceable = byte_array::init::<T>(block, len);
+        TraceableObject::new(payload).store(object_ptr);
+        self.weaks.push(HeapHandle::new(object_ptr.into()));
+    }
+
+    fn take_identity_hash(&mut self) -> u32 {
+        let identity_hash = self.next_identity_hash;
+        self.next_identity_hash = identity_hash.wrapping_add(1);
+        identity_hash
+    }
+
+    fn alloc_string(&mut self, value: &str) -> Result<HeapHandle<String>, GCError> {
+        let object_ptr = self.alloc_host_object(ObjectType::of::<String>())?;
+        self.init_host_object(object_ptr, value.to_string());
+        Ok(HeapHandle::new(object_ptr.into()))
+    }
+
+    fn new(collector: Box<dyn CollectorStrategy>, config: HeapConfig) -> HeapInner {
+        let hash_seed = match config.hash_seed {
+            None if config.deterministic => Some(0),
+            hash_seed => hash_seed,
+        };
+        let hash_state = hash_seed
+            .map_or_else(SeededState::random, SeededState::with_seed)
+            .with_algorithm(config.hash_algorithm);
         HeapInner {
-            space,
+            collector,
+            config,
             globals: vec![],
+            free_globals: vec![],
+            weak_handles: vec![],
+            free_weak_handles: vec![],
+            ephemerons: vec![],
+            weak_sets: vec![],
+            named_roots: BTreeMap::new(),
+            unplaced: vec![],
+            interned: InternTable::default(),
+            symbols: InternTable::default(),
+            payloads: PayloadCache::default(),
+            control_blocks: ControlBlocks::default(),
+            hash_state,
+            next_identity_hash: 0,
             scopes: vec![],
+            sealed: vec![],
             weaks: vec![],
+            finalization_queue: vec![],
+            post_gc_tasks: vec![],
+            oom_policy: OomPolicy::default(),
+            watermarks: vec![],
+            memory_pressure: MemoryPressure::None,
+            stressing: false,
+            gc_callbacks: vec![],
+            patch_sites: vec![],
+            incremental: None,
+            events: EventSinks::default(),
+            profiler: None,
+        }
+    }
+
+    // Returns whether any object's trace panicked.  Finishes any
+    // incremental collection in progress.
+    fn trace(&mut self, minor: bool) -> bool {
+        let old_objects = if minor {
+            self.mark_old_objects()
+        } else {
+            vec![]
+        };
+        let incremental = self.incremental.take().unwrap_or_default();
+        let written = self.written_objects();
+        let queue = self.visit_roots(incremental.queue);
+        let mut visitor = ObjectVisitor::with_queue(self.collector.as_mut(), queue);
+        let traced = incremental.traced.unwrap_or_default();
+        for object_ptr in old_objects.into_iter().chain(traced).chain(written) {
+            visitor.enqueue(object_ptr);
+        }
+        let panicked = visitor.process_queue() || incremental.panicked;
+        trace_ephemerons(&self.ephemerons, &mut visitor) || panicked
+    }
+
+    // Stops the collector's WriteBarrier logging, returning the objects
+    // written to during an incremental collection which it has already
+    // visited, at their new addresses.  Those not visited yet are traced
+    // as they're reached, if they are.
+    fn written_objects(&self) -> Vec<ObjectPtr> {
+        let written = self
+            .collector
+            .write_barrier()
+            .and_then(WriteBarrier::take_written)
+            .unwrap_or_default();
+        let mut objects = vec![];
+        for addr in written {
+            let object_ptr = HeaderPtr::new(addr).to_object_ptr();
+            if let Some(new_header_ptr) = object_ptr.header().new_header_ptr {
+                objects.push(new_header_ptr.to_object_ptr());
+            }
+        }
+        // An object may have been written through both of its copies.
+        objects.sort_by_key(|object_ptr| object_ptr.addr());
+        objects.dedup_by_key(|object_ptr| object_ptr.addr());
+        objects
+    }
+
+    // Scopes can drop out of order, e.g. one held by a generator outliving
+    // the scope it was made in.  Handles find their scope by index, so an
+    // inner scope's entry is only removed once every scope above it has
+    // gone, until then it's None and roots nothing.
+    fn remove_scope(&mut self, index: usize) -> Vec<HeapHandle<()>> {
+        let cells = self.scopes[index].take().unwrap();
+        while let Some(None) = self.scopes.last() {
+            self.scopes.pop();
+        }
+        cells
+    }
+
+    // Visits the roots, adding them to `queue` to be traced.
+    fn visit_roots(&mut self, queue: VecDeque<ObjectPtr>) -> VecDeque<ObjectPtr> {
+        let mut visitor = ObjectVisitor::with_queue(self.collector.as_mut(), queue);
+        trace_roots(&self.globals, &self.named_roots, &self.scopes, &mut visitor);
+        for object in self.unplaced.iter() {
+            unsafe { (**object).trace(&mut visitor) };
+        }
+        visitor.into_queue()
+    }
+
+    // Every object, the indices of the objects each refers to, and the
+    // indices of those the roots refer to.  References to anything else,
+    // which only a corrupt heap holds, are left out.  Must not run during a
+    // collection.
+    fn object_graph(&mut self) -> (Vec<ObjectPtr>, Vec<Vec<usize>>, Vec<usize>) {
+        let object_ptrs: Vec<ObjectPtr> = self
+            .weaks
+            .iter()
+            .map(|handle| handle.ptr().try_into().unwrap())
+            .collect();
+        let index_of: HashMap<usize, usize> = object_ptrs
+            .iter()
+            .enumerate()
+            .map(|(index, object_ptr)| (object_ptr.addr() as usize, index))
+            .collect();
+        let indices = |object_ptrs: Vec<ObjectPtr>| -> Vec<usize> {
+            object_ptrs
+                .iter()
+                .filter_map(|object_ptr| index_of.get(&(object_ptr.addr() as usize)).copied())
+                .collect()
+        };
+        let (globals, named_roots, scopes) = (&self.globals, &self.named_roots, &self.scopes);
+        let mut visitor = ObjectVisitor::with_queue(self.collector.as_mut(), VecDeque::new());
+        let roots = indices(visitor.record(|visitor| {
+            trace_roots(globals, named_roots, scopes, visitor);
+        }));
+        let edges = object_ptrs
+            .iter()
+            .map(|object_ptr| indices(visitor.record_object(*object_ptr)))
+            .collect();
+        (object_ptrs, edges, roots)
+    }
+
+    // Every object and what it refers to, with the indices of those the
+    // roots refer to.  Must not run during a collection.
+    fn snapshot_nodes(&mut self) -> (Vec<usize>, Vec<SnapshotNode>) {
+        let (object_ptrs, edges, roots) = self.object_graph();
+        let mut nodes = Vec::with_capacity(object_ptrs.len());
+        for (object_ptr, edges) in object_ptrs.iter().zip(edges) {
+            let header = object_ptr.header();
+            let id = (header.identity_hash() as u64 + 1) * 2 + 1;
+            let self_size = header.alloc_size() + payload_size(*object_ptr);
+            let mut node = match object_str(*object_ptr) {
+                Some(text) => SnapshotNode::string(text, id, self_size),
+                None => SnapshotNode {
+                    name: header.object_type.name().to_string(),
+                    is_string: false,
+                    id,
+                    self_size,
+                    edges: vec![],
+                },
+            };
+            node.edges = edges;
+            nodes.push(node);
+        }
+        (roots, nodes)
+    }
+
+    fn usage_by_type(&self) -> Vec<TypeUsage> {
+        let mut by_type: HashMap<ObjectType, TypeUsage> = HashMap::new();
+        for handle in self.weaks.iter() {
+            let object_ptr: ObjectPtr = handle.ptr().try_into().unwrap();
+            let object_type = object_ptr.header().object_type;
+            let usage = by_type.entry(object_type).or_insert_with(|| TypeUsage {
+                type_name: object_type.name(),
+                count: 0,
+                bytes: 0,
+                payload_bytes: 0,
+            });
+            usage.count += 1;
+            usage.bytes += object_ptr.header().alloc_size();
+            usage.payload_bytes += payload_size(object_ptr);
+        }
+        let mut usage: Vec<TypeUsage> = by_type.into_values().collect();
+        usage.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.type_name.cmp(b.type_name)));
+        usage
+    }
+
+    // Must not run during a collection, see Heap::dump.
+    fn heap_dump(&mut self) -> HeapDump {
+        let (object_ptrs, edges, roots) = self.object_graph();
+        let mut dump = HeapDump::new(roots);
+        for (object_ptr, references) in object_ptrs.iter().zip(edges) {
+            let header = object_ptr.header();
+            let type_index = dump.type_index(header.object_type.name());
+            dump.push(DumpedObject {
+                type_index,
+                address: object_ptr.addr() as u64,
+                heap_size: header.alloc_size(),
+                payload_size: payload_size(*object_ptr),
+                identity_hash: header.identity_hash(),
+                user_tag: header.user_tag(),
+                flags: header.flags(),
+                references,
+            });
+        }
+        dump
+    }
+
+    // Panics if the heap is inconsistent, see Heap::verify.
+    fn verify(&mut self) {
+        if self.incremental.is_some() {
+            return;
+        }
+        let mut objects = HashSet::new();
+        for handle in self.weaks.iter() {
+            let object_ptr: ObjectPtr = match handle.ptr().try_into() {
+                Ok(object_ptr) => object_ptr,
+                Err(_) => verify_failed(format!("{:?} is listed as an object", handle.ptr())),
+            };
+            if !objects.insert(object_ptr.addr() as usize) {
+                verify_failed(format!("{:?} is listed twice", object_ptr));
+            }
+            let header = object_ptr.header();
+            if !type_registry::is_registered(header.object_type) {
+                verify_failed(format!(
+                    "{:?} has unknown type id {}",
+                    object_ptr,
+                    header.object_type.id()
+                ));
+            }
+            if header.new_header_ptr.is_some() {
+                verify_failed(format!(
+                    "{} at {:?} is still forwarded",
+                    header.object_type.name(),
+                    object_ptr
+                ));
+            }
+        }
+        let check =
+            |visitor: &ObjectVisitor, referrer: &dyn Fn() -> String, found: Vec<ObjectPtr>| {
+                if visitor.malformed() > 0 {
+                    verify_failed(format!("{} holds a malformed value", referrer()));
+                }
+                for object_ptr in found {
+                    if !objects.contains(&(object_ptr.addr() as usize)) {
+                        verify_failed(format!(
+                            "{} refers to {:?}, outside the heap",
+                            referrer(),
+                            object_ptr
+                        ));
+                    }
+                }
+            };
+        let (globals, named_roots, scopes) = (&self.globals, &self.named_roots, &self.scopes);
+        let mut visitor = ObjectVisitor::with_queue(self.collector.as_mut(), VecDeque::new());
+        let found = visitor.record(|visitor| trace_roots(globals, named_roots, scopes, visitor));
+        check(&visitor, &|| "a root".to_string(), found);
+        let weak_targets = self.weak_handles.iter().flatten();
+        let found = visitor.record(|visitor| {
+            for object in weak_targets.filter_map(|target| target.object.as_ref()) {
+                object.trace(visitor);
+            }
+        });
+        check(&visitor, &|| "a WeakHandle".to_string(), found);
+        for handle in self.weaks.iter() {
+            let object_ptr: ObjectPtr = handle.ptr().try_into().unwrap();
+            let found = visitor.record_object(object_ptr);
+            let referrer = || {
+                let name = object_ptr.header().object_type.name();
+                format!("{} at {:?}", name, object_ptr)
+            };
+            check(&visitor, &referrer, found);
+        }
+    }
+
+    // Traces about `budget_bytes` of objects, starting an
+    // incremental collection if none is in progress.  Returns whether
+    // everything reachable has been traced.
+    fn trace_step(&mut self, budget_bytes: usize) -> Result<bool, GCError> {
+        let mut incremental = match self.incremental.take() {
+            Some(incremental) => incremental,
+            None => {
+                let size_in_bytes = self.collector.size_in_bytes();
+                self.collector.prepare_collection(size_in_bytes)?;
+                let logging = self
+                    .collector
+                    .write_barrier()
+                    .is_some_and(WriteBarrier::start_logging);
+                IncrementalCollection {
+                    queue: self.visit_roots(VecDeque::new()),
+                    traced: (!logging).then(Vec::new),
+                    ..IncrementalCollection::default()
+                }
+            }
+        };
+        let mut visitor = ObjectVisitor::with_queue(self.collector.as_mut(), incremental.queue);
+        incremental.panicked |=
+            visitor.process_queue_for(budget_bytes, incremental.traced.as_mut());
+        incremental.queue = visitor.into_queue();
+        let done = incremental.queue.is_empty();
+        self.incremental = Some(incremental);
+        Ok(done)
+    }
+
+    // A minor collection keeps every old object, marking them as survivors
+    // up front so they aren't moved.  Those which may point into the young
+    // generation (see CollectorStrategy::is_remembered) are returned to be
+    // traced as roots.
+    fn mark_old_objects(&mut self) -> Vec<ObjectPtr> {
+        let mut old_objects = vec![];
+        for handle in self.weaks.iter() {
+            let object_ptr: ObjectPtr = match handle.ptr().try_into() {
+                Ok(object_ptr) => object_ptr,
+                Err(_) => continue,
+            };
+            let header = object_ptr.header();
+            if !self.collector.is_young(header) {
+                header.new_header_ptr = Some(header.as_ptr());
+                if self.collector.is_remembered(header) {
+                    old_objects.push(object_ptr);
+                }
+            }
         }
+        old_objects
     }
 
-    fn trace(&mut self, visitor: &mut ObjectVisitor) {
-        visitor.trace_maybe_handles(&mut self.globals);
-        for scope in self.scopes.iter_mut() {
-            // FIXME:  Scope should be an object, not a vec here.
-            visitor.trace_handles(scope);
+    // Must run before update_weak clears the forwarding pointers.  Returns
+    // the callbacks to run with each moved object's old and new address.
+    fn update_patch_sites(&mut self) -> Vec<(MoveCallback, *mut u8, *mut u8)> {
+        let mut moves = vec![];
+        for slot in self.patch_sites.iter_mut() {
+            let site = match slot {
+                Some(site) => site,
+                None => continue,
+            };
+            let object_ptr: ObjectPtr = match site.object.ptr().try_into() {
+                Ok(object_ptr) => object_ptr,
+                Err(_) => continue,
+            };
+            match object_ptr.header().new_header_ptr {
+                Some(new_header_ptr) => {
+                    let new_object_ptr = new_header_ptr.to_object_ptr();
+                    if new_object_ptr.addr() != object_ptr.addr() {
+                        site.object = HeapHandle::new(new_object_ptr.into());
+                        moves.push((
+                            site.on_move.clone(),
+                            object_ptr.addr(),
+                            new_object_ptr.addr(),
+                        ));
+                    }
+                }
+                // Patch sites don't keep their object alive.
+                None => *slot = None,
+            }
         }
+        moves
+    }
 
-        while let Some(object_ptr) = visitor.queue.pop_front() {
-            let object = TraceableObject::load(object_ptr);
-            let traceable = object.as_traceable();
-            traceable.trace(visitor);
+    // Slides the survivors of the collection just finished to the addresses
+    // the collector plans, adding the moves for patch sites to `moves`.
+    // Returns whether any object's trace panicked.
+    fn compact(&mut self, moves: &mut Vec<(MoveCallback, *mut u8, *mut u8)>) -> bool {
+        let addr_of = |handle: &HeapHandle<()>| handle.ptr().header().unwrap().as_ptr().addr();
+        self.weaks
+            .sort_unstable_by_key(|handle| addr_of(handle) as usize);
+        let new_headers = match self.collector.plan_compaction(&self.weaks) {
+            Some(new_headers) => new_headers,
+            None => return false,
+        };
+        for (handle, new_header_ptr) in self.weaks.iter().zip(new_headers) {
+            handle.ptr().header().unwrap().new_header_ptr = Some(new_header_ptr);
+        }
+        // With every survivor forwarded, tracing updates each handle to the
+        // new address without visit copying anything.
+        let queue = self.visit_roots(VecDeque::new());
+        let mut visitor = ObjectVisitor::with_queue(self.collector.as_mut(), queue);
+        for handle in self.weaks.iter() {
+            visitor.enqueue(handle.ptr().try_into().unwrap());
+        }
+        let panicked = visitor.process_queue();
+        moves.append(&mut self.update_patch_sites());
+        self.interned.update_weak();
+        self.symbols.update_weak();
+        self.update_weak_handles();
+        self.update_ephemerons();
+        self.update_weak_sets();
+        // Objects only move towards lower addresses, so moving them in
+        // address order never overwrites one which hasn't moved yet.
+        for handle in self.weaks.iter_mut() {
+            let ptr = handle.ptr();
+            let header = ptr.header().unwrap();
+            let new_header_ptr = header.new_header_ptr.take().unwrap();
+            if new_header_ptr.addr() != header.as_ptr().addr() {
+                unsafe {
+                    std::ptr::copy(
+                        header.as_ptr().addr(),
+                        new_header_ptr.addr(),
+                        header.alloc_size(),
+                    );
+                }
+            }
+            *handle = HeapHandle::new(new_header_ptr.to_object_ptr().into());
         }
+        self.collector.finish_collection(&self.weaks);
+        panicked
     }
 
-    fn update_weak(&mut self) -> Vec<Box<dyn Traceable>> {
+    fn update_weak(&mut self, stats: &mut CollectionStats) -> Vec<TraceableObject> {
+        stats.weak_handles_cleared = self.update_weak_handles();
+        self.update_ephemerons();
+        self.update_weak_sets();
         let mut doomed = vec![];
         let mut survivors = vec![];
-        for handle in self.weaks.iter() {
+        for handle in std::mem::take(&mut self.weaks) {
             let maybe_object_ptr: Option<ObjectPtr> = handle.ptr().try_into().ok();
             if let Some(object_ptr) = maybe_object_ptr {
                 let old_header = object_ptr.header();
                 if let Some(new_header_ptr) = old_header.new_header_ptr {
-                    survivors.push(HeapHandle::new(new_header_ptr.to_object_ptr().into()));
+                    if new_header_ptr.addr() != old_header.as_ptr().addr() {
+                        stats.objects_copied += 1;
+                    }
+                    let new_object_ptr = new_header_ptr.to_object_ptr();
+                    // Clears the mark left on objects which didn't move.
+                    new_object_ptr.header().new_header_ptr = None;
+                    survivors.push(HeapHandle::new(new_object_ptr.into()));
                 } else {
-                    let object = TraceableObject::load(object_ptr);
-                    doomed.push(object.into_box());
+                    doomed.push(TraceableObject::load(object_ptr));
                 }
             }
         }
-        std::mem::swap(&mut self.weaks, &mut survivors);
+        self.weaks = survivors;
         doomed
     }
+
+    // Must run after tracing and before forwarding pointers are cleared.
+    // The callbacks of cleared WeakHandles run with the post-GC tasks.
+    // Returns how many were cleared.
+    fn update_weak_handles(&mut self) -> usize {
+        let mut cleared = 0;
+        for target in self.weak_handles.iter_mut().flatten() {
+            let object_ptr: ObjectPtr =
+                match target.object.as_ref().map(|handle| handle.ptr().try_into()) {
+                    Some(Ok(object_ptr)) => object_ptr,
+                    // Values other than objects are never collected.
+                    _ => continue,
+                };
+            target.object = object_ptr
+                .header()
+                .new_header_ptr
+                .map(|new_header_ptr| HeapHandle::new(new_header_ptr.to_object_ptr().into()));
+            if target.object.is_none() {
+                cleared += 1;
+                if let Some(on_collect) = target.on_collect.take() {
+                    self.post_gc_tasks.push(on_collect);
+                }
+            }
+        }
+        cleared
+    }
+
+    // Must run after tracing and before forwarding pointers are cleared.
+    // Clears the ephemerons whose keys were collected.
+    fn update_ephemerons(&mut self) {
+        for target in self.ephemerons.iter_mut().flatten() {
+            target.entry = target.entry.take().and_then(|(key, value)| {
+                Some((forwarded_handle(&key)?, forwarded_handle(&value)?))
+            });
+        }
+    }
+
+    // Must run after tracing and before forwarding pointers are cleared.
+    // Sets which were collected are forgotten, their payloads are finalized
+    // with the other doomed objects.
+    fn update_weak_sets(&mut self) {
+        self.weak_sets
+            .retain_mut(|(set, entries)| match forwarded_handle(set) {
+                Some(forwarded) => {
+                    *set = forwarded;
+                    unsafe { (**entries).prune() };
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+fn trace_roots(
+    globals: &Vec<Option<HeapHandle<()>>>,
+    named_roots: &BTreeMap<String, HeapHandle<()>>,
+    scopes: &[Option<Vec<HeapHandle<()>>>],
+    visitor: &mut ObjectVisitor,
+) {
+    visitor.trace_maybe_handles(globals);
+    for handle in named_roots.values() {
+        handle.trace(visitor);
+    }
+    for scope in scopes.iter().flatten() {
+        // FIXME:  Scope should be an object, not a vec here.
+        visitor.trace_handles(scope);
+    }
+}
+
+// The bytes of an object's payload outside the heap.
+fn payload_size(object_ptr: ObjectPtr) -> usize {
+    TraceableObject::load(object_ptr).block().1.size()
+}
+
+fn verify_failed(message: String) -> ! {
+    panic!("vmgc: heap verification failed: {}", message);
+}
+
+// Traces the values of ephemerons whose keys have been found live, again
+// and again, as each value traced may be what keeps another ephemeron's key
+// alive.  Values whose keys are never found live aren't traced, so a value
+// referring to its own key doesn't keep either alive.  Returns whether any
+// object's trace panicked.
+fn trace_ephemerons(ephemerons: &[Option<EphemeronTarget>], visitor: &mut ObjectVisitor) -> bool {
+    let mut panicked = false;
+    let mut pending: Vec<_> = ephemerons
+        .iter()
+        .flatten()
+        .filter_map(|target| target.entry.as_ref())
+        .collect();
+    loop {
+        let (live, dead): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|(key, _)| forwarded_handle(key).is_some());
+        if live.is_empty() {
+            return panicked;
+        }
+        for (_, value) in live {
+            value.trace(visitor);
+        }
+        panicked |= visitor.process_queue();
+        pending = dead;
+    }
+}
+
+// Where `handle`'s object is being moved to, or None if it hasn't been
+// visited by the collection in progress.  Values other than objects are
+// never collected.
+pub(crate) fn forwarded_handle<T>(handle: &HeapHandle<T>) -> Option<HeapHandle<T>> {
+    match handle.ptr().header() {
+        Some(header) => header
+            .new_header_ptr
+            .map(|new_header_ptr| HeapHandle::new(new_header_ptr.to_object_ptr().into())),
+        None => Some(handle.clone()),
+    }
 }
 
 impl std::fmt::Debug for HeapInner {
@@ -68,404 +812,930 @@ impl std::fmt::Debug for HeapInner {
 
 #[derive(Debug)]
 pub struct Heap {
-    max_size_in_bytes: usize,
     inner: Arc<RefCell<HeapInner>>,
 }
 
 impl Heap {
+    /// Creates a fixed-size heap of `size_in_bytes`, half of which is usable
+    /// for allocation at any time.  Use Heap::with_config for a heap which
+    /// grows as needed, uses a different collector, or allocates to-space
+    /// only while collecting (HeapConfig::to_space_on_demand).
     pub fn new(size_in_bytes: usize) -> Result<Heap, GCError> {
-        let half_size = size_in_bytes / 2;
+        Heap::with_config(HeapConfig::fixed(size_in_bytes))
+    }
+
+    pub fn with_config(config: HeapConfig) -> Result<Heap, GCError> {
+        config.validate()?;
+        let size = config.initial_size_in_bytes;
+        let collector: Box<dyn CollectorStrategy> = match config.collector {
+            CollectorKind::SemiSpace if config.to_space_on_demand => {
+                Box::new(SemiSpaceCollector::with_to_space_on_demand(size)?)
+            }
+            CollectorKind::SemiSpace => Box::new(SemiSpaceCollector::new(size)?),
+            CollectorKind::Immix => Box::new(ImmixCollector::new(size)),
+            CollectorKind::Generational => Box::new(GenerationalCollector::with_promotion_age(
+                size,
+                config.promotion_age,
+            )?),
+            CollectorKind::MarkSweep => Box::new(MarkSweepCollector::new(size)?),
+        };
+        Heap::with_strategy(config, collector)
+    }
+
+    /// Creates a heap which places objects with a custom collector strategy.
+    /// `config.collector` is ignored, the strategy should already be sized to
+    /// `config.initial_size_in_bytes`.
+    pub fn with_strategy(
+        config: HeapConfig,
+        collector: Box<dyn CollectorStrategy>,
+    ) -> Result<Heap, GCError> {
+        config.validate()?;
         Ok(Heap {
-            max_size_in_bytes: size_in_bytes,
-            inner: Arc::new(RefCell::new(HeapInner::new(Space::new(half_size)?))),
+            inner: Arc::new(RefCell::new(HeapInner::new(collector, config))),
         })
     }
 
+    /// The current total size of the heap, including any reserve the
+    /// collector needs (e.g. the second semispace).
+    pub fn size_in_bytes(&self) -> usize {
+        self.inner.borrow().collector.size_in_bytes()
+    }
+
+    /// Resizes the heap to `size_in_bytes` by collecting into a new space.
+    /// The size must be within the HeapConfig bounds and large enough to
+    /// hold the live objects, otherwise the heap is left unchanged.
+    pub fn resize(&self, size_in_bytes: usize) -> Result<(), GCError> {
+        if !self.inner.borrow().config.allows_size(size_in_bytes) {
+            return Err(GCError::InvalidConfig);
+        }
+        let capacity = self.inner.borrow().collector.capacity_for(size_in_bytes);
+        if self.used_bytes() > capacity {
+            self.collect()?;
+            if self.used_bytes() > capacity {
+                return Err(GCError::NoSpace);
+            }
+        }
+        self.collect_into(size_in_bytes)?;
+        Ok(())
+    }
+
+    /// The number of bytes generated code should bump allocate for a host
+    /// object before handing it to HandleScope::adopt_raw.
+    #[cfg(feature = "jit")]
+    pub const HOST_OBJECT_ALLOC_SIZE: usize = HOST_OBJECT_ALLOC_SIZE;
+
+    /// The allocation top and limit words for inline allocation, or None if
+    /// the collector strategy doesn't bump allocate.
+    ///
+    /// # Safety
+    ///
+    /// Generated code must only advance the top word within the limit, and
+    /// must not touch either word while Rust code is using the heap.
+    /// Allocated memory is garbage until passed to HandleScope::adopt_raw,
+    /// which must happen before the next allocation or collection.
+    #[cfg(feature = "jit")]
+    pub unsafe fn bump_pointer(&self) -> Option<BumpPointer> {
+        self.inner.borrow_mut().collector.bump_pointer()
+    }
+
     pub fn used_bytes(&self) -> usize {
-        self.inner.borrow().space.used_bytes()
+        self.inner.borrow().collector.used_bytes()
     }
 
     pub fn free_bytes(&self) -> usize {
-        self.inner.borrow().space.free_bytes()
+        self.inner.borrow().collector.free_bytes()
     }
 
-    pub fn collect(&self) -> Result<(), GCError> {
-        let doomed = {
-            let mut visitor =
-                ObjectVisitor::new(Space::new(self.inner.borrow().space.size_in_bytes)?);
-            let mut inner = self.inner.borrow_mut();
-            inner.trace(&mut visitor);
-            let doomed = inner.update_weak();
-            std::mem::swap(&mut inner.space, &mut visitor.new_space);
-            doomed
+    /// Collects, then shrinks the heap if little of it is in use, see
+    /// HeapConfig::shrink_threshold.
+    pub fn collect(&self) -> Result<CollectionStats, GCError> {
+        let size_in_bytes = self.size_in_bytes();
+        let stats = self.collect_into(size_in_bytes)?;
+        match self.maybe_shrink()? {
+            Some(shrinking) => Ok(stats.then(shrinking)),
+            None => Ok(stats),
+        }
+    }
+
+    // Returns the stats of the collection which shrank the heap, if any.
+    fn maybe_shrink(&self) -> Result<Option<CollectionStats>, GCError> {
+        let new_size_in_bytes = {
+            let inner = self.inner.borrow();
+            let config = &inner.config;
+            let size_in_bytes = inner.collector.size_in_bytes();
+            let used_bytes = inner.collector.used_bytes() as f64;
+            let capacity = inner.collector.capacity_for(size_in_bytes) as f64;
+            if used_bytes >= capacity * config.shrink_threshold {
+                return Ok(None);
+            }
+            let shrunk = (size_in_bytes as f64 / config.growth_factor) as usize;
+            let new_size_in_bytes = shrunk.max(config.min_size_in_bytes);
+            // Leave room to allocate, so the next collection doesn't grow
+            // the heap straight back.
+            let new_capacity = inner.collector.capacity_for(new_size_in_bytes) as f64;
+            if new_size_in_bytes >= size_in_bytes || used_bytes * 2.0 > new_capacity {
+                return Ok(None);
+            }
+            new_size_in_bytes
         };
-        std::mem::drop(doomed);
-        Ok(())
+        match self.collect_into(new_size_in_bytes) {
+            // The collector can't resize, e.g. MarkSweepCollector.
+            Err(GCError::InvalidConfig) => Ok(None),
+            result => result.map(Some),
+        }
     }
 
-    fn emplace<T: HostObject>(&self, object: Box<T>) -> Result<ObjectPtr, GCError> {
-        let object_size = std::mem::size_of::<TraceableObject>();
-        let header = {
-            let maybe_header =
-                ObjectHeader::new(&mut self.inner.borrow_mut().space, object_size, T::TYPE_ID);
-            // Collect here.  Release inner mut-borrow and call collect, try again.
-            match maybe_header {
-                Err(_) => {
-                    self.collect()?;
-                    ObjectHeader::new(&mut self.inner.borrow_mut().space, object_size, T::TYPE_ID)?
+    /// Collects and shrinks the heap to the smallest size allowed by its
+    /// HeapConfig which holds the live objects, freeing the rest of its
+    /// memory.  The next allocation will likely grow it again, so this suits
+    /// a host about to go idle.  Does nothing if the collector can't resize.
+    pub fn shrink_to_fit(&self) -> Result<(), GCError> {
+        self.collect_into(self.size_in_bytes())?;
+        let new_size_in_bytes = {
+            let inner = self.inner.borrow();
+            let used_bytes = inner.collector.used_bytes();
+            // capacity_for grows with the size, so find the smallest size
+            // between the minimum and now which fits.
+            let (mut low, mut high) = (
+                inner.config.min_size_in_bytes,
+                inner.collector.size_in_bytes(),
+            );
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if inner.collector.capacity_for(mid) >= used_bytes {
+                    high = mid;
+                } else {
+                    low = mid + 1;
                 }
-                Ok(header) => header,
             }
+            low
         };
-        let object_ptr = header.as_ptr().to_object_ptr();
-        TraceableObject::from_box(object).store(object_ptr);
-        self.inner
-            .borrow_mut()
-            .weaks
-            .push(HeapHandle::new(object_ptr.into()));
-        Ok(object_ptr)
+        if new_size_in_bytes == self.size_in_bytes() {
+            return Ok(());
+        }
+        match self.collect_into(new_size_in_bytes) {
+            Ok(_) | Err(GCError::InvalidConfig) => Ok(()),
+            Err(error) => Err(error),
+        }
     }
-}
-
-#[derive(Debug)]
-struct Root {
-    inner: Arc<RefCell<HeapInner>>,
-    index: usize,
-}
-
-#[derive(Debug)]
-pub struct GlobalHandle<T> {
-    root: Root,
-    _phantom: PhantomData<T>,
-}
 
-impl<T> GlobalHandle<T> {
-    fn ptr(&self) -> TaggedPtr {
-        let inner = self.root.inner.borrow();
-        let cell = inner.globals[self.root.index].as_ref().unwrap();
-        cell.ptr()
+    /// Collects only the young generation, promoting its survivors, which is
+    /// much cheaper than collect when most new objects die young.  Does a
+    /// full collection if the collector has no young generation or can't
+    /// promote all of it, see CollectorKind::Generational.
+    pub fn collect_minor(&self) -> Result<CollectionStats, GCError> {
+        match self.try_prepare_minor_collection()? {
+            Some(collection) => self.run_collection(collection),
+            None => self.collect(),
+        }
     }
 
-    pub fn erase_type(self) -> GlobalHandle<()> {
-        GlobalHandle {
-            root: self.root,
-            _phantom: PhantomData::<()>::default(),
+    // Prepares a minor collection, running the Begin callbacks as
+    // prepare_collection does, or returns None if there can't be one.  A
+    // minor collection can't start while an incremental one is running.  If
+    // the callbacks leave too much to promote, a full collection is
+    // prepared instead.
+    fn try_prepare_minor_collection(&self) -> Result<Option<Collection>, GCError> {
+        let prepare = |heap: &Heap| {
+            let mut inner = heap.inner.borrow_mut();
+            inner.incremental.is_none() && inner.collector.prepare_minor_collection()
+        };
+        if !prepare(self) {
+            return Ok(None);
+        }
+        if self.run_gc_callbacks(GcPhase::Begin) && !prepare(self) {
+            let size_in_bytes = self.size_in_bytes();
+            let mut inner = self.inner.borrow_mut();
+            inner.collector.prepare_collection(size_in_bytes)?;
+            return Ok(Some(Collection::Full));
         }
+        Ok(Some(Collection::Minor))
     }
-}
 
-impl<T> From<GlobalHandle<T>> for HeapHandle<T> {
-    fn from(handle: GlobalHandle<T>) -> Self {
-        HeapHandle::<T>::new(handle.ptr())
+    // Prepares the collector for a full collection into `size_in_bytes`,
+    // then runs the Begin callbacks.  They may allocate, and so collect,
+    // using up what was prepared, so it's prepared again if any ran.  The
+    // first prepare checks the collection can happen at all (e.g. a
+    // MarkSweep heap can't resize) before calling them.
+    fn prepare_collection(&self, size_in_bytes: usize) -> Result<(), GCError> {
+        let prepare = || {
+            self.inner
+                .borrow_mut()
+                .collector
+                .prepare_collection(size_in_bytes)
+        };
+        prepare()?;
+        if self.run_gc_callbacks(GcPhase::Begin) {
+            prepare()?;
+        }
+        Ok(())
     }
-}
 
-impl Drop for Root {
-    fn drop(&mut self) {
-        self.inner.borrow_mut().globals[self.index] = None;
+    /// Does a slice of a full collection, tracing about `budget_bytes` of
+    /// objects, so an interpreter can spread a collection's pause between
+    /// its own steps.  The first call starts a collection and each call
+    /// continues it.  Returns true once the collection has finished.
+    ///
+    /// Objects may be allocated and modified between steps.  The final
+    /// step visits the roots again, and traces again the objects which
+    /// ObjectMuts were taken to since they were traced, for references
+    /// added since.  If an ObjectMut was held from before the first step,
+    /// or the collector has no WriteBarrier, it must trace every live
+    /// object again instead.  Stores through interior mutability on a
+    /// shared reference aren't seen.
+    ///
+    /// Any other collection finishes the one in progress first.  Collectors
+    /// which can't collect incrementally (see
+    /// CollectorStrategy::supports_incremental) do a whole collection.
+    pub fn collect_step(&self, budget_bytes: usize) -> Result<bool, GCError> {
+        if !self.inner.borrow().collector.supports_incremental() {
+            self.collect()?;
+            return Ok(true);
+        }
+        if !self.is_collecting() {
+            // Before trace_step prepares the collector, as they may collect.
+            self.run_gc_callbacks(GcPhase::Begin);
+        }
+        let done = self.inner.borrow_mut().trace_step(budget_bytes)?;
+        if done {
+            self.run_collection(Collection::Full)?;
+        }
+        Ok(done)
     }
-}
 
-pub struct HandleScope<'heap> {
-    heap: &'heap Heap,
-    index: usize,
-}
+    /// Runs collect_step until the collection finishes or `budget` has
+    /// passed, e.g. in an idle frame.  Starts a collection if none is in
+    /// progress and returns true once it has finished.  At least one step
+    /// runs, and the last step of a collection visits the roots and the
+    /// objects written to since they were traced again (see collect_step),
+    /// so it may overrun the budget.
+    pub fn collect_with_deadline(&self, budget: Duration) -> Result<bool, GCError> {
+        let deadline = Instant::now() + budget;
+        loop {
+            if self.collect_step(DEADLINE_STEP_BYTES)? {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+        }
+    }
 
-impl<'heap> HandleScope<'heap> {
-    pub fn new(heap: &Heap) -> HandleScope {
-        let mut inner = heap.inner.borrow_mut();
-        let index = inner.scopes.len();
-        inner.scopes.push(vec![]);
-        HandleScope { heap, index }
+    /// Whether a collection started by collect_step hasn't finished yet.
+    pub fn is_collecting(&self) -> bool {
+        self.inner.borrow().incremental.is_some()
     }
 
-    pub fn create_child_scope(&self) -> HandleScope<'heap> {
-        HandleScope::new(self.heap)
+    // Collects, leaving the heap with a budget of `size_in_bytes`.
+    fn collect_into(&self, size_in_bytes: usize) -> Result<CollectionStats, GCError> {
+        if self.is_collecting() {
+            // Finishing is already a full collection.
+            let stats = self.run_collection(Collection::Full)?;
+            if size_in_bytes == self.size_in_bytes() {
+                return Ok(stats);
+            }
+        }
+        self.prepare_collection(size_in_bytes)?;
+        self.run_collection(Collection::Full)
     }
 
-    pub fn create_num(&self, value: f64) -> LocalHandle<f64> {
-        LocalHandle::<f64>::new(self, value.into())
+    /// Collects, then slides the survivors together to defragment the heap,
+    /// for collectors which otherwise never move objects (see
+    /// CollectorKind::MarkSweep).  Every handle is updated and patch sites
+    /// are told of each move, but raw addresses held elsewhere are not.
+    /// Copying collectors compact on every collection, so for them this is
+    /// the same as collect.
+    pub fn compact(&self) -> Result<CollectionStats, GCError> {
+        if self.is_collecting() {
+            self.run_collection(Collection::Full)?;
+        }
+        self.prepare_collection(self.size_in_bytes())?;
+        self.run_collection(Collection::Compacting)
     }
 
-    pub fn create_bool(&self, value: bool) -> LocalHandle<bool> {
-        LocalHandle::<bool>::new(self, value.into())
+    // Runs a collection the collector has been prepared for, after its Begin
+    // callbacks.  Panics in tracing or finalizers are caught and the
+    // collection completes before returning GCError::Panicked.
+    fn run_collection(&self, collection: Collection) -> Result<CollectionStats, GCError> {
+        let started = Instant::now();
+        let mut stats = CollectionStats::default();
+        let (doomed, moves, mut panicked) = {
+            let mut inner = self.inner.borrow_mut();
+            let inner = &mut *inner;
+            let used_bytes_before = inner.collector.used_bytes();
+            inner.events.send(GcEvent::CollectionStarted {
+                used_bytes: used_bytes_before,
+            });
+            let mut panicked = inner.trace(collection == Collection::Minor);
+            let mut moves = inner.update_patch_sites();
+            inner.interned.update_weak();
+            inner.symbols.update_weak();
+            let doomed = inner.update_weak(&mut stats);
+            inner.collector.finish_collection(&inner.weaks);
+            if collection == Collection::Compacting {
+                panicked |= inner.compact(&mut moves);
+            }
+            let used_bytes_after = inner.collector.used_bytes();
+            inner.events.send(GcEvent::CollectionFinished {
+                used_bytes_before,
+                used_bytes_after,
+                objects_finalized: doomed.len(),
+            });
+            if cfg!(debug_assertions) {
+                inner.verify();
+            }
+            stats.used_bytes_before = used_bytes_before;
+            stats.used_bytes_after = used_bytes_after;
+            stats.objects_finalized = doomed.len();
+            (doomed, moves, panicked)
+        };
+        for (on_move, old_addr, new_addr) in moves {
+            (on_move.borrow_mut())(old_addr, new_addr);
+        }
+        let deferred = {
+            let mut inner = self.inner.borrow_mut();
+            inner.finalization_queue.extend(doomed);
+            inner.config.deferred_finalization
+        };
+        if !deferred {
+            panicked |= self.run_finalizers().is_err();
+        }
+        self.run_post_gc_tasks();
+        self.run_gc_callbacks(GcPhase::End);
+        if panicked {
+            return Err(GCError::Panicked);
+        }
+        stats.duration = started.elapsed();
+        Ok(stats)
     }
 
-    pub fn create_null(&self) -> LocalHandle<()> {
-        LocalHandle::<()>::new(self, TaggedPtr::NULL)
+    /// Finalizes the dead objects queued by collections, see
+    /// HeapConfig::deferred_finalization, returning how many there were.
+    /// Every finalizer runs even if some panic, then GCError::Panicked is
+    /// returned.  Objects still queued when the heap drops are never
+    /// finalized, like live ones.
+    pub fn run_finalizers(&self) -> Result<usize, GCError> {
+        let doomed = std::mem::take(&mut self.inner.borrow_mut().finalization_queue);
+        let count = doomed.len();
+        let result = self.finalize(doomed);
+        let mut inner = self.inner.borrow_mut();
+        inner.payloads.prune();
+        if count > 0 {
+            inner.events.send(GcEvent::FinalizersRun { count });
+        }
+        match result {
+            Ok(()) => Ok(count),
+            Err(_) => Err(GCError::Panicked),
+        }
     }
 
-    pub fn create<T: HostObject + Default>(&self) -> Result<LocalHandle<T>, GCError> {
-        let object_ptr = self.heap.emplace(Box::new(T::default()))?;
-        Ok(LocalHandle::<T>::new(self, object_ptr.into()))
+    /// The number of dead objects waiting for run_finalizers.
+    pub fn pending_finalizers(&self) -> usize {
+        self.inner.borrow().finalization_queue.len()
     }
 
-    pub fn take<T: HostObject>(&self, object: T) -> Result<LocalHandle<T>, GCError> {
-        let object_ptr = self.heap.emplace(Box::new(object))?;
-        Ok(LocalHandle::<T>::new(self, object_ptr.into()))
+    /// Calls `on_move` with the old and new address of `handle`'s object
+    /// whenever a collection moves it, once the collection has finished, so
+    /// code with the address embedded can be patched.  Patch sites don't keep
+    /// the object alive and are forgotten once it is collected.
+    pub fn register_patch_site<T, F>(&self, handle: &HeapHandle<T>, on_move: F) -> PatchSiteId
+    where
+        F: FnMut(*mut u8, *mut u8) + 'static,
+    {
+        let mut inner = self.inner.borrow_mut();
+        let index = inner.patch_sites.len();
+        inner.patch_sites.push(Some(PatchSite {
+            object: handle.erase_type(),
+            on_move: Rc::new(RefCell::new(on_move)),
+        }));
+        PatchSiteId(index)
     }
 
-    // Should this be create_str?
-    // Could also do generically for ToOwned?
-    // fn from_unowned<T, S>(...) where T: ToOwned<S>, S : HostObject {...}
-    pub fn str(&self, object: &str) -> Result<LocalHandle<String>, GCError> {
-        self.take(object.to_string())
+    pub fn unregister_patch_site(&self, id: PatchSiteId) {
+        self.inner.borrow_mut().patch_sites[id.0] = None;
     }
 
-    fn add(&self, ptr: TaggedPtr) -> usize {
-        let mut inner = self.heap.inner.borrow_mut();
-        let cells = &mut inner.scopes[self.index];
-        let index = cells.len();
-        cells.push(HeapHandle::new(ptr));
-        index
+    // Whether `id` is still registered, i.e. its object hasn't been
+    // collected.
+    #[cfg(feature = "testing")]
+    pub(crate) fn has_patch_site(&self, id: &PatchSiteId) -> bool {
+        self.inner.borrow().patch_sites[id.0].is_some()
     }
 
-    pub fn from_global<T>(&self, handle: &GlobalHandle<T>) -> LocalHandle<T> {
-        LocalHandle::<T>::new(self, handle.ptr())
+    // The number of objects of type T not yet finalized, live or not.
+    #[cfg(feature = "testing")]
+    pub(crate) fn count_objects<T: HostObject>(&self) -> usize {
+        let inner = self.inner.borrow();
+        inner
+            .weaks
+            .iter()
+            .filter_map(|handle| handle.ptr().try_into().ok())
+            .filter(|object_ptr| TraceableObject::try_downcast::<T>(*object_ptr).is_some())
+            .count()
     }
 
-    pub fn from_heap<T>(&self, handle: &HeapHandle<T>) -> LocalHandle<T> {
-        LocalHandle::<T>::new(self, handle.ptr())
+    /// Roots `handle` under `name` (e.g. "core.String") until unregistered,
+    /// so subsystems can share well-known objects.  Replaces any root
+    /// already registered under `name`.
+    pub fn register_named_root<T>(&self, name: &str, handle: HeapHandle<T>) {
+        self.inner
+            .borrow_mut()
+            .named_roots
+            .insert(name.to_string(), handle.erase_type());
     }
 
-    pub fn from_local<T>(&self, handle: &LocalHandle<'_, T>) -> LocalHandle<T> {
-        LocalHandle::<T>::new(self, handle.ptr())
+    /// Returns whether a root was registered under `name`.
+    pub fn unregister_named_root(&self, name: &str) -> bool {
+        self.inner.borrow_mut().named_roots.remove(name).is_some()
     }
 
-    pub fn from_maybe_heap<T>(
+    pub fn lookup_named_root<'a>(
         &self,
-        maybe_handle: &Option<HeapHandle<T>>,
-    ) -> Option<LocalHandle<T>> {
-        maybe_handle
-            .clone()
-            .map(|handle| LocalHandle::<T>::new(self, handle.ptr()))
+        scope: &'a HandleScope,
+        name: &str,
+    ) -> Option<LocalHandle<'a, ()>> {
+        let handle = self.inner.borrow().named_roots.get(name).cloned()?;
+        Some(scope.from_heap(&handle))
     }
 
-    pub fn as_ref<T: HostObject>(&self, handle: &GlobalHandle<T>) -> &T {
-        let local = self.from_global(handle);
-        local.as_ref()
+    /// The names of all registered roots, in sorted order.
+    pub fn named_roots(&self) -> Vec<String> {
+        self.inner.borrow().named_roots.keys().cloned().collect()
     }
 
-    pub fn as_mut<T: HostObject>(&self, handle: &GlobalHandle<T>) -> &mut T {
-        let local = self.from_global(handle);
-        local.as_mut()
+    /// Hashes heap values with this heap's seed.  Native hash tables of heap
+    /// values should use it rather than an unseeded hasher.
+    pub fn hash_state(&self) -> SeededState {
+        self.inner.borrow().hash_state
     }
 
-    fn get_ptr(&self, index: usize) -> TaggedPtr {
-        let inner = self.heap.inner.borrow();
-        inner.scopes[self.index][index].ptr()
+    pub fn intern_stats(&self) -> InternStats {
+        self.inner.borrow().interned.stats()
     }
-}
 
-impl<'heap> Drop for HandleScope<'heap> {
-    fn drop(&mut self) {
-        let mut inner = self.heap.inner.borrow_mut();
-        inner.scopes.pop();
+    /// Whether `handle` is the heap's single String for its text, see
+    /// HandleScope::intern.  Two interned strings are equal only if they are
+    /// the same object, see LocalHandle::ptr_eq.
+    pub fn is_interned(&self, handle: &LocalHandle<'_, String>) -> bool {
+        let object_ptr = handle.get_object_ptr().unwrap();
+        let inner = self.inner.borrow();
+        inner.interned.is_canonical(handle.as_ref(), object_ptr)
     }
-}
-
-#[derive(Copy)]
-pub struct LocalHandle<'a, T> {
-    scope: &'a HandleScope<'a>,
-    index: usize,
-    phantom: PhantomData<T>,
-}
 
-// Derive Clone requires T to be Cloneable, which isn't required for Handles.
-impl<'a, T> Clone for LocalHandle<'a, T> {
-    fn clone(&self) -> Self {
-        LocalHandle {
-            scope: self.scope,
-            index: self.index,
-            phantom: PhantomData::<T>::default(),
+    /// Starts sampling allocations, one each time another
+    /// `sample_interval_bytes` have been allocated, to find which types of
+    /// object the heap's growth comes from.  Restarts the profile if already
+    /// profiling.  Objects allocated by generated code bumping the pointer
+    /// itself aren't seen.
+    pub fn start_allocation_profiling(&self, sample_interval_bytes: usize) -> Result<(), GCError> {
+        if sample_interval_bytes == 0 {
+            return Err(GCError::InvalidConfig);
         }
+        self.inner.borrow_mut().profiler = Some(AllocationProfiler::new(sample_interval_bytes));
+        Ok(())
     }
 
-    fn clone_from(&mut self, source: &Self) {
-        self.scope = source.scope;
-        self.index = source.index;
+    /// The allocations sampled so far, or None if not profiling.
+    pub fn allocation_profile(&self) -> Option<AllocationProfile> {
+        let inner = self.inner.borrow();
+        inner.profiler.as_ref().map(AllocationProfiler::report)
     }
-}
 
-impl<'a, T> LocalHandle<'a, T> {
-    fn new(scope: &'a HandleScope, ptr: TaggedPtr) -> Self {
-        Self {
-            scope: scope,
-            index: scope.add(ptr),
-            phantom: PhantomData::<T>::default(),
+    /// Stops profiling, returning the final profile.
+    pub fn stop_allocation_profiling(&self) -> Option<AllocationProfile> {
+        let profiler = self.inner.borrow_mut().profiler.take();
+        profiler.as_ref().map(AllocationProfiler::report)
+    }
+
+    /// Collects, then writes every object and the references between them
+    /// to `writer` as a Chrome DevTools .heapsnapshot, to find what keeps
+    /// objects alive.  Objects are named by type, strings by their text.
+    pub fn write_snapshot(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        self.collect()
+            .map_err(|error| std::io::Error::other(error.to_string()))?;
+        let (roots, nodes) = self.inner.borrow_mut().snapshot_nodes();
+        snapshot::write_snapshot(roots, &nodes, &mut writer)
+    }
+
+    /// Collects, then writes the objects and the references between them to
+    /// `writer` as a Graphviz digraph, to see what keeps objects alive in a
+    /// small heap, e.g. with `dot -Tsvg`.  Objects are labeled by type,
+    /// strings by their text, and their size in bytes.
+    pub fn write_dot(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        self.collect()
+            .map_err(|error| std::io::Error::other(error.to_string()))?;
+        let (roots, nodes) = self.inner.borrow_mut().snapshot_nodes();
+        dot::write_dot(&roots, &nodes, &mut writer)
+    }
+
+    /// Checks every object's header and that every handle the roots and
+    /// objects hold is a valid value and refers to a live object in the
+    /// heap, panicking with what's wrong if not.  Catches handles left
+    /// pointing where an object used to be, e.g. by a Traceable::trace which
+    /// skipped them while the object moved.  Runs after every collection in
+    /// debug builds.
+    /// Does nothing while collect_step is part way through a collection.
+    pub fn verify(&self) {
+        self.inner.borrow_mut().verify();
+    }
+
+    /// Writes every object's header and the objects it refers to, in a
+    /// compact binary format loaded by HeapDump::read, e.g. for a deployed
+    /// VM to save its heap on a fatal error for postmortem debugging.
+    /// Unlike write_snapshot it doesn't collect, so the dump shows garbage
+    /// too, unreachable from the roots.  Finishes any collection started by
+    /// collect_step first.
+    pub fn dump(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        if self.is_collecting() {
+            self.collect()
+                .map_err(|error| std::io::Error::other(error.to_string()))?;
         }
+        let dump = self.inner.borrow_mut().heap_dump();
+        dump.write(&mut writer)
     }
 
-    #[cfg(test)]
-    pub(crate) fn ptr_for_test(&self) -> TaggedPtr {
-        self.ptr()
+    /// Counts the live handles, e.g. to find a loop leaking local handles
+    /// into a long lived scope.
+    pub fn handle_stats(&self) -> HandleStats {
+        let inner = self.inner.borrow();
+        HandleStats {
+            local_handles: inner.scopes.iter().flatten().map(Vec::len).collect(),
+            global_handles: inner.globals.iter().flatten().count(),
+            weak_handles: inner.weak_handles.iter().flatten().count(),
+        }
     }
 
-    fn ptr(&self) -> TaggedPtr {
-        self.scope.get_ptr(self.index)
+    /// Counts the objects in the heap and the bytes they take by type, most
+    /// bytes first, to see what's filling it without writing a snapshot.
+    /// Includes garbage not yet collected; collect first to count only
+    /// reachable objects.
+    pub fn usage_by_type(&self) -> Vec<TypeUsage> {
+        self.inner.borrow().usage_by_type()
     }
 
-    fn get_object_ptr(&self) -> Option<ObjectPtr> {
-        self.ptr().try_into().ok()
+    /// Returns a channel of GcEvents describing future collector activity.
+    /// The receiver may be moved to another thread for monitoring.
+    pub fn subscribe_events(&self) -> Receiver<GcEvent> {
+        self.inner.borrow_mut().events.subscribe()
     }
 
-    pub fn erase_type(&self) -> LocalHandle<'a, ()> {
-        LocalHandle {
-            scope: self.scope,
-            index: self.index,
-            phantom: PhantomData::<()>::default(),
+    /// Schedules `task` to run once the next collection has completed.
+    /// Tasks run outside of the collector with the heap unborrowed, so they
+    /// are free to allocate or rebuild caches of raw object pointers.  Tasks
+    /// scheduled while tasks are running wait for the following collection.
+    pub fn post_gc<F: FnOnce(&Heap) + 'static>(&self, task: F) {
+        self.inner.borrow_mut().post_gc_tasks.push(Box::new(task));
+    }
+
+    fn run_post_gc_tasks(&self) {
+        let tasks = std::mem::take(&mut self.inner.borrow_mut().post_gc_tasks);
+        for task in tasks {
+            task(self);
         }
     }
-}
 
-impl<'a> LocalHandle<'a, ()> {
-    pub fn is_null(&self) -> bool {
-        self.ptr().is_null()
+    // Collects if HeapConfig::gc_stress is set, unless this is already
+    // within such a collection, e.g. a finalizer allocating.
+    fn stress(&self) -> Result<(), GCError> {
+        {
+            let mut inner = self.inner.borrow_mut();
+            if !inner.config.gc_stress || inner.stressing {
+                return Ok(());
+            }
+            inner.stressing = true;
+        }
+        // Not collect, which may shrink the heap below what reserve needs.
+        let result = self.collect_into(self.size_in_bytes());
+        self.inner.borrow_mut().stressing = false;
+        result.map(|_| ())
     }
 
-    pub fn is_bool(&self) -> bool {
-        self.ptr().is_bool()
+    /// Calls `callback` with the heap at `phase` of every collection, e.g.
+    /// to flush caches as it begins or to update pointers held outside the
+    /// heap once it ends.  For an incremental collection, Begin is at the
+    /// start of its first step.  Callbacks of either phase may allocate, and
+    /// Begin callbacks run before the collector is prepared, so allocating
+    /// in a full heap collects first.  A collection started from a callback
+    /// doesn't call the callbacks again.
+    pub fn add_gc_callback<F>(&self, phase: GcPhase, callback: F)
+    where
+        F: FnMut(&Heap) + 'static,
+    {
+        self.inner
+            .borrow_mut()
+            .gc_callbacks
+            .push((phase, Box::new(callback)));
     }
 
-    pub fn is_num(&self) -> bool {
-        self.ptr().is_num()
+    // Returns whether any ran.
+    fn run_gc_callbacks(&self, phase: GcPhase) -> bool {
+        // Taken while they run, as they may add more or collect.
+        let mut callbacks = std::mem::take(&mut self.inner.borrow_mut().gc_callbacks);
+        let mut ran = false;
+        for (callback_phase, callback) in callbacks.iter_mut() {
+            if *callback_phase == phase {
+                callback(self);
+                ran = true;
+            }
+        }
+        let mut inner = self.inner.borrow_mut();
+        let added = std::mem::replace(&mut inner.gc_callbacks, callbacks);
+        inner.gc_callbacks.extend(added);
+        ran
     }
 
-    pub fn try_as_ref<S: HostObject>(&self) -> Option<&'a S> {
-        if let Some(object_ptr) = self.get_object_ptr() {
-            if object_ptr.is_type(S::TYPE_ID) {
-                if let Some(ptr) = TraceableObject::try_downcast::<S>(object_ptr) {
-                    return Some(unsafe { &*ptr });
-                }
+    // Makes sure at least `bytes` are free, collecting if needed.  Once this
+    // succeeds, that many bytes can be allocated without a collection moving
+    // objects.
+    fn reserve(&self, bytes: usize) -> Result<(), GCError> {
+        self.stress()?;
+        self.check_watermarks(bytes);
+        {
+            let mut inner = self.inner.borrow_mut();
+            if inner.collector.free_bytes() >= bytes {
+                return Ok(());
             }
+            let event = GcEvent::LimitCrossed {
+                requested_bytes: bytes,
+                used_bytes: inner.collector.used_bytes(),
+                limit_bytes: inner.collector.used_bytes() + inner.collector.free_bytes(),
+            };
+            inner.events.send(event);
         }
-        None
+        // Collect here.  Release inner mut-borrow and call collect, try again.
+        // A minor collection is tried first, if the collector has one.
+        if let Some(collection) = self.try_prepare_minor_collection()? {
+            self.run_collection(collection)?;
+        }
+        if self.inner.borrow().collector.free_bytes() < bytes {
+            self.collect()?;
+        }
+        while self.inner.borrow().collector.free_bytes() < bytes {
+            if self.grow()? {
+                continue;
+            }
+            let free_bytes = self.inner.borrow().collector.free_bytes();
+            self.out_of_memory(bytes)?;
+            self.collect()?;
+            // A callback which asks to retry without freeing anything would
+            // otherwise be called forever.
+            if self.inner.borrow().collector.free_bytes() <= free_bytes {
+                return Err(GCError::NoSpace);
+            }
+        }
+        Ok(())
     }
 
-    pub fn try_as_mut<S: HostObject>(&self) -> Option<&'a mut S> {
-        if let Some(object_ptr) = self.get_object_ptr() {
-            if object_ptr.is_type(S::TYPE_ID) {
-                if let Some(ptr) = TraceableObject::try_downcast::<S>(object_ptr) {
-                    let mut_ptr = ptr as *mut S;
-                    return Some(unsafe { &mut *mut_ptr });
-                }
+    // Grows the heap by HeapConfig::growth_factor, up to max_size_in_bytes,
+    // by collecting into a larger space.  Returns false if it can't grow.
+    fn grow(&self) -> Result<bool, GCError> {
+        let new_size_in_bytes = {
+            let inner = self.inner.borrow();
+            let size_in_bytes = inner.collector.size_in_bytes();
+            let growth_factor = inner.config.growth_factor;
+            let damped = inner.memory_pressure.growth_factor(growth_factor);
+            let mut grown = (size_in_bytes as f64 * damped) as usize;
+            // A small heap may not grow at all by the smaller step.
+            if grown <= size_in_bytes {
+                grown = (size_in_bytes as f64 * growth_factor) as usize;
             }
+            let new_size_in_bytes = grown.min(inner.config.max_size_in_bytes);
+            if new_size_in_bytes <= size_in_bytes {
+                return Ok(false);
+            }
+            new_size_in_bytes
+        };
+        match self.collect_into(new_size_in_bytes) {
+            Ok(_) => Ok(true),
+            // The collector can't resize, e.g. MarkSweepCollector.
+            Err(GCError::InvalidConfig) => Ok(false),
+            Err(error) => Err(error),
         }
-        None
     }
 
-    pub fn is_of_type<S: HostObject>(&self) -> bool {
-        let maybe_ref: Option<&S> = self.try_as_ref();
-        maybe_ref.is_some()
+    /// Tells the heap how short of memory the system is.  Under Moderate or
+    /// Critical pressure the heap collects and shrinks as far as its
+    /// HeapConfig allows, and grows in smaller steps until pressure is back
+    /// to None.  Critical also compacts and runs any deferred finalizers.
+    pub fn notify_memory_pressure(&self, level: MemoryPressure) -> Result<(), GCError> {
+        self.inner.borrow_mut().memory_pressure = level;
+        if level == MemoryPressure::Critical {
+            self.compact()?;
+        }
+        if level != MemoryPressure::None {
+            self.shrink_to_fit()?;
+        }
+        if level == MemoryPressure::Critical {
+            self.run_finalizers()?;
+        }
+        Ok(())
     }
-}
 
-pub trait DowncastTo<T> {
-    fn try_downcast(self) -> Option<T>;
-}
+    /// Calls `callback` with the heap and its used bytes when an allocation
+    /// would take usage above `fraction` (e.g. 0.9) of the space available,
+    /// before the allocation happens, so the embedder can shed caches or
+    /// throttle scripts before allocations start failing.  Fires once per
+    /// crossing, again only after usage has dropped back below.
+    pub fn add_usage_watermark<F>(&self, fraction: f64, callback: F)
+    where
+        F: FnMut(&Heap, usize) + 'static,
+    {
+        self.inner.borrow_mut().watermarks.push(Watermark {
+            fraction,
+            armed: true,
+            callback: Rc::new(RefCell::new(callback)),
+        });
+    }
 
-impl<'a, T: HostObject> DowncastTo<LocalHandle<'a, T>> for LocalHandle<'a, ()> {
-    fn try_downcast(self) -> Option<LocalHandle<'a, T>> {
-        if let Some(object_ptr) = self.get_object_ptr() {
-            if object_ptr.is_type(T::TYPE_ID) {
-                let ptr = TraceableObject::try_downcast::<T>(object_ptr);
-                if ptr.is_some() {
-                    return Some(LocalHandle {
-                        scope: self.scope,
-                        index: self.index,
-                        phantom: PhantomData::<T>::default(),
-                    });
+    fn check_watermarks(&self, bytes: usize) {
+        let (used_bytes, crossed) = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.watermarks.is_empty() {
+                return;
+            }
+            let used_bytes = inner.collector.used_bytes();
+            let limit_bytes = used_bytes + inner.collector.free_bytes();
+            let usage = (used_bytes + bytes) as f64 / limit_bytes as f64;
+            let mut crossed = vec![];
+            for watermark in inner.watermarks.iter_mut() {
+                if usage <= watermark.fraction {
+                    watermark.armed = true;
+                } else if watermark.armed {
+                    watermark.armed = false;
+                    crossed.push(watermark.callback.clone());
                 }
             }
+            (used_bytes, crossed)
+        };
+        // Run with the heap unborrowed, callbacks may allocate or collect.
+        for callback in crossed {
+            (callback.borrow_mut())(self, used_bytes);
         }
-        None
-    }
-}
-
-impl<'a> DowncastTo<LocalHandle<'a, f64>> for LocalHandle<'a, ()> {
-    fn try_downcast(self) -> Option<LocalHandle<'a, f64>> {
-        self.try_into()
-            .ok()
-            .map(|value| self.scope.create_num(value))
     }
-}
 
-impl<'a> DowncastTo<LocalHandle<'a, bool>> for LocalHandle<'a, ()> {
-    fn try_downcast(self) -> Option<LocalHandle<'a, bool>> {
-        self.try_into()
-            .ok()
-            .map(|value| self.scope.create_bool(value))
+    /// Replaces the OomPolicy, which is ReturnError for a new heap.
+    pub fn set_oom_policy(&self, policy: OomPolicy) {
+        self.inner.borrow_mut().oom_policy = policy;
     }
-}
 
-impl<'a, T: HostObject> LocalHandle<'a, T> {
-    pub fn borrow(&self) -> &'a T {
-        let object_ptr = self.get_object_ptr().unwrap();
-        let ptr = TraceableObject::downcast::<T>(object_ptr);
-        unsafe { &*ptr }
+    // Applies the OomPolicy to an allocation of `bytes` which doesn't fit
+    // after collecting.  Ok means collect and try again.
+    fn out_of_memory(&self, bytes: usize) -> Result<(), GCError> {
+        // The callback runs with the heap unborrowed, so it may use it.
+        let mut policy = std::mem::take(&mut self.inner.borrow_mut().oom_policy);
+        let retry = match &mut policy {
+            OomPolicy::ReturnError => false,
+            OomPolicy::Callback(callback) => callback(self, bytes),
+            OomPolicy::Abort => {
+                eprintln!("vmgc: out of memory allocating {} bytes", bytes);
+                eprintln!("{}", self.usage_report());
+                std::process::abort();
+            }
+        };
+        self.inner.borrow_mut().oom_policy = policy;
+        if !retry {
+            return Err(GCError::NoSpace);
+        }
+        Ok(())
     }
 
-    pub fn borrow_mut(&self) -> &'a mut T {
-        let object_ptr = self.get_object_ptr().unwrap();
-        let ptr = TraceableObject::downcast_mut::<T>(object_ptr);
-        unsafe { &mut *ptr }
+    // A summary of the heap's usage, for OomPolicy::Abort.
+    fn usage_report(&self) -> String {
+        let inner = self.inner.borrow();
+        format!(
+            "heap: {} bytes used, {} bytes free, {} objects, {} globals, {} scopes",
+            inner.collector.used_bytes(),
+            inner.collector.free_bytes(),
+            inner.weaks.len(),
+            inner.globals.iter().flatten().count(),
+            inner.scopes.iter().flatten().count(),
+        )
     }
 
-    // Old names:
-    pub fn as_ref(&self) -> &'a T {
-        self.borrow()
+    // Called when the only reference to an object goes away.
+    fn reclaim_unique(&self, object_ptr: ObjectPtr) {
+        if self.inner.borrow().config.eager_reclamation {
+            self.reclaim(vec![object_ptr]);
+        }
     }
 
-    pub fn as_mut(&self) -> &'a mut T {
-        self.borrow_mut()
+    // Finalizes objects known to be dead and frees their memory, newest
+    // first so a run of recent allocations is bumped back over.
+    fn reclaim(&self, objects: Vec<ObjectPtr>) {
+        let doomed = {
+            let mut inner = self.inner.borrow_mut();
+            // Mid collection an object may be in to-space with its old copy
+            // still listed, let the collection take care of it.
+            if inner.incremental.is_some() {
+                return;
+            }
+            let mut doomed = Vec::with_capacity(objects.len());
+            for object_ptr in objects {
+                let is_object = |handle: &HeapHandle<()>| {
+                    let weak_ptr: Result<ObjectPtr, GCError> = handle.ptr().try_into();
+                    weak_ptr.is_ok_and(|weak_ptr| weak_ptr.addr() == object_ptr.addr())
+                };
+                // Temporaries are usually among the most recent allocations.
+                let index = inner.weaks.iter().rposition(is_object).unwrap();
+                inner.weaks.swap_remove(index);
+                doomed.push(TraceableObject::load(object_ptr));
+                let header = object_ptr.header();
+                let (addr, size) = (header.as_ptr().addr(), header.alloc_size());
+                inner.collector.unalloc(addr, size);
+            }
+            if inner.config.deferred_finalization {
+                inner.finalization_queue.extend(doomed);
+                return;
+            }
+            doomed
+        };
+        if let Err(panic) = self.finalize(doomed) {
+            std::panic::resume_unwind(panic);
+        }
     }
-}
 
-impl<'a> TryInto<f64> for LocalHandle<'a, ()> {
-    type Error = GCError;
-    fn try_into(self) -> Result<f64, GCError> {
-        self.ptr().try_into()
+    // Runs the finalizers with the heap unborrowed, then frees the payloads.
+    // Every finalizer runs even if some panic, the first panic is returned.
+    fn finalize(&self, doomed: Vec<TraceableObject>) -> std::thread::Result<()> {
+        let mut result = Ok(());
+        let mut blocks = Vec::with_capacity(doomed.len());
+        for object in doomed {
+            blocks.push(object.block());
+            let finalized =
+                std::panic::catch_unwind(AssertUnwindSafe(|| unsafe { object.drop_payload() }));
+            if result.is_ok() {
+                result = finalized;
+            }
+        }
+        let mut inner = self.inner.borrow_mut();
+        for (ptr, layout) in blocks {
+            inner.control_blocks.free(ptr, layout);
+        }
+        result
     }
-}
 
-impl<'a> Into<f64> for LocalHandle<'a, f64> {
-    fn into(self) -> f64 {
-        self.ptr().try_into().unwrap()
+    fn emplace<T: HostObject>(&self, mut object: T) -> Result<ObjectPtr, GCError> {
+        let unplaced: *mut dyn Traceable = &mut object;
+        self.inner.borrow_mut().unplaced.push(unplaced);
+        let allocated = self.alloc_host_object(ObjectType::of::<T>());
+        self.inner.borrow_mut().unplaced.pop();
+        let object_ptr = allocated?;
+        self.inner.borrow_mut().init_host_object(object_ptr, object);
+        Ok(object_ptr)
     }
-}
 
-impl<'a> TryInto<bool> for LocalHandle<'a, ()> {
-    type Error = GCError;
-    fn try_into(self) -> Result<bool, GCError> {
-        self.ptr().try_into()
+    // Allocates a ByteArray or GcString of `len` zeroed bytes.
+    fn emplace_bytes<T: TrailingBytes>(&self, len: usize) -> Result<ObjectPtr, GCError> {
+        let layout = byte_array::layout_for(len).ok_or(GCError::NoSpace)?;
+        let object_ptr = self.alloc_host_object(ObjectType::of::<T>())?;
+        let mut inner = self.inner.borrow_mut();
+        inner.init_bytes_object::<T>(object_ptr, len, layout);
+        Ok(object_ptr)
     }
-}
 
-impl<'a> Into<bool> for LocalHandle<'a, bool> {
-    fn into(self) -> bool {
-        self.ptr().try_into().unwrap()
+    // Allocates a host object's memory and header, collecting first if
+    // needed.
+    fn alloc_host_object(&self, object_type: ObjectType) -> Result<ObjectPtr, GCError> {
+        self.reserve(HOST_OBJECT_ALLOC_SIZE)?;
+        let allocated = self.inner.borrow_mut().alloc_host_object(object_type);
+        match allocated {
+            // The free bytes reserve counted may be too fragmented to use,
+            // e.g. in a MarkSweepCollector, so compact and retry once.
+            Err(GCError::NoSpace) => {
+                self.compact()?;
+                self.inner.borrow_mut().alloc_host_object(object_type)
+            }
+            result => result,
+        }
     }
-}
 
-impl<'a, T> From<LocalHandle<'a, T>> for HeapHandle<T> {
-    fn from(handle: LocalHandle<'a, T>) -> Self {
-        HeapHandle::<T>::new(handle.ptr())
+    /// Moves `object` into the heap and roots it globally, for callers
+    /// without a HandleScope at hand.  The Box is for older call sites, its
+    /// contents are moved out like HandleScope::take.
+    #[allow(clippy::boxed_local)]
+    pub fn alloc_global<T: HostObject>(&self, object: Box<T>) -> Result<GlobalHandle<T>, GCError> {
+        let object_ptr = self.emplace(*object)?;
+        Ok(self.root_global(object_ptr.into()))
     }
-}
 
-impl<'a, T> From<LocalHandle<'a, T>> for GlobalHandle<T> {
-    fn from(handle: LocalHandle<'a, T>) -> Self {
-        let ptr = handle.ptr();
+    fn root_global<T>(&self, ptr: TaggedPtr) -> GlobalHandle<T> {
         let index = {
-            // TODO: Scan for available cells.
-            let mut inner = handle.scope.heap.inner.borrow_mut();
-            let index = inner.globals.len();
-            inner.globals.push(Some(HeapHandle::<()>::new(ptr)));
-            index
+            let mut inner = self.inner.borrow_mut();
+            let cell = Some(HeapHandle::<()>::new(ptr));
+            match inner.free_globals.pop() {
+                Some(index) => {
+                    inner.globals[index] = cell;
+                    index
+                }
+                None => {
+                    inner.globals.push(cell);
+                    inner.globals.len() - 1
+                }
+            }
         };
         GlobalHandle {
             root: Root {
-                inner: Arc::clone(&handle.scope.heap.inner),
+                inner: Arc::clone(&self.inner),
                 index,
             },
             _phantom: PhantomData::<T>::default(),
@@ -473,148 +1743,2561 @@ impl<'a, T> From<LocalHandle<'a, T>> for GlobalHandle<T> {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug)]
+struct Root {
+    inner: Arc<RefCell<HeapInner>>,
+    index: usize,
+}
 
-    use std::cell::Cell;
-    use std::convert::TryInto;
-    use std::hash::{Hash, Hasher};
-    use std::rc::Rc;
+/// Roots an object from outside the heap until dropped.  Host objects must
+/// not hold GlobalHandles: the globals table would keep the holder alive
+/// through its own field, a cycle the collector can't see, and the whole
+/// heap would leak.  Host objects refer to each other with HeapHandles
+/// visited from Traceable::trace instead.
+#[derive(Debug)]
+pub struct GlobalHandle<T> {
+    root: Root,
+    _phantom: PhantomData<T>,
+}
 
-    #[derive(Default)]
-    struct DropObject {
-        counter: Rc<Cell<u32>>,
+impl<T> GlobalHandle<T> {
+    fn ptr(&self) -> TaggedPtr {
+        self.root.ptr()
     }
 
-    impl HostObject for DropObject {
-        const TYPE_ID: ObjectType = ObjectType::Host;
+    /// See LocalHandle::type_name.
+    pub fn type_name(&self) -> &'static str {
+        self.ptr().type_name()
     }
 
-    impl Traceable for DropObject {
-        fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+    pub fn erase_type(self) -> GlobalHandle<()> {
+        GlobalHandle {
+            root: self.root,
+            _phantom: PhantomData::<()>::default(),
+        }
     }
+}
 
-    impl Drop for DropObject {
-        fn drop(&mut self) {
-            let counter = self.counter.get();
-            self.counter.set(counter + 1);
-        }
+impl<T> From<GlobalHandle<T>> for HeapHandle<T> {
+    fn from(handle: GlobalHandle<T>) -> Self {
+        HeapHandle::<T>::new(handle.ptr())
     }
+}
 
-    impl Hash for DropObject {
-        fn hash<H: Hasher>(&self, state: &mut H) {
-            (self as *const DropObject as usize).hash(state);
-        }
+/// A traced reference to a global's object, e.g. for storing in a host
+/// object.  The global is left in place.
+impl<T> From<&GlobalHandle<T>> for HeapHandle<T> {
+    fn from(handle: &GlobalHandle<T>) -> Self {
+        HeapHandle::<T>::new(handle.ptr())
     }
+}
 
-    #[test]
-    pub fn smoke_test() {
-        let heap = Heap::new(1000).unwrap();
-        assert_eq!(heap.used_bytes(), 0);
-        let two: GlobalHandle<DropObject> = {
-            let scope = HandleScope::new(&heap);
-            let one = scope.create::<DropObject>().unwrap();
-            let two = scope.create::<DropObject>().unwrap();
-            std::mem::drop(one);
-            two.into()
+impl Root {
+    fn ptr(&self) -> TaggedPtr {
+        let inner = self.inner.borrow();
+        inner.globals[self.index].as_ref().unwrap().ptr()
+    }
+
+    fn set_ptr(&self, ptr: TaggedPtr) {
+        let mut inner = self.inner.borrow_mut();
+        inner.globals[self.index] = Some(HeapHandle::new(ptr));
+    }
+}
+
+/// A global root which can be re-pointed and emptied in place, e.g. for a
+/// module cache or symbol table entry, keeping its slot in the globals
+/// table.  Null counts as empty.  Like GlobalHandles, host objects must not
+/// hold them.
+#[derive(Debug)]
+pub struct PersistentHandle<T> {
+    root: Root,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> PersistentHandle<T> {
+    /// An empty handle, see reset.
+    pub fn new(heap: &Heap) -> PersistentHandle<T> {
+        let global = heap.root_global::<T>(TaggedPtr::NULL);
+        PersistentHandle {
+            root: global.root,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Roots `value` instead of whatever was rooted before.
+    pub fn reset(&mut self, value: &LocalHandle<'_, T>) {
+        let ptr = value.ptr();
+        escape(ptr);
+        self.root.set_ptr(ptr);
+    }
+
+    /// Stops rooting the value, if any.
+    pub fn clear(&mut self) {
+        self.root.set_ptr(TaggedPtr::NULL);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.ptr().is_null()
+    }
+
+    /// A handle to the value in `scope`, or None if empty.
+    pub fn get<'a>(&self, scope: &'a HandleScope) -> Option<LocalHandle<'a, T>> {
+        let ptr = self.root.ptr();
+        if ptr.is_null() {
+            return None;
+        }
+        Some(LocalHandle::<T>::new(scope, ptr))
+    }
+}
+
+impl<'a, T> From<LocalHandle<'a, T>> for PersistentHandle<T> {
+    fn from(handle: LocalHandle<'a, T>) -> Self {
+        let mut persistent = PersistentHandle::new(handle.scope.heap);
+        persistent.reset(&handle);
+        persistent
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let mut inner = self
+            .inner
+            .try_borrow_mut()
+            .expect("GlobalHandle dropped during a collection, host objects must hold HeapHandles");
+        inner.globals[self.index] = None;
+        inner.free_globals.push(self.index);
+    }
+}
+
+/// A reference which doesn't keep its object alive, e.g. for caches.  Made
+/// with LocalHandle::downgrade, it's cleared once a collection finds
+/// nothing else referring to the object, see on_collect to be told when.
+/// Host objects may hold them.
+#[derive(Debug)]
+pub struct WeakHandle<T> {
+    inner: Arc<RefCell<HeapInner>>,
+    index: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> WeakHandle<T> {
+    /// A handle to the object in `scope`, or None if it has been collected.
+    pub fn upgrade<'a>(&self, scope: &'a HandleScope) -> Option<LocalHandle<'a, T>> {
+        let inner = self.inner.borrow();
+        let ptr = inner.weak_handles[self.index]
+            .as_ref()
+            .unwrap()
+            .object
+            .as_ref()?
+            .ptr();
+        std::mem::drop(inner);
+        Some(LocalHandle::<T>::new(scope, ptr))
+    }
+
+    /// Whether the object hasn't been collected yet.
+    pub fn is_live(&self) -> bool {
+        let inner = self.inner.borrow();
+        inner.weak_handles[self.index]
+            .as_ref()
+            .unwrap()
+            .object
+            .is_some()
+    }
+
+    /// Calls `on_collect` once the object has been collected, e.g. to clear
+    /// a cache entry or close an OS resource tied to it.  It runs with the
+    /// post-GC tasks (see Heap::post_gc) after the collection's finalizers,
+    /// so it may allocate.  Replaces any earlier callback, and isn't called
+    /// if the WeakHandle is dropped first or the object is already gone.
+    pub fn on_collect<F: FnOnce(&Heap) + 'static>(&self, on_collect: F) {
+        let mut inner = self.inner.borrow_mut();
+        let target = inner.weak_handles[self.index].as_mut().unwrap();
+        if target.object.is_some() {
+            target.on_collect = Some(Box::new(on_collect));
+        }
+    }
+}
+
+impl<T> Drop for WeakHandle<T> {
+    fn drop(&mut self) {
+        let mut inner = self
+            .inner
+            .try_borrow_mut()
+            .expect("WeakHandle dropped during a collection");
+        inner.weak_handles[self.index] = None;
+        inner.free_weak_handles.push(self.index);
+    }
+}
+
+/// A key and value where the key is held weakly and the value only while
+/// the key is alive, e.g. for caches of data about objects which mustn't
+/// keep them alive.  Unlike a WeakHandle paired with a strong value, the
+/// value may refer to the key (or to the keys of other ephemerons) without
+/// keeping it alive.  Made with HandleScope::create_ephemeron, both are
+/// cleared once a collection finds nothing else referring to the key.
+#[derive(Debug)]
+pub struct Ephemeron<K, V> {
+    inner: Arc<RefCell<HeapInner>>,
+    index: usize,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<K, V> Ephemeron<K, V> {
+    fn entry(&self) -> Option<(TaggedPtr, TaggedPtr)> {
+        let inner = self.inner.borrow();
+        let (key, value) = inner.ephemerons[self.index]
+            .as_ref()
+            .unwrap()
+            .entry
+            .as_ref()?;
+        Some((key.ptr(), value.ptr()))
+    }
+
+    /// A handle to the key in `scope`, or None if it has been collected.
+    pub fn key<'a>(&self, scope: &'a HandleScope) -> Option<LocalHandle<'a, K>> {
+        let (key, _) = self.entry()?;
+        Some(LocalHandle::<K>::new(scope, key))
+    }
+
+    /// A handle to the value in `scope`, or None if the key has been
+    /// collected.
+    pub fn value<'a>(&self, scope: &'a HandleScope) -> Option<LocalHandle<'a, V>> {
+        let (_, value) = self.entry()?;
+        Some(LocalHandle::<V>::new(scope, value))
+    }
+
+    /// Whether the key hasn't been collected yet.
+    pub fn is_live(&self) -> bool {
+        self.entry().is_some()
+    }
+}
+
+impl<K, V> Drop for Ephemeron<K, V> {
+    fn drop(&mut self) {
+        let mut inner = self
+            .inner
+            .try_borrow_mut()
+            .expect("Ephemeron dropped during a collection");
+        inner.ephemerons[self.index] = None;
+    }
+}
+
+pub struct HandleScope<'heap> {
+    heap: &'heap Heap,
+    index: usize,
+    // Indices of the handles to objects this scope allocated, if it's a
+    // region, see new_region.
+    temporaries: Option<RefCell<Vec<usize>>>,
+}
+
+impl<'heap> HandleScope<'heap> {
+    pub fn new(heap: &Heap) -> HandleScope {
+        let mut inner = heap.inner.borrow_mut();
+        let index = inner.scopes.len();
+        inner.scopes.push(Some(vec![]));
+        HandleScope {
+            heap,
+            index,
+            temporaries: None,
+        }
+    }
+
+    /// A scope for temporaries, e.g. an interpreter frame's.  Objects it
+    /// allocates with take (or helpers built on it, like create and str)
+    /// which haven't escaped by the time it drops are finalized right away,
+    /// without waiting for a collection to trace them, and their memory is
+    /// given back.  Usually they're the most recent allocations, so the
+    /// collector's bump pointer just moves back over them.
+    ///
+    /// An object escapes when a handle to it is turned into a HeapHandle
+    /// (e.g. to store it in another object) or a GlobalHandle, or is copied
+    /// into any other scope with from_local.  Otherwise the scope's own
+    /// handles are assumed to be the only references.  Nothing is reclaimed
+    /// if a scope made after the region is still live when it drops.
+    pub fn new_region(heap: &Heap) -> HandleScope<'_> {
+        let mut scope = HandleScope::new(heap);
+        scope.temporaries = Some(RefCell::default());
+        scope
+    }
+
+    pub fn create_child_scope(&self) -> HandleScope<'heap> {
+        HandleScope::new(self.heap)
+    }
+
+    pub fn create_num(&self, value: f64) -> LocalHandle<f64> {
+        LocalHandle::<f64>::new(self, value.into())
+    }
+
+    /// A small integer, stored exactly rather than as an f64.  Ints convert
+    /// to f64 and order against nums numerically, but never equal or hash
+    /// like them: 1 and 1.0 are different keys.
+    pub fn create_int(&self, value: i32) -> LocalHandle<'_, i32> {
+        LocalHandle::<i32>::new(self, value.into())
+    }
+
+    pub fn create_bool(&self, value: bool) -> LocalHandle<bool> {
+        LocalHandle::<bool>::new(self, value.into())
+    }
+
+    pub fn create_null(&self) -> LocalHandle<()> {
+        LocalHandle::<()>::new(self, TaggedPtr::NULL)
+    }
+
+    /// See TaggedPtr::UNDEFINED.
+    pub fn create_undefined(&self) -> LocalHandle<'_, ()> {
+        LocalHandle::<()>::new(self, TaggedPtr::UNDEFINED)
+    }
+
+    pub fn create<T: HostObject + Default>(&self) -> Result<LocalHandle<T>, GCError> {
+        let object = with_default_state(self.heap.hash_state(), T::default);
+        let object_ptr = self.heap.emplace(object)?;
+        Ok(self.add_object(object_ptr))
+    }
+
+    /// Turns memory allocated by generated code into a host object holding
+    /// `object`, writing its header.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be Heap::HOST_OBJECT_ALLOC_SIZE bytes bump allocated
+    /// through Heap::bump_pointer, with no allocation or collection since.
+    #[cfg(feature = "jit")]
+    pub unsafe fn adopt_raw<T: HostObject>(&self, addr: *mut u8, object: T) -> LocalHandle<'_, T> {
+        let object_size = std::mem::size_of::<TraceableObject>();
+        let header = ObjectHeader::init(HeaderPtr::new(addr), object_size, ObjectType::of::<T>());
+        let object_ptr = header.as_ptr().to_object_ptr();
+        let mut inner = self.heap.inner.borrow_mut();
+        header.set_identity_hash(inner.take_identity_hash());
+        inner.init_host_object(object_ptr, object);
+        std::mem::drop(inner);
+        LocalHandle::<T>::new(self, object_ptr.into())
+    }
+
+    /// An empty Set hashing with the heap's HashAlgorithm and seed.
+    pub fn create_set<T: 'static>(&self) -> Result<LocalHandle<'_, Set<T>>, GCError> {
+        self.take(Set::with_hasher(self.heap.hash_state()))
+    }
+
+    /// An empty Map hashing with the heap's HashAlgorithm and seed.
+    pub fn create_map<K: 'static, V: 'static>(
+        &self,
+    ) -> Result<LocalHandle<'_, Map<K, V>>, GCError> {
+        self.take(Map::with_hasher(self.heap.hash_state()))
+    }
+
+    /// An empty OrderedMap hashing with the heap's HashAlgorithm and seed.
+    pub fn create_ordered_map<K: 'static, V: 'static>(
+        &self,
+    ) -> Result<LocalHandle<'_, OrderedMap<K, V>>, GCError> {
+        self.take(OrderedMap::with_hash_state(self.heap.hash_state()))
+    }
+
+    pub fn take<T: HostObject>(&self, object: T) -> Result<LocalHandle<T>, GCError> {
+        let object_ptr = self.heap.emplace(object)?;
+        Ok(self.add_object(object_ptr))
+    }
+
+    /// Like take, also storing `tag` in the object's header for
+    /// LocalHandle::user_tag, e.g. the hosted language's class ID.
+    pub fn take_tagged<T: HostObject>(
+        &self,
+        object: T,
+        tag: u32,
+    ) -> Result<LocalHandle<'_, T>, GCError> {
+        let object_ptr = self.heap.emplace(object)?;
+        object_ptr.header().set_user_tag(tag);
+        Ok(self.add_object(object_ptr))
+    }
+
+    // Should this be create_str?
+    // Could also do generically for ToOwned?
+    /// An empty WeakSet, which doesn't keep its entries alive.
+    pub fn create_weak_set<T: 'static>(&self) -> Result<LocalHandle<'_, WeakSet<T>>, GCError> {
+        let set = self.take(WeakSet::new())?;
+        // The heap prunes it after each collection, so it must only be freed
+        // by one.
+        escape(set.ptr());
+        let entries: *mut dyn WeakEntries =
+            TraceableObject::downcast_mut::<WeakSet<T>>(set.get_object_ptr().unwrap());
+        let handle = HeapHandle::new(set.ptr());
+        self.heap
+            .inner
+            .borrow_mut()
+            .weak_sets
+            .push((handle, entries));
+        Ok(set)
+    }
+
+    /// An Ephemeron holding `value` for as long as `key` is alive.
+    pub fn create_ephemeron<K, V>(
+        &self,
+        key: &LocalHandle<'_, K>,
+        value: &LocalHandle<'_, V>,
+    ) -> Ephemeron<K, V> {
+        let (key, value) = (key.ptr(), value.ptr());
+        // Region scopes mustn't free either out from under the Ephemeron.
+        escape(key);
+        escape(value);
+        let index = {
+            let mut inner = self.heap.inner.borrow_mut();
+            inner.ephemerons.push(Some(EphemeronTarget {
+                entry: Some((HeapHandle::new(key), HeapHandle::new(value))),
+            }));
+            inner.ephemerons.len() - 1
+        };
+        Ephemeron {
+            inner: Arc::clone(&self.heap.inner),
+            index,
+            _phantom: PhantomData,
+        }
+    }
+
+    // fn from_unowned<T, S>(...) where T: ToOwned<S>, S : HostObject {...}
+    pub fn str(&self, object: &str) -> Result<LocalHandle<String>, GCError> {
+        self.take(object.to_string())
+    }
+
+    /// `text` as a small string immediate if it fits (see
+    /// TaggedPtr::small_str), otherwise as a String object.  Read either
+    /// with LocalHandle::with_str.
+    pub fn str_value(&self, text: &str) -> Result<LocalHandle<'_, ()>, GCError> {
+        match TaggedPtr::small_str(text) {
+            Some(ptr) => Ok(LocalHandle::<()>::new(self, ptr)),
+            None => Ok(self.str(text)?.erase_type()),
+        }
+    }
+
+    /// A SharedStr of `text`, sharing bytes with any other live SharedStr
+    /// made from equal text.
+    pub fn shared_str(&self, text: &str) -> Result<LocalHandle<'_, SharedStr>, GCError> {
+        let payload = self.heap.inner.borrow_mut().payloads.get_or_insert(text);
+        self.take(SharedStr::new(payload))
+    }
+
+    /// A SharedStr of `range` within `text`, sharing its bytes.
+    pub fn substring(
+        &self,
+        text: &LocalHandle<'_, SharedStr>,
+        range: std::ops::Range<usize>,
+    ) -> Result<LocalHandle<'_, SharedStr>, GCError> {
+        let substring = text.as_ref().substring(range);
+        self.take(substring)
+    }
+
+    /// A ByteArray of `len` zeroes.
+    pub fn byte_array(&self, len: usize) -> Result<LocalHandle<'_, ByteArray>, GCError> {
+        let object_ptr = self.heap.emplace_bytes::<ByteArray>(len)?;
+        Ok(self.add_object(object_ptr))
+    }
+
+    /// A GcString holding a copy of `text`.
+    pub fn gc_str(&self, text: &str) -> Result<LocalHandle<'_, GcString>, GCError> {
+        let object_ptr = self.heap.emplace_bytes::<GcString>(text.len())?;
+        let payload = TraceableObject::downcast_mut::<GcString>(object_ptr);
+        byte_array::bytes_of_mut(unsafe { &mut *payload }).copy_from_slice(text.as_bytes());
+        Ok(self.add_object(object_ptr))
+    }
+
+    /// A ByteArray holding a copy of `bytes`.
+    pub fn byte_array_from(&self, bytes: &[u8]) -> Result<LocalHandle<'_, ByteArray>, GCError> {
+        let array = self.byte_array(bytes.len())?;
+        array.as_mut().copy_from(0, bytes);
+        Ok(array)
+    }
+
+    /// A view of `range` within `array`, sharing its elements.  Panics if
+    /// `range` is out of bounds.
+    pub fn array_view<E: ArrayElement>(
+        &self,
+        array: &LocalHandle<'_, TypedArray<E>>,
+        range: std::ops::Range<usize>,
+    ) -> Result<LocalHandle<'_, TypedArrayView<E>>, GCError> {
+        let view = TypedArrayView::new(array.clone().into(), range);
+        self.take(view)
+    }
+
+    /// Returns the heap's single String for `text`, allocating it on first
+    /// use.  Interned strings are only kept alive by other references, so
+    /// the table doesn't grow with text nothing holds on to.  Interned
+    /// strings must not be mutated.
+    pub fn intern(&self, text: &str) -> Result<LocalHandle<'_, String>, GCError> {
+        let existing = self.heap.inner.borrow().interned.get(text);
+        if let Some(handle) = existing {
+            return Ok(self.from_heap(&handle));
+        }
+        let handle = self.str(text)?;
+        let canonical = handle.clone().into();
+        self.heap
+            .inner
+            .borrow_mut()
+            .interned
+            .insert(text, canonical);
+        Ok(handle)
+    }
+
+    /// Returns the heap's single Symbol named `name`, allocating it on first
+    /// use.  Like interned strings, symbols nothing else refers to are
+    /// collected, so a later call may make a new one.
+    pub fn symbol(&self, name: &str) -> Result<LocalHandle<'_, Symbol>, GCError> {
+        let existing = self.heap.inner.borrow().symbols.get(name);
+        if let Some(handle) = existing {
+            return Ok(self.from_heap(&handle));
+        }
+        let handle = self.take(Symbol::new(name))?;
+        let canonical = handle.clone().into();
+        self.heap.inner.borrow_mut().symbols.insert(name, canonical);
+        Ok(handle)
+    }
+
+    /// Moves each item into the heap and appends it to a new list.  If an
+    /// allocation fails part way, the items already moved stay rooted by the
+    /// list (which is rooted by this scope) and the error is returned.
+    pub fn take_iter<T: HostObject>(
+        &self,
+        iter: impl IntoIterator<Item = T>,
+    ) -> Result<LocalHandle<'_, List<T>>, GCError> {
+        let list = self.create::<List<T>>()?;
+        for item in iter {
+            let object_ptr = self.heap.emplace(item)?;
+            list.as_mut().push(HeapHandle::new(object_ptr.into()));
+        }
+        Ok(list)
+    }
+
+    /// Allocates a new string holding `a` followed by `b`.
+    pub fn concat(
+        &self,
+        a: LocalHandle<String>,
+        b: LocalHandle<String>,
+    ) -> Result<LocalHandle<'_, String>, GCError> {
+        self.concat_all(&[a, b])
+    }
+
+    /// Allocates a new string joining all of `parts`.  The bytes are copied
+    /// out before allocating, so a collection during the allocation can't
+    /// leave us reading from moved operands.
+    pub fn concat_all(
+        &self,
+        parts: &[LocalHandle<String>],
+    ) -> Result<LocalHandle<'_, String>, GCError> {
+        let mut result = String::with_capacity(parts.iter().map(|part| part.as_ref().len()).sum());
+        for part in parts {
+            result.push_str(part.as_ref());
+        }
+        self.take(result)
+    }
+
+    /// A Rope holding a copy of `text`.
+    pub fn rope(&self, text: &str) -> Result<LocalHandle<'_, Rope>, GCError> {
+        self.take(Rope::leaf(text.to_string()))
+    }
+
+    /// Allocates a new rope of `left` followed by `right`, sharing rather
+    /// than copying their text.
+    pub fn rope_concat(
+        &self,
+        left: &LocalHandle<'_, Rope>,
+        right: &LocalHandle<'_, Rope>,
+    ) -> Result<LocalHandle<'_, Rope>, GCError> {
+        self.take(Rope::concat(left.clone().into(), right.clone().into()))
+    }
+
+    pub fn list_from_f64s(&self, values: &[f64]) -> Result<LocalHandle<'_, List<f64>>, GCError> {
+        let handles: Vec<HeapHandle<f64>> = values.iter().map(|value| (*value).into()).collect();
+        self.take(List::from(handles))
+    }
+
+    /// Allocates the list and all of its strings under a single borrow of
+    /// the heap, collecting at most once up front.
+    pub fn list_from_strs(
+        &self,
+        values: &[&str],
+    ) -> Result<LocalHandle<'_, List<String>>, GCError> {
+        self.heap.reserve(bulk_alloc_size(values.len() + 1)?)?;
+        let list_ptr = {
+            let mut inner = self.heap.inner.borrow_mut();
+            let list_ptr = inner.alloc_host_object(ObjectType::of::<List<String>>())?;
+            let mut list = List::<String>::default();
+            for value in values {
+                list.push(inner.alloc_string(value)?);
+            }
+            inner.init_host_object(list_ptr, list);
+            list_ptr
+        };
+        Ok(LocalHandle::new(self, list_ptr.into()))
+    }
+
+    /// Like list_from_strs, builds a map of strings in one pass.
+    pub fn map_from_pairs(
+        &self,
+        pairs: &[(&str, &str)],
+    ) -> Result<LocalHandle<'_, Map<String, String>>, GCError> {
+        self.heap.reserve(bulk_alloc_size(2 * pairs.len() + 1)?)?;
+        let map_ptr = {
+            let mut inner = self.heap.inner.borrow_mut();
+            let map_ptr = inner.alloc_host_object(ObjectType::of::<Map<String, String>>())?;
+            let mut entries = Vec::with_capacity(pairs.len());
+            for (key, value) in pairs {
+                entries.push((inner.alloc_string(key)?, inner.alloc_string(value)?));
+            }
+            let mut map: Box<Map<String, String>> = Box::new(Map::with_hasher(inner.hash_state));
+            map.extend(entries);
+            inner.init_host_object(map_ptr, *map);
+            map_ptr
+        };
+        Ok(LocalHandle::new(self, map_ptr.into()))
+    }
+
+    // Roots a newly allocated object, noting it as a temporary in a region.
+    fn add_object<T>(&self, object_ptr: ObjectPtr) -> LocalHandle<'_, T> {
+        let handle = LocalHandle::<T>::new(self, object_ptr.into());
+        if let Some(temporaries) = &self.temporaries {
+            temporaries.borrow_mut().push(handle.index);
+        }
+        handle
+    }
+
+    fn add(&self, ptr: TaggedPtr) -> usize {
+        let index = {
+            let mut inner = self.heap.inner.borrow_mut();
+            if let Some(sealed) = inner.sealed.last() {
+                assert!(
+                    self.index > *sealed,
+                    "handle created in a scope sealed by SealedHandleScope"
+                );
+            }
+            let cells = inner.scopes[self.index].as_mut().unwrap();
+            cells.push(HeapHandle::new(ptr));
+            cells.len() - 1
+        };
+        // Once rooted, so only the caller's unrooted objects are lost.
+        // Making a handle can't fail, so a finalizer's panic is ignored.
+        let _ = self.heap.stress();
+        index
+    }
+
+    pub fn from_global<T>(&self, handle: &GlobalHandle<T>) -> LocalHandle<T> {
+        LocalHandle::<T>::new(self, handle.ptr())
+    }
+
+    pub fn from_heap<T>(&self, handle: &HeapHandle<T>) -> LocalHandle<T> {
+        LocalHandle::<T>::new(self, handle.ptr())
+    }
+
+    pub fn from_local<T>(&self, handle: &LocalHandle<'_, T>) -> LocalHandle<T> {
+        let ptr = handle.ptr();
+        // Scopes may be dropped in any order, so a copy in any other scope
+        // may outlive the one it came from.
+        if self.index != handle.scope.index {
+            escape(ptr);
+        }
+        LocalHandle::<T>::new(self, ptr)
+    }
+
+    pub fn from_maybe_heap<T>(
+        &self,
+        maybe_handle: &Option<HeapHandle<T>>,
+    ) -> Option<LocalHandle<T>> {
+        maybe_handle
+            .clone()
+            .map(|handle| LocalHandle::<T>::new(self, handle.ptr()))
+    }
+
+    pub fn as_ref<T: HostObject>(&self, handle: &GlobalHandle<T>) -> &T {
+        let local = self.from_global(handle);
+        local.as_ref()
+    }
+
+    pub fn as_mut<T: HostObject>(&self, handle: &GlobalHandle<T>) -> ObjectMut<'_, T> {
+        let local = self.from_global(handle);
+        local.as_mut()
+    }
+
+    /// Moves `object` into the heap behind a UniqueHandle, see
+    /// HeapConfig::eager_reclamation.
+    pub fn take_unique<T: HostObject>(&self, object: T) -> Result<UniqueHandle<'_, T>, GCError> {
+        let local = self.take(object)?;
+        Ok(UniqueHandle {
+            payload: local.borrow_mut(),
+            local: Some(local),
+        })
+    }
+
+    fn get_ptr(&self, index: usize) -> TaggedPtr {
+        let inner = self.heap.inner.borrow();
+        inner.scopes[self.index].as_ref().unwrap()[index].ptr()
+    }
+
+    fn set_ptr(&self, index: usize, ptr: TaggedPtr) {
+        let mut inner = self.heap.inner.borrow_mut();
+        inner.scopes[self.index].as_mut().unwrap()[index] = HeapHandle::new(ptr);
+    }
+
+    fn clear_ptr(&self, index: usize) {
+        let mut inner = self.heap.inner.borrow_mut();
+        inner.scopes[self.index].as_mut().unwrap()[index] = HeapHandle::default();
+    }
+}
+
+/// A handle to an object which nothing else refers to.  It can't be stored
+/// into other objects without first calling share(), so when it drops the
+/// object is known to be dead and (with HeapConfig::eager_reclamation) is
+/// finalized immediately.  If it was the most recent allocation its memory
+/// is reused as well.
+pub struct UniqueHandle<'a, T: HostObject> {
+    local: Option<LocalHandle<'a, T>>,
+    // Held for as long as the handle, as anything may be stored through
+    // deref_mut.
+    payload: ObjectMut<'a, T>,
+}
+
+impl<'a, T: HostObject> UniqueHandle<'a, T> {
+    /// Gives up uniqueness, leaving the object to the tracing collector.
+    pub fn share(mut self) -> LocalHandle<'a, T> {
+        self.local.take().unwrap()
+    }
+}
+
+impl<'a, T: HostObject> std::ops::Deref for UniqueHandle<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.payload
+    }
+}
+
+impl<'a, T: HostObject> std::ops::DerefMut for UniqueHandle<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.payload
+    }
+}
+
+impl<'a, T: HostObject> Drop for UniqueHandle<'a, T> {
+    fn drop(&mut self) {
+        if let Some(local) = self.local.take() {
+            let object_ptr = local.get_object_ptr().unwrap();
+            local.scope.clear_ptr(local.index);
+            local.scope.heap.reclaim_unique(object_ptr);
+        }
+    }
+}
+
+/// A child scope which can hand one value back to its parent, e.g. the
+/// result of a function whose temporaries die with the child.  The parent's
+/// handle is reserved up front, like V8's EscapableHandleScope.
+pub struct EscapableHandleScope<'a> {
+    scope: HandleScope<'a>,
+    parent: &'a HandleScope<'a>,
+    // The reserved handle in the parent.
+    slot: usize,
+    escaped: Cell<bool>,
+}
+
+impl<'a> EscapableHandleScope<'a> {
+    pub fn new(parent: &'a HandleScope<'a>) -> EscapableHandleScope<'a> {
+        let slot = parent.add(TaggedPtr::NULL);
+        EscapableHandleScope {
+            scope: parent.create_child_scope(),
+            parent,
+            slot,
+            escaped: Cell::new(false),
+        }
+    }
+
+    /// Returns a handle to `handle`'s value in the parent scope.  Panics if
+    /// called more than once.
+    pub fn escape<T>(&self, handle: &LocalHandle<'_, T>) -> LocalHandle<'a, T> {
+        assert!(!self.escaped.replace(true), "escape called twice");
+        let ptr = handle.ptr();
+        escape(ptr);
+        self.parent.set_ptr(self.slot, ptr);
+        LocalHandle {
+            scope: self.parent,
+            index: self.slot,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a> std::ops::Deref for EscapableHandleScope<'a> {
+    type Target = HandleScope<'a>;
+
+    fn deref(&self) -> &HandleScope<'a> {
+        &self.scope
+    }
+}
+
+/// Panics if a handle is created in any enclosing scope while this is alive,
+/// to check that a performance critical or GC sensitive region doesn't make
+/// handles behind its back.  Scopes opened inside it work as normal.
+pub struct SealedHandleScope<'heap> {
+    heap: &'heap Heap,
+    index: usize,
+}
+
+impl<'heap> SealedHandleScope<'heap> {
+    pub fn new(heap: &Heap) -> SealedHandleScope<'_> {
+        let mut inner = heap.inner.borrow_mut();
+        let index = inner.scopes.len();
+        inner.scopes.push(Some(vec![]));
+        inner.sealed.push(index);
+        SealedHandleScope { heap, index }
+    }
+}
+
+impl<'heap> Drop for SealedHandleScope<'heap> {
+    fn drop(&mut self) {
+        let mut inner = self.heap.inner.borrow_mut();
+        inner.sealed.retain(|sealed| *sealed != self.index);
+        inner.remove_scope(self.index);
+    }
+}
+
+fn bulk_alloc_size(object_count: usize) -> Result<usize, GCError> {
+    HOST_OBJECT_ALLOC_SIZE
+        .checked_mul(object_count)
+        .ok_or(GCError::NoSpace)
+}
+
+// Marks `ptr`'s object as possibly referenced from outside its scope, see
+// HandleScope::new_region.  Mid collection both copies are marked, as
+// either may be the one which survives.
+fn escape(ptr: TaggedPtr) {
+    if let Ok(object_ptr) = TryInto::<ObjectPtr>::try_into(ptr) {
+        object_ptr.header().mark_escaped();
+        forwarded(object_ptr).header().mark_escaped();
+    }
+}
+
+impl<'heap> Drop for HandleScope<'heap> {
+    fn drop(&mut self) {
+        let (cells, outlived) = {
+            let mut inner = self.heap.inner.borrow_mut();
+            let cells = inner.remove_scope(self.index);
+            let outlived = inner.scopes.iter().skip(self.index).any(Option::is_some);
+            (cells, outlived)
+        };
+        // While unwinding, escaping objects may not have been marked yet.
+        // Scopes made since and dropped out of order may still refer to the
+        // temporaries some way escape doesn't see, so leave them to the
+        // collector.
+        if let Some(temporaries) = self.temporaries.take() {
+            if std::thread::panicking() || outlived {
+                return;
+            }
+            let doomed = temporaries
+                .into_inner()
+                .iter()
+                .rev()
+                .filter_map(|index| cells[*index].ptr().try_into().ok())
+                .filter(|object_ptr: &ObjectPtr| !object_ptr.header().is_escaped())
+                .collect();
+            self.heap.reclaim(doomed);
+        }
+    }
+}
+
+#[derive(Copy)]
+pub struct LocalHandle<'a, T> {
+    scope: &'a HandleScope<'a>,
+    index: usize,
+    phantom: PhantomData<T>,
+}
+
+// Derive Clone requires T to be Cloneable, which isn't required for Handles.
+impl<'a, T> Clone for LocalHandle<'a, T> {
+    fn clone(&self) -> Self {
+        LocalHandle {
+            scope: self.scope,
+            index: self.index,
+            phantom: PhantomData::<T>::default(),
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.scope = source.scope;
+        self.index = source.index;
+    }
+}
+
+impl<'a, T> LocalHandle<'a, T> {
+    fn new(scope: &'a HandleScope, ptr: TaggedPtr) -> Self {
+        Self {
+            scope: scope,
+            index: scope.add(ptr),
+            phantom: PhantomData::<T>::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn ptr_for_test(&self) -> TaggedPtr {
+        self.ptr()
+    }
+
+    fn ptr(&self) -> TaggedPtr {
+        self.scope.get_ptr(self.index)
+    }
+
+    fn get_object_ptr(&self) -> Option<ObjectPtr> {
+        self.ptr().try_into().ok().map(read_barrier)
+    }
+
+    /// Whether both handles refer to the same object, without comparing
+    /// contents.  Values which aren't objects are compared by value.
+    pub fn ptr_eq<S>(&self, other: &LocalHandle<'_, S>) -> bool {
+        match (self.get_object_ptr(), other.get_object_ptr()) {
+            (Some(lhs), Some(rhs)) => lhs.addr() == rhs.addr(),
+            (None, None) => self.ptr() == other.ptr(),
+            _ => false,
+        }
+    }
+
+    /// Names the type of the value for use in error messages, e.g. "num" or
+    /// the Rust type name of a host object.
+    pub fn type_name(&self) -> &'static str {
+        self.ptr().type_name()
+    }
+
+    /// The tag given to HandleScope::take_tagged, 0 for objects allocated
+    /// otherwise and None for values which aren't objects.  Reading it needs
+    /// no downcast, so VMs can dispatch on it with a switch.
+    pub fn user_tag(&self) -> Option<u32> {
+        self.ptr().header().map(|header| header.user_tag())
+    }
+
+    /// Copies the value out into a Rust value, e.g.
+    /// `let values: Vec<f64> = args.try_from_heap(&scope)?`.
+    pub fn try_from_heap<R: TryFromHeap>(&self, scope: &HandleScope) -> Result<R, GCError> {
+        R::try_from_heap(scope, &HeapHandle::new(self.ptr()))
+    }
+
+    /// Orders this value against `rhs`, see TaggedPtr::compare.
+    pub fn compare<S>(&self, rhs: &LocalHandle<'_, S>) -> Option<std::cmp::Ordering> {
+        self.ptr().compare(&rhs.ptr())
+    }
+
+    /// Renders the value as human readable text, e.g. for a hosted
+    /// language's print.  Nested containers are cut off at cycles and at a
+    /// fixed depth.
+    pub fn display(&self, scope: &HandleScope) -> String {
+        let mut formatter = ValueFormatter::new(scope);
+        formatter.write_ptr(self.ptr()).unwrap();
+        formatter.finish()
+    }
+
+    /// A WeakHandle to the value, which doesn't keep it alive.
+    pub fn downgrade(&self) -> WeakHandle<T> {
+        let ptr = self.ptr();
+        // Region scopes mustn't free it out from under the WeakHandle.
+        escape(ptr);
+        let heap = self.scope.heap;
+        let index = {
+            let mut inner = heap.inner.borrow_mut();
+            let target = Some(WeakTarget {
+                object: Some(HeapHandle::new(ptr)),
+                on_collect: None,
+            });
+            match inner.free_weak_handles.pop() {
+                Some(index) => {
+                    inner.weak_handles[index] = target;
+                    index
+                }
+                None => {
+                    inner.weak_handles.push(target);
+                    inner.weak_handles.len() - 1
+                }
+            }
+        };
+        WeakHandle {
+            inner: Arc::clone(&heap.inner),
+            index,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn erase_type(&self) -> LocalHandle<'a, ()> {
+        LocalHandle {
+            scope: self.scope,
+            index: self.index,
+            phantom: PhantomData::<()>::default(),
+        }
+    }
+}
+
+impl<'a> LocalHandle<'a, ()> {
+    pub fn is_null(&self) -> bool {
+        self.ptr().is_null()
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        self.ptr().is_undefined()
+    }
+
+    pub fn kind(&self) -> ValueKind {
+        self.ptr().kind()
+    }
+
+    pub fn is_bool(&self) -> bool {
+        self.ptr().is_bool()
+    }
+
+    pub fn is_num(&self) -> bool {
+        self.ptr().is_num()
+    }
+
+    pub fn is_int(&self) -> bool {
+        self.ptr().is_int()
+    }
+
+    /// Calls `f` with the text of a small string, String or SharedStr.
+    pub fn with_str<R>(&self, f: impl FnOnce(&str) -> R) -> Option<R> {
+        self.ptr().with_str(f)
+    }
+
+    pub fn try_as_ref<S: HostObject>(&self) -> Option<&'a S> {
+        if let Some(object_ptr) = self.get_object_ptr() {
+            if object_ptr.is_type(ObjectType::of::<S>()) {
+                if let Some(ptr) = TraceableObject::try_downcast::<S>(object_ptr) {
+                    return Some(unsafe { &*ptr });
+                }
+            }
+        }
+        None
+    }
+
+    pub fn try_as_mut<S: HostObject>(&self) -> Option<ObjectMut<'a, S>> {
+        let object_ptr = self.get_object_ptr()?;
+        if !read_barrier(object_ptr).is_type(ObjectType::of::<S>()) {
+            return None;
+        }
+        Some(ObjectMut::new(object_ptr))
+    }
+
+    pub fn is_of_type<S: HostObject>(&self) -> bool {
+        let maybe_ref: Option<&S> = self.try_as_ref();
+        maybe_ref.is_some()
+    }
+}
+
+pub trait DowncastTo<T> {
+    fn try_downcast(self) -> Option<T>;
+}
+
+impl<'a, T: HostObject> DowncastTo<LocalHandle<'a, T>> for LocalHandle<'a, ()> {
+    fn try_downcast(self) -> Option<LocalHandle<'a, T>> {
+        if let Some(object_ptr) = self.get_object_ptr() {
+            if object_ptr.is_type(ObjectType::of::<T>()) {
+                let ptr = TraceableObject::try_downcast::<T>(object_ptr);
+                if ptr.is_some() {
+                    return Some(LocalHandle {
+                        scope: self.scope,
+                        index: self.index,
+                        phantom: PhantomData::<T>::default(),
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+impl GlobalHandle<()> {
+    /// Whether the global holds a host object of type S, e.g. to check
+    /// before try_downcast, which drops the global if it fails.
+    pub fn is_of_type<S: HostObject>(&self) -> bool {
+        let object_ptr: Option<ObjectPtr> = self.ptr().try_into().ok();
+        object_ptr.is_some_and(|object_ptr| {
+            object_ptr.is_type(ObjectType::of::<S>())
+                && TraceableObject::try_downcast::<S>(object_ptr).is_some()
+        })
+    }
+}
+
+/// Re-types an untyped global without a scope.  The global's slot is kept.
+impl<T: HostObject> DowncastTo<GlobalHandle<T>> for GlobalHandle<()> {
+    fn try_downcast(self) -> Option<GlobalHandle<T>> {
+        if !self.is_of_type::<T>() {
+            return None;
+        }
+        Some(GlobalHandle {
+            root: self.root,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+impl<'a> DowncastTo<LocalHandle<'a, f64>> for LocalHandle<'a, ()> {
+    fn try_downcast(self) -> Option<LocalHandle<'a, f64>> {
+        self.try_into()
+            .ok()
+            .map(|value| self.scope.create_num(value))
+    }
+}
+
+impl<'a> DowncastTo<LocalHandle<'a, i32>> for LocalHandle<'a, ()> {
+    fn try_downcast(self) -> Option<LocalHandle<'a, i32>> {
+        if !self.is_int() {
+            return None;
+        }
+        self.try_into()
+            .ok()
+            .map(|value| self.scope.create_int(value))
+    }
+}
+
+impl<'a> DowncastTo<LocalHandle<'a, bool>> for LocalHandle<'a, ()> {
+    fn try_downcast(self) -> Option<LocalHandle<'a, bool>> {
+        self.try_into()
+            .ok()
+            .map(|value| self.scope.create_bool(value))
+    }
+}
+
+impl<'a, T: HostObject> LocalHandle<'a, T> {
+    pub fn borrow(&self) -> &'a T {
+        let object_ptr = self.get_object_ptr().unwrap();
+        let ptr = TraceableObject::downcast::<T>(object_ptr);
+        unsafe { &*ptr }
+    }
+
+    pub fn borrow_mut(&self) -> ObjectMut<'a, T> {
+        ObjectMut::new(self.get_object_ptr().unwrap())
+    }
+
+    // Old names:
+    pub fn as_ref(&self) -> &'a T {
+        self.borrow()
+    }
+
+    pub fn as_mut(&self) -> ObjectMut<'a, T> {
+        self.borrow_mut()
+    }
+}
+
+// Operations calling back into the embedder, which may allocate and so move
+// the list.  They work on rooted copies of the elements rather than holding
+// a reference into the list across the calls.
+impl<'a, T: 'static> LocalHandle<'a, List<T>> {
+    /// Keeps only the elements `keep` returns true for, in order.
+    pub fn retain(&self, mut keep: impl FnMut(&LocalHandle<'a, T>) -> bool) {
+        let mut elements = self.local_elements();
+        elements.retain(|element| keep(element));
+        self.set_elements(elements);
+    }
+
+    /// Sorts the list with `compare`, keeping equal elements in order.
+    pub fn sort_by(
+        &self,
+        mut compare: impl FnMut(&LocalHandle<'a, T>, &LocalHandle<'a, T>) -> Ordering,
+    ) {
+        let mut elements = self.local_elements();
+        elements.sort_by(|a, b| compare(a, b));
+        self.set_elements(elements);
+    }
+
+    fn local_elements(&self) -> Vec<LocalHandle<'a, T>> {
+        let list = self.as_ref();
+        list.iter()
+            .map(|element| self.scope.from_heap(element))
+            .collect()
+    }
+
+    fn set_elements(&self, elements: Vec<LocalHandle<'a, T>>) {
+        *self.as_mut() = List::from(elements);
+    }
+}
+
+// Map operations taking and returning LocalHandles, rooting what they hand
+// out in the map's scope.  The iterators yield a snapshot, so the embedder
+// may allocate (or change the map) while iterating.  Only insert_local
+// stores its key, lookups don't escape theirs from a region scope.
+impl<'a, K: 'static, V: 'static> LocalHandle<'a, Map<K, V>>
+where
+    HeapHandle<K>: Eq,
+{
+    pub fn get_local(&self, key: &LocalHandle<'_, K>) -> Option<LocalHandle<'a, V>> {
+        let value = self.as_ref().get(&HeapHandle::new(key.ptr()));
+        value.map(|value| self.scope.from_heap(value))
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert_local(
+        &self,
+        key: &LocalHandle<'_, K>,
+        value: &LocalHandle<'_, V>,
+    ) -> Option<LocalHandle<'a, V>> {
+        let old = self
+            .as_mut()
+            .insert(key.clone().into(), value.clone().into());
+        old.map(|old| self.scope.from_heap(&old))
+    }
+
+    pub fn remove(&self, key: &LocalHandle<'_, K>) -> Option<LocalHandle<'a, V>> {
+        let value = self.as_mut().remove(&HeapHandle::new(key.ptr()));
+        value.map(|value| self.scope.from_heap(&value))
+    }
+
+    pub fn contains_key(&self, key: &LocalHandle<'_, K>) -> bool {
+        self.as_ref().contains_key(&HeapHandle::new(key.ptr()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_ref().is_empty()
+    }
+
+    /// The entries in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = (LocalHandle<'a, K>, LocalHandle<'a, V>)> {
+        let scope = self.scope;
+        let entries: Vec<_> = self
+            .as_ref()
+            .iter()
+            .map(|(key, value)| (scope.from_heap(key), scope.from_heap(value)))
+            .collect();
+        entries.into_iter()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = LocalHandle<'a, K>> {
+        self.entries().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = LocalHandle<'a, V>> {
+        self.entries().map(|(_, value)| value)
+    }
+}
+
+impl<'a> TryInto<f64> for LocalHandle<'a, ()> {
+    type Error = GCError;
+    fn try_into(self) -> Result<f64, GCError> {
+        self.ptr().try_into()
+    }
+}
+
+impl<'a> Into<f64> for LocalHandle<'a, f64> {
+    fn into(self) -> f64 {
+        self.ptr().try_into().unwrap()
+    }
+}
+
+macro_rules! impl_local_try_into_integer {
+    ($($int:ty),*) => {
+        $(
+            impl<'a> TryInto<$int> for LocalHandle<'a, ()> {
+                type Error = GCError;
+                fn try_into(self) -> Result<$int, GCError> {
+                    self.ptr().try_into()
+                }
+            }
+
+            impl<'a> TryInto<$int> for LocalHandle<'a, f64> {
+                type Error = GCError;
+                fn try_into(self) -> Result<$int, GCError> {
+                    self.ptr().try_into()
+                }
+            }
+        )*
+    };
+}
+
+impl_local_try_into_integer!(i32, i64, u32, usize);
+
+impl<'a> From<LocalHandle<'a, i32>> for i32 {
+    fn from(handle: LocalHandle<'a, i32>) -> i32 {
+        handle.ptr().try_into().unwrap()
+    }
+}
+
+impl<'a> TryInto<bool> for LocalHandle<'a, ()> {
+    type Error = GCError;
+    fn try_into(self) -> Result<bool, GCError> {
+        self.ptr().try_into()
+    }
+}
+
+impl<'a> Into<bool> for LocalHandle<'a, bool> {
+    fn into(self) -> bool {
+        self.ptr().try_into().unwrap()
+    }
+}
+
+impl<'a, T> From<LocalHandle<'a, T>> for HeapHandle<T> {
+    fn from(handle: LocalHandle<'a, T>) -> Self {
+        let ptr = handle.ptr();
+        escape(ptr);
+        HeapHandle::<T>::new(ptr)
+    }
+}
+
+impl<'a, T> From<LocalHandle<'a, T>> for GlobalHandle<T> {
+    fn from(handle: LocalHandle<'a, T>) -> Self {
+        let ptr = handle.ptr();
+        escape(ptr);
+        handle.scope.heap.root_global(ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::convert::TryInto;
+    use std::hash::{BuildHasher, Hash, Hasher};
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct DropObject {
+        counter: Rc<Cell<u32>>,
+    }
+
+    impl HostObject for DropObject {}
+
+    impl Traceable for DropObject {
+        fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+    }
+
+    impl Drop for DropObject {
+        fn drop(&mut self) {
+            let counter = self.counter.get();
+            self.counter.set(counter + 1);
+        }
+    }
+
+    impl Hash for DropObject {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            (self as *const DropObject as usize).hash(state);
+        }
+    }
+
+    #[test]
+    pub fn smoke_test() {
+        let heap = Heap::new(1000).unwrap();
+        assert_eq!(heap.used_bytes(), 0);
+        let two: GlobalHandle<DropObject> = {
+            let scope = HandleScope::new(&heap);
+            let one = scope.create::<DropObject>().unwrap();
+            let two = scope.create::<DropObject>().unwrap();
+            std::mem::drop(one);
+            two.into()
         };
         let used_before_collection = heap.used_bytes();
         heap.collect().unwrap();
-        let used_after_collection = heap.used_bytes();
-        assert!(0 < used_after_collection);
-        assert!(used_before_collection > used_after_collection);
-        std::mem::drop(two);
+        let used_after_collection = heap.used_bytes();
+        assert!(0 < used_after_collection);
+        assert!(used_before_collection > used_after_collection);
+        std::mem::drop(two);
+        heap.collect().unwrap();
+        assert_eq!(0, heap.used_bytes());
+    }
+
+    #[test]
+    fn finalizer_test() {
+        let heap = Heap::new(1000).unwrap();
+        let counter = Rc::new(Cell::new(0));
+        let scope = HandleScope::new(&heap);
+
+        let handle = scope.create::<DropObject>().unwrap();
+        handle.as_mut().counter = Rc::clone(&counter);
+        std::mem::drop(handle);
+        assert_eq!(0u32, counter.get());
+        std::mem::drop(scope);
+        assert_eq!(0u32, counter.get());
+        heap.collect().ok();
+        assert_eq!(1u32, counter.get());
+    }
+
+    #[test]
+    fn deferred_finalization_test() {
+        let heap = Heap::with_config(HeapConfig {
+            deferred_finalization: true,
+            ..HeapConfig::fixed(1000)
+        })
+        .unwrap();
+        let counter = Rc::new(Cell::new(0));
+        let events = heap.subscribe_events();
+        {
+            let scope = HandleScope::new(&heap);
+            let handle = scope.create::<DropObject>().unwrap();
+            handle.as_mut().counter = Rc::clone(&counter);
+        }
+        heap.collect().unwrap();
+        {
+            // Reclaimed when the scope drops, but still queued.
+            let region = HandleScope::new_region(&heap);
+            let handle = region.create::<DropObject>().unwrap();
+            handle.as_mut().counter = Rc::clone(&counter);
+        }
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), 0);
+        assert_eq!(counter.get(), 0);
+        assert_eq!(heap.pending_finalizers(), 2);
+
+        assert_eq!(heap.run_finalizers().unwrap(), 2);
+        assert_eq!(counter.get(), 2);
+        assert_eq!(heap.pending_finalizers(), 0);
+        assert_eq!(heap.run_finalizers().unwrap(), 0);
+        let finalizers_run: Vec<GcEvent> = events
+            .try_iter()
+            .filter(|event| matches!(event, GcEvent::FinalizersRun { .. }))
+            .collect();
+        assert_eq!(finalizers_run, [GcEvent::FinalizersRun { count: 2 }]);
+    }
+
+    #[test]
+    fn tracing_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let handle = scope.create::<List<DropObject>>().unwrap();
+
+        let mut list = handle.as_mut();
+        list.push(scope.create::<DropObject>().unwrap().into());
+        list.push(scope.create::<DropObject>().unwrap().into());
+        list.push(scope.create::<DropObject>().unwrap().into());
+        std::mem::drop(list);
+
+        let used = heap.used_bytes();
+        heap.collect().ok();
+        assert_eq!(used, heap.used_bytes());
+        std::mem::drop(handle);
+        heap.collect().ok();
+        assert_eq!(used, heap.used_bytes());
+        std::mem::drop(scope);
+        heap.collect().ok();
+        assert_eq!(0, heap.used_bytes());
+    }
+
+    #[test]
+    fn tagged_num_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+
+        let a = scope.create_num(1.0);
+        let b = scope.create_num(2.0);
+        assert_eq!(0, heap.used_bytes());
+        let a_value: f64 = a.ptr().try_into().unwrap();
+        assert_eq!(1.0, a_value);
+        let b_value: f64 = b.ptr().try_into().unwrap();
+        assert_eq!(2.0, b_value);
+    }
+
+    #[test]
+    fn add_f64_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let one = scope.create_num(1.0);
+        let two = scope.create_num(2.0);
+        let one_value: f64 = one.try_into().unwrap();
+        assert_eq!(1.0, one_value);
+        let two_value: f64 = two.try_into().unwrap();
+        assert_eq!(2.0, two_value);
+        let three_value = one_value + two_value;
+        let three = scope.create_num(three_value);
+        let three_global = GlobalHandle::from(three);
+        std::mem::drop(scope);
+
+        let scope = HandleScope::new(&heap);
+        let three = scope.from_global(&three_global);
+        let three_value: f64 = three.try_into().unwrap();
+        assert_eq!(3.0, three_value);
+    }
+
+    #[test]
+    fn alloc_global_test() {
+        let heap = Heap::new(1000).unwrap();
+        let global = heap.alloc_global(Box::new("global".to_string())).unwrap();
+        heap.collect().unwrap();
+        {
+            let scope = HandleScope::new(&heap);
+            assert_eq!(scope.as_ref(&global), "global");
+        }
+        std::mem::drop(global);
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), 0);
+    }
+
+    #[test]
+    fn member_from_global_test() {
+        let heap = Heap::new(1000).unwrap();
+        let global = heap.alloc_global(Box::new("member".to_string())).unwrap();
+        let scope = HandleScope::new(&heap);
+        let holder = scope.create::<List<()>>().unwrap();
+        holder.as_mut().push(HeapHandle::from(&global));
+        // The holder's traced reference keeps the string alive on its own.
+        std::mem::drop(global);
+        heap.collect().unwrap();
+        let member = holder.as_ref()[0].clone();
+        assert_eq!(member.try_as_ref::<String>().unwrap(), "member");
+    }
+
+    #[derive(Default)]
+    struct PanickyObject {
+        child: Option<HeapHandle<String>>,
+        panic_in_trace: Cell<bool>,
+        panic_in_drop: bool,
+    }
+
+    impl HostObject for PanickyObject {}
+
+    impl Traceable for PanickyObject {
+        fn trace(&mut self, visitor: &mut ObjectVisitor) {
+            if self.panic_in_trace.replace(false) {
+                panic!("trace panicked");
+            }
+            if let Some(child) = &self.child {
+                child.trace(visitor);
+            }
+        }
+    }
+
+    impl Drop for PanickyObject {
+        fn drop(&mut self) {
+            if self.panic_in_drop {
+                panic!("drop panicked");
+            }
+        }
+    }
+
+    #[test]
+    fn trace_panic_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let object = scope.create::<PanickyObject>().unwrap();
+        object.as_mut().child = Some(scope.str("child").unwrap().into());
+        object.as_ref().panic_in_trace.set(true);
+        assert!(matches!(heap.collect(), Err(GCError::Panicked)));
+        // The collection completed, reaching the child on the second try.
+        let child = object.as_ref().child.clone().unwrap();
+        assert_eq!(child.as_ref(), "child");
+        heap.collect().unwrap();
+        assert_eq!(object.as_ref().child.as_ref().unwrap().as_ref(), "child");
+    }
+
+    #[test]
+    fn finalizer_panic_test() {
+        let heap = Heap::new(1000).unwrap();
+        let counter = Rc::new(Cell::new(0));
+        {
+            let scope = HandleScope::new(&heap);
+            let object = scope.create::<PanickyObject>().unwrap();
+            object.as_mut().panic_in_drop = true;
+            let dropped = scope.create::<DropObject>().unwrap();
+            dropped.as_mut().counter = counter.clone();
+        }
+        assert!(matches!(heap.collect(), Err(GCError::Panicked)));
+        // The other finalizers still ran and the heap is usable.
+        assert_eq!(counter.get(), 1);
+        assert_eq!(heap.used_bytes(), 0);
+        let scope = HandleScope::new(&heap);
+        scope.str("after").unwrap();
+        heap.collect().unwrap();
+    }
+
+    #[test]
+    fn oom_callback_test() {
+        let heap = Heap::new(1000).unwrap();
+        let cache = heap
+            .alloc_global(Box::new(List::<String>::default()))
+            .unwrap();
+        let cache = Rc::new(RefCell::new(Some(cache)));
+        let calls = Rc::new(Cell::new(0));
+        let (cache_in_callback, calls_in_callback) = (cache.clone(), calls.clone());
+        heap.set_oom_policy(OomPolicy::Callback(Box::new(move |_heap, _bytes| {
+            calls_in_callback.set(calls_in_callback.get() + 1);
+            // Dropping the cache unroots its strings.
+            cache_in_callback.borrow_mut().take().is_some()
+        })));
+
+        let scope = HandleScope::new(&heap);
+        let mut cached = 0;
+        while cache.borrow().is_some() {
+            let inner = scope.create_child_scope();
+            let string = inner.str("cached").unwrap();
+            if let Some(cache) = cache.borrow().as_ref() {
+                inner.as_mut(cache).push(string.into());
+                cached += 1;
+            }
+        }
+        assert!(cached > 1);
+        assert_eq!(calls.get(), 1);
+
+        // Once the callback has nothing left to free, allocations fail.
+        let result = loop {
+            if let Err(error) = scope.str("full") {
+                break error;
+            }
+        };
+        assert!(matches!(result, GCError::NoSpace));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn oom_callback_without_progress_test() {
+        let heap = Heap::new(1000).unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let calls_in_callback = calls.clone();
+        heap.set_oom_policy(OomPolicy::Callback(Box::new(move |_heap, _bytes| {
+            calls_in_callback.set(calls_in_callback.get() + 1);
+            true
+        })));
+        let scope = HandleScope::new(&heap);
+        let result = loop {
+            if let Err(error) = scope.str("full") {
+                break error;
+            }
+        };
+        assert!(matches!(result, GCError::NoSpace));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn usage_watermark_test() {
+        let heap = Heap::new(1000).unwrap();
+        let fired = Rc::new(RefCell::new(vec![]));
+        for fraction in [0.5, 0.9] {
+            let fired = fired.clone();
+            heap.add_usage_watermark(fraction, move |heap, used_bytes| {
+                assert!(used_bytes <= heap.used_bytes());
+                fired.borrow_mut().push(fraction);
+            });
+        }
+        let fill = |scope: &HandleScope, count: usize| {
+            for _ in 0..count {
+                scope.str("fill").unwrap();
+            }
+        };
+        let capacity = heap.free_bytes() / HOST_OBJECT_ALLOC_SIZE;
+        {
+            let scope = HandleScope::new(&heap);
+            fill(&scope, capacity * 3 / 4);
+            assert_eq!(*fired.borrow(), vec![0.5]);
+            fill(&scope, capacity - capacity * 3 / 4);
+            assert_eq!(*fired.borrow(), vec![0.5, 0.9]);
+        }
+        // Collecting drops usage below both, re-arming them.
+        heap.collect().unwrap();
+        let scope = HandleScope::new(&heap);
+        fill(&scope, capacity);
+        assert_eq!(*fired.borrow(), vec![0.5, 0.9, 0.5, 0.9]);
+    }
+
+    #[test]
+    fn collect_step_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        for index in 0..20 {
+            list.as_mut()
+                .push(scope.str(&index.to_string()).unwrap().into());
+        }
+        let live_bytes = heap.used_bytes();
+        {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+        }
+        let mut steps = 0;
+        while !heap.collect_step(HOST_OBJECT_ALLOC_SIZE).unwrap() {
+            assert!(heap.is_collecting());
+            steps += 1;
+            // The list was traced by the first step, so this string is only
+            // found by tracing it again when finishing.
+            let added = {
+                let inner = HandleScope::new(&heap);
+                inner.str("added").unwrap().into()
+            };
+            list.as_mut().push(added);
+        }
+        assert!(steps > 1);
+        assert!(!heap.is_collecting());
+        assert_eq!(list.as_ref().len(), 20 + steps);
+        assert_eq!(list.as_ref()[5].as_ref(), "5");
+        assert_eq!(list.as_ref()[20].as_ref(), "added");
+        assert_eq!(
+            heap.used_bytes(),
+            live_bytes + steps * HOST_OBJECT_ALLOC_SIZE
+        );
+
+        // Other collections finish the one in progress.
+        assert!(!heap.collect_step(HOST_OBJECT_ALLOC_SIZE).unwrap());
+        heap.collect().unwrap();
+        assert!(!heap.is_collecting());
+        assert_eq!(list.as_ref()[25].as_ref(), "added");
+    }
+
+    #[test]
+    fn collect_step_write_barrier_test() {
+        #[derive(Default)]
+        struct TraceCounter {
+            traces: Rc<Cell<usize>>,
+            children: Vec<HeapHandle<String>>,
+        }
+
+        impl HostObject for TraceCounter {}
+
+        impl Traceable for TraceCounter {
+            fn trace(&mut self, visitor: &mut ObjectVisitor) {
+                self.traces.set(self.traces.get() + 1);
+                visitor.trace_handles(&self.children);
+            }
+        }
+
+        // Debug builds trace every object again to verify the heap.
+        let verify_traces = cfg!(debug_assertions) as usize;
+        for collector in [CollectorKind::SemiSpace, CollectorKind::MarkSweep] {
+            let heap = Heap::with_config(HeapConfig {
+                collector,
+                ..HeapConfig::fixed(1 << 16)
+            })
+            .unwrap();
+            let scope = HandleScope::new(&heap);
+            let traces = Rc::new(Cell::new(0));
+            let list = scope.create::<List<TraceCounter>>().unwrap();
+            for _ in 0..20 {
+                let counter = TraceCounter {
+                    traces: traces.clone(),
+                    children: vec![],
+                };
+                list.as_mut().push(scope.take(counter).unwrap().into());
+            }
+
+            // Only the counter written to between steps is traced again, for
+            // the strings which are only reachable through it.
+            traces.set(0);
+            let mut steps = 0;
+            while !heap.collect_step(HOST_OBJECT_ALLOC_SIZE).unwrap() {
+                steps += 1;
+                let added = {
+                    let inner = HandleScope::new(&heap);
+                    inner.str("added").unwrap().into()
+                };
+                list.as_ref()[0].as_mut().children.push(added);
+            }
+            assert_eq!(traces.get(), 21 + 20 * verify_traces);
+            heap.verify();
+            let first = list.as_ref()[0].as_ref();
+            assert_eq!(first.children.len(), steps);
+            assert!(first.children.iter().all(|child| child.as_ref() == "added"));
+
+            // Stores through an ObjectMut taken before the collection started
+            // aren't logged, so every object is traced again.
+            let mut payload = list.as_ref()[1].as_mut();
+            traces.set(0);
+            while !heap.collect_step(HOST_OBJECT_ALLOC_SIZE).unwrap() {}
+            assert_eq!(traces.get(), 40 + 20 * verify_traces);
+            payload.children.push(scope.str("held").unwrap().into());
+            std::mem::drop(payload);
+            heap.collect().unwrap();
+            assert_eq!(list.as_ref()[1].as_ref().children[0].as_ref(), "held");
+        }
+    }
+
+    #[test]
+    fn collect_with_deadline_test() {
+        let heap = Heap::new(100000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        for index in 0..200 {
+            list.as_mut()
+                .push(scope.str(&index.to_string()).unwrap().into());
+        }
+        // Out of time after the first step.
+        assert!(!heap.collect_with_deadline(Duration::ZERO).unwrap());
+        assert!(heap.is_collecting());
+        // The list was traced by the first step, the write barrier has it
+        // traced again for this string.
+        let added = {
+            let inner = HandleScope::new(&heap);
+            inner.str("added").unwrap().into()
+        };
+        list.as_mut().push(added);
+        assert!(heap.collect_with_deadline(Duration::from_secs(60)).unwrap());
+        assert!(!heap.is_collecting());
+        heap.verify();
+        assert_eq!(list.as_ref()[199].as_ref(), "199");
+        assert_eq!(list.as_ref()[200].as_ref(), "added");
+    }
+
+    #[test]
+    fn user_tag_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let tagged = scope.take_tagged("point".to_string(), 7).unwrap();
+        let untagged = scope.str("plain").unwrap();
+        heap.collect().unwrap();
+        assert_eq!(tagged.user_tag(), Some(7));
+        assert_eq!(tagged.erase_type().user_tag(), Some(7));
+        assert_eq!(untagged.user_tag(), Some(0));
+        assert_eq!(scope.create_num(1.0).user_tag(), None);
+    }
+
+    #[test]
+    fn list_push_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<f64>>().unwrap();
+        let one = scope.create_num(1.0);
+        let mut list_value = list.as_mut();
+        list_value.push(one.into());
+        std::mem::drop(list_value);
+        heap.collect().ok();
+        let list_value = list.as_ref();
+        assert_eq!(list_value.len(), 1);
+    }
+
+    #[test]
+    fn list_get_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        list.as_mut().push(scope.str("Foo").unwrap().into());
+        let foo = list.as_ref().get(&scope, 0).unwrap();
+        assert!(list.as_ref().get(&scope, 1).is_none());
+        // The element stays rooted even once it is removed from the list.
+        list.as_mut().clear();
         heap.collect().unwrap();
-        assert_eq!(0, heap.used_bytes());
+        assert_eq!(foo.as_ref(), "Foo");
     }
 
     #[test]
-    fn finalizer_test() {
+    fn list_search_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        for text in ["a", "b", "a"] {
+            list.as_mut().push(scope.str(text).unwrap().into());
+        }
+        // Equal strings match, not just the same object.
+        let a = scope.str("a").unwrap().into();
+        assert!(list.as_ref().contains(&a));
+        assert_eq!(list.as_ref().index_of(&a), Some(0));
+        assert_eq!(
+            list.as_ref().index_of(&scope.str("c").unwrap().into()),
+            None
+        );
+    }
+
+    #[test]
+    fn list_retain_sort_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        for text in ["pear", "fig", "apple", "kiwi", "date"] {
+            list.as_mut().push(scope.str(text).unwrap().into());
+        }
+        // The callbacks may allocate and collect, moving the list.
+        list.retain(|element| {
+            scope.str("garbage").unwrap();
+            heap.collect().unwrap();
+            element.as_ref() != "kiwi"
+        });
+        list.sort_by(|a, b| {
+            heap.collect().unwrap();
+            a.as_ref().len().cmp(&b.as_ref().len())
+        });
+        assert_eq!(
+            list.display(&scope),
+            "[\"fig\", \"pear\", \"date\", \"apple\"]"
+        );
+        list.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        assert_eq!(list.as_ref()[0].as_ref(), "apple");
+    }
+
+    #[test]
+    fn bulk_constructors_test() {
+        let heap = Heap::new(2000).unwrap();
+        let scope = HandleScope::new(&heap);
+
+        let nums = scope.list_from_f64s(&[1.0, 2.0]).unwrap();
+        let strs = scope.list_from_strs(&["a", "b", "c"]).unwrap();
+        let map = scope.map_from_pairs(&[("k", "v")]).unwrap();
+        heap.collect().unwrap();
+
+        let values: Vec<f64> = nums.try_from_heap(&scope).unwrap();
+        assert_eq!(values, vec![1.0, 2.0]);
+        assert_eq!(strs.display(&scope), "[\"a\", \"b\", \"c\"]");
+        let key = scope.str("k").unwrap();
+        let value = map.as_ref().get(&key.into()).unwrap();
+        assert_eq!(value.as_ref(), "v");
+
+        // Nothing is allocated when the whole batch can't fit.
+        let used = heap.used_bytes();
+        let too_many = vec!["x"; 100];
+        assert!(scope.list_from_strs(&too_many).is_err());
+        assert_eq!(heap.used_bytes(), used);
+    }
+
+    #[test]
+    fn take_iter_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let words = vec!["a", "b"].into_iter().map(String::from);
+        let list = scope.take_iter(words).unwrap();
+        heap.collect().unwrap();
+        assert_eq!(list.display(&scope), "[\"a\", \"b\"]");
+
+        // Running out of space part way keeps the finished elements.
+        let counter = Rc::new(Cell::new(0));
+        let objects = (0..100).map(|_| DropObject {
+            counter: Rc::clone(&counter),
+        });
+        assert!(scope.take_iter(objects).is_err());
+        heap.collect().unwrap();
+        assert!(heap.used_bytes() > 0);
+        std::mem::drop(scope);
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), 0);
+    }
+
+    #[test]
+    fn concat_test() {
         let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let foo = scope.str("Foo").unwrap();
+        let bar = scope.str("Bar").unwrap();
+        let foobar = scope.concat(foo.clone(), bar.clone()).unwrap();
+        assert_eq!(foobar.as_ref(), "FooBar");
+        let all = scope.concat_all(&[foo, bar, foobar]).unwrap();
+        heap.collect().unwrap();
+        assert_eq!(all.as_ref(), "FooBarFooBar");
+    }
+
+    #[test]
+    fn unique_handle_test() {
+        let heap = Heap::with_config(HeapConfig {
+            eager_reclamation: true,
+            ..HeapConfig::fixed(1000)
+        })
+        .unwrap();
         let counter = Rc::new(Cell::new(0));
         let scope = HandleScope::new(&heap);
+        let used = heap.used_bytes();
+        {
+            let mut unique = scope.take_unique(DropObject::default()).unwrap();
+            unique.counter = Rc::clone(&counter);
+        }
+        assert_eq!(counter.get(), 1);
+        assert_eq!(heap.used_bytes(), used);
 
-        let handle = scope.create::<DropObject>().unwrap();
-        handle.as_mut().counter = Rc::clone(&counter);
-        std::mem::drop(handle);
-        assert_eq!(0u32, counter.get());
+        // Shared objects are left to the collector.
+        let unique = scope.take_unique("foo".to_string()).unwrap();
+        let shared = unique.share();
+        heap.collect().unwrap();
+        assert_eq!(shared.as_ref(), "foo");
+
+        // Without eager reclamation unique objects wait for a collection.
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        {
+            let mut unique = scope.take_unique(DropObject::default()).unwrap();
+            unique.counter = Rc::clone(&counter);
+        }
+        assert_eq!(counter.get(), 1);
+        heap.collect().unwrap();
+        assert_eq!(counter.get(), 2);
+    }
+
+    #[test]
+    fn region_test() {
+        let heap = Heap::new(1000).unwrap();
+        let counter = Rc::new(Cell::new(0));
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        let used = heap.used_bytes();
+        {
+            let region = HandleScope::new_region(&heap);
+            let temporary = region.create::<DropObject>().unwrap();
+            temporary.as_mut().counter = Rc::clone(&counter);
+            region.str("temporary").unwrap();
+            // Dropped first, so doesn't stop the region reclaiming.
+            let _inner = region.create_child_scope();
+        }
+        // Dead as soon as the region drops, no collection needed.
+        assert_eq!(counter.get(), 1);
+        assert_eq!(heap.used_bytes(), used);
+
+        // A copy in a scope made after the region escapes, as the scopes may
+        // be dropped in either order.
+        let region = HandleScope::new_region(&heap);
+        let later = HandleScope::new(&heap);
+        let kept = later.from_local(&region.str("temp").unwrap());
+        assert!(kept.ptr_for_test().header().unwrap().is_escaped());
+        std::mem::drop(region);
+        later.str("overwritten!").unwrap();
+        assert_eq!(kept.as_ref(), "temp");
+        // Nor is anything reclaimed while a later scope is live.
+        let region = HandleScope::new_region(&heap);
+        let later = HandleScope::new(&heap);
+        region.create::<DropObject>().unwrap().as_mut().counter = Rc::clone(&counter);
+        std::mem::drop(region);
+        assert_eq!(counter.get(), 1);
+        std::mem::drop(later);
+        heap.collect().unwrap();
+        assert_eq!(counter.get(), 2);
+
+        let outer = {
+            let region = HandleScope::new_region(&heap);
+            list.as_mut().push(region.str("stored").unwrap().into());
+            let returned = region.str("returned").unwrap();
+            scope.from_local(&returned)
+        };
+        heap.collect().unwrap();
+        assert_eq!(list.as_ref()[0].as_ref(), "stored");
+        assert_eq!(outer.as_ref(), "returned");
+    }
+
+    #[test]
+    fn collection_stats_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let kept = scope.str("kept").unwrap();
+        let _also_kept = scope.str("also kept").unwrap();
+        let weak = {
+            let inner = HandleScope::new(&heap);
+            inner.str("a").unwrap();
+            inner.str("b").unwrap();
+            inner.str("c").unwrap().downgrade()
+        };
+        let used_bytes = heap.used_bytes();
+        let stats = heap.collect().unwrap();
+        assert_eq!(stats.used_bytes_before, used_bytes);
+        assert_eq!(stats.used_bytes_after, heap.used_bytes());
+        assert!(stats.used_bytes_after < stats.used_bytes_before);
+        assert_eq!(stats.objects_copied, 2);
+        assert_eq!(stats.objects_finalized, 3);
+        assert_eq!(stats.weak_handles_cleared, 1);
+        assert!(!weak.is_live());
+        assert_eq!(kept.as_ref(), "kept");
+
+        // Non-moving collectors copy nothing.
+        let heap = Heap::with_config(HeapConfig {
+            collector: CollectorKind::MarkSweep,
+            ..HeapConfig::fixed(10000)
+        })
+        .unwrap();
+        let scope = HandleScope::new(&heap);
+        scope.str("kept").unwrap();
+        let stats = heap.collect().unwrap();
+        assert_eq!(stats.objects_copied, 0);
+        assert_eq!(stats.used_bytes_after, stats.used_bytes_before);
+    }
+
+    #[test]
+    fn verify_test() {
+        for collector in [
+            CollectorKind::SemiSpace,
+            CollectorKind::Immix,
+            CollectorKind::Generational,
+            CollectorKind::MarkSweep,
+        ] {
+            let heap = Heap::with_config(HeapConfig {
+                collector,
+                ..HeapConfig::fixed(1 << 18)
+            })
+            .unwrap();
+            let scope = HandleScope::new(&heap);
+            let map = scope.create::<Map<String, List<f64>>>().unwrap();
+            let list = scope.create::<List<f64>>().unwrap();
+            list.as_mut().push(scope.create_num(1.0).into());
+            map.as_mut()
+                .insert(scope.str("key").unwrap().into(), list.into());
+            let weak = scope.str("weak").unwrap().downgrade();
+            heap.verify();
+            heap.collect().unwrap();
+            heap.collect_minor().unwrap();
+            heap.compact().unwrap();
+            heap.verify();
+            assert!(weak.is_live());
+            // Part way through, objects are legitimately forwarded.
+            heap.collect_step(1).unwrap();
+            heap.verify();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "outside the heap")]
+    fn verify_stale_handle_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        let string = scope.str("moved").unwrap();
+        // Held outside the heap, so not updated when the string moves.
+        let stale: HeapHandle<String> = string.clone().into();
+        heap.collect().unwrap();
+        list.as_mut().push(stale);
+        heap.verify();
+    }
+
+    #[test]
+    fn gc_stress_test() {
+        let heap = Heap::with_config(HeapConfig {
+            gc_stress: true,
+            ..HeapConfig::fixed(10000)
+        })
+        .unwrap();
+        let collections = Rc::new(Cell::new(0));
+        let counter = collections.clone();
+        heap.add_gc_callback(GcPhase::End, move |_heap| {
+            counter.set(counter.get() + 1);
+        });
+        let scope = HandleScope::new(&heap);
+        // Before allocating the list and once it's rooted.
+        let list = scope.create::<List<String>>().unwrap();
+        assert_eq!(collections.get(), 2);
+        list.as_mut().push(scope.str("kept").unwrap().into());
+        assert_eq!(collections.get(), 4);
+        let element = scope.from_heap(&list.as_ref()[0]);
+        assert_eq!(collections.get(), 5);
+        assert_eq!(element.as_ref(), "kept");
+
+        // Objects are freed as soon as nothing roots them.
+        let used_bytes = heap.used_bytes();
+        {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+        }
+        scope.create_null();
+        assert_eq!(heap.used_bytes(), used_bytes);
+    }
+
+    #[test]
+    fn take_collecting_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let left = scope.rope("left").unwrap();
+        let right = scope.rope("right").unwrap();
+        {
+            let inner = HandleScope::new(&heap);
+            while heap.free_bytes() >= HOST_OBJECT_ALLOC_SIZE {
+                inner.str("garbage").unwrap();
+            }
+        }
+        // Making room for the rope moves the ropes it refers to, which are
+        // roots until it's in the heap.
+        let joined = scope.rope_concat(&left, &right).unwrap();
+        heap.verify();
+        assert_eq!(joined.as_ref().to_string(), "leftright");
+    }
+
+    #[test]
+    fn gc_callback_test() {
+        let heap = Heap::new(10000).unwrap();
+        let phases = Rc::new(RefCell::new(vec![]));
+        let log = phases.clone();
+        heap.add_gc_callback(GcPhase::Begin, move |heap| {
+            log.borrow_mut().push((GcPhase::Begin, heap.used_bytes()));
+        });
+        let log = phases.clone();
+        heap.add_gc_callback(GcPhase::End, move |heap| {
+            log.borrow_mut().push((GcPhase::End, heap.used_bytes()));
+            // Allowed, without calling the callbacks again.
+            let scope = HandleScope::new(heap);
+            scope.str("allocated").unwrap();
+            heap.collect().unwrap();
+        });
+        {
+            let scope = HandleScope::new(&heap);
+            scope.str("garbage").unwrap();
+        }
+        let used_bytes = heap.used_bytes();
+        heap.collect().unwrap();
+        assert_eq!(
+            *phases.borrow(),
+            vec![(GcPhase::Begin, used_bytes), (GcPhase::End, 0)]
+        );
+
+        phases.borrow_mut().clear();
+        heap.collect_minor().unwrap();
+        while !heap.collect_step(1).unwrap() {}
+        let called: Vec<GcPhase> = phases.borrow().iter().map(|(phase, _)| *phase).collect();
+        assert_eq!(
+            called,
+            vec![GcPhase::Begin, GcPhase::End, GcPhase::Begin, GcPhase::End]
+        );
+    }
+
+    #[test]
+    fn gc_callback_allocation_test() {
+        for collector in [
+            CollectorKind::SemiSpace,
+            CollectorKind::Immix,
+            CollectorKind::Generational,
+            CollectorKind::MarkSweep,
+        ] {
+            let heap = Heap::with_config(HeapConfig {
+                collector,
+                ..HeapConfig::fixed(1 << 16)
+            })
+            .unwrap();
+            let began = Rc::new(Cell::new(0));
+            let began_in_callback = began.clone();
+            // Allocating while the heap is full collects from the callback.
+            heap.add_gc_callback(GcPhase::Begin, move |heap| {
+                began_in_callback.set(began_in_callback.get() + 1);
+                let scope = HandleScope::new(heap);
+                {
+                    let garbage = scope.create_child_scope();
+                    while heap.free_bytes() >= HOST_OBJECT_ALLOC_SIZE {
+                        garbage.str("garbage").unwrap();
+                    }
+                }
+                scope.str("allocated").unwrap();
+            });
+            let scope = HandleScope::new(&heap);
+            let kept = scope.str("kept").unwrap();
+            heap.collect().unwrap();
+            heap.collect_minor().unwrap();
+            heap.compact().unwrap();
+            while !heap.collect_step(1).unwrap() {}
+            heap.verify();
+            assert_eq!(kept.as_ref(), "kept");
+            assert!(began.get() >= 4);
+        }
+    }
+
+    #[test]
+    fn weak_handle_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let strong = heap.alloc_global(Box::new("kept".to_string())).unwrap();
+        let weak = scope.from_global(&strong).downgrade();
+        let garbage = {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap().downgrade()
+        };
+        heap.collect().unwrap();
+        assert_eq!(weak.upgrade(&scope).unwrap().as_ref(), "kept");
+        assert!(!garbage.is_live());
+        assert!(garbage.upgrade(&scope).is_none());
+
+        // Upgrading roots the object again.
+        let upgraded = weak.upgrade(&scope).unwrap();
+        std::mem::drop(strong);
+        heap.collect().unwrap();
+        assert_eq!(upgraded.as_ref(), "kept");
         std::mem::drop(scope);
-        assert_eq!(0u32, counter.get());
-        heap.collect().ok();
-        assert_eq!(1u32, counter.get());
+        heap.collect().unwrap();
+        assert!(!weak.is_live());
     }
 
     #[test]
-    fn tracing_test() {
+    fn ephemeron_test() {
+        let collectors = [
+            CollectorKind::SemiSpace,
+            CollectorKind::Immix,
+            CollectorKind::Generational,
+            CollectorKind::MarkSweep,
+        ];
+        for collector in collectors {
+            let heap = Heap::with_config(HeapConfig {
+                collector,
+                ..HeapConfig::fixed(1 << 16)
+            })
+            .unwrap();
+            let counter = Rc::new(Cell::new(0));
+            let scope = HandleScope::new(&heap);
+            let key = scope.str("key").unwrap();
+            let (ephemeron, cyclic) = {
+                let inner = HandleScope::new(&heap);
+                let value = inner
+                    .take(DropObject {
+                        counter: counter.clone(),
+                    })
+                    .unwrap();
+                let ephemeron = inner.create_ephemeron(&key, &value);
+                // The value refers to its own key.
+                let cyclic_key = inner.str("cyclic").unwrap();
+                let cyclic_value = inner.create::<List<String>>().unwrap();
+                cyclic_value.as_mut().push(cyclic_key.clone().into());
+                (
+                    ephemeron,
+                    inner.create_ephemeron(&cyclic_key, &cyclic_value),
+                )
+            };
+            heap.collect().unwrap();
+            heap.collect_minor().unwrap();
+            heap.compact().unwrap();
+            assert_eq!(counter.get(), 0);
+            assert!(ephemeron.key(&scope).unwrap().ptr_eq(&key));
+            assert!(ephemeron.value(&scope).is_some());
+            assert!(!cyclic.is_live());
+            assert!(cyclic.value(&scope).is_none());
+
+            std::mem::drop(scope);
+            heap.collect().unwrap();
+            assert!(!ephemeron.is_live());
+            assert_eq!(counter.get(), 1);
+        }
+    }
+
+    #[test]
+    fn ephemeron_chain_test() {
+        // Each value is the next ephemeron's key, so the first key keeps
+        // them all alive, however the ephemerons are ordered.
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let first = scope.str("0").unwrap();
+        let chain: Vec<Ephemeron<String, String>> = {
+            let inner = HandleScope::new(&heap);
+            let keys: Vec<_> = (0..4).map(|i| inner.str(&i.to_string()).unwrap()).collect();
+            let mut chain: Vec<_> = (0..3)
+                .map(|i| {
+                    let key = if i == 0 { &first } else { &keys[i] };
+                    inner.create_ephemeron(key, &keys[i + 1])
+                })
+                .collect();
+            chain.reverse();
+            chain
+        };
+        heap.collect().unwrap();
+        assert!(chain.iter().all(Ephemeron::is_live));
+        assert_eq!(chain[0].value(&scope).unwrap().as_ref(), "3");
+
+        std::mem::drop(first);
+        std::mem::drop(scope);
+        heap.collect().unwrap();
+        assert!(!chain.iter().any(Ephemeron::is_live));
+        assert_eq!(heap.used_bytes(), 0);
+    }
+
+    #[test]
+    fn weak_handle_on_collect_test() {
         let heap = Heap::new(1000).unwrap();
+        let collected = Rc::new(RefCell::new(vec![]));
         let scope = HandleScope::new(&heap);
-        let handle = scope.create::<List<DropObject>>().unwrap();
+        let kept = scope.str("kept").unwrap();
+        let weaks: Vec<WeakHandle<String>> = ["kept", "a", "b"]
+            .iter()
+            .map(|name| {
+                let inner = HandleScope::new(&heap);
+                let weak = match *name {
+                    "kept" => kept.downgrade(),
+                    _ => inner.str(name).unwrap().downgrade(),
+                };
+                let log = collected.clone();
+                weak.on_collect(move |heap| {
+                    // Called after the collection, free to allocate.
+                    let scope = HandleScope::new(heap);
+                    scope.str("allocated").unwrap();
+                    log.borrow_mut().push(name.to_string());
+                });
+                weak
+            })
+            .collect();
+        heap.collect().unwrap();
+        assert_eq!(*collected.borrow(), vec!["a", "b"]);
+        assert!(weaks[0].is_live());
 
-        let list = handle.as_mut();
-        list.push(scope.create::<DropObject>().unwrap().into());
-        list.push(scope.create::<DropObject>().unwrap().into());
-        list.push(scope.create::<DropObject>().unwrap().into());
-        std::mem::drop(list);
+        // Each callback runs once.
+        heap.collect().unwrap();
+        assert_eq!(collected.borrow().len(), 2);
+    }
+
+    fn make_string<'a>(scope: &'a HandleScope<'a>, text: &str) -> LocalHandle<'a, String> {
+        let inner = EscapableHandleScope::new(scope);
+        inner.str("temporary").unwrap();
+        let result = inner.str(text).unwrap();
+        inner.escape(&result)
+    }
+
+    #[test]
+    fn escapable_scope_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let string = make_string(&scope, "escaped");
+        let used = heap.used_bytes();
+        heap.collect().unwrap();
+        // The temporary died with the inner scope.
+        assert!(heap.used_bytes() < used);
+        assert_eq!(string.as_ref(), "escaped");
+    }
+
+    #[test]
+    #[should_panic(expected = "escape called twice")]
+    fn escape_twice_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let inner = EscapableHandleScope::new(&scope);
+        let string = inner.str("string").unwrap();
+        inner.escape(&string);
+        inner.escape(&string);
+    }
+
+    #[test]
+    fn global_slot_reuse_test() {
+        let heap = Heap::new(1000).unwrap();
+        let kept = heap.alloc_global(Box::new("kept".to_string())).unwrap();
+        for _ in 0..10 {
+            let scope = HandleScope::new(&heap);
+            let global: GlobalHandle<String> = scope.str("global").unwrap().into();
+            heap.collect().unwrap();
+            assert_eq!(scope.as_ref(&global), "global");
+        }
+        assert_eq!(heap.inner.borrow().globals.len(), 2);
+        assert_eq!(HandleScope::new(&heap).as_ref(&kept), "kept");
+    }
+
+    #[test]
+    fn weak_handle_slot_reuse_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let string = scope.str("string").unwrap();
+        let kept = string.downgrade();
+        for _ in 0..10 {
+            let weak = string.downgrade();
+            assert!(weak.is_live());
+        }
+        assert_eq!(heap.inner.borrow().weak_handles.len(), 2);
+        assert_eq!(heap.handle_stats().weak_handles, 1);
+        assert_eq!(kept.upgrade(&scope).unwrap().as_ref(), "string");
+    }
+
+    #[test]
+    fn sealed_scope_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        {
+            let _sealed = SealedHandleScope::new(&heap);
+            let inner = HandleScope::new(&heap);
+            assert_eq!(inner.str("inner").unwrap().as_ref(), "inner");
+        }
+        assert_eq!(scope.str("outer").unwrap().as_ref(), "outer");
+    }
+
+    #[test]
+    #[should_panic(expected = "sealed by SealedHandleScope")]
+    fn sealed_scope_panics_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let _sealed = SealedHandleScope::new(&heap);
+        scope.create_num(1.0);
+    }
+
+    #[test]
+    fn out_of_order_scope_drop_test() {
+        let heap = Heap::new(1000).unwrap();
+        let outer = HandleScope::new(&heap);
+        let first = HandleScope::new(&heap);
+        let second = HandleScope::new(&heap);
+        first.str("first").unwrap();
+        let kept = second.str("kept").unwrap();
+        std::mem::drop(first);
+        heap.collect().unwrap();
+        assert_eq!(kept.as_ref(), "kept");
+        assert_eq!(heap.inner.borrow().scopes.len(), 3);
+
+        let third = HandleScope::new(&heap);
+        let added = third.str("added").unwrap();
+        std::mem::drop(second);
+        heap.collect().unwrap();
+        assert_eq!(added.as_ref(), "added");
+        std::mem::drop(third);
+        // Only the outer scope is left.
+        assert_eq!(heap.inner.borrow().scopes.len(), 1);
+        assert_eq!(outer.str("outer").unwrap().as_ref(), "outer");
+    }
+
+    #[test]
+    fn persistent_handle_test() {
+        let heap = Heap::new(1000).unwrap();
+        let mut persistent = PersistentHandle::<String>::new(&heap);
+        let scope = HandleScope::new(&heap);
+        assert!(persistent.is_empty());
+        assert!(persistent.get(&scope).is_none());
+        {
+            let inner = HandleScope::new(&heap);
+            persistent.reset(&inner.str("first").unwrap());
+        }
+        heap.collect().unwrap();
+        {
+            let inner = HandleScope::new(&heap);
+            assert_eq!(persistent.get(&inner).unwrap().as_ref(), "first");
+        }
+
+        {
+            let inner = HandleScope::new(&heap);
+            persistent.reset(&inner.str("second").unwrap());
+        }
+        let used = heap.used_bytes();
+        heap.collect().unwrap();
+        // The first string is no longer rooted.
+        assert!(heap.used_bytes() < used);
+        assert_eq!(persistent.get(&scope).unwrap().as_ref(), "second");
+        assert_eq!(heap.inner.borrow().globals.len(), 1);
 
-        let used = heap.used_bytes();
-        heap.collect().ok();
-        assert_eq!(used, heap.used_bytes());
-        std::mem::drop(handle);
-        heap.collect().ok();
-        assert_eq!(used, heap.used_bytes());
-        std::mem::drop(scope);
-        heap.collect().ok();
-        assert_eq!(0, heap.used_bytes());
+        persistent.clear();
+        assert!(persistent.is_empty());
     }
 
     #[test]
-    fn tagged_num_test() {
+    fn handle_stats_test() {
         let heap = Heap::new(1000).unwrap();
         let scope = HandleScope::new(&heap);
-
-        let a = scope.create_num(1.0);
-        let b = scope.create_num(2.0);
-        assert_eq!(0, heap.used_bytes());
-        let a_value: f64 = a.ptr().try_into().unwrap();
-        assert_eq!(1.0, a_value);
-        let b_value: f64 = b.ptr().try_into().unwrap();
-        assert_eq!(2.0, b_value);
+        let string = scope.str("string").unwrap();
+        scope.create_num(1.0);
+        let _global: GlobalHandle<String> = string.clone().into();
+        let weak = string.downgrade();
+        {
+            let inner = HandleScope::new(&heap);
+            inner.from_local(&string);
+            assert_eq!(heap.handle_stats().local_handles, vec![2, 1]);
+        }
+        std::mem::drop(weak);
+        assert_eq!(
+            heap.handle_stats(),
+            HandleStats {
+                local_handles: vec![2],
+                global_handles: 1,
+                weak_handles: 0,
+            }
+        );
     }
 
     #[test]
-    fn add_f64_test() {
-        let heap = Heap::new(1000).unwrap();
+    fn usage_by_type_test() {
+        let heap = Heap::new(10000).unwrap();
         let scope = HandleScope::new(&heap);
-        let one = scope.create_num(1.0);
-        let two = scope.create_num(2.0);
-        let one_value: f64 = one.try_into().unwrap();
-        assert_eq!(1.0, one_value);
-        let two_value: f64 = two.try_into().unwrap();
-        assert_eq!(2.0, two_value);
-        let three_value = one_value + two_value;
-        let three = scope.create_num(three_value);
-        let three_global = GlobalHandle::from(three);
-        std::mem::drop(scope);
+        let list = scope.create::<List<String>>().unwrap();
+        list.as_mut().push(scope.str("one").unwrap().into());
+        list.as_mut().push(scope.str("two").unwrap().into());
+        {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+        }
 
-        let scope = HandleScope::new(&heap);
-        let three = scope.from_global(&three_global);
-        let three_value: f64 = three.try_into().unwrap();
-        assert_eq!(3.0, three_value);
+        let usage = heap.usage_by_type();
+        assert_eq!(usage.len(), 2);
+        let strings = &usage[0];
+        assert_eq!(strings.type_name, std::any::type_name::<String>());
+        assert_eq!(strings.count, 3);
+        assert!(strings.bytes >= usage[1].bytes);
+        assert_eq!(usage[1].type_name, std::any::type_name::<List<String>>());
+        assert_eq!(usage[1].count, 1);
+        let total: usize = usage.iter().map(|usage| usage.bytes).sum();
+        assert_eq!(total, heap.used_bytes());
+
+        heap.collect().unwrap();
+        assert_eq!(heap.usage_by_type()[0].count, 2);
     }
 
     #[test]
-    fn list_push_test() {
+    fn global_downcast_test() {
         let heap = Heap::new(1000).unwrap();
+        let global = heap
+            .alloc_global(Box::new("global".to_string()))
+            .unwrap()
+            .erase_type();
+        assert!(!global.is_of_type::<List<String>>());
+        assert!(global.is_of_type::<String>());
+        let typed: GlobalHandle<String> = global.try_downcast().unwrap();
+        heap.collect().unwrap();
         let scope = HandleScope::new(&heap);
-        let list = scope.create::<List<f64>>().unwrap();
-        let one = scope.create_num(1.0);
-        let list_value = list.as_mut();
-        list_value.push(one.into());
-        std::mem::drop(list_value);
-        heap.collect().ok();
-        let list_value = list.as_ref();
-        assert_eq!(list_value.len(), 1);
+        assert_eq!(scope.as_ref(&typed), "global");
+
+        let erased = typed.erase_type();
+        let list: Option<GlobalHandle<List<String>>> = erased.try_downcast();
+        assert!(list.is_none());
     }
 
     #[test]
@@ -643,12 +4326,12 @@ mod tests {
         let scope = HandleScope::new(&heap);
         let list = scope.create::<List<String>>().unwrap();
         let string = scope.str("Foo").unwrap();
-        let list_value = list.as_mut();
+        let mut list_value = list.as_mut();
         list_value.push(string.clone().into());
         list_value.push(string.clone().into());
         std::mem::drop(list_value);
         heap.collect().ok();
-        let list_value = list.as_mut();
+        let mut list_value = list.as_mut();
         assert_eq!(list_value.len(), 2);
         assert_eq!(list_value[0].as_ref(), "Foo");
         assert_eq!(list_value[1].as_ref(), "Foo");
@@ -663,7 +4346,7 @@ mod tests {
         let map = scope.create::<Map<String, String>>().unwrap();
         let foo = scope.str("Foo").unwrap();
         let bar = scope.str("Bar").unwrap();
-        let map_value = map.as_mut();
+        let mut map_value = map.as_mut();
         map_value.insert(foo.clone().into(), bar.clone().into());
         std::mem::drop(map_value);
         std::mem::drop(foo);
@@ -671,7 +4354,7 @@ mod tests {
 
         // Check if lookup works before collect.
         {
-            let map_value = map.as_mut();
+            let mut map_value = map.as_mut();
             let foo = scope.str("Foo").unwrap();
             let bar = scope.from_heap(map_value.get(&foo.into()).unwrap());
             assert_eq!(bar.as_ref(), "Bar");
@@ -685,6 +4368,92 @@ mod tests {
         assert_eq!(bar.as_ref(), "Bar");
     }
 
+    #[test]
+    fn map_local_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let map = scope.create_map::<String, String>().unwrap();
+        let str = |text: &str| scope.str(text).unwrap();
+        assert!(map.is_empty());
+        assert!(map.insert_local(&str("a"), &str("1")).is_none());
+        map.insert_local(&str("b"), &str("2"));
+        let old = map.insert_local(&str("a"), &str("3")).unwrap();
+        assert_eq!(old.as_ref(), "1");
+        heap.collect().unwrap();
+
+        assert_eq!(map.len(), 2);
+        assert!(map.contains_key(&str("b")));
+        assert_eq!(map.get_local(&str("a")).unwrap().as_ref(), "3");
+        let mut keys: Vec<String> = map.keys().map(|key| key.as_ref().clone()).collect();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+
+        // Entries stay rooted while the iteration allocates.
+        for (key, value) in map.entries() {
+            heap.collect().unwrap();
+            let joined = scope.concat(key, value).unwrap();
+            assert!(["a3", "b2"].contains(&joined.as_ref().as_str()));
+        }
+        let removed = map.remove(&str("b")).unwrap();
+        heap.collect().unwrap();
+        assert_eq!(removed.as_ref(), "2");
+        assert!(map.remove(&str("b")).is_none());
+        assert!(map.values().all(|value| value.as_ref() == "3"));
+
+        // Only inserting escapes the key from a region scope.
+        let region = HandleScope::new_region(&heap);
+        let key = region.str("a").unwrap();
+        assert!(map.contains_key(&key));
+        assert_eq!(map.get_local(&key).unwrap().as_ref(), "3");
+        assert!(map.remove(&region.str("c").unwrap()).is_none());
+        assert!(!key.ptr_for_test().header().unwrap().is_escaped());
+        let used = heap.used_bytes();
+        std::mem::drop(region);
+        assert!(heap.used_bytes() < used);
+    }
+
+    #[test]
+    fn set_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let set = scope.create_set::<String>().unwrap();
+        for name in ["b", "a", "b", "c"] {
+            set.as_mut().insert(scope.str(name).unwrap().into());
+        }
+        let used_bytes = heap.used_bytes();
+        {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+        }
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), used_bytes);
+
+        // Elements are equal strings, not the same objects.
+        assert_eq!(set.as_ref().len(), 3);
+        let key = |name: &str| -> HeapHandle<String> { scope.str(name).unwrap().into() };
+        assert!(set.as_ref().contains(&key("a")));
+        assert!(set.as_mut().remove(&key("b")));
+        assert!(!set.as_ref().contains(&key("b")));
+        let mut names: Vec<_> = set
+            .as_ref()
+            .iter()
+            .map(|name| name.as_ref().clone())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a", "c"]);
+
+        // Sets compare by their elements, in any order.
+        let other = scope.create_set::<String>().unwrap();
+        other.as_mut().insert(key("c"));
+        other.as_mut().insert(key("a"));
+        assert!(set.ptr_for_test() == other.ptr_for_test());
+        other.as_mut().insert(key("d"));
+        assert!(set.ptr_for_test() != other.ptr_for_test());
+        let single = scope.create_set::<String>().unwrap();
+        single.as_mut().insert(key("a"));
+        assert_eq!(single.display(&scope), "{\"a\"}");
+    }
+
     #[test]
     fn typed_handle_test() {
         let heap = Heap::new(1000).unwrap();
@@ -816,6 +4585,205 @@ mod tests {
         assert!(before_size < heap.used_bytes());
     }
 
+    #[test]
+    fn post_gc_test() {
+        let heap = Heap::new(1000).unwrap();
+        let counter = Rc::new(Cell::new(0));
+        let task_counter = Rc::clone(&counter);
+        heap.post_gc(move |heap| {
+            task_counter.set(task_counter.get() + 1);
+            // Tasks may allocate, the heap is not borrowed while they run.
+            let scope = HandleScope::new(heap);
+            scope.str("foo").unwrap();
+        });
+        assert_eq!(0u32, counter.get());
+        heap.collect().unwrap();
+        assert_eq!(1u32, counter.get());
+        heap.collect().unwrap();
+        assert_eq!(1u32, counter.get());
+    }
+
+    #[test]
+    fn subscribe_events_test() {
+        let heap = Heap::new(1000).unwrap();
+        let events = heap.subscribe_events();
+        {
+            let scope = HandleScope::new(&heap);
+            scope.create::<DropObject>().unwrap();
+        }
+        heap.collect().unwrap();
+        let received: Vec<GcEvent> = events.try_iter().collect();
+        assert_eq!(received.len(), 3);
+        assert!(matches!(received[0], GcEvent::CollectionStarted { .. }));
+        assert!(matches!(
+            received[1],
+            GcEvent::CollectionFinished {
+                used_bytes_after: 0,
+                objects_finalized: 1,
+                ..
+            }
+        ));
+        assert_eq!(received[2], GcEvent::FinalizersRun { count: 1 });
+
+        // Dropped receivers are forgotten rather than failing collection.
+        std::mem::drop(events);
+        heap.collect().unwrap();
+    }
+
+    #[test]
+    fn growth_test() {
+        let config = HeapConfig {
+            initial_size_in_bytes: 1000,
+            min_size_in_bytes: 1000,
+            max_size_in_bytes: 6000,
+            growth_factor: 2.0,
+            ..HeapConfig::default()
+        };
+        let heap = Heap::with_config(config).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        // Adds strings until they and the list no longer fit in `bytes`,
+        // whatever the size of an object on this target.
+        let fill_past = |bytes: usize| {
+            while (list.as_ref().len() + 1) * HOST_OBJECT_ALLOC_SIZE <= bytes {
+                let inner = HandleScope::new(&heap);
+                list.as_mut().push(inner.str("kept").unwrap().into());
+            }
+        };
+        // More than fits in half the initial size, so the heap doubles.
+        fill_past(500);
+        assert_eq!(heap.size_in_bytes(), 2000);
+        fill_past(1000);
+        assert_eq!(heap.size_in_bytes(), 4000);
+        // Growth stops at the maximum size.
+        fill_past(2000);
+        assert_eq!(heap.size_in_bytes(), 6000);
+        let result = loop {
+            let inner = HandleScope::new(&heap);
+            match inner.str("full") {
+                Ok(string) => list.as_mut().push(string.into()),
+                Err(error) => break error,
+            }
+        };
+        assert!(matches!(result, GCError::NoSpace));
+        assert_eq!(heap.size_in_bytes(), 6000);
+    }
+
+    #[test]
+    fn shrink_test() {
+        let config = HeapConfig {
+            initial_size_in_bytes: 8000,
+            min_size_in_bytes: 1000,
+            max_size_in_bytes: 8000,
+            growth_factor: 2.0,
+            shrink_threshold: 0.25,
+            ..HeapConfig::default()
+        };
+        let heap = Heap::with_config(config.clone()).unwrap();
+        let scope = HandleScope::new(&heap);
+        let string = scope.str("kept").unwrap();
+        // Each mostly empty collection halves the heap, down to the minimum.
+        heap.collect().unwrap();
+        assert_eq!(heap.size_in_bytes(), 4000);
+        heap.collect().unwrap();
+        heap.collect().unwrap();
+        heap.collect().unwrap();
+        assert_eq!(heap.size_in_bytes(), 1000);
+        assert_eq!(string.as_ref(), "kept");
+
+        let heap = Heap::with_config(HeapConfig {
+            min_size_in_bytes: 0,
+            shrink_threshold: 0.0,
+            ..config
+        })
+        .unwrap();
+        let scope = HandleScope::new(&heap);
+        let string = scope.str("kept").unwrap();
+        heap.collect().unwrap();
+        assert_eq!(heap.size_in_bytes(), 8000);
+        heap.shrink_to_fit().unwrap();
+        assert_eq!(heap.free_bytes(), 0);
+        assert_eq!(string.as_ref(), "kept");
+    }
+
+    #[test]
+    fn memory_pressure_test() {
+        let config = HeapConfig {
+            initial_size_in_bytes: 8000,
+            min_size_in_bytes: 1000,
+            max_size_in_bytes: 16000,
+            growth_factor: 2.0,
+            deferred_finalization: true,
+            ..HeapConfig::default()
+        };
+        let heap = Heap::with_config(config).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+        }
+        heap.notify_memory_pressure(MemoryPressure::Moderate)
+            .unwrap();
+        assert_eq!(heap.size_in_bytes(), 1000);
+        assert_eq!(heap.pending_finalizers(), 1);
+
+        // Under pressure the heap grows by a quarter rather than doubling.
+        heap.notify_memory_pressure(MemoryPressure::Critical)
+            .unwrap();
+        assert_eq!(heap.pending_finalizers(), 0);
+        while heap.size_in_bytes() == 1000 {
+            let inner = HandleScope::new(&heap);
+            list.as_mut().push(inner.str("kept").unwrap().into());
+        }
+        assert_eq!(heap.size_in_bytes(), 1250);
+
+        heap.notify_memory_pressure(MemoryPressure::None).unwrap();
+        while heap.size_in_bytes() == 1250 {
+            let inner = HandleScope::new(&heap);
+            list.as_mut().push(inner.str("kept").unwrap().into());
+        }
+        assert_eq!(heap.size_in_bytes(), 2500);
+    }
+
+    #[test]
+    fn resize_test() {
+        let config = HeapConfig {
+            initial_size_in_bytes: 1000,
+            min_size_in_bytes: 500,
+            max_size_in_bytes: 4000,
+            growth_factor: 2.0,
+            ..HeapConfig::default()
+        };
+        let heap = Heap::with_config(config).unwrap();
+        assert_eq!(heap.size_in_bytes(), 1000);
+        let scope = HandleScope::new(&heap);
+        let string = scope.str("foo").unwrap();
+        let used = heap.used_bytes();
+
+        heap.resize(4000).unwrap();
+        assert_eq!(heap.size_in_bytes(), 4000);
+        assert_eq!(heap.used_bytes(), used);
+        assert_eq!(string.as_ref(), "foo");
+
+        heap.resize(500).unwrap();
+        assert_eq!(heap.size_in_bytes(), 500);
+        assert_eq!(string.as_ref(), "foo");
+
+        assert!(matches!(heap.resize(8000), Err(GCError::InvalidConfig)));
+        assert!(matches!(heap.resize(100), Err(GCError::InvalidConfig)));
+        assert_eq!(heap.size_in_bytes(), 500);
+
+        // Heap::new heaps are fixed.
+        let fixed = Heap::new(1000).unwrap();
+        assert!(matches!(fixed.resize(2000), Err(GCError::InvalidConfig)));
+        let bad_config = HeapConfig {
+            growth_factor: 0.5,
+            ..HeapConfig::default()
+        };
+        assert!(Heap::with_config(bad_config).is_err());
+    }
+
     #[test]
     fn test_collect_on_allocate() {
         // Make a heap
@@ -845,4 +4813,402 @@ mod tests {
         scope.str("foo").unwrap();
         assert_eq!(heap.used_bytes(), one_object_size);
     }
+
+    #[test]
+    fn structural_key_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let pair = |a: f64, b: f64| -> HeapHandle<()> {
+            let list = scope.create::<List<()>>().unwrap();
+            list.as_mut().push(scope.create_num(a).into());
+            list.as_mut().push(scope.create_num(b).into());
+            list.erase_type().into()
+        };
+        let map = scope.create::<Map<(), ()>>().unwrap();
+        map.as_mut().insert(
+            pair(1.0, 2.0),
+            scope.str("one two").unwrap().erase_type().into(),
+        );
+        heap.collect().unwrap();
+        // Keys survive being moved, and equal lists find them.
+        let value = map.as_ref().get(&pair(1.0, 2.0)).cloned().unwrap();
+        assert_eq!(value.try_as_ref::<String>().unwrap(), "one two");
+        assert!(map.as_ref().get(&pair(2.0, 1.0)).is_none());
+
+        // Cycles don't recurse forever.
+        let cycle = scope.create::<List<()>>().unwrap();
+        cycle.as_mut().push(cycle.clone().into());
+        let key: HeapHandle<()> = cycle.erase_type().into();
+        map.as_mut().insert(key.clone(), key.clone());
+        assert!(map.as_ref().contains_key(&key));
+    }
+
+    #[test]
+    fn compare_test() {
+        use std::cmp::Ordering;
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let one = scope.create_num(1.0);
+        let two = scope.create_num(2.0);
+        assert_eq!(one.compare(&two), Some(Ordering::Less));
+        assert_eq!(scope.create_num(f64::NAN).compare(&one), None);
+        assert_eq!(
+            scope.create_bool(true).compare(&scope.create_bool(false)),
+            Some(Ordering::Greater)
+        );
+
+        let apple = scope.str("apple").unwrap();
+        let banana = scope.shared_str("banana").unwrap();
+        assert_eq!(apple.compare(&banana), Some(Ordering::Less));
+        assert_eq!(banana.compare(&apple), Some(Ordering::Greater));
+        assert_eq!(
+            apple.compare(&scope.str("apple").unwrap()),
+            Some(Ordering::Equal)
+        );
+        // Mixed kinds and objects without an order are unordered.
+        assert_eq!(apple.compare(&one), None);
+        let list = scope.create::<List<()>>().unwrap();
+        assert_eq!(list.compare(&list), None);
+    }
+
+    #[test]
+    fn hash_seed_test() {
+        let hash_of = |seed| {
+            let heap = Heap::with_config(HeapConfig {
+                hash_seed: Some(seed),
+                ..HeapConfig::fixed(1000)
+            })
+            .unwrap();
+            let scope = HandleScope::new(&heap);
+            let key: HeapHandle<String> = scope.str("key").unwrap().into();
+            heap.hash_state().hash_one(&key)
+        };
+        assert_eq!(hash_of(1), hash_of(1));
+        assert_ne!(hash_of(1), hash_of(2));
+    }
+
+    #[test]
+    fn identity_key_survives_moves_test() {
+        // Objects without content hashing are keyed by identity, which must
+        // not change when the collector moves them.
+        let collectors = [
+            CollectorKind::SemiSpace,
+            CollectorKind::Immix,
+            CollectorKind::Generational,
+            CollectorKind::MarkSweep,
+        ];
+        for collector in collectors {
+            let heap = Heap::with_config(HeapConfig {
+                collector,
+                ..HeapConfig::fixed(1 << 16)
+            })
+            .unwrap();
+            let scope = HandleScope::new(&heap);
+            let map = scope.create::<Map<(), f64>>().unwrap();
+            let keys: Vec<_> = (0..8)
+                .map(|_| scope.create::<DropObject>().unwrap().erase_type())
+                .collect();
+            for (index, key) in keys.iter().enumerate() {
+                let value = scope.create_num(index as f64);
+                map.as_mut().insert(key.clone().into(), value.into());
+            }
+            heap.collect().unwrap();
+            heap.compact().unwrap();
+            heap.collect().unwrap();
+            for (index, key) in keys.iter().enumerate() {
+                let value = map.as_ref().get(&key.clone().into()).cloned().unwrap();
+                let value: f64 = value.into();
+                assert_eq!(value, index as f64);
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_test() {
+        // Arrays hash by identity, so the map's iteration order (and so
+        // the order the collector copies them in) depends on identity
+        // hashes and the seed.
+        let run = || {
+            let heap = Heap::with_config(HeapConfig {
+                deterministic: true,
+                ..HeapConfig::fixed(100000)
+            })
+            .unwrap();
+            let scope = HandleScope::new(&heap);
+            let map = scope.create::<Map<(), ()>>().unwrap();
+            for index in 0..32 {
+                let key = scope.take(TypedArray::from(vec![index as f64])).unwrap();
+                map.as_mut()
+                    .insert(key.erase_type().into(), HeapHandle::new(TaggedPtr::NULL));
+            }
+            heap.collect().unwrap();
+            let offsets: Vec<usize> = map
+                .as_ref()
+                .keys()
+                .map(|key| {
+                    let key_ptr: ObjectPtr = key.ptr().try_into().unwrap();
+                    let map_ptr: ObjectPtr = map.ptr().try_into().unwrap();
+                    key_ptr.addr() as usize - map_ptr.addr() as usize
+                })
+                .collect();
+            (map.display(&scope), offsets)
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn hash_algorithm_test() {
+        let heap = Heap::with_config(HeapConfig {
+            hash_algorithm: HashAlgorithm::FxHash,
+            ..HeapConfig::fixed(10000)
+        })
+        .unwrap();
+        assert_eq!(heap.hash_state().algorithm(), HashAlgorithm::FxHash);
+        let scope = HandleScope::new(&heap);
+        let map = scope.create_map::<String, f64>().unwrap();
+        map.as_mut()
+            .insert(scope.str("a").unwrap().into(), 1.0.into());
+        heap.collect().unwrap();
+        assert_eq!(map.as_ref().hasher().algorithm(), HashAlgorithm::FxHash);
+        let key = scope.str("a").unwrap().into();
+        assert!(map.as_ref().contains_key(&key));
+    }
+
+    #[test]
+    fn intern_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let a = scope.intern("a").unwrap();
+        assert!(a.ptr() == scope.intern("a").unwrap().ptr());
+        {
+            let inner_scope = HandleScope::new(&heap);
+            inner_scope.intern("dynamic").unwrap();
+        }
+        let before = heap.intern_stats();
+        assert_eq!(before.entries, 2);
+        heap.collect().unwrap();
+        assert_eq!(
+            heap.intern_stats(),
+            InternStats {
+                entries: 1,
+                evictions: 1
+            }
+        );
+        // Surviving entries follow their string when it moves.
+        assert!(a.ptr() == scope.intern("a").unwrap().ptr());
+        assert_eq!(heap.used_bytes(), HOST_OBJECT_ALLOC_SIZE);
+    }
+
+    #[test]
+    fn is_interned_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let a = scope.intern("a").unwrap();
+        let copy = scope.str("a").unwrap();
+        assert!(heap.is_interned(&a));
+        assert!(!heap.is_interned(&copy));
+        assert!(!heap.is_interned(&scope.str("b").unwrap()));
+        heap.collect().unwrap();
+        assert!(heap.is_interned(&a));
+        assert!(a.ptr_eq(&scope.intern("a").unwrap()));
+        assert!(!a.ptr_eq(&copy));
+        assert!(a.ptr() == copy.ptr());
+        assert!(scope.create_num(1.0).ptr_eq(&scope.create_num(1.0)));
+        assert!(!scope.create_null().ptr_eq(&a));
+    }
+
+    #[test]
+    fn symbol_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let foo = scope.symbol("foo").unwrap();
+        assert!(foo.ptr() == scope.symbol("foo").unwrap().ptr());
+        assert!(foo.ptr() != scope.symbol("bar").unwrap().ptr());
+        // Symbols and strings are interned separately.
+        assert!(foo.ptr() != scope.intern("foo").unwrap().ptr());
+        assert_eq!(foo.as_ref().name(), "foo");
+        {
+            let inner = HandleScope::new(&heap);
+            inner.symbol("dynamic").unwrap();
+        }
+        heap.collect().unwrap();
+        assert_eq!(heap.inner.borrow().symbols.stats().entries, 2);
+        // Surviving entries follow their symbol when it moves.
+        assert!(foo.ptr() == scope.symbol("foo").unwrap().ptr());
+        assert_eq!(foo.display(&scope), "foo");
+    }
+
+    #[test]
+    fn named_root_test() {
+        let heap = Heap::new(1000).unwrap();
+        {
+            let scope = HandleScope::new(&heap);
+            heap.register_named_root("core.String", scope.str("String").unwrap().into());
+            heap.register_named_root("core.Bool", scope.create_bool(true).into());
+        }
+        heap.collect().unwrap();
+        assert_eq!(heap.named_roots(), vec!["core.Bool", "core.String"]);
+
+        let scope = HandleScope::new(&heap);
+        let string = heap.lookup_named_root(&scope, "core.String").unwrap();
+        assert_eq!(string.try_as_ref::<String>().unwrap(), "String");
+        assert!(heap.lookup_named_root(&scope, "core.Num").is_none());
+
+        assert!(heap.unregister_named_root("core.String"));
+        assert!(!heap.unregister_named_root("core.String"));
+        std::mem::drop(string);
+        std::mem::drop(scope);
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), 0);
+    }
+
+    #[test]
+    fn patch_site_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let kept = scope.str("kept").unwrap();
+        let moves = Rc::new(RefCell::new(vec![]));
+        let recorded = moves.clone();
+        heap.register_patch_site(&kept.clone().into(), move |old, new| {
+            recorded.borrow_mut().push((old, new));
+        });
+        let old_addr = kept.get_object_ptr().unwrap().addr();
+        heap.collect().unwrap();
+        let new_addr = kept.get_object_ptr().unwrap().addr();
+        assert_eq!(*moves.borrow(), vec![(old_addr, new_addr)]);
+
+        // Sites for collected objects are dropped, unregistered ones never run.
+        let calls = Rc::new(Cell::new(0));
+        {
+            let inner_scope = HandleScope::new(&heap);
+            let dropped = inner_scope.str("dropped").unwrap();
+            let counter = calls.clone();
+            heap.register_patch_site(&dropped.into(), move |_, _| counter.set(counter.get() + 1));
+        }
+        let counter = calls.clone();
+        let id = heap.register_patch_site(&kept.clone().into(), move |_, _| {
+            counter.set(counter.get() + 1)
+        });
+        heap.unregister_patch_site(id);
+        heap.collect().unwrap();
+        assert_eq!(calls.get(), 0);
+        assert_eq!(moves.borrow().len(), 2);
+    }
+
+    struct Pair {
+        value: f64,
+        next: HeapHandle<()>,
+        counter: Rc<Cell<u32>>,
+    }
+
+    impl HostObject for Pair {}
+
+    impl Traceable for Pair {
+        fn trace(&mut self, visitor: &mut ObjectVisitor) {
+            self.next.trace(visitor);
+        }
+    }
+
+    impl Drop for Pair {
+        fn drop(&mut self) {
+            self.counter.set(self.counter.get() + 1);
+        }
+    }
+
+    #[test]
+    fn payload_stays_put_test() {
+        let collectors = [
+            CollectorKind::SemiSpace,
+            CollectorKind::Immix,
+            CollectorKind::Generational,
+            CollectorKind::MarkSweep,
+        ];
+        for collector in collectors {
+            let heap = Heap::with_config(HeapConfig {
+                collector,
+                ..HeapConfig::fixed(1 << 16)
+            })
+            .unwrap();
+            let counter = Rc::new(Cell::new(0));
+            {
+                let scope = HandleScope::new(&heap);
+                let pair = scope
+                    .take(Pair {
+                        value: 1.5,
+                        next: HeapHandle::default(),
+                        counter: Rc::clone(&counter),
+                    })
+                    .unwrap();
+                pair.as_mut().next = scope.str("next").unwrap().erase_type().into();
+                // Only the header moves, so a reference taken before a
+                // collection still refers to the object after it.
+                let mut payload = pair.as_mut();
+                heap.collect().unwrap();
+                heap.compact().unwrap();
+                payload.value += 1.0;
+                heap.collect().unwrap();
+                assert_eq!(pair.as_ref().value, 2.5);
+                let next = pair.as_ref().next.try_as_ref::<String>().unwrap();
+                assert_eq!(next, "next");
+                assert_eq!(counter.get(), 0);
+            }
+            heap.collect().unwrap();
+            assert_eq!(counter.get(), 1);
+        }
+
+        // Likewise between the steps of an incremental collection, where
+        // handles may still refer to an object's old copy.
+        let heap = Heap::new(1 << 16).unwrap();
+        let scope = HandleScope::new(&heap);
+        let counter = Rc::new(Cell::new(0));
+        let pair = scope
+            .take(Pair {
+                value: 1.0,
+                next: HeapHandle::default(),
+                counter,
+            })
+            .unwrap();
+        let list = scope.create::<List<Pair>>().unwrap();
+        list.as_mut().push(pair.clone().into());
+        for _ in 0..20 {
+            scope.str("filler").unwrap();
+        }
+        // The pair is copied before the list is traced, so the list's handle
+        // still refers to the pair's old copy.
+        assert!(!heap.collect_step(HOST_OBJECT_ALLOC_SIZE).unwrap());
+        list.as_ref()[0].as_mut().value = 7.0;
+        while !heap.collect_step(HOST_OBJECT_ALLOC_SIZE).unwrap() {}
+        assert_eq!(pair.as_ref().value, 7.0);
+        assert_eq!(list.as_ref()[0].as_ref().value, 7.0);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn bump_pointer_test() {
+        let collectors = [
+            CollectorKind::SemiSpace,
+            CollectorKind::Immix,
+            CollectorKind::Generational,
+            CollectorKind::MarkSweep,
+        ];
+        for collector in collectors {
+            let heap = Heap::with_config(HeapConfig {
+                collector,
+                ..HeapConfig::fixed(1 << 16)
+            })
+            .unwrap();
+            let scope = HandleScope::new(&heap);
+            // Allocate once from Rust so Immix has a hole to bump into.
+            scope.str("first").unwrap();
+            let handle = unsafe {
+                let bump = heap.bump_pointer().unwrap();
+                let addr = *bump.top;
+                assert!(addr + Heap::HOST_OBJECT_ALLOC_SIZE <= *bump.limit);
+                *bump.top = addr + Heap::HOST_OBJECT_ALLOC_SIZE;
+                scope.adopt_raw(addr as *mut u8, "jit".to_string())
+            };
+            heap.collect().unwrap();
+            assert_eq!(*handle.as_ref(), "jit");
+        }
+    }
 }
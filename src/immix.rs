@@ -0,0 +1,463 @@
+use std::alloc::{alloc, dealloc, Layout};
+
+#[cfg(feature = "jit")]
+use crate::collector::BumpPointer;
+use crate::collector::CollectorStrategy;
+use crate::object::HeapHandle;
+use crate::pointer::*;
+use crate::types::*;
+
+// Sizes follow the Immix paper: 32KB blocks divided into 128 byte lines.
+pub(crate) const BLOCK_SIZE: usize = 32 * 1024;
+const LINE_SIZE: usize = 128;
+const LINES_PER_BLOCK: usize = BLOCK_SIZE / LINE_SIZE;
+
+struct Block {
+    base: *mut u8,
+    // A marked line may hold an object.  Lines are marked when handed to the
+    // bump allocator and recomputed from the survivors after a collection.
+    line_marks: [bool; LINES_PER_BLOCK],
+    // Set during a collection when the block's live objects are being moved
+    // out of it.
+    evacuating: bool,
+}
+
+impl Block {
+    fn new() -> Result<Block, GCError> {
+        let base = unsafe { alloc(Self::layout()) };
+        if base.is_null() {
+            return Err(GCError::OSOutOfMemory);
+        }
+        Ok(Block {
+            base,
+            line_marks: [false; LINES_PER_BLOCK],
+            evacuating: false,
+        })
+    }
+
+    fn layout() -> Layout {
+        // Blocks are aligned to their size so an address finds its block by
+        // masking.
+        Layout::from_size_align(BLOCK_SIZE, BLOCK_SIZE).unwrap()
+    }
+
+    fn contains(&self, addr: *mut u8) -> bool {
+        (addr as usize) & !(BLOCK_SIZE - 1) == self.base as usize
+    }
+
+    fn line_of(&self, addr: *mut u8) -> usize {
+        (addr as usize - self.base as usize) / LINE_SIZE
+    }
+
+    fn line_addr(&self, line: usize) -> *mut u8 {
+        unsafe { self.base.add(line * LINE_SIZE) }
+    }
+
+    fn marked_lines(&self) -> usize {
+        self.line_marks.iter().filter(|marked| **marked).count()
+    }
+
+    fn holes(&self) -> usize {
+        let mut holes = 0;
+        let mut previous_marked = true;
+        for marked in self.line_marks.iter() {
+            if !marked && previous_marked {
+                holes += 1;
+            }
+            previous_marked = *marked;
+        }
+        holes
+    }
+
+    // Finds the first run of unmarked lines at or after `from_line` which is
+    // at least `size` bytes, returning its [start, end) lines.
+    fn find_hole(&self, from_line: usize, size: usize) -> Option<(usize, usize)> {
+        let lines_needed = size.div_ceil(LINE_SIZE);
+        let mut line = from_line;
+        while line < LINES_PER_BLOCK {
+            if self.line_marks[line] {
+                line += 1;
+                continue;
+            }
+            let start = line;
+            while line < LINES_PER_BLOCK && !self.line_marks[line] {
+                line += 1;
+            }
+            if line - start >= lines_needed {
+                return Some((start, line));
+            }
+        }
+        None
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.base, Self::layout()) };
+    }
+}
+
+// Bump allocates through the holes of a list of blocks.
+#[derive(Default)]
+struct BumpCursor {
+    cursor: usize,
+    limit: usize,
+    block: usize,
+    line: usize,
+}
+
+impl BumpCursor {
+    fn remaining(&self) -> usize {
+        self.limit - self.cursor
+    }
+}
+
+/// A mark-region collector after Immix.  Objects are bump allocated into
+/// holes of free lines within 32KB blocks.  Collection marks objects in
+/// place and only evacuates the live objects of fragmented blocks, so unlike
+/// semispace copying the whole budget is usable for allocation.
+pub struct ImmixCollector {
+    blocks: Vec<Block>,
+    max_blocks: usize,
+    allocator: BumpCursor,
+    // Only used while collecting, allocates the copies of evacuated objects.
+    evacuator: BumpCursor,
+    used_bytes: usize,
+}
+
+impl ImmixCollector {
+    pub fn new(size_in_bytes: usize) -> ImmixCollector {
+        ImmixCollector {
+            blocks: vec![],
+            max_blocks: Self::blocks_for(size_in_bytes),
+            allocator: BumpCursor::default(),
+            evacuator: BumpCursor::default(),
+            used_bytes: 0,
+        }
+    }
+
+    // Budgets are rounded up to whole blocks.
+    fn blocks_for(size_in_bytes: usize) -> usize {
+        size_in_bytes.div_ceil(BLOCK_SIZE).max(1)
+    }
+
+    fn block_index_of(&self, addr: *mut u8) -> usize {
+        self.blocks
+            .iter()
+            .position(|block| block.contains(addr))
+            .unwrap()
+    }
+
+    // Moves `bump` to the next hole (skipping evacuating blocks) which can
+    // fit `size`, adding a block if the budget allows.  The hole's lines are
+    // marked as they are handed out.
+    fn next_hole(&mut self, which: CursorKind, size: usize) -> Result<(), GCError> {
+        if size > BLOCK_SIZE {
+            return Err(GCError::NoSpace);
+        }
+        let mut bump = std::mem::take(self.cursor_mut(which));
+        let result = loop {
+            if bump.block == self.blocks.len() {
+                if self.blocks.len() >= self.max_blocks {
+                    break Err(GCError::NoSpace);
+                }
+                self.blocks.push(Block::new()?);
+            }
+            let block = &mut self.blocks[bump.block];
+            let hole = if block.evacuating {
+                None
+            } else {
+                block.find_hole(bump.line, size)
+            };
+            match hole {
+                Some((start, end)) => {
+                    for line in start..end {
+                        block.line_marks[line] = true;
+                    }
+                    bump.cursor = block.line_addr(start) as usize;
+                    bump.limit = bump.cursor + (end - start) * LINE_SIZE;
+                    bump.line = end;
+                    break Ok(());
+                }
+                None => {
+                    bump.block += 1;
+                    bump.line = 0;
+                }
+            }
+        };
+        *self.cursor_mut(which) = bump;
+        result
+    }
+
+    fn cursor_mut(&mut self, which: CursorKind) -> &mut BumpCursor {
+        match which {
+            CursorKind::Mutator => &mut self.allocator,
+            CursorKind::Evacuator => &mut self.evacuator,
+        }
+    }
+
+    fn bump(&mut self, which: CursorKind, size: usize) -> Result<*mut u8, GCError> {
+        if self.cursor_mut(which).remaining() < size {
+            self.next_hole(which, size)?;
+        }
+        let bump = self.cursor_mut(which);
+        let result = bump.cursor as *mut u8;
+        bump.cursor += size;
+        unsafe { result.write_bytes(0, size) };
+        Ok(result)
+    }
+
+    fn unmarked_line_bytes(&self) -> usize {
+        let marked: usize = self.blocks.iter().map(Block::marked_lines).sum();
+        (self.blocks.len() * LINES_PER_BLOCK - marked) * LINE_SIZE
+    }
+
+    // Picks sparse, fragmented blocks to evacuate, as many as the free lines
+    // elsewhere can absorb.  Starts over if a collection is prepared again.
+    fn select_evacuation_candidates(&mut self) {
+        for block in self.blocks.iter_mut() {
+            block.evacuating = false;
+        }
+        let mut candidates: Vec<usize> = (0..self.blocks.len())
+            .filter(|index| {
+                let block = &self.blocks[*index];
+                let marked = block.marked_lines();
+                marked > 0 && marked <= LINES_PER_BLOCK / 2 && block.holes() > 1
+            })
+            .collect();
+        candidates.sort_by_key(|index| self.blocks[*index].marked_lines());
+        let unallocated_blocks = self.max_blocks.saturating_sub(self.blocks.len());
+        let mut headroom = self.unmarked_line_bytes() + unallocated_blocks * BLOCK_SIZE;
+        for index in candidates {
+            let block = &mut self.blocks[index];
+            let needed = block.marked_lines() * LINE_SIZE;
+            let freed = (LINES_PER_BLOCK - block.marked_lines()) * LINE_SIZE;
+            if needed > headroom.saturating_sub(freed) {
+                break;
+            }
+            headroom -= needed + freed;
+            block.evacuating = true;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum CursorKind {
+    Mutator,
+    Evacuator,
+}
+
+impl CollectorStrategy for ImmixCollector {
+    fn size_in_bytes(&self) -> usize {
+        self.max_blocks * BLOCK_SIZE
+    }
+
+    fn capacity_for(&self, size_in_bytes: usize) -> usize {
+        Self::blocks_for(size_in_bytes) * BLOCK_SIZE
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    fn free_bytes(&self) -> usize {
+        let unallocated_blocks = self.max_blocks.saturating_sub(self.blocks.len());
+        self.unmarked_line_bytes() + self.allocator.remaining() + unallocated_blocks * BLOCK_SIZE
+    }
+
+    fn alloc(&mut self, size: usize) -> Result<*mut u8, GCError> {
+        let result = self.bump(CursorKind::Mutator, size)?;
+        self.used_bytes += size;
+        Ok(result)
+    }
+
+    fn unalloc(&mut self, addr: *mut u8, size: usize) {
+        // Only the most recent allocation can be bumped back over.
+        if addr as usize + size == self.allocator.cursor {
+            self.allocator.cursor = addr as usize;
+            self.used_bytes -= size;
+        }
+    }
+
+    fn prepare_collection(&mut self, size_in_bytes: usize) -> Result<(), GCError> {
+        self.max_blocks = Self::blocks_for(size_in_bytes);
+        self.select_evacuation_candidates();
+        self.evacuator = BumpCursor::default();
+        Ok(())
+    }
+
+    fn relocate(&mut self, header: &mut ObjectHeader) -> HeaderPtr {
+        let header_ptr = header.as_ptr();
+        let index = self.block_index_of(header_ptr.addr());
+        if self.blocks[index].evacuating {
+            // Evacuation is opportunistic, objects stay put if there is no
+            // room to move them.
+            if let Ok(addr) = self.bump(CursorKind::Evacuator, header.alloc_size()) {
+                return HeaderPtr::new(addr);
+            }
+        }
+        header_ptr
+    }
+
+    // Generated code bumping the cursor isn't counted in used_bytes until
+    // the next collection recounts the survivors.
+    #[cfg(feature = "jit")]
+    fn bump_pointer(&mut self) -> Option<BumpPointer> {
+        Some(BumpPointer {
+            top: &mut self.allocator.cursor,
+            limit: &self.allocator.limit,
+        })
+    }
+
+    fn finish_collection(&mut self, survivors: &[HeapHandle<()>]) {
+        for block in self.blocks.iter_mut() {
+            block.line_marks = [false; LINES_PER_BLOCK];
+            block.evacuating = false;
+        }
+        self.used_bytes = 0;
+        for survivor in survivors {
+            let ptr = survivor.ptr();
+            let header = ptr.header().unwrap();
+            let start = header.as_ptr().addr();
+            let size = header.alloc_size();
+            let index = self.block_index_of(start);
+            let block = &mut self.blocks[index];
+            let last = unsafe { start.add(size - 1) };
+            for line in block.line_of(start)..=block.line_of(last) {
+                block.line_marks[line] = true;
+            }
+            self.used_bytes += size;
+        }
+        // Return empty blocks beyond the budget, e.g. after shrinking.
+        while self.blocks.len() > self.max_blocks {
+            match self
+                .blocks
+                .iter()
+                .position(|block| block.marked_lines() == 0)
+            {
+                Some(index) => {
+                    self.blocks.remove(index);
+                }
+                None => break,
+            }
+        }
+        self.allocator = BumpCursor::default();
+        self.evacuator = BumpCursor::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::*;
+    use crate::heap::*;
+    use crate::object::*;
+
+    use std::cell::Cell;
+    use std::convert::TryInto;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct DropObject {
+        counter: Rc<Cell<u32>>,
+    }
+
+    impl HostObject for DropObject {}
+
+    impl Traceable for DropObject {
+        fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+    }
+
+    impl Drop for DropObject {
+        fn drop(&mut self) {
+            self.counter.set(self.counter.get() + 1);
+        }
+    }
+
+    fn immix_heap(size_in_bytes: usize) -> Heap {
+        Heap::with_config(HeapConfig {
+            collector: CollectorKind::Immix,
+            ..HeapConfig::fixed(size_in_bytes)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn whole_budget_is_usable_test() {
+        let heap = immix_heap(2 * BLOCK_SIZE);
+        assert_eq!(heap.size_in_bytes(), 2 * BLOCK_SIZE);
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        // Far more than a semispace heap of the same size could hold.
+        while heap.used_bytes() < BLOCK_SIZE + BLOCK_SIZE / 2 {
+            list.as_mut().push(scope.str("foo").unwrap().into());
+        }
+        heap.collect().unwrap();
+        assert!(heap.used_bytes() > BLOCK_SIZE);
+        assert!(list.as_ref().iter().all(|s| s.as_ref() == "foo"));
+    }
+
+    #[test]
+    fn sweep_and_reuse_test() {
+        let heap = immix_heap(BLOCK_SIZE);
+        let counter = Rc::new(Cell::new(0));
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<DropObject>>().unwrap();
+        for _ in 0..20 {
+            let inner = scope.create_child_scope();
+            inner.create::<DropObject>().unwrap().as_mut().counter = Rc::clone(&counter);
+            let kept = scope.create::<DropObject>().unwrap();
+            kept.as_mut().counter = Rc::clone(&counter);
+            list.as_mut().push(kept.into());
+        }
+        let used = heap.used_bytes();
+        heap.collect().unwrap();
+        assert_eq!(counter.get(), 20);
+        assert!(heap.used_bytes() < used);
+
+        // Filling the heap repeatedly only works if dead lines are reused.
+        for _ in 0..10_000 {
+            let inner = scope.create_child_scope();
+            inner.str("garbage").unwrap();
+        }
+        assert_eq!(list.as_ref().len(), 20);
+        heap.collect().unwrap();
+        assert_eq!(counter.get(), 20);
+    }
+
+    #[test]
+    fn evacuates_fragmented_blocks_test() {
+        fn addr(handle: &HeapHandle<String>) -> usize {
+            let object_ptr: ObjectPtr = handle.ptr().try_into().unwrap();
+            object_ptr.addr() as usize
+        }
+
+        let heap = immix_heap(4 * BLOCK_SIZE);
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        // Fill most of the first block, keeping every 16th string alive.
+        for index in 0..700 {
+            let inner = scope.create_child_scope();
+            let string = inner.str("foo").unwrap();
+            if index % 16 == 0 {
+                list.as_mut().push(string.into());
+            }
+        }
+        let start: Vec<usize> = list.as_ref().iter().map(addr).collect();
+
+        // The block was full when the collection started, nothing moves.
+        heap.collect().unwrap();
+        let after_mark: Vec<usize> = list.as_ref().iter().map(addr).collect();
+        assert_eq!(start, after_mark);
+
+        // Now the block is sparse and fragmented, so the survivors are
+        // evacuated into a fresh block.
+        heap.collect().unwrap();
+        let after_evacuate: Vec<usize> = list.as_ref().iter().map(addr).collect();
+        let old_block = start[0] & !(BLOCK_SIZE - 1);
+        assert!(after_evacuate
+            .iter()
+            .all(|addr| addr & !(BLOCK_SIZE - 1) != old_block));
+        assert!(list.as_ref().iter().all(|s| s.as_ref() == "foo"));
+    }
+}
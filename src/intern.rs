@@ -0,0 +1,110 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use crate::display::ValueFormatter;
+use crate::object::*;
+use crate::pointer::*;
+
+/// Counters for the intern table, see Heap::intern_stats.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InternStats {
+    // Strings currently interned.
+    pub entries: usize,
+    // Strings dropped from the table because nothing else held them.
+    pub evictions: usize,
+}
+
+/// A name made by HandleScope::symbol, e.g. for a method or property.
+/// There is only ever one live Symbol per name in a heap, so symbols
+/// compare equal (and hash) by identity without looking at the text.
+#[derive(Debug)]
+pub struct Symbol {
+    name: String,
+}
+
+impl Symbol {
+    pub(crate) fn new(name: &str) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl HostObject for Symbol {}
+
+impl Traceable for Symbol {
+    fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+
+    // Sorted by name, so the order doesn't depend on allocation order.
+    fn object_cmp(&self, _lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> Option<Ordering> {
+        let rhs_ptr = TraceableObject::try_downcast::<Symbol>(rhs_object_ptr)?;
+        Some(self.name.cmp(&unsafe { &*rhs_ptr }.name))
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_str(&self.name)
+    }
+}
+
+// Maps text to its canonical object, a String or Symbol.  Entries are weak:
+// the table doesn't keep objects alive, and entries for collected objects
+// are pruned during collection.
+pub(crate) struct InternTable<T = String> {
+    entries: HashMap<String, HeapHandle<T>>,
+    evictions: usize,
+}
+
+impl<T> Default for InternTable<T> {
+    fn default() -> Self {
+        InternTable {
+            entries: HashMap::new(),
+            evictions: 0,
+        }
+    }
+}
+
+impl<T> InternTable<T> {
+    pub(crate) fn get(&self, text: &str) -> Option<HeapHandle<T>> {
+        self.entries.get(text).cloned()
+    }
+
+    // Whether `object_ptr` is the canonical object for `text`.
+    pub(crate) fn is_canonical(&self, text: &str, object_ptr: ObjectPtr) -> bool {
+        self.entries.get(text).is_some_and(|handle| {
+            let canonical: ObjectPtr = handle.ptr().try_into().unwrap();
+            read_barrier(canonical).addr() == object_ptr.addr()
+        })
+    }
+
+    pub(crate) fn insert(&mut self, text: &str, handle: HeapHandle<T>) {
+        self.entries.insert(text.to_string(), handle);
+    }
+
+    // Must run after tracing and before forwarding pointers are cleared.
+    pub(crate) fn update_weak(&mut self) {
+        let before = self.entries.len();
+        self.entries.retain(|_, handle| {
+            let object_ptr: ObjectPtr = handle.ptr().try_into().unwrap();
+            match object_ptr.header().new_header_ptr {
+                Some(new_header_ptr) => {
+                    *handle = HeapHandle::new(new_header_ptr.to_object_ptr().into());
+                    true
+                }
+                None => false,
+            }
+        });
+        self.evictions += before - self.entries.len();
+    }
+
+    pub(crate) fn stats(&self) -> InternStats {
+        InternStats {
+            entries: self.entries.len(),
+            evictions: self.evictions,
+        }
+    }
+}
@@ -1,10 +1,74 @@
+mod byte_array;
+mod collector;
+mod config;
+mod control_blocks;
+mod convert;
+mod deque;
+mod display;
+mod dot;
+mod dump;
+mod events;
+mod gc_cell;
+mod gc_string;
+mod generational;
+mod hash;
 mod heap;
+mod immix;
+mod intern;
+mod mark_sweep;
 mod object;
+mod ordered_map;
 mod pointer;
+mod profile;
+mod rope;
+mod shared_str;
+mod small_vec;
+mod snapshot;
+mod sorted_map;
 mod space;
+mod stack;
+mod std_traceable;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod type_registry;
+mod typed_array;
 mod types;
+mod weak_set;
 
-pub use heap::{DowncastTo, GlobalHandle, HandleScope, Heap, LocalHandle};
-pub use object::{HeapHandle, HostObject, List, Map, ObjectVisitor, Traceable};
-pub use pointer::ObjectType;
+pub use byte_array::ByteArray;
+#[cfg(feature = "jit")]
+pub use collector::BumpPointer;
+pub use collector::{CollectorStrategy, SemiSpaceCollector};
+pub use config::{CollectorKind, HeapConfig, MemoryPressure, OomPolicy};
+pub use convert::{IntoHeap, TryFromHeap};
+pub use deque::Deque;
+pub use display::ValueFormatter;
+pub use dump::{DumpedObject, HeapDump};
+pub use events::GcEvent;
+pub use gc_cell::GcCell;
+pub use gc_string::GcString;
+pub use generational::GenerationalCollector;
+pub use hash::{HashAlgorithm, HeapHasher, SeededState};
+pub use heap::{
+    CollectionStats, DowncastTo, Ephemeron, EscapableHandleScope, GcPhase, GlobalHandle,
+    HandleScope, HandleStats, Heap, LocalHandle, PatchSiteId, PersistentHandle, SealedHandleScope,
+    TypeUsage, UniqueHandle, WeakHandle,
+};
+pub use immix::ImmixCollector;
+pub use intern::{InternStats, Symbol};
+pub use mark_sweep::MarkSweepCollector;
+pub use object::{HeapHandle, HostObject, List, Map, ObjectVisitor, Set, Traceable};
+pub use ordered_map::OrderedMap;
+pub use pointer::{HeaderFlags, HeaderPtr, ObjectHeader, ObjectType, ValueKind};
+pub use profile::{AllocationProfile, TypeAllocations};
+pub use rope::{Chunks, Rope};
+pub use shared_str::SharedStr;
+pub use sorted_map::SortedMap;
+pub use space::{CardTable, Space, WriteBarrier, CARD_SIZE};
+pub use stack::{Frame, ValueStack};
+pub use typed_array::{
+    ArrayElement, Float32Array, Float64Array, Int32Array, TypedArray, TypedArrayView, Uint32Array,
+    Uint8Array,
+};
 pub use types::GCError;
+pub use weak_set::WeakSet;
@@ -0,0 +1,224 @@
+#[cfg(feature = "jit")]
+use crate::collector::BumpPointer;
+use crate::collector::CollectorStrategy;
+use crate::object::HeapHandle;
+use crate::pointer::*;
+use crate::space::*;
+use crate::types::*;
+use std::rc::Rc;
+
+/// Never moves objects, for embedders which hand raw object addresses to
+/// code the collector can't update.  Tracing marks reachable objects by
+/// forwarding them to themselves, then the space is swept lazily: the memory
+/// of everything else goes on its free list as allocations need it.
+///
+/// Heap::compact slides the survivors to the start of the space, for
+/// embedders which can tolerate the occasional move to undo fragmentation.
+/// The whole budget is usable, but the heap can't be resized.
+pub struct MarkSweepCollector {
+    space: Space,
+    barrier: Rc<WriteBarrier>,
+}
+
+impl MarkSweepCollector {
+    pub fn new(size_in_bytes: usize) -> Result<MarkSweepCollector, GCError> {
+        let barrier = Rc::new(WriteBarrier::default());
+        Ok(MarkSweepCollector {
+            space: Space::with_barrier(size_in_bytes, &barrier)?,
+            barrier,
+        })
+    }
+}
+
+impl CollectorStrategy for MarkSweepCollector {
+    fn size_in_bytes(&self) -> usize {
+        self.space.size_in_bytes
+    }
+
+    fn capacity_for(&self, size_in_bytes: usize) -> usize {
+        size_in_bytes
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.space.used_bytes()
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.space.free_bytes()
+    }
+
+    fn alloc(&mut self, size: usize) -> Result<*mut u8, GCError> {
+        self.space.alloc(size)
+    }
+
+    fn unalloc(&mut self, addr: *mut u8, size: usize) {
+        self.space.free(addr, size);
+    }
+
+    fn prepare_collection(&mut self, size_in_bytes: usize) -> Result<(), GCError> {
+        // Growing would need a second space, objects can't move to a new one.
+        if size_in_bytes != self.space.size_in_bytes {
+            return Err(GCError::InvalidConfig);
+        }
+        Ok(())
+    }
+
+    // Nothing moves, and new objects only reuse memory which was free
+    // before the collection started.
+    fn supports_incremental(&self) -> bool {
+        true
+    }
+
+    fn write_barrier(&self) -> Option<&WriteBarrier> {
+        Some(&self.barrier)
+    }
+
+    fn relocate(&mut self, header: &mut ObjectHeader) -> HeaderPtr {
+        header.as_ptr()
+    }
+
+    fn finish_collection(&mut self, survivors: &[HeapHandle<()>]) {
+        let mut live: Vec<(*mut u8, usize)> = survivors
+            .iter()
+            .map(|survivor| {
+                let ptr = survivor.ptr();
+                let header = ptr.header().unwrap();
+                (header.as_ptr().addr(), header.alloc_size())
+            })
+            .collect();
+        live.sort_unstable_by_key(|(addr, _)| *addr as usize);
+        self.space.sweep(live);
+    }
+
+    fn plan_compaction(&mut self, survivors: &[HeapHandle<()>]) -> Option<Vec<HeaderPtr>> {
+        let mut next = self.space.base();
+        let mut new_headers = vec![];
+        for survivor in survivors {
+            let ptr = survivor.ptr();
+            new_headers.push(HeaderPtr::new(next));
+            next = unsafe { next.add(ptr.header().unwrap().alloc_size()) };
+        }
+        Some(new_headers)
+    }
+
+    #[cfg(feature = "jit")]
+    fn bump_pointer(&mut self) -> Option<BumpPointer> {
+        Some(self.space.bump_pointer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::config::{CollectorKind, HeapConfig};
+    use crate::heap::*;
+    use crate::object::*;
+    use crate::space::Space;
+
+    fn mark_sweep_heap(size_in_bytes: usize) -> Heap {
+        Heap::with_config(HeapConfig {
+            collector: CollectorKind::MarkSweep,
+            ..HeapConfig::fixed(size_in_bytes)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn objects_dont_move_test() {
+        let heap = mark_sweep_heap(10000);
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        let mut addrs = vec![];
+        for index in 0..10 {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+            let kept = inner.str(&index.to_string()).unwrap();
+            addrs.push(kept.ptr_for_test().header().unwrap().as_ptr().addr());
+            list.as_mut().push(kept.into());
+        }
+        let used_bytes = heap.used_bytes();
+        heap.collect().unwrap();
+        // The garbage between the kept strings was swept.
+        assert!(heap.used_bytes() < used_bytes);
+        for (index, string) in list.as_ref().iter().enumerate() {
+            assert_eq!(string.ptr().header().unwrap().as_ptr().addr(), addrs[index]);
+            assert_eq!(string.as_ref(), &index.to_string());
+        }
+    }
+
+    #[test]
+    fn compact_test() {
+        let heap = mark_sweep_heap(10000);
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        for index in 0..10 {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+            list.as_mut()
+                .push(inner.str(&index.to_string()).unwrap().into());
+        }
+        heap.collect().unwrap();
+        let used_bytes = heap.used_bytes();
+        let moved = Rc::new(Cell::new(0));
+        let watched = moved.clone();
+        heap.register_patch_site(&list.as_ref()[9], move |_, _| {
+            watched.set(watched.get() + 1)
+        });
+
+        heap.compact().unwrap();
+        assert_eq!(heap.used_bytes(), used_bytes);
+        assert_eq!(moved.get(), 1);
+        for (index, string) in list.as_ref().iter().enumerate() {
+            assert_eq!(string.as_ref(), &index.to_string());
+        }
+        // The survivors are packed together.
+        let addrs: Vec<usize> = list
+            .as_ref()
+            .iter()
+            .map(|string| string.ptr().header().unwrap().as_ptr().addr() as usize)
+            .collect();
+        let lowest = *addrs.iter().min().unwrap();
+        assert!(addrs.iter().all(|addr| *addr < lowest + used_bytes));
+    }
+
+    #[test]
+    fn reuses_swept_memory_test() {
+        let heap = mark_sweep_heap(10000);
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        // Many times the heap's size, only possible by reusing freed memory.
+        for index in 0..2000 {
+            let inner = HandleScope::new(&heap);
+            let string = inner.str("kept").unwrap();
+            if index % 100 == 0 {
+                list.as_mut().push(string.into());
+            }
+        }
+        assert_eq!(list.as_ref().len(), 20);
+        assert!(list.as_ref().iter().all(|string| string.as_ref() == "kept"));
+    }
+
+    #[test]
+    fn lazy_sweep_test() {
+        // Full, so allocations must come from sweeping.
+        let mut space = Space::new(300).unwrap();
+        let first = space.alloc(100).unwrap();
+        let second = space.alloc(100).unwrap();
+        let third = space.alloc(100).unwrap();
+        space.sweep(vec![(second, 100)]);
+        assert!(space.is_sweeping());
+        assert_eq!(space.used_bytes(), 100);
+        assert_eq!(space.free_bytes(), 200);
+
+        // Sweeps only as far as the first gap.
+        assert_eq!(space.alloc(100).unwrap(), first);
+        assert!(space.is_sweeping());
+        // The gap after the last survivor goes back to bump allocation.
+        assert_eq!(space.alloc(100).unwrap(), third);
+        assert!(!space.is_sweeping());
+        assert_eq!(space.free_bytes(), 0);
+        assert!(space.alloc(1).is_err());
+    }
+}
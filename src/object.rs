@@ -1,48 +1,171 @@
+use std::alloc::Layout;
 use std::any::Any;
 use std::cell::Cell;
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
-use std::ops::IndexMut;
-
+use std::ops::{Deref, DerefMut, IndexMut};
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+
+use crate::byte_array::block_layout;
+use crate::collector::CollectorStrategy;
+use crate::convert::TryFromHeap;
+use crate::display::ValueFormatter;
+use crate::gc_string::GcString;
+use crate::hash::SeededState;
 use crate::heap::{HandleScope, LocalHandle};
 use crate::pointer::*;
-use crate::space::*;
+use crate::shared_str::SharedStr;
+use crate::small_vec::HandleVec;
+use crate::space::{close_write, open_write, write_barrier, WriteBarrier};
 use crate::types::GCError;
 
-pub struct ObjectVisitor {
-    pub new_space: Space,
-    pub queue: VecDeque<ObjectPtr>,
-}
-
-impl ObjectVisitor {
-    pub fn new(space: Space) -> ObjectVisitor {
+pub struct ObjectVisitor<'a> {
+    collector: &'a mut dyn CollectorStrategy,
+    queue: VecDeque<ObjectPtr>,
+    // The objects visited, while recording the heap's graph, see record.
+    recorded: Option<Vec<ObjectPtr>>,
+    // Values recorded whose bits aren't a valid TaggedPtr.
+    malformed: usize,
+}
+
+impl<'a> ObjectVisitor<'a> {
+    // Starts from `queue`, e.g. one left by into_queue.
+    pub(crate) fn with_queue(
+        collector: &'a mut dyn CollectorStrategy,
+        queue: VecDeque<ObjectPtr>,
+    ) -> ObjectVisitor<'a> {
         ObjectVisitor {
-            new_space: space,
-            queue: VecDeque::default(),
+            collector,
+            queue,
+            recorded: None,
+            malformed: 0,
         }
     }
 
+    // Records the objects `trace` visits, rather than collecting them.
+    // Nothing is moved and the objects aren't read, so handles which don't
+    // point to an object can be found, see Heap::verify.
+    pub(crate) fn record(&mut self, trace: impl FnOnce(&mut Self)) -> Vec<ObjectPtr> {
+        self.recorded = Some(vec![]);
+        trace(self);
+        self.recorded.take().unwrap()
+    }
+
+    // The objects `object_ptr` refers to, see record.  Those it reached
+    // before panicking if its trace panics.
+    pub(crate) fn record_object(&mut self, object_ptr: ObjectPtr) -> Vec<ObjectPtr> {
+        self.record(|visitor| {
+            let _ = visitor.trace_object(object_ptr);
+        })
+    }
+
+    // How many malformed values have been recorded, see TaggedPtr::is_valid.
+    pub(crate) fn malformed(&self) -> usize {
+        self.malformed
+    }
+
+    // The objects visited but not yet traced.
+    pub(crate) fn into_queue(self) -> VecDeque<ObjectPtr> {
+        self.queue
+    }
+
     fn visit(&mut self, header: &mut ObjectHeader) -> ObjectPtr {
+        if let Some(recorded) = self.recorded.as_mut() {
+            let object_ptr = header.as_ptr().to_object_ptr();
+            recorded.push(object_ptr);
+            return object_ptr;
+        }
         if let Some(new_header_ptr) = header.new_header_ptr {
             return new_header_ptr.to_object_ptr();
         }
-        let alloc_size = header.alloc_size();
-        let new_header_ptr = HeaderPtr::new(self.new_space.alloc(alloc_size).unwrap());
-        unsafe {
-            std::ptr::copy_nonoverlapping(
-                header.as_ptr().addr(),
-                new_header_ptr.addr(),
-                alloc_size,
-            );
+        let new_header_ptr = self.collector.relocate(header);
+        if new_header_ptr.addr() != header.as_ptr().addr() {
+            let alloc_size = header.alloc_size();
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    header.as_ptr().addr(),
+                    new_header_ptr.addr(),
+                    alloc_size,
+                );
+            }
+            new_header_ptr.to_object_ptr().header().increment_age();
         }
+        // Objects left in place are marked by forwarding to themselves, as
+        // are the copies of moved objects, so that visiting a handle which
+        // was already updated doesn't move its object again.
         header.new_header_ptr = Some(new_header_ptr);
         let object_ptr = new_header_ptr.to_object_ptr();
+        object_ptr.header().new_header_ptr = Some(new_header_ptr);
         self.queue.push_back(object_ptr);
         object_ptr
     }
 
+    // Queues an object to be traced without visiting it, e.g. an old object
+    // whose fields are roots for a minor collection.
+    pub(crate) fn enqueue(&mut self, object_ptr: ObjectPtr) {
+        self.queue.push_back(object_ptr);
+    }
+
+    // Traces everything reachable from the objects visited so far.  Returns
+    // whether any object's trace panicked.
+    pub(crate) fn process_queue(&mut self) -> bool {
+        let mut panicked = false;
+        while let Some(object_ptr) = self.queue.pop_front() {
+            panicked |= self.trace_queued(object_ptr);
+        }
+        panicked
+    }
+
+    // Like process_queue, but stops once objects totalling `budget_bytes`
+    // have been traced, adding each of them to `traced` if given.  At least
+    // one object is traced if any are queued.
+    pub(crate) fn process_queue_for(
+        &mut self,
+        budget_bytes: usize,
+        mut traced: Option<&mut Vec<ObjectPtr>>,
+    ) -> bool {
+        let mut panicked = false;
+        let mut traced_bytes = 0;
+        while let Some(object_ptr) = self.queue.pop_front() {
+            panicked |= self.trace_queued(object_ptr);
+            if let Some(traced) = traced.as_mut() {
+                traced.push(object_ptr);
+            }
+            traced_bytes += object_ptr.header().alloc_size();
+            if traced_bytes >= budget_bytes {
+                break;
+            }
+        }
+        panicked
+    }
+
+    // Returns whether the object's trace panicked.
+    fn trace_queued(&mut self, object_ptr: ObjectPtr) -> bool {
+        if self.trace_object(object_ptr).is_ok() {
+            return false;
+        }
+        // The collection can only complete once every handle has been
+        // visited, so trace the object again to reach the handles the
+        // panic skipped.  Handles it did reach are already forwarded.
+        if self.trace_object(object_ptr).is_err() {
+            eprintln!("vmgc: Traceable::trace panicked twice, the heap can't be recovered");
+            std::process::abort();
+        }
+        true
+    }
+
+    fn trace_object(&mut self, object_ptr: ObjectPtr) -> std::thread::Result<()> {
+        std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let object = TraceableObject::load(object_ptr);
+            let traceable = object.as_traceable();
+            traceable.trace(self);
+        }))
+    }
+
     pub fn trace_handles<T>(&mut self, handles: &Vec<HeapHandle<T>>) {
         for index in 0..handles.len() {
             let handle = &handles[index];
@@ -59,6 +182,10 @@ impl ObjectVisitor {
     }
 }
 
+/// A reference held by a heap object to another, for host objects' fields.
+/// HeapHandles aren't roots: the holder must visit each of them from its
+/// Traceable::trace, which also updates them when the target moves.  Native
+/// code outside the heap holds LocalHandles or GlobalHandles instead.
 #[derive(PartialEq, Eq, Debug)]
 #[repr(transparent)]
 pub struct HeapHandle<T> {
@@ -94,8 +221,12 @@ impl<T> HeapHandle<T> {
     }
 
     pub fn trace(&self, visitor: &mut ObjectVisitor) {
-        if let Some(header) = self.ptr().header() {
-            self.ptr.set(visitor.visit(header).into());
+        match self.ptr().header() {
+            Some(header) => self.ptr.set(visitor.visit(header).into()),
+            None if visitor.recorded.is_some() && !self.ptr().is_valid() => {
+                visitor.malformed += 1;
+            }
+            None => {}
         }
     }
 
@@ -107,8 +238,18 @@ impl<T> HeapHandle<T> {
         }
     }
 
+    /// Orders this value against `rhs`, see TaggedPtr::compare.
+    pub fn compare<S>(&self, rhs: &HeapHandle<S>) -> Option<std::cmp::Ordering> {
+        self.ptr().compare(&rhs.ptr())
+    }
+
     fn get_object_ptr(&self) -> Option<ObjectPtr> {
-        self.ptr().try_into().ok()
+        self.ptr().try_into().ok().map(read_barrier)
+    }
+
+    /// Copies the value out into a Rust value, see TryFromHeap.
+    pub fn try_from_heap<R: TryFromHeap>(&self, scope: &HandleScope) -> Result<R, GCError> {
+        R::try_from_heap(scope, &self.erase_type())
     }
 }
 
@@ -124,10 +265,22 @@ impl HeapHandle<()> {
         self.ptr().is_null()
     }
 
+    pub fn kind(&self) -> ValueKind {
+        self.ptr().kind()
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        self.ptr().is_undefined()
+    }
+
     pub fn is_num(&self) -> bool {
         self.ptr().is_num()
     }
 
+    pub fn is_int(&self) -> bool {
+        self.ptr().is_int()
+    }
+
     pub fn is_bool(&self) -> bool {
         self.ptr().is_bool()
     }
@@ -138,7 +291,7 @@ impl HeapHandle<()> {
 
     pub fn try_as_ref<S: HostObject>(&self) -> Option<&S> {
         if let Some(object_ptr) = self.get_object_ptr() {
-            if object_ptr.is_type(S::TYPE_ID) {
+            if object_ptr.is_type(ObjectType::of::<S>()) {
                 if let Some(ptr) = TraceableObject::try_downcast::<S>(object_ptr) {
                     return Some(unsafe { &*ptr });
                 }
@@ -147,16 +300,12 @@ impl HeapHandle<()> {
         None
     }
 
-    pub fn try_as_mut<S: HostObject>(&self) -> Option<&mut S> {
-        if let Some(object_ptr) = self.get_object_ptr() {
-            if object_ptr.is_type(S::TYPE_ID) {
-                if let Some(ptr) = TraceableObject::try_downcast::<S>(object_ptr) {
-                    let mut_ptr = ptr as *mut S;
-                    return Some(unsafe { &mut *mut_ptr });
-                }
-            }
+    pub fn try_as_mut<S: HostObject>(&self) -> Option<ObjectMut<'_, S>> {
+        let object_ptr = self.get_object_ptr()?;
+        if !read_barrier(object_ptr).is_type(ObjectType::of::<S>()) {
+            return None;
         }
-        None
+        Some(ObjectMut::new(object_ptr))
     }
 
     pub fn is_of_type<S: HostObject>(&self) -> bool {
@@ -172,10 +321,8 @@ impl<T: HostObject> HeapHandle<T> {
         return unsafe { &*ptr };
     }
 
-    pub fn borrow_mut(&self) -> &mut T {
-        let object_ptr = self.get_object_ptr().unwrap();
-        let ptr = TraceableObject::downcast_mut::<T>(object_ptr);
-        return unsafe { &mut *ptr };
+    pub fn borrow_mut(&self) -> ObjectMut<'_, T> {
+        ObjectMut::new(self.get_object_ptr().unwrap())
     }
 
     // Old names, remove:
@@ -183,11 +330,63 @@ impl<T: HostObject> HeapHandle<T> {
         self.borrow()
     }
 
-    pub fn as_mut(&self) -> &mut T {
+    pub fn as_mut(&self) -> ObjectMut<'_, T> {
         self.borrow_mut()
     }
 }
 
+/// Mutable access to a host object, from as_mut or borrow_mut.  The
+/// object's card (see CardTable) is dirtied both when it's taken and when
+/// it's dropped, after any stores through it.  A minor collection may run
+/// in between, e.g. allocating the argument in
+/// `list.as_mut().push(scope.str("young")?.into())`, and clear the card.
+pub struct ObjectMut<'a, T> {
+    header_addr: *mut u8,
+    // The barrier of the heap the object is in, if its space is tracked.
+    barrier: Option<Rc<WriteBarrier>>,
+    payload: &'a mut T,
+}
+
+impl<'a, T: HostObject> ObjectMut<'a, T> {
+    /// This will panic if the ObjectPtr does not point to a HostObject of
+    /// type T.
+    pub(crate) fn new(object_ptr: ObjectPtr) -> ObjectMut<'a, T> {
+        let object_ptr = read_barrier(object_ptr);
+        let payload = TraceableObject::downcast::<T>(object_ptr) as *mut T;
+        let header_addr = object_ptr.header().as_ptr().addr();
+        ObjectMut {
+            header_addr,
+            barrier: open_write(header_addr),
+            payload: unsafe { &mut *payload },
+        }
+    }
+}
+
+impl<T> Deref for ObjectMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.payload
+    }
+}
+
+impl<T> DerefMut for ObjectMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.payload
+    }
+}
+
+impl<T> Drop for ObjectMut<'_, T> {
+    // The object may have moved since, but then the collection which moved
+    // it left every card dirty, see WriteBarrier::has_open_writes.
+    fn drop(&mut self) {
+        if let Some(barrier) = self.barrier.take() {
+            write_barrier(self.header_addr);
+            close_write(&barrier);
+        }
+    }
+}
+
 // Derive Clone requires T to be Cloneable, which isn't required for Handles.
 impl<T> Clone for HeapHandle<T> {
     fn clone(&self) -> Self {
@@ -212,6 +411,34 @@ impl Into<f64> for HeapHandle<f64> {
     }
 }
 
+macro_rules! impl_heap_try_into_integer {
+    ($($int:ty),*) => {
+        $(
+            impl TryInto<$int> for HeapHandle<()> {
+                type Error = GCError;
+                fn try_into(self) -> Result<$int, GCError> {
+                    self.ptr().try_into()
+                }
+            }
+
+            impl TryInto<$int> for HeapHandle<f64> {
+                type Error = GCError;
+                fn try_into(self) -> Result<$int, GCError> {
+                    self.ptr().try_into()
+                }
+            }
+        )*
+    };
+}
+
+impl_heap_try_into_integer!(i32, i64, u32, usize);
+
+impl From<HeapHandle<i32>> for i32 {
+    fn from(handle: HeapHandle<i32>) -> i32 {
+        handle.ptr().try_into().unwrap()
+    }
+}
+
 impl TryInto<bool> for HeapHandle<()> {
     type Error = GCError;
     fn try_into(self) -> Result<bool, GCError> {
@@ -237,6 +464,12 @@ impl From<f64> for HeapHandle<f64> {
     }
 }
 
+impl From<i32> for HeapHandle<i32> {
+    fn from(value: i32) -> Self {
+        HeapHandle::new(value.into())
+    }
+}
+
 pub trait AsAny: Any {
     fn as_any(&self) -> &dyn Any;
     fn type_name(&self) -> &'static str;
@@ -268,12 +501,31 @@ pub trait Traceable: AsAny {
 
     // FIXME: If these were separate from Traceable, we could implement
     // Traceable for Option<Traceable>.
-    fn object_hash(&self, ptr: ObjectPtr) -> u64 {
-        ptr.addr() as u64
+    // Feeds the object's hash into `state`, which is seeded by the caller
+    // (e.g. with the heap's hash seed).
+    fn object_hash(&self, ptr: ObjectPtr, state: &mut dyn Hasher) {
+        state.write_u32(ptr.header().identity_hash());
     }
 
     fn object_eq(&self, lhs: ObjectPtr, rhs: ObjectPtr) -> bool {
-        lhs.addr().eq(&rhs.addr())
+        // Handles to an object may disagree about its address while an
+        // incremental collection is copying it, see Heap::collect_step.
+        forwarded(lhs).addr().eq(&forwarded(rhs).addr())
+    }
+
+    // Orders this object against `rhs`, for sorted collections and the
+    // hosted language's comparison operators.  None means unordered, which
+    // is the default.
+    fn object_cmp(&self, _lhs: ObjectPtr, _rhs: ObjectPtr) -> Option<Ordering> {
+        None
+    }
+
+    // Used by LocalHandle::display, objects without a textual form print
+    // as their type name.
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_str("<")?;
+        formatter.write_str(AsAny::type_name(self))?;
+        formatter.write_str(">")
     }
 }
 
@@ -283,27 +535,31 @@ pub struct TraceableObject {
 }
 
 impl TraceableObject {
-    pub fn from_box(traceable: Box<dyn Traceable>) -> TraceableObject {
-        TraceableObject {
-            ptr: Box::into_raw(traceable),
-        }
+    // `ptr` is a payload in the heap's ControlBlocks.
+    pub(crate) fn new(ptr: *mut dyn Traceable) -> TraceableObject {
+        TraceableObject { ptr }
     }
 
-    pub fn into_box(self) -> Box<dyn Traceable> {
-        unsafe { Box::from_raw(self.ptr) }
+    // The payload's address and layout, for giving back its memory.
+    pub(crate) fn block(&self) -> (*mut u8, Layout) {
+        let payload = unsafe { &*self.ptr };
+        let layout = block_layout(payload).unwrap_or_else(|| Layout::for_value(payload));
+        (self.ptr as *mut u8, layout)
+    }
+
+    // Runs the payload's finalizer.  It must not be used afterwards.
+    pub(crate) unsafe fn drop_payload(&self) {
+        std::ptr::drop_in_place(self.ptr);
     }
 
     pub fn store(&self, object_ptr: ObjectPtr) {
-        // FIXME: Express this precondition in the type system?
-        assert!(object_ptr.header().object_type == ObjectType::Host);
         unsafe {
             *(object_ptr.addr() as *mut *mut dyn Traceable) = self.ptr;
         }
     }
 
     pub fn load(object_ptr: ObjectPtr) -> TraceableObject {
-        // FIXME: Express this precondition in the type system?
-        assert!(object_ptr.header().object_type == ObjectType::Host);
+        let object_ptr = read_barrier(object_ptr);
         let traceable_ptr = unsafe { *(object_ptr.addr() as *mut *mut dyn Traceable) };
         TraceableObject { ptr: traceable_ptr }
     }
@@ -312,66 +568,103 @@ impl TraceableObject {
         unsafe { &mut (*self.ptr) }
     }
 
-    pub fn try_downcast<T: 'static>(object_ptr: ObjectPtr) -> Option<*const T> {
-        // FIXME: Express this precondition in the type system?
-        assert!(object_ptr.header().object_type == ObjectType::Host);
-        let traceable_ptr = unsafe { *(object_ptr.addr() as *const *const dyn Traceable) };
-        let traceable_ref = unsafe { &(*traceable_ptr) };
-        traceable_ref
-            .as_any()
-            .downcast_ref()
-            .map(|t_ref| t_ref as *const T)
+    pub fn try_downcast<T: HostObject>(object_ptr: ObjectPtr) -> Option<*const T> {
+        if !read_barrier(object_ptr).is_type(ObjectType::of::<T>()) {
+            return None;
+        }
+        Some(Self::load(object_ptr).ptr as *const T)
     }
 
     /// This will panic (in unwrap) if the ObjectPtr does not point to a
     /// HostObject of type T.
-    pub fn downcast<T: 'static>(object_ptr: ObjectPtr) -> *const T {
+    pub fn downcast<T: HostObject>(object_ptr: ObjectPtr) -> *const T {
         Self::try_downcast(object_ptr).unwrap()
     }
 
     /// This will panic (in unwrap) if the ObjectPtr does not point to a
     /// HostObject of type T.
-    pub fn downcast_mut<T: 'static>(object_ptr: ObjectPtr) -> *mut T {
+    pub fn downcast_mut<T: HostObject>(object_ptr: ObjectPtr) -> *mut T {
+        let object_ptr = read_barrier(object_ptr);
+        write_barrier(object_ptr.header().as_ptr().addr());
         Self::downcast::<T>(object_ptr) as *mut T
     }
 }
 
 // We will eventually add a HeapObject as an optimization
 // for things which don't hold pointers out to rust objects.
-pub trait HostObject: Traceable {
-    const TYPE_ID: ObjectType;
-}
+pub trait HostObject: Traceable {}
 
-impl HostObject for String {
-    const TYPE_ID: ObjectType = ObjectType::Host;
-}
+impl HostObject for String {}
 
 impl Traceable for String {
     fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
 
-    fn object_hash(&self, _ptr: ObjectPtr) -> u64 {
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        self.hash(&mut hasher);
-        hasher.finish()
+    fn object_hash(&self, _ptr: ObjectPtr, mut state: &mut dyn Hasher) {
+        self.hash(&mut state);
     }
 
     fn object_eq(&self, _lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> bool {
         // FIXME: This still assumes ObjectPtr is an object!
-        let maybe_rhs_ptr = TraceableObject::try_downcast::<String>(rhs_object_ptr);
-        if let Some(rhs_ptr) = maybe_rhs_ptr {
-            let rhs = unsafe { &*rhs_ptr };
-            return self.eq(rhs);
-        }
-        false
+        object_str(rhs_object_ptr) == Some(self.as_str())
+    }
+
+    fn object_cmp(&self, _lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> Option<Ordering> {
+        str_cmp(self, rhs_object_ptr)
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_quoted(self)
     }
 }
 
-pub type Map<K, V> = HashMap<HeapHandle<K>, HeapHandle<V>>;
+// Maps made with HandleScope::create_map hash with the heap's configured
+// algorithm and seed.
+pub type Map<K, V> = HashMap<HeapHandle<K>, HeapHandle<V>, SeededState>;
 
-impl<K: 'static, V: 'static> HostObject for Map<K, V> {
-    const TYPE_ID: ObjectType = ObjectType::Host;
+impl<K: 'static, V: 'static> HostObject for Map<K, V> {}
+
+// Orders `lhs` against a String or SharedStr, by bytes.
+pub(crate) fn str_cmp(lhs: &str, rhs_object_ptr: ObjectPtr) -> Option<Ordering> {
+    object_str(rhs_object_ptr).map(|rhs| lhs.cmp(rhs))
+}
+
+// The text of a String, SharedStr or GcString object, which all hash and
+// compare alike.
+pub(crate) fn object_str<'a>(object_ptr: ObjectPtr) -> Option<&'a str> {
+    if let Some(ptr) = TraceableObject::try_downcast::<String>(object_ptr) {
+        return Some(unsafe { &*ptr }.as_str());
+    }
+    if let Some(ptr) = TraceableObject::try_downcast::<SharedStr>(object_ptr) {
+        return Some(unsafe { &*ptr }.as_str());
+    }
+    if let Some(ptr) = TraceableObject::try_downcast::<GcString>(object_ptr) {
+        return Some(unsafe { &*ptr }.as_str());
+    }
+    None
 }
 
+// Containers nested deeper than this hash and compare by identity, which
+// also stops cycles.
+const MAX_STRUCTURAL_DEPTH: usize = 32;
+
+thread_local! {
+    static STRUCTURAL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// Runs `structural` one level deeper, or `shallow` once too deep.
+fn nested<R>(shallow: impl FnOnce() -> R, structural: impl FnOnce() -> R) -> R {
+    let depth = STRUCTURAL_DEPTH.with(Cell::get);
+    if depth >= MAX_STRUCTURAL_DEPTH {
+        return shallow();
+    }
+    STRUCTURAL_DEPTH.with(|cell| cell.set(depth + 1));
+    let result = structural();
+    STRUCTURAL_DEPTH.with(|cell| cell.set(depth));
+    result
+}
+
+// Maps hash and compare by their entries, so they can be keys of other
+// Maps.  Like List keys, they must not be mutated while used as a key.
 impl<K: 'static, V: 'static> Traceable for Map<K, V> {
     fn trace(&mut self, visitor: &mut ObjectVisitor) {
         for (key, value) in self.iter_mut() {
@@ -379,24 +672,153 @@ impl<K: 'static, V: 'static> Traceable for Map<K, V> {
             value.trace(visitor);
         }
     }
+
+    fn object_hash(&self, _ptr: ObjectPtr, state: &mut dyn Hasher) {
+        state.write_usize(self.len());
+        nested(
+            || (),
+            || {
+                // Entries are visited in arbitrary order, so their hashes are
+                // combined with an order independent sum.
+                let sum = self.iter().fold(0u64, |sum, (key, value)| {
+                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                    key.hash(&mut hasher);
+                    value.hash(&mut hasher);
+                    sum.wrapping_add(hasher.finish())
+                });
+                state.write_u64(sum);
+            },
+        )
+    }
+
+    fn object_eq(&self, lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> bool {
+        // Bound as a pointer, clippy objects to locals of Map type.
+        let rhs_ptr = match TraceableObject::try_downcast::<Map<K, V>>(rhs_object_ptr) {
+            Some(rhs_ptr) => rhs_ptr,
+            None => return false,
+        };
+        if self.len() != unsafe { &*rhs_ptr }.len() {
+            return false;
+        }
+        nested(
+            || lhs.addr() == rhs_object_ptr.addr(),
+            || {
+                self.iter().all(|(key, value)| {
+                    unsafe { &*rhs_ptr }.iter().any(|(rhs_key, rhs_value)| {
+                        key.ptr() == rhs_key.ptr() && value.ptr() == rhs_value.ptr()
+                    })
+                })
+            },
+        )
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_map(self.iter())
+    }
+}
+
+// Sets made with HandleScope::create_set hash with the heap's configured
+// algorithm and seed.  Elements are told apart with object_hash and
+// object_eq, so e.g. two equal strings are one element.
+pub type Set<T> = HashSet<HeapHandle<T>, SeededState>;
+
+impl<T: 'static> HostObject for Set<T> {}
+
+// Like Maps, Sets hash and compare by their elements.
+impl<T: 'static> Traceable for Set<T> {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        for element in self.iter() {
+            element.trace(visitor);
+        }
+    }
+
+    fn object_hash(&self, _ptr: ObjectPtr, state: &mut dyn Hasher) {
+        state.write_usize(self.len());
+        nested(
+            || (),
+            || {
+                let sum = self.iter().fold(0u64, |sum, element| {
+                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                    element.hash(&mut hasher);
+                    sum.wrapping_add(hasher.finish())
+                });
+                state.write_u64(sum);
+            },
+        )
+    }
+
+    fn object_eq(&self, lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> bool {
+        // Bound as a pointer, as with Map.
+        let rhs_ptr = match TraceableObject::try_downcast::<Set<T>>(rhs_object_ptr) {
+            Some(rhs_ptr) => rhs_ptr,
+            None => return false,
+        };
+        if self.len() != unsafe { &*rhs_ptr }.len() {
+            return false;
+        }
+        nested(
+            || lhs.addr() == rhs_object_ptr.addr(),
+            || {
+                self.iter().all(|element| {
+                    unsafe { &*rhs_ptr }
+                        .iter()
+                        .any(|rhs_element| element.ptr() == rhs_element.ptr())
+                })
+            },
+        )
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_set(self.iter())
+    }
 }
 
+// Short lists keep their elements inline, see HandleVec.
 #[derive(Clone, Hash)]
-pub struct List<T>(Vec<HeapHandle<T>>);
+pub struct List<T>(HandleVec<T>);
 
 impl<T> Default for List<T> {
     fn default() -> Self {
-        List(vec![])
+        List(HandleVec::new())
     }
 }
 
-impl<T: 'static> HostObject for List<T> {
-    const TYPE_ID: ObjectType = ObjectType::Host;
-}
+impl<T: 'static> HostObject for List<T> {}
 
+// Lists hash and compare by their elements, so they can be used as Map keys
+// like tuples.  A list must not be mutated while it is a key.
 impl<T: 'static> Traceable for List<T> {
     fn trace(&mut self, visitor: &mut ObjectVisitor) {
-        visitor.trace_handles(&self.0);
+        for handle in self.0.as_slice() {
+            handle.trace(visitor);
+        }
+    }
+
+    fn object_hash(&self, _ptr: ObjectPtr, mut state: &mut dyn Hasher) {
+        state.write_usize(self.len());
+        nested(|| (), || self.0.hash(&mut state))
+    }
+
+    fn object_eq(&self, lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> bool {
+        let rhs = match TraceableObject::try_downcast::<List<T>>(rhs_object_ptr) {
+            Some(rhs_ptr) => unsafe { &*rhs_ptr },
+            None => return false,
+        };
+        if self.len() != rhs.len() {
+            return false;
+        }
+        nested(
+            || lhs.addr() == rhs_object_ptr.addr(),
+            || {
+                self.iter()
+                    .zip(rhs.iter())
+                    .all(|(element, rhs_element)| element.ptr() == rhs_element.ptr())
+            },
+        )
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_list(self.iter())
     }
 }
 
@@ -426,10 +848,25 @@ impl<T: HostObject> List<T> {
 }
 
 impl<T> List<T> {
+    // Unlike push, this is available for every T, for use by generic code in
+    // this crate which already has a correctly typed handle.
+    pub(crate) fn append(&mut self, handle: HeapHandle<T>) {
+        self.0.push(handle);
+    }
+
     pub fn pop<'a>(&mut self, scope: &'a HandleScope) -> Option<LocalHandle<'a, T>> {
         self.0.pop().map(|handle| scope.from_heap(&handle))
     }
 
+    /// Bounds checked access which roots the element in `scope`, unlike
+    /// indexing which hands out a reference into the list.
+    pub fn get<'a>(&self, scope: &'a HandleScope, index: usize) -> Option<LocalHandle<'a, T>> {
+        self.0
+            .as_slice()
+            .get(index)
+            .map(|handle| scope.from_heap(handle))
+    }
+
     pub fn truncate(&mut self, len: usize) {
         self.0.truncate(len);
     }
@@ -448,7 +885,17 @@ impl<T> List<T> {
     }
 
     pub fn swap(&mut self, a: usize, b: usize) {
-        self.0.swap(a, b)
+        self.0.as_mut_slice().swap(a, b)
+    }
+
+    /// Whether an element equals `value`, compared like Map keys.
+    pub fn contains(&self, value: &HeapHandle<T>) -> bool {
+        self.index_of(value).is_some()
+    }
+
+    /// The index of the first element equal to `value`.
+    pub fn index_of(&self, value: &HeapHandle<T>) -> Option<usize> {
+        self.iter().position(|element| element.ptr() == value.ptr())
     }
 
     pub fn len(&self) -> usize {
@@ -456,19 +903,19 @@ impl<T> List<T> {
     }
 
     pub fn first(&self) -> Option<&HeapHandle<T>> {
-        self.0.first()
+        self.0.as_slice().first()
     }
 
     pub fn last(&self) -> Option<&HeapHandle<T>> {
-        self.0.last()
+        self.0.as_slice().last()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.0.len() == 0
     }
 
     pub fn iter(&self) -> std::slice::Iter<'_, HeapHandle<T>> {
-        self.0.iter()
+        self.0.as_slice().iter()
     }
 
     pub fn split_off(&mut self, at: usize) -> Self {
@@ -476,7 +923,7 @@ impl<T> List<T> {
     }
 
     pub fn clear(&mut self) {
-        self.0.clear()
+        self.0.truncate(0)
     }
 }
 
@@ -485,7 +932,7 @@ impl<'a, T> IntoIterator for &'a List<T> {
     type IntoIter = std::slice::Iter<'a, HeapHandle<T>>;
 
     fn into_iter(self) -> Self::IntoIter {
-        (&self.0).into_iter()
+        self.0.as_slice().iter()
     }
 }
 
@@ -494,7 +941,7 @@ impl<T, I: std::slice::SliceIndex<[HeapHandle<T>]>> std::ops::Index<I> for List<
 
     #[inline]
     fn index(&self, index: I) -> &Self::Output {
-        std::ops::Index::index(&self.0, index)
+        std::ops::Index::index(self.0.as_slice(), index)
     }
 }
 
@@ -507,18 +954,18 @@ impl<'a, T> From<Vec<LocalHandle<'a, T>>> for List<T> {
 // FIXME: Is this even needed?  Is this just clone?
 impl<'a, T> From<Vec<HeapHandle<T>>> for List<T> {
     fn from(elements: Vec<HeapHandle<T>>) -> Self {
-        List(elements)
+        List(elements.into())
     }
 }
 
 impl<'a, T> Into<Vec<HeapHandle<T>>> for List<T> {
     fn into(self) -> Vec<HeapHandle<T>> {
-        self.0
+        self.0.into_vec()
     }
 }
 
 impl<T> IndexMut<usize> for List<T> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        self.0.index_mut(index)
+        self.0.as_mut_slice().index_mut(index)
     }
 }
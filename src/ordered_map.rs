@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::display::ValueFormatter;
+use crate::hash::SeededState;
+use crate::object::*;
+
+/// A Map which iterates in insertion order, as scripting languages expect
+/// of their dictionaries.  Entries live in a vector and a hash index maps
+/// each key to its position.  Replacing a value keeps the key's original
+/// position, removing it and inserting again moves it to the end.
+pub struct OrderedMap<K, V> {
+    // Removed entries leave a None until the vector is compacted.
+    entries: Vec<Option<(HeapHandle<K>, HeapHandle<V>)>>,
+    index: HashMap<HeapHandle<K>, usize, SeededState>,
+}
+
+impl<K, V> Default for OrderedMap<K, V> {
+    fn default() -> Self {
+        OrderedMap::with_hash_state(SeededState::default())
+    }
+}
+
+impl<K: 'static, V: 'static> HostObject for OrderedMap<K, V> {}
+
+impl<K: 'static, V: 'static> Traceable for OrderedMap<K, V> {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        for (key, value) in self.entries.iter().flatten() {
+            key.trace(visitor);
+            value.trace(visitor);
+        }
+        for key in self.index.keys() {
+            key.trace(visitor);
+        }
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_map(self.iter())
+    }
+}
+
+impl<K, V> OrderedMap<K, V> {
+    /// An empty map hashing keys with `hash_state`, see
+    /// HandleScope::create_ordered_map.
+    pub fn with_hash_state(hash_state: SeededState) -> Self {
+        OrderedMap {
+            entries: vec![],
+            index: HashMap::with_hasher(hash_state),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&HeapHandle<K>, &HeapHandle<V>)> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &HeapHandle<K>> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &HeapHandle<V>> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+}
+
+impl<K, V> OrderedMap<K, V>
+where
+    HeapHandle<K>: Eq + Hash,
+{
+    /// Returns the previous value for `key`, if any.
+    pub fn insert(&mut self, key: HeapHandle<K>, value: HeapHandle<V>) -> Option<HeapHandle<V>> {
+        if let Some(position) = self.index.get(&key) {
+            let (_, old) = self.entries[*position].as_mut().unwrap();
+            return Some(std::mem::replace(old, value));
+        }
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push(Some((key, value)));
+        None
+    }
+
+    pub fn get(&self, key: &HeapHandle<K>) -> Option<&HeapHandle<V>> {
+        let position = *self.index.get(key)?;
+        self.entries[position].as_ref().map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &HeapHandle<K>) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Removes `key`, keeping the order of the remaining entries.
+    pub fn remove(&mut self, key: &HeapHandle<K>) -> Option<HeapHandle<V>> {
+        let position = self.index.remove(key)?;
+        let (_, value) = self.entries[position].take().unwrap();
+        // Compact once more than half the entries are holes.
+        if self.index.len() * 2 < self.entries.len() {
+            self.compact();
+        }
+        Some(value)
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(Option::is_some);
+        for (position, entry) in self.entries.iter().enumerate() {
+            let (key, _) = entry.as_ref().unwrap();
+            *self.index.get_mut(key).unwrap() = position;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+
+    #[test]
+    fn insertion_order_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let map = scope.create_ordered_map::<String, f64>().unwrap();
+        for (index, name) in ["c", "a", "d", "b"].iter().enumerate() {
+            let key = scope.str(name).unwrap().into();
+            map.as_mut().insert(key, (index as f64).into());
+        }
+        heap.collect().unwrap();
+        assert_eq!(
+            map.display(&scope),
+            "{\"c\": 0, \"a\": 1, \"d\": 2, \"b\": 3}"
+        );
+
+        // Replacing keeps the position, reinserting moves to the end.
+        let a: HeapHandle<String> = scope.str("a").unwrap().into();
+        let c: HeapHandle<String> = scope.str("c").unwrap().into();
+        let old = map.as_mut().insert(a.clone(), 10.0.into());
+        assert_eq!(old.map(|value| value.into()), Some(1.0));
+        map.as_mut().remove(&c);
+        map.as_mut().insert(c.clone(), 20.0.into());
+        assert_eq!(
+            map.display(&scope),
+            "{\"a\": 10, \"d\": 2, \"b\": 3, \"c\": 20}"
+        );
+
+        map.as_mut().remove(&a);
+        map.as_mut().remove(&c);
+        map.as_mut().remove(&scope.str("d").unwrap().into());
+        heap.collect().unwrap();
+        assert_eq!(map.as_ref().len(), 1);
+        let b: HeapHandle<String> = scope.str("b").unwrap().into();
+        assert_eq!(
+            map.as_ref().get(&b).cloned().map(|value| value.into()),
+            Some(3.0)
+        );
+        // HeapHandles aren't roots, so keys are looked up afresh after a
+        // collection.
+        let a: HeapHandle<String> = scope.str("a").unwrap().into();
+        assert!(!map.as_ref().contains_key(&a));
+    }
+}
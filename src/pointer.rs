@@ -1,37 +1,85 @@
+use std::cmp::Ordering;
 use std::convert::{From, TryFrom, TryInto};
 use std::hash::{Hash, Hasher};
 
-use crate::object::TraceableObject;
-use crate::space::Space;
+use crate::collector::CollectorStrategy;
+use crate::object::{object_str, str_cmp, HostObject, TraceableObject};
+use crate::type_registry;
 use crate::types::*;
 
 // f64.from_bits and f64.to_bits exist, it might be
 // possible to do this without unsafe and w/o a union.
+//
+// The bits are u64 rather than usize so the same NaN-boxing works on 32-bit
+// targets, where a pointer simply leaves the high payload bits zero.
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub union TaggedPtr {
     number: f64,
-    bits: usize,
+    bits: u64,
 }
 
-const SIGN_MASK: usize = 1 << 63;
-const QUIET_NAN_MASK: usize = 0x7ffc000000000000;
+const SIGN_MASK: u64 = 1 << 63;
+const QUIET_NAN_MASK: u64 = 0x7ffc000000000000;
 // If sign and quiet nan are set, this is a pointer.
-const PTR_TAG_MASK: usize = SIGN_MASK | QUIET_NAN_MASK;
+const PTR_TAG_MASK: u64 = SIGN_MASK | QUIET_NAN_MASK;
 // The rest of the bits are the poitner.
-const PTR_MASK: usize = !PTR_TAG_MASK;
+const PTR_MASK: u64 = !PTR_TAG_MASK;
 
 // Used for identifying singletons.  All singletons have quiet nan bits set.
-// const SINGLETON_TAG_MASK: usize = 7;
+// const SINGLETON_TAG_MASK: u64 = 7;
 
-// const TAG_NAN: usize = 0;
-const TAG_NULL: usize = 1;
-const TAG_FALSE: usize = 2;
-const TAG_TRUE: usize = 3;
-// const TAG_UNUSED: usize = 4;
-// const TAG_UNUSED2: usize = 5;
-// const TAG_UNUSED3: usize = 6;
-// const TAG_UNUSED4: usize = 7;
+// const TAG_NAN: u64 = 0;
+const TAG_NULL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+const TAG_UNDEFINED: u64 = 4;
+// const TAG_UNUSED2: u64 = 5;
+// const TAG_UNUSED3: u64 = 6;
+// const TAG_UNUSED4: u64 = 7;
+
+// Small integers are quiet NaNs with this bit set and the i32 in the low 32
+// bits, well clear of the singleton tags.
+const INT_TAG: u64 = 1 << 32;
+const INT_TAG_MASK: u64 = PTR_TAG_MASK | SMALL_STR_TAG | INT_TAG;
+
+// Short strings are quiet NaNs with this bit set and up to six bytes in the
+// low 48 bits, first byte lowest.  The string ends at the first zero byte,
+// so strings containing NUL are never stored this way.
+const SMALL_STR_TAG: u64 = 1 << 49;
+const SMALL_STR_TAG_MASK: u64 = PTR_TAG_MASK | SMALL_STR_TAG;
+const SMALL_STR_CAPACITY: usize = 6;
+
+/// What a value is, for dispatching with one match instead of a chain of
+/// is_null, is_bool, try_as_ref etc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Null,
+    Undefined,
+    Bool,
+    Num,
+    Int,
+    /// The Rust type name of the object, see TaggedPtr::type_name.  Small
+    /// strings are reported as String objects.
+    HostObject(&'static str),
+}
+
+/// The text of a string stored in a TaggedPtr rather than the heap, see
+/// TaggedPtr::small_str.
+#[derive(Clone, Copy)]
+pub struct SmallStr {
+    bytes: [u8; SMALL_STR_CAPACITY],
+    len: usize,
+}
+
+impl std::ops::Deref for SmallStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        // Only made from the bytes of a str.
+        std::str::from_utf8(&self.bytes[..self.len]).unwrap()
+    }
+}
 
 impl TaggedPtr {
     pub const NULL: TaggedPtr = TaggedPtr {
@@ -43,12 +91,73 @@ impl TaggedPtr {
     pub const TRUE: TaggedPtr = TaggedPtr {
         bits: QUIET_NAN_MASK | TAG_TRUE,
     };
+    // "No value", for languages which tell it apart from null.
+    pub const UNDEFINED: TaggedPtr = TaggedPtr {
+        bits: QUIET_NAN_MASK | TAG_UNDEFINED,
+    };
 
     // It's a number if it's not NaN.
     pub fn is_num(&self) -> bool {
         unsafe { (self.bits & QUIET_NAN_MASK) != QUIET_NAN_MASK }
     }
 
+    /// `text` stored in the value itself, or None if it's too long (more than
+    /// six bytes) or contains NUL.  Small strings compare, hash and display
+    /// like String objects with the same text, see HandleScope::str_value.
+    pub fn small_str(text: &str) -> Option<TaggedPtr> {
+        if text.len() > SMALL_STR_CAPACITY || text.contains('\0') {
+            return None;
+        }
+        let mut bits = QUIET_NAN_MASK | SMALL_STR_TAG;
+        for (index, byte) in text.bytes().enumerate() {
+            bits |= (byte as u64) << (8 * index);
+        }
+        Some(TaggedPtr { bits })
+    }
+
+    pub fn is_small_str(&self) -> bool {
+        unsafe { (self.bits & SMALL_STR_TAG_MASK) == (QUIET_NAN_MASK | SMALL_STR_TAG) }
+    }
+
+    pub fn as_small_str(&self) -> Option<SmallStr> {
+        if !self.is_small_str() {
+            return None;
+        }
+        let bits = unsafe { self.bits };
+        let mut small = SmallStr {
+            bytes: [0; SMALL_STR_CAPACITY],
+            len: 0,
+        };
+        while small.len < SMALL_STR_CAPACITY {
+            let byte = (bits >> (8 * small.len)) as u8;
+            if byte == 0 {
+                break;
+            }
+            small.bytes[small.len] = byte;
+            small.len += 1;
+        }
+        Some(small)
+    }
+
+    /// Calls `f` with the text of a string value, whether small or a String,
+    /// SharedStr or GcString object.  None for other values.
+    pub fn with_str<R>(&self, f: impl FnOnce(&str) -> R) -> Option<R> {
+        if let Some(small) = self.as_small_str() {
+            return Some(f(&small));
+        }
+        let object_ptr: ObjectPtr = (*self).try_into().ok()?;
+        object_str(object_ptr).map(f)
+    }
+
+    // Small integers are stored exactly, without going through f64.
+    pub fn is_int(&self) -> bool {
+        unsafe { (self.bits & INT_TAG_MASK) == (QUIET_NAN_MASK | INT_TAG) }
+    }
+
+    fn int_value(&self) -> i32 {
+        unsafe { self.bits as u32 as i32 }
+    }
+
     // It's an object if object mask is set.
     fn is_ptr(&self) -> bool {
         unsafe { (self.bits & PTR_TAG_MASK) == PTR_TAG_MASK }
@@ -66,14 +175,101 @@ impl TaggedPtr {
         unsafe { self.bits == TaggedPtr::NULL.bits }
     }
 
+    // Whether the bits are one of the encodings above, rather than a NaN
+    // with an unused tag.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.is_num()
+            || self.is_int()
+            || self.is_small_str()
+            || self.is_ptr()
+            || self.is_null()
+            || self.is_bool()
+            || self.is_undefined()
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        unsafe { self.bits == TaggedPtr::UNDEFINED.bits }
+    }
+
     pub fn is_bool(&self) -> bool {
         self.is_true_singleton() || self.is_false_singleton()
     }
 
-    // fn singleton_tag(&self) -> usize {
+    // fn singleton_tag(&self) -> u64 {
     //     unsafe { self.bits & SINGLETON_TAG_MASK }
     // }
 
+    /// Names the type of the value, "num", "int", "bool", "null" and
+    /// "undefined" for immediates and the Rust type name for host objects.
+    pub fn type_name(&self) -> &'static str {
+        match self.kind() {
+            ValueKind::Null => "null",
+            ValueKind::Undefined => "undefined",
+            ValueKind::Bool => "bool",
+            ValueKind::Num => "num",
+            ValueKind::Int => "int",
+            ValueKind::HostObject(name) => name,
+        }
+    }
+
+    pub fn kind(&self) -> ValueKind {
+        if self.is_num() {
+            return ValueKind::Num;
+        }
+        if self.is_int() {
+            return ValueKind::Int;
+        }
+        if self.is_bool() {
+            return ValueKind::Bool;
+        }
+        if self.is_null() {
+            return ValueKind::Null;
+        }
+        if self.is_undefined() {
+            return ValueKind::Undefined;
+        }
+        if self.is_small_str() {
+            return ValueKind::HostObject(std::any::type_name::<String>());
+        }
+        let object_ptr: ObjectPtr = (*self).try_into().unwrap();
+        ValueKind::HostObject(object_ptr.header().object_type.name())
+    }
+
+    /// Orders two values of the same kind: numbers (ints or nums)
+    /// numerically, false before true, and host objects by
+    /// Traceable::object_cmp.  Values of different kinds, NaN and objects
+    /// without an order give None.
+    pub fn compare(&self, rhs: &TaggedPtr) -> Option<Ordering> {
+        if self.is_int() && rhs.is_int() {
+            return Some(self.int_value().cmp(&rhs.int_value()));
+        }
+        let is_number = |ptr: &TaggedPtr| ptr.is_num() || ptr.is_int();
+        if is_number(self) && is_number(rhs) {
+            let lhs: f64 = (*self).try_into().unwrap();
+            let rhs: f64 = (*rhs).try_into().unwrap();
+            return lhs.partial_cmp(&rhs);
+        }
+        if self.is_bool() && rhs.is_bool() {
+            return Some(self.is_true_singleton().cmp(&rhs.is_true_singleton()));
+        }
+        if (self.is_null() && rhs.is_null()) || (self.is_undefined() && rhs.is_undefined()) {
+            return Some(Ordering::Equal);
+        }
+        if let Some(small) = self.as_small_str() {
+            if let Some(rhs_small) = rhs.as_small_str() {
+                return Some((*small).cmp(&*rhs_small));
+            }
+            return str_cmp(&small, (*rhs).try_into().ok()?);
+        }
+        if let Some(rhs_small) = rhs.as_small_str() {
+            return str_cmp(&rhs_small, (*self).try_into().ok()?).map(Ordering::reverse);
+        }
+        let lhs_ptr: ObjectPtr = (*self).try_into().ok()?;
+        let rhs_ptr: ObjectPtr = (*rhs).try_into().ok()?;
+        let object = TraceableObject::load(lhs_ptr);
+        object.as_traceable().object_cmp(lhs_ptr, rhs_ptr)
+    }
+
     pub fn header(&self) -> Option<&mut ObjectHeader> {
         (*self).try_into().ok().map(ObjectHeader::from_object_ptr)
     }
@@ -91,17 +287,56 @@ impl From<f64> for TaggedPtr {
     }
 }
 
+// An int converts exactly, but stays an int: the two are never equal.
+impl From<i32> for TaggedPtr {
+    fn from(value: i32) -> TaggedPtr {
+        TaggedPtr {
+            bits: QUIET_NAN_MASK | INT_TAG | value as u32 as u64,
+        }
+    }
+}
+
+// Ints convert to f64 exactly.
 impl TryInto<f64> for TaggedPtr {
     type Error = GCError;
     fn try_into(self) -> Result<f64, GCError> {
         if self.is_num() {
             Ok(unsafe { self.number })
+        } else if self.is_int() {
+            Ok(self.int_value().into())
         } else {
             Err(GCError::TypeError)
         }
     }
 }
 
+// Integer conversions require the number to be integral and in range, so
+// e.g. 1.5 or -1 are never silently accepted as an index.
+macro_rules! impl_try_into_integer {
+    ($($int:ty),*) => {
+        $(
+            impl TryInto<$int> for TaggedPtr {
+                type Error = GCError;
+                fn try_into(self) -> Result<$int, GCError> {
+                    if self.is_int() {
+                        return <$int>::try_from(self.int_value()).map_err(|_| GCError::TypeError);
+                    }
+                    let value: f64 = self.try_into()?;
+                    // MAX + 1 is exactly representable for these types, while
+                    // MAX itself may round up.
+                    let in_range = value >= <$int>::MIN as f64 && value < <$int>::MAX as f64 + 1.0;
+                    if value.fract() != 0.0 || !in_range {
+                        return Err(GCError::TypeError);
+                    }
+                    Ok(value as $int)
+                }
+            }
+        )*
+    };
+}
+
+impl_try_into_integer!(i32, i64, u32, usize);
+
 impl From<bool> for TaggedPtr {
     fn from(value: bool) -> TaggedPtr {
         if value {
@@ -131,7 +366,7 @@ impl TryFrom<TaggedPtr> for bool {
 impl From<ObjectPtr> for TaggedPtr {
     fn from(ptr: ObjectPtr) -> TaggedPtr {
         TaggedPtr {
-            bits: unsafe { std::mem::transmute::<ObjectPtr, usize>(ptr) | PTR_TAG_MASK },
+            bits: unsafe { std::mem::transmute::<ObjectPtr, usize>(ptr) as u64 | PTR_TAG_MASK },
         }
     }
 }
@@ -140,7 +375,9 @@ impl TryFrom<TaggedPtr> for ObjectPtr {
     type Error = GCError;
     fn try_from(tagged: TaggedPtr) -> Result<ObjectPtr, GCError> {
         if tagged.is_ptr() {
-            Ok(unsafe { std::mem::transmute::<usize, ObjectPtr>(tagged.bits & PTR_MASK) })
+            Ok(unsafe {
+                std::mem::transmute::<usize, ObjectPtr>((tagged.bits & PTR_MASK) as usize)
+            })
         } else {
             Err(GCError::TypeError)
         }
@@ -156,6 +393,10 @@ impl std::fmt::Debug for TaggedPtr {
 
 impl PartialEq for TaggedPtr {
     fn eq(&self, rhs: &TaggedPtr) -> bool {
+        if self.is_small_str() || rhs.is_small_str() {
+            let equal = self.with_str(|lhs| rhs.with_str(|rhs| lhs == rhs));
+            return equal.flatten().unwrap_or(false);
+        }
         // Note: this will make -0 != 0
         if self.is_ptr() != rhs.is_ptr() {
             return false;
@@ -163,14 +404,8 @@ impl PartialEq for TaggedPtr {
         if self.is_ptr() {
             let lhs_ptr: ObjectPtr = self.clone().try_into().unwrap();
             let rhs_ptr: ObjectPtr = rhs.clone().try_into().unwrap();
-            let lhs_type = lhs_ptr.header().object_type;
-            let rhs_type = rhs_ptr.header().object_type;
-            match (lhs_type, rhs_type) {
-                (ObjectType::Host, ObjectType::Host) => {
-                    let lhs_object = TraceableObject::load(lhs_ptr);
-                    lhs_object.as_traceable().object_eq(lhs_ptr, rhs_ptr)
-                }
-            }
+            let lhs_object = TraceableObject::load(lhs_ptr);
+            lhs_object.as_traceable().object_eq(lhs_ptr, rhs_ptr)
         } else {
             unsafe { self.bits == rhs.bits }
         }
@@ -183,12 +418,11 @@ impl Hash for TaggedPtr {
     fn hash<H: Hasher>(&self, state: &mut H) {
         if self.is_ptr() {
             let ptr: ObjectPtr = self.clone().try_into().unwrap();
-            match ptr.header().object_type {
-                ObjectType::Host => {
-                    let object = TraceableObject::load(ptr);
-                    object.as_traceable().object_hash(ptr).hash(state);
-                }
-            }
+            let object = TraceableObject::load(ptr);
+            object.as_traceable().object_hash(ptr, state);
+        } else if let Some(small) = self.as_small_str() {
+            // Matches String's object_hash.
+            (*small).hash(state);
         } else {
             unsafe { self.bits.hash(state) }
         }
@@ -226,6 +460,30 @@ impl ObjectPtr {
     }
 }
 
+/// All reads of objects through handles pass through here.  It is a no-op
+/// unless the read_barrier feature is enabled, in which case accessing an
+/// object which has been moved (and not yet had its handles updated) follows
+/// the forwarding pointer.  Collectors which run concurrently with the
+/// mutator will need this; the access paths shouldn't need redesigning then.
+#[inline(always)]
+pub fn read_barrier(object_ptr: ObjectPtr) -> ObjectPtr {
+    if cfg!(feature = "read_barrier") {
+        forwarded(object_ptr)
+    } else {
+        object_ptr
+    }
+}
+
+// Where the object is being moved to, if a collection has copied it.
+// Outside of collections this is the object itself.
+#[inline(always)]
+pub(crate) fn forwarded(object_ptr: ObjectPtr) -> ObjectPtr {
+    match object_ptr.header().new_header_ptr {
+        Some(new_header_ptr) => new_header_ptr.to_object_ptr(),
+        None => object_ptr,
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 #[repr(transparent)]
 pub struct HeaderPtr(*mut u8);
@@ -244,17 +502,51 @@ impl HeaderPtr {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-#[repr(u16)]
-pub enum ObjectType {
-    Host, // FIXME: Means uses the class TraceableObject, names should match.
+/// Which HostObject type an object holds.  Each type is given its own id
+/// by a registry the first time it's used, so checking an object's type is
+/// a single compare of its header rather than a downcast through Any.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[repr(transparent)]
+pub struct ObjectType(u16);
+
+impl ObjectType {
+    pub(crate) fn new(id: u16) -> ObjectType {
+        ObjectType(id)
+    }
+
+    pub fn of<T: HostObject>() -> ObjectType {
+        type_registry::type_of::<T>()
+    }
+
+    /// The id stored in the header, e.g. for generated code to compare
+    /// against.  Only meaningful within one process.
+    pub fn id(&self) -> u16 {
+        self.0
+    }
+
+    /// The Rust type name, as from std::any::type_name.
+    pub fn name(&self) -> &'static str {
+        type_registry::name_of(*self)
+    }
 }
 
 #[derive(Debug)]
 #[repr(C)]
 pub struct ObjectHeader {
-    object_size: usize,
+    // Objects are small (host objects are a pointer to their payload), so
+    // 32 bits leaves room for the hash and tag without growing the header.
+    object_size: u32,
     pub object_type: ObjectType,
+    // FLAG_* bits, see HeaderFlags.  Fits in padding.
+    flags: u8,
+    // How many collections have copied the object, saturating.  Also fits
+    // in padding.
+    age: u8,
+    // Hashes the object by identity, see HeapConfig::deterministic.  Copied
+    // along with the object, so it doesn't change when the object moves.
+    identity_hash: u32,
+    // The embedder's own type ID or class tag, see HandleScope::take_tagged.
+    user_tag: u32,
 
     // When we move the object to the new space, we'll record in this field
     // where we moved it to.
@@ -263,18 +555,116 @@ pub struct ObjectHeader {
 
 const HEADER_SIZE: usize = std::mem::size_of::<ObjectHeader>();
 
+// Set once a handle to the object may be held outside the HandleScope which
+// allocated it, see HandleScope::new_region.
+const FLAG_ESCAPED: u8 = 1 << 0;
+// Reserved for objects the collector must not move.  Nothing pins yet.
+const FLAG_PINNED: u8 = 1 << 1;
+// Reserved for objects which must not be mutated.  Nothing freezes yet.
+const FLAG_FROZEN: u8 = 1 << 2;
+// Set once the object has been given its identity hash.
+const FLAG_HAS_IDENTITY_HASH: u8 = 1 << 3;
+
+/// A snapshot of an object's state from its header, see
+/// ObjectHeader::flags.  Read only: the heap sets these itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderFlags {
+    bits: u8,
+    marked: bool,
+    age: u8,
+}
+
+impl HeaderFlags {
+    /// Whether the collection in progress has found the object live.
+    pub fn is_marked(&self) -> bool {
+        self.marked
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.bits & FLAG_PINNED != 0
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.bits & FLAG_FROZEN != 0
+    }
+
+    /// Whether a handle to the object may outlive the scope which
+    /// allocated it, see HandleScope::new_region.
+    pub fn is_escaped(&self) -> bool {
+        self.bits & FLAG_ESCAPED != 0
+    }
+
+    pub fn has_identity_hash(&self) -> bool {
+        self.bits & FLAG_HAS_IDENTITY_HASH != 0
+    }
+
+    /// See ObjectHeader::age.
+    pub fn age(&self) -> u8 {
+        self.age
+    }
+
+    // The FLAG_* bits, for HeapDump.
+    pub(crate) fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    // Flags read back from a HeapDump, which is never taken mid-collection.
+    pub(crate) fn from_bits(bits: u8, age: u8) -> HeaderFlags {
+        HeaderFlags {
+            bits,
+            marked: false,
+            age,
+        }
+    }
+}
+
+// The number of bytes Space::alloc is asked for to hold an object.
+pub(crate) const fn alloc_size_for(object_size: usize) -> usize {
+    HEADER_SIZE + object_size
+}
+
+// Layout of the header for code which writes objects directly, offsets are
+// in bytes from the start of the header.
+#[cfg(feature = "jit")]
+impl ObjectHeader {
+    pub const SIZE: usize = HEADER_SIZE;
+    pub const OBJECT_SIZE_OFFSET: usize = std::mem::offset_of!(ObjectHeader, object_size);
+    pub const OBJECT_TYPE_OFFSET: usize = std::mem::offset_of!(ObjectHeader, object_type);
+    pub const FLAGS_OFFSET: usize = std::mem::offset_of!(ObjectHeader, flags);
+    pub const USER_TAG_OFFSET: usize = std::mem::offset_of!(ObjectHeader, user_tag);
+    pub const NEW_HEADER_PTR_OFFSET: usize = std::mem::offset_of!(ObjectHeader, new_header_ptr);
+}
+
 impl ObjectHeader {
     // Should only be called by ObjectHeader::new
     pub(crate) fn new<'a>(
-        space: &mut Space,
+        collector: &mut dyn CollectorStrategy,
         object_size: usize,
         object_type: ObjectType,
     ) -> Result<&'a mut ObjectHeader, GCError> {
-        let header_ptr = HeaderPtr::new(space.alloc(HEADER_SIZE + object_size)?);
-        let header = ObjectHeader::from_ptr(header_ptr);
-        header.object_size = object_size;
-        header.object_type = object_type;
-        Ok(header)
+        let header_ptr = HeaderPtr::new(collector.alloc(alloc_size_for(object_size))?);
+        Ok(ObjectHeader::init(header_ptr, object_size, object_type))
+    }
+
+    // Writes a fresh header at `header_ptr`, which need not be zeroed.
+    pub(crate) fn init<'a>(
+        header_ptr: HeaderPtr,
+        object_size: usize,
+        object_type: ObjectType,
+    ) -> &'a mut ObjectHeader {
+        let header = header_ptr.addr() as *mut ObjectHeader;
+        unsafe {
+            header.write(ObjectHeader {
+                object_size: object_size.try_into().unwrap(),
+                object_type,
+                flags: 0,
+                age: 0,
+                identity_hash: 0,
+                user_tag: 0,
+                new_header_ptr: None,
+            });
+            &mut *header
+        }
     }
 
     fn from_ptr<'a>(header_ptr: HeaderPtr) -> &'a mut ObjectHeader {
@@ -286,7 +676,51 @@ impl ObjectHeader {
     }
 
     pub fn alloc_size(&self) -> usize {
-        HEADER_SIZE + self.object_size
+        alloc_size_for(self.object_size as usize)
+    }
+
+    pub fn identity_hash(&self) -> u32 {
+        self.identity_hash
+    }
+
+    pub(crate) fn set_identity_hash(&mut self, identity_hash: u32) {
+        self.identity_hash = identity_hash;
+        self.flags |= FLAG_HAS_IDENTITY_HASH;
+    }
+
+    pub fn flags(&self) -> HeaderFlags {
+        HeaderFlags {
+            bits: self.flags,
+            // Collections mark by forwarding, see ObjectVisitor::visit.
+            marked: self.new_header_ptr.is_some(),
+            age: self.age,
+        }
+    }
+
+    pub fn user_tag(&self) -> u32 {
+        self.user_tag
+    }
+
+    pub(crate) fn set_user_tag(&mut self, user_tag: u32) {
+        self.user_tag = user_tag;
+    }
+
+    /// How many collections have copied this object, see
+    /// HeapConfig::promotion_age.
+    pub fn age(&self) -> u8 {
+        self.age
+    }
+
+    pub(crate) fn increment_age(&mut self) {
+        self.age = self.age.saturating_add(1);
+    }
+
+    pub(crate) fn is_escaped(&self) -> bool {
+        self.flags().is_escaped()
+    }
+
+    pub(crate) fn mark_escaped(&mut self) {
+        self.flags |= FLAG_ESCAPED;
     }
 
     pub fn as_ptr(&mut self) -> HeaderPtr {
@@ -301,17 +735,13 @@ mod tests {
     use crate::object::*;
 
     // A bit hacky to make u32 traceable, but seems convienent for a test.
-    impl HostObject for u32 {
-        const TYPE_ID: ObjectType = ObjectType::Host;
-    }
+    impl HostObject for u32 {}
 
     impl Traceable for u32 {
         fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
 
-        fn object_hash(&self, _ptr: ObjectPtr) -> u64 {
-            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-            self.hash(&mut hasher);
-            hasher.finish()
+        fn object_hash(&self, _ptr: ObjectPtr, mut state: &mut dyn Hasher) {
+            self.hash(&mut state);
         }
 
         fn object_eq(&self, _lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> bool {
@@ -329,6 +759,11 @@ mod tests {
         // This should be compile time instead:
         // https://github.com/rust-lang/rfcs/issues/2790
         assert_eq!(std::mem::size_of::<TaggedPtr>(), 8);
+        // The flags and age fill padding after object_type, so only the
+        // forwarding pointer (with its own discriminant, a raw pointer has
+        // no niche) differs between 32 and 64-bit targets.
+        let pointer_size = std::mem::size_of::<usize>();
+        assert_eq!(std::mem::size_of::<ObjectHeader>(), 16 + 2 * pointer_size);
     }
 
     #[test]
@@ -338,6 +773,29 @@ mod tests {
         assert!(!zero.is_null());
     }
 
+    #[test]
+    pub fn undefined_test() {
+        let undefined = TaggedPtr::UNDEFINED;
+        assert!(undefined.is_undefined());
+        assert!(!undefined.is_null() && !undefined.is_num() && !undefined.is_int());
+        assert!(!TaggedPtr::NULL.is_undefined());
+        assert!(undefined != TaggedPtr::NULL);
+        assert_eq!(undefined.type_name(), "undefined");
+        assert_eq!(bool::try_from(undefined).ok(), None);
+
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let handle = scope.create_undefined();
+        heap.collect().unwrap();
+        assert!(handle.is_undefined());
+        assert!(!handle.is_null());
+        assert_eq!(handle.display(&scope), "undefined");
+        let as_f64: Result<f64, GCError> = handle.try_into();
+        assert!(as_f64.is_err());
+        let typed: Option<LocalHandle<String>> = handle.try_downcast();
+        assert!(typed.is_none());
+    }
+
     #[test]
     pub fn truthiness_test() {
         // This layer intentionally only gives an answer for True and False
@@ -353,6 +811,206 @@ mod tests {
         assert_eq!(bool::try_from(tagged).ok(), None);
     }
 
+    #[test]
+    pub fn type_name_test() {
+        assert_eq!(TaggedPtr::NULL.type_name(), "null");
+        assert_eq!(TaggedPtr::TRUE.type_name(), "bool");
+        assert_eq!(TaggedPtr::from(1.0).type_name(), "num");
+
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let string = scope.str("foo").unwrap();
+        assert_eq!(string.type_name(), std::any::type_name::<String>());
+        let global = GlobalHandle::from(scope.create::<List<()>>().unwrap());
+        assert_eq!(global.type_name(), std::any::type_name::<List<()>>());
+        assert_eq!(scope.create_num(1.0).type_name(), "num");
+    }
+
+    #[test]
+    pub fn kind_test() {
+        assert_eq!(TaggedPtr::NULL.kind(), ValueKind::Null);
+        assert_eq!(TaggedPtr::UNDEFINED.kind(), ValueKind::Undefined);
+        assert_eq!(TaggedPtr::FALSE.kind(), ValueKind::Bool);
+        assert_eq!(TaggedPtr::from(1.0).kind(), ValueKind::Num);
+        assert_eq!(TaggedPtr::from(1).kind(), ValueKind::Int);
+
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let string = ValueKind::HostObject(std::any::type_name::<String>());
+        assert_eq!(scope.str("foo").unwrap().erase_type().kind(), string);
+        assert_eq!(scope.str_value("foo").unwrap().kind(), string);
+        let list: HeapHandle<()> = scope.create::<List<()>>().unwrap().erase_type().into();
+        assert_eq!(
+            list.kind(),
+            ValueKind::HostObject(std::any::type_name::<List<()>>())
+        );
+        assert_eq!(HeapHandle::<()>::default().kind(), ValueKind::Null);
+    }
+
+    #[test]
+    pub fn integer_test() {
+        let tagged = |value: f64| TaggedPtr::from(value);
+        let as_i64: Result<i64, GCError> = tagged(-3.0).try_into();
+        assert_eq!(as_i64.unwrap(), -3);
+        let as_usize: Result<usize, GCError> = tagged(-3.0).try_into();
+        assert!(as_usize.is_err());
+        let as_u32: Result<u32, GCError> = tagged(4294967295.0).try_into();
+        assert_eq!(as_u32.unwrap(), u32::MAX);
+        let as_u32: Result<u32, GCError> = tagged(4294967296.0).try_into();
+        assert!(as_u32.is_err());
+        let as_i64: Result<i64, GCError> = tagged(9223372036854775808.0).try_into();
+        assert!(as_i64.is_err());
+        let as_i64: Result<i64, GCError> = tagged(-9223372036854775808.0).try_into();
+        assert_eq!(as_i64.unwrap(), i64::MIN);
+        for value in [1.5, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let as_i64: Result<i64, GCError> = tagged(value).try_into();
+            assert!(as_i64.is_err());
+        }
+        let as_usize: Result<usize, GCError> = TaggedPtr::TRUE.try_into();
+        assert!(as_usize.is_err());
+
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let index: usize = scope.create_num(2.0).try_into().unwrap();
+        assert_eq!(index, 2);
+        let index: Result<usize, GCError> = scope.create_num(2.5).erase_type().try_into();
+        assert!(index.is_err());
+    }
+
+    #[test]
+    pub fn small_integer_test() {
+        for value in [0, 1, -1, i32::MAX, i32::MIN] {
+            let tagged = TaggedPtr::from(value);
+            assert!(tagged.is_int());
+            assert!(!tagged.is_num() && !tagged.is_null() && !tagged.is_bool());
+            let as_i32: i32 = tagged.try_into().unwrap();
+            assert_eq!(as_i32, value);
+            let as_f64: f64 = tagged.try_into().unwrap();
+            assert_eq!(as_f64, value as f64);
+        }
+        assert!(!TaggedPtr::NULL.is_int() && !TaggedPtr::from(1.0).is_int());
+        assert_eq!(TaggedPtr::from(3).type_name(), "int");
+        let as_u32: Result<u32, GCError> = TaggedPtr::from(-1).try_into();
+        assert!(as_u32.is_err());
+        let as_i32: Result<i32, GCError> = TaggedPtr::from(2147483648.0).try_into();
+        assert!(as_i32.is_err());
+        // Ints order against nums, but are never equal to them.
+        assert_eq!(
+            TaggedPtr::from(1).compare(&TaggedPtr::from(1.5)),
+            Some(Ordering::Less)
+        );
+        assert!(TaggedPtr::from(1) != TaggedPtr::from(1.0));
+
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let int = scope.create_int(7);
+        heap.collect().unwrap();
+        let value: i32 = int.into();
+        assert_eq!(value, 7);
+        assert_eq!(int.display(&scope), "7");
+        let typed: Option<LocalHandle<i32>> = scope.create_num(7.0).erase_type().try_downcast();
+        assert!(typed.is_none());
+        let typed: Option<LocalHandle<i32>> = int.erase_type().try_downcast();
+        assert!(typed.is_some());
+    }
+
+    #[test]
+    pub fn small_str_test() {
+        for text in ["", "a", "foo", "héllo"] {
+            let tagged = TaggedPtr::small_str(text).unwrap();
+            assert!(tagged.is_small_str());
+            assert!(!tagged.is_num() && !tagged.is_int() && !tagged.is_ptr());
+            assert_eq!(&*tagged.as_small_str().unwrap(), text);
+        }
+        assert!(TaggedPtr::small_str("toolong").is_none());
+        assert!(TaggedPtr::small_str("a\0b").is_none());
+        assert!(!TaggedPtr::from(0).is_small_str() && !TaggedPtr::NULL.is_small_str());
+        assert_eq!(
+            TaggedPtr::small_str("abc").unwrap().type_name(),
+            std::any::type_name::<String>()
+        );
+
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let small = scope.str_value("abc").unwrap();
+        let large = scope.str_value("abcdefg").unwrap();
+        let object = scope.str("abc").unwrap().erase_type();
+        heap.collect().unwrap();
+        assert!(small.ptr_for_test().is_small_str());
+        assert!(!large.ptr_for_test().is_small_str());
+        assert_eq!(small.with_str(str::to_string).unwrap(), "abc");
+        assert_eq!(large.with_str(str::len), Some(7));
+        assert_eq!(scope.create_num(1.0).erase_type().with_str(str::len), None);
+        assert_eq!(small.display(&scope), "\"abc\"");
+
+        // Small strings are interchangeable with String objects of the same text.
+        let hash = |ptr: TaggedPtr| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            ptr.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert!(small.ptr_for_test() == object.ptr_for_test());
+        assert!(object.ptr_for_test() == small.ptr_for_test());
+        assert_eq!(hash(small.ptr_for_test()), hash(object.ptr_for_test()));
+        assert_eq!(
+            small.ptr_for_test().compare(&large.ptr_for_test()),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            large.ptr_for_test().compare(&small.ptr_for_test()),
+            Some(Ordering::Greater)
+        );
+        let value: String = small.try_from_heap(&scope).unwrap();
+        assert_eq!(value, "abc");
+    }
+
+    #[test]
+    pub fn read_barrier_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let old: ObjectPtr = scope.take(1).unwrap().ptr_for_test().try_into().unwrap();
+        let new: ObjectPtr = scope.take(2).unwrap().ptr_for_test().try_into().unwrap();
+        assert_eq!(read_barrier(old).addr(), old.addr());
+
+        // Pretend `old` was moved to `new` by a concurrent collector.
+        old.header().new_header_ptr = Some(new.to_header_ptr());
+        let expected = if cfg!(feature = "read_barrier") {
+            new.addr()
+        } else {
+            old.addr()
+        };
+        assert_eq!(read_barrier(old).addr(), expected);
+        old.header().new_header_ptr = None;
+    }
+
+    #[test]
+    pub fn header_flags_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let flags = |handle: &LocalHandle<'_, u32>| {
+            let object_ptr: ObjectPtr = handle.ptr_for_test().try_into().unwrap();
+            object_ptr.header().flags()
+        };
+        let kept = scope.take(1).unwrap();
+        let fresh = flags(&kept);
+        assert!(fresh.has_identity_hash());
+        assert!(!fresh.is_marked() && !fresh.is_escaped());
+        assert!(!fresh.is_pinned() && !fresh.is_frozen());
+        assert_eq!(fresh.age(), 0);
+
+        heap.collect().unwrap();
+        assert_eq!(flags(&kept).age(), 1);
+        assert!(!flags(&kept).is_marked());
+
+        let escaped = {
+            let inner = EscapableHandleScope::new(&scope);
+            let value = inner.take(2).unwrap();
+            inner.escape(&value)
+        };
+        assert!(flags(&escaped).is_escaped());
+        assert!(!flags(&kept).is_escaped());
+    }
+
     #[test]
     pub fn eq_test() {
         assert_eq!(TaggedPtr::TRUE, TaggedPtr::TRUE);
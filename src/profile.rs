@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::pointer::ObjectType;
+
+// Samples allocations for Heap::start_allocation_profiling.
+pub(crate) struct AllocationProfiler {
+    sample_interval_bytes: usize,
+    // Counts down with each allocation, sampling the one reaching zero.
+    bytes_until_sample: usize,
+    total_bytes: usize,
+    by_type: HashMap<ObjectType, (usize, usize)>,
+}
+
+impl AllocationProfiler {
+    pub(crate) fn new(sample_interval_bytes: usize) -> AllocationProfiler {
+        AllocationProfiler {
+            sample_interval_bytes,
+            bytes_until_sample: sample_interval_bytes,
+            total_bytes: 0,
+            by_type: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, object_type: ObjectType, alloc_size: usize) {
+        self.total_bytes += alloc_size;
+        if alloc_size < self.bytes_until_sample {
+            self.bytes_until_sample -= alloc_size;
+            return;
+        }
+        // An allocation spanning several intervals is still one sample.
+        let overshoot = (alloc_size - self.bytes_until_sample) % self.sample_interval_bytes;
+        self.bytes_until_sample = self.sample_interval_bytes - overshoot;
+        let (samples, sampled_bytes) = self.by_type.entry(object_type).or_default();
+        *samples += 1;
+        *sampled_bytes += alloc_size;
+    }
+
+    pub(crate) fn report(&self) -> AllocationProfile {
+        let mut types: Vec<TypeAllocations> = self
+            .by_type
+            .iter()
+            .map(|(object_type, (samples, sampled_bytes))| TypeAllocations {
+                type_name: object_type.name(),
+                samples: *samples,
+                sampled_bytes: *sampled_bytes,
+                estimated_bytes: samples * self.sample_interval_bytes,
+            })
+            .collect();
+        types.sort_by(|a, b| {
+            b.estimated_bytes
+                .cmp(&a.estimated_bytes)
+                .then(a.type_name.cmp(b.type_name))
+        });
+        AllocationProfile {
+            sample_interval_bytes: self.sample_interval_bytes,
+            total_bytes: self.total_bytes,
+            types,
+        }
+    }
+}
+
+/// Where a heap's allocations went while it was being profiled, see
+/// Heap::start_allocation_profiling.  Allocations are sampled, so the
+/// per-type numbers are estimates, most reliable for the types allocating
+/// the most.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationProfile {
+    pub sample_interval_bytes: usize,
+    // Everything allocated since profiling started, sampled or not.
+    pub total_bytes: usize,
+    // Most allocated first.
+    pub types: Vec<TypeAllocations>,
+}
+
+/// The sampled allocations of one type of object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeAllocations {
+    pub type_name: &'static str,
+    pub samples: usize,
+    // The sizes of the sampled allocations themselves.
+    pub sampled_bytes: usize,
+    // Each sample standing for an interval's worth of allocation.
+    pub estimated_bytes: usize,
+}
+
+impl fmt::Display for AllocationProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} bytes allocated, sampled every {} bytes",
+            self.total_bytes, self.sample_interval_bytes
+        )?;
+        for allocations in self.types.iter() {
+            writeln!(
+                f,
+                "{:>12} bytes {:>8} samples  {}",
+                allocations.estimated_bytes, allocations.samples, allocations.type_name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heap::*;
+    use crate::object::*;
+
+    #[test]
+    fn allocation_profile_test() {
+        let heap = Heap::new(1 << 16).unwrap();
+        assert!(heap.allocation_profile().is_none());
+        assert!(heap.start_allocation_profiling(0).is_err());
+        heap.start_allocation_profiling(1).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        for _ in 0..3 {
+            list.as_mut().push(scope.str("text").unwrap().into());
+        }
+        // Every allocation is sampled.
+        let profile = heap.allocation_profile().unwrap();
+        assert_eq!(profile.total_bytes, heap.used_bytes());
+        let strings = &profile.types[0];
+        assert_eq!(strings.type_name, std::any::type_name::<String>());
+        assert_eq!(strings.samples, 3);
+        assert_eq!(strings.sampled_bytes * 4, profile.total_bytes * 3);
+        assert_eq!(profile.types[1].samples, 1);
+        assert!(profile.to_string().contains("3 samples"));
+
+        // Sparser sampling takes one sample per interval.
+        heap.start_allocation_profiling(1000).unwrap();
+        for _ in 0..1000 {
+            let inner = HandleScope::new(&heap);
+            inner.str("garbage").unwrap();
+        }
+        let profile = heap.stop_allocation_profiling().unwrap();
+        assert!(heap.allocation_profile().is_none());
+        assert_eq!(profile.types.len(), 1);
+        let samples = profile.total_bytes / 1000;
+        assert_eq!(profile.types[0].samples, samples);
+        assert_eq!(profile.types[0].estimated_bytes, samples * 1000);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::display::ValueFormatter;
+use crate::object::*;
+
+/// A string built by concatenation without copying, e.g. for a VM
+/// appending to a large string in a loop.  Made by HandleScope::rope and
+/// joined by HandleScope::rope_concat in constant time; the text is only
+/// copied into one piece by flatten.  Ropes compare by identity.
+pub struct Rope {
+    node: Node,
+    len: usize,
+}
+
+enum Node {
+    Leaf(String),
+    Concat(HeapHandle<Rope>, HeapHandle<Rope>),
+}
+
+impl Rope {
+    pub(crate) fn leaf(text: String) -> Rope {
+        Rope {
+            len: text.len(),
+            node: Node::Leaf(text),
+        }
+    }
+
+    pub(crate) fn concat(left: HeapHandle<Rope>, right: HeapHandle<Rope>) -> Rope {
+        Rope {
+            len: left.as_ref().len + right.as_ref().len,
+            node: Node::Concat(left, right),
+        }
+    }
+
+    /// The length in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The text, if it's already in one piece.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.node {
+            Node::Leaf(text) => Some(text),
+            Node::Concat(..) => None,
+        }
+    }
+
+    /// The pieces of the text in order, without copying.
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks { stack: vec![self] }
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks().flat_map(str::chars)
+    }
+
+    /// Copies the text into one piece, which later calls return directly.
+    /// Any parts nothing else refers to become garbage.
+    pub fn flatten(&mut self) -> &str {
+        if let Node::Concat(..) = self.node {
+            let mut text = String::with_capacity(self.len);
+            text.extend(self.chunks());
+            self.node = Node::Leaf(text);
+        }
+        self.as_str().unwrap()
+    }
+}
+
+/// Iterates a Rope's pieces, see Rope::chunks.  Walks the tree with its
+/// own stack, so deeply nested ropes can't overflow the thread's.
+pub struct Chunks<'a> {
+    stack: Vec<&'a Rope>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        while let Some(rope) = self.stack.pop() {
+            match &rope.node {
+                Node::Leaf(text) if text.is_empty() => {}
+                Node::Leaf(text) => return Some(text),
+                Node::Concat(left, right) => {
+                    self.stack.push(right.as_ref());
+                    self.stack.push(left.as_ref());
+                }
+            }
+        }
+        None
+    }
+}
+
+impl std::fmt::Display for Rope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+impl HostObject for Rope {}
+
+impl Traceable for Rope {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        if let Node::Concat(left, right) = &self.node {
+            left.trace(visitor);
+            right.trace(visitor);
+        }
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_quoted(&self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heap::*;
+
+    #[test]
+    fn rope_test() {
+        let heap = Heap::new(1 << 20).unwrap();
+        let mut rope = PersistentHandle::new(&heap);
+        for index in 0..1000 {
+            let scope = HandleScope::new(&heap);
+            let part = scope.rope(&index.to_string()).unwrap();
+            let joined = match rope.get(&scope) {
+                Some(rope) => scope.rope_concat(&rope, &part).unwrap(),
+                None => part,
+            };
+            rope.reset(&joined);
+            if index % 100 == 0 {
+                heap.collect().unwrap();
+            }
+        }
+        let scope = HandleScope::new(&heap);
+        let rope = rope.get(&scope).unwrap();
+        let expected: String = (0..1000).map(|index| index.to_string()).collect();
+        assert_eq!(rope.as_ref().len(), expected.len());
+        assert!(rope.as_ref().as_str().is_none());
+        assert_eq!(rope.as_ref().chunks().count(), 1000);
+        assert_eq!(rope.as_ref().to_string(), expected);
+        assert!(rope.as_ref().chars().eq(expected.chars()));
+        let tail = scope.rope("!").unwrap();
+        let shared = scope.rope_concat(&rope, &tail).unwrap();
+
+        // Flattening frees the parts only the flattened rope referred to.
+        let used_bytes = heap.used_bytes();
+        assert_eq!(rope.as_mut().flatten(), expected);
+        heap.collect().unwrap();
+        assert!(heap.used_bytes() < used_bytes);
+        assert_eq!(rope.as_ref().as_str(), Some(expected.as_str()));
+        assert_eq!(shared.as_ref().chunks().count(), 2);
+        assert_eq!(shared.as_ref().to_string(), expected + "!");
+
+        let small = scope.rope("ab").unwrap();
+        let both = scope.rope_concat(&small, &small).unwrap();
+        assert_eq!(both.display(&scope), "\"abab\"");
+    }
+}
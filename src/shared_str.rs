@@ -0,0 +1,167 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::rc::{Rc, Weak};
+
+use crate::display::ValueFormatter;
+use crate::object::*;
+use crate::pointer::*;
+
+/// An immutable string which shares its bytes with other SharedStrs, made
+/// by HandleScope::shared_str and HandleScope::substring.  Mutating through
+/// to_mut first copies the bytes if anything else shares them.
+#[derive(Clone, Debug)]
+pub struct SharedStr {
+    payload: Rc<String>,
+    // The part of the payload this string covers, None for all of it.
+    range: Option<Range<usize>>,
+}
+
+impl SharedStr {
+    pub(crate) fn new(payload: Rc<String>) -> SharedStr {
+        SharedStr {
+            payload,
+            range: None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.range {
+            Some(range) => &self.payload[range.clone()],
+            None => &self.payload,
+        }
+    }
+
+    /// Whether the bytes are shared with another SharedStr.
+    pub fn is_shared(&self) -> bool {
+        Rc::strong_count(&self.payload) > 1 || self.range.is_some()
+    }
+
+    /// A string covering `range` of this one, sharing its bytes.  Panics
+    /// like str slicing if `range` is out of bounds or not on a char
+    /// boundary.
+    pub fn substring(&self, range: Range<usize>) -> SharedStr {
+        let base = self.range.as_ref().map_or(0, |own| own.start);
+        // Slicing checks the bounds.
+        let _ = &self.as_str()[range.clone()];
+        SharedStr {
+            payload: self.payload.clone(),
+            range: Some(base + range.start..base + range.end),
+        }
+    }
+
+    /// The string's bytes for modification, copied first if shared.
+    pub fn to_mut(&mut self) -> &mut String {
+        if self.is_shared() {
+            self.payload = Rc::new(self.as_str().to_string());
+            self.range = None;
+        }
+        Rc::get_mut(&mut self.payload).unwrap()
+    }
+}
+
+impl std::ops::Deref for SharedStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl HostObject for SharedStr {}
+
+impl Traceable for SharedStr {
+    fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+
+    // Hashes and compares like String (and GcString), so either can be used as a Map key
+    // for the other.
+    fn object_hash(&self, _ptr: ObjectPtr, mut state: &mut dyn Hasher) {
+        self.as_str().hash(&mut state);
+    }
+
+    fn object_eq(&self, _lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> bool {
+        object_str(rhs_object_ptr) == Some(self.as_str())
+    }
+
+    fn object_cmp(&self, _lhs: ObjectPtr, rhs_object_ptr: ObjectPtr) -> Option<Ordering> {
+        str_cmp(self.as_str(), rhs_object_ptr)
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_quoted(self.as_str())
+    }
+}
+
+// The payloads of live SharedStrs made by HandleScope::shared_str, so that
+// equal text shares bytes.  Held weakly and pruned after collections.
+#[derive(Default)]
+pub(crate) struct PayloadCache {
+    payloads: HashMap<u64, Vec<Weak<String>>>,
+}
+
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl PayloadCache {
+    pub(crate) fn get_or_insert(&mut self, text: &str) -> Rc<String> {
+        let bucket = self.payloads.entry(text_hash(text)).or_default();
+        let existing = bucket
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|payload| payload.as_str() == text);
+        existing.unwrap_or_else(|| {
+            let payload = Rc::new(text.to_string());
+            bucket.push(Rc::downgrade(&payload));
+            payload
+        })
+    }
+
+    // Forgets payloads which no SharedStr holds anymore.
+    pub(crate) fn prune(&mut self) {
+        self.payloads.retain(|_, bucket| {
+            bucket.retain(|payload| payload.strong_count() > 0);
+            !bucket.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+
+    #[test]
+    fn substring_test() {
+        let text = SharedStr::new(Rc::new("hello world".to_string()));
+        let world = text.substring(6..11);
+        assert_eq!(world.as_str(), "world");
+        assert_eq!(world.substring(1..3).as_str(), "or");
+        assert!(Rc::ptr_eq(&text.payload, &world.payload));
+    }
+
+    #[test]
+    fn copy_on_write_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let a = scope.shared_str("literal").unwrap();
+        let b = scope.shared_str("literal").unwrap();
+        assert!(Rc::ptr_eq(&a.as_ref().payload, &b.as_ref().payload));
+        let sub = scope.substring(&a, 0..3).unwrap();
+        heap.collect().unwrap();
+        assert_eq!(sub.as_ref().as_str(), "lit");
+
+        b.as_mut().to_mut().push_str("!");
+        assert_eq!(b.as_ref().as_str(), "literal!");
+        assert_eq!(a.as_ref().as_str(), "literal");
+        assert!(!b.as_ref().is_shared());
+
+        let key: HeapHandle<()> = scope.str("literal").unwrap().erase_type().into();
+        let shared: HeapHandle<()> = a.erase_type().into();
+        assert!(key == shared);
+    }
+}
@@ -0,0 +1,236 @@
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+
+use crate::object::HeapHandle;
+use crate::pointer::TaggedPtr;
+
+// Lists up to this long (argument lists, small tuples) keep their elements
+// in the List itself rather than in a separate allocation.
+pub(crate) const INLINE_CAPACITY: usize = 4;
+
+// The element storage for List.  Starts inline and moves to a Vec the first
+// time it outgrows INLINE_CAPACITY.  Unused inline slots hold null.
+pub(crate) enum HandleVec<T> {
+    Inline {
+        len: usize,
+        items: [HeapHandle<T>; INLINE_CAPACITY],
+    },
+    Spilled(Vec<HeapHandle<T>>),
+}
+
+fn null<T>() -> HeapHandle<T> {
+    HeapHandle::new(TaggedPtr::NULL)
+}
+
+impl<T> HandleVec<T> {
+    pub(crate) fn new() -> HandleVec<T> {
+        HandleVec::Inline {
+            len: 0,
+            items: std::array::from_fn(|_| null()),
+        }
+    }
+
+    #[cfg(test)]
+    fn is_inline(&self) -> bool {
+        matches!(self, HandleVec::Inline { .. })
+    }
+
+    pub(crate) fn as_slice(&self) -> &[HeapHandle<T>] {
+        match self {
+            HandleVec::Inline { len, items } => &items[..*len],
+            HandleVec::Spilled(vec) => vec,
+        }
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [HeapHandle<T>] {
+        match self {
+            HandleVec::Inline { len, items } => &mut items[..*len],
+            HandleVec::Spilled(vec) => vec,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn spill(&mut self) -> &mut Vec<HeapHandle<T>> {
+        if let HandleVec::Inline { len, items } = self {
+            let mut vec = Vec::with_capacity(INLINE_CAPACITY * 2);
+            vec.extend(
+                items[..*len]
+                    .iter_mut()
+                    .map(|slot| std::mem::replace(slot, null())),
+            );
+            *self = HandleVec::Spilled(vec);
+        }
+        match self {
+            HandleVec::Spilled(vec) => vec,
+            HandleVec::Inline { .. } => unreachable!(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, handle: HeapHandle<T>) {
+        match self {
+            HandleVec::Inline { len, items } if *len < INLINE_CAPACITY => {
+                items[*len] = handle;
+                *len += 1;
+            }
+            _ => self.spill().push(handle),
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<HeapHandle<T>> {
+        match self {
+            HandleVec::Inline { len: 0, .. } => None,
+            HandleVec::Inline { len, items } => {
+                *len -= 1;
+                Some(std::mem::replace(&mut items[*len], null()))
+            }
+            HandleVec::Spilled(vec) => vec.pop(),
+        }
+    }
+
+    pub(crate) fn truncate(&mut self, new_len: usize) {
+        match self {
+            HandleVec::Inline { len, items } => {
+                for slot in items.iter_mut().take(*len).skip(new_len) {
+                    *slot = null();
+                }
+                *len = new_len.min(*len);
+            }
+            HandleVec::Spilled(vec) => vec.truncate(new_len),
+        }
+    }
+
+    pub(crate) fn resize(&mut self, new_len: usize, value: HeapHandle<T>) {
+        self.truncate(new_len);
+        while self.len() < new_len {
+            self.push(value.clone());
+        }
+    }
+
+    pub(crate) fn insert(&mut self, index: usize, handle: HeapHandle<T>) {
+        match self {
+            HandleVec::Inline { len, items } if *len < INLINE_CAPACITY => {
+                assert!(index <= *len, "insertion index out of bounds");
+                items[*len] = handle;
+                items[index..=*len].rotate_right(1);
+                *len += 1;
+            }
+            _ => self.spill().insert(index, handle),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, index: usize) -> HeapHandle<T> {
+        match self {
+            HandleVec::Inline { len, items } => {
+                assert!(index < *len, "removal index out of bounds");
+                items[index..*len].rotate_left(1);
+                *len -= 1;
+                std::mem::replace(&mut items[*len], null())
+            }
+            HandleVec::Spilled(vec) => vec.remove(index),
+        }
+    }
+
+    pub(crate) fn split_off(&mut self, at: usize) -> HandleVec<T> {
+        assert!(at <= self.len(), "split index out of bounds");
+        let tail = self.as_slice()[at..].iter().cloned().collect();
+        self.truncate(at);
+        tail
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<HeapHandle<T>> {
+        match self {
+            HandleVec::Inline { len, items } => IntoIterator::into_iter(items).take(len).collect(),
+            HandleVec::Spilled(vec) => vec,
+        }
+    }
+}
+
+impl<T> Default for HandleVec<T> {
+    fn default() -> Self {
+        HandleVec::new()
+    }
+}
+
+impl<T> Clone for HandleVec<T> {
+    fn clone(&self) -> Self {
+        self.as_slice().iter().cloned().collect()
+    }
+}
+
+impl<T> Hash for HandleVec<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state);
+    }
+}
+
+impl<T> FromIterator<HeapHandle<T>> for HandleVec<T> {
+    fn from_iter<I: IntoIterator<Item = HeapHandle<T>>>(iter: I) -> Self {
+        let mut result = HandleVec::new();
+        for handle in iter {
+            result.push(handle);
+        }
+        result
+    }
+}
+
+impl<T> From<Vec<HeapHandle<T>>> for HandleVec<T> {
+    fn from(vec: Vec<HeapHandle<T>>) -> Self {
+        if vec.len() <= INLINE_CAPACITY {
+            vec.into_iter().collect()
+        } else {
+            HandleVec::Spilled(vec)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn nums(handles: &HandleVec<f64>) -> Vec<f64> {
+        handles
+            .as_slice()
+            .iter()
+            .map(|handle| handle.ptr().try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn spill_test() {
+        let mut handles = HandleVec::<f64>::new();
+        for value in 0..INLINE_CAPACITY {
+            handles.push((value as f64).into());
+        }
+        assert!(handles.is_inline());
+        handles.insert(1, 10.0.into());
+        assert!(!handles.is_inline());
+        assert_eq!(nums(&handles), vec![0.0, 10.0, 1.0, 2.0, 3.0]);
+
+        let tail = handles.split_off(3);
+        assert!(tail.is_inline());
+        assert_eq!(nums(&tail), vec![2.0, 3.0]);
+    }
+
+    #[test]
+    fn inline_edits_test() {
+        let mut handles = HandleVec::<f64>::new();
+        handles.push(1.0.into());
+        handles.push(3.0.into());
+        handles.insert(1, 2.0.into());
+        handles.insert(0, 0.0.into());
+        assert_eq!(nums(&handles), vec![0.0, 1.0, 2.0, 3.0]);
+        let removed: f64 = handles.remove(1).into();
+        assert_eq!(removed, 1.0);
+        let popped: f64 = handles.pop().unwrap().into();
+        assert_eq!(popped, 3.0);
+        handles.resize(3, 5.0.into());
+        assert_eq!(nums(&handles), vec![0.0, 2.0, 5.0]);
+        handles.truncate(1);
+        assert_eq!(nums(&handles), vec![0.0]);
+        assert!(handles.is_inline());
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Strings longer than this are cut short in snapshots, as DevTools does.
+const MAX_STRING_NAME: usize = 1024;
+
+// An object in a heap snapshot, see Heap::write_snapshot.
+pub(crate) struct SnapshotNode {
+    // The type name, or the text of a string.
+    pub(crate) name: String,
+    pub(crate) is_string: bool,
+    // Stable across collections, so snapshots can be compared.
+    pub(crate) id: u64,
+    // The object and its payload, in bytes.
+    pub(crate) self_size: usize,
+    // Indices of the nodes this one refers to.
+    pub(crate) edges: Vec<usize>,
+}
+
+impl SnapshotNode {
+    pub(crate) fn string(text: &str, id: u64, self_size: usize) -> SnapshotNode {
+        let mut end = text.len().min(MAX_STRING_NAME);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        SnapshotNode {
+            name: text[..end].to_string(),
+            is_string: true,
+            id,
+            self_size,
+            edges: vec![],
+        }
+    }
+}
+
+// Indices into NODE_TYPES and EDGE_TYPES.
+const NODE_STRING: usize = 2;
+const NODE_OBJECT: usize = 3;
+const NODE_SYNTHETIC: usize = 9;
+const EDGE_ELEMENT: usize = 1;
+
+const NODE_FIELDS: &str =
+    r#"["type","name","id","self_size","edge_count","trace_node_id","detachedness"]"#;
+const NODE_TYPES: &str = r#"[["hidden","array","string","object","code","closure","regexp","number","native","synthetic","concatenated string","sliced string","symbol","bigint","object shape"],"string","number","number","number","number","number"]"#;
+const EDGE_FIELDS: &str = r#"["type","name_or_index","to_node"]"#;
+const EDGE_TYPES: &str = r#"[["context","element","property","internal","hidden","shortcut","weak"],"string_or_number","node"]"#;
+
+// Writes `roots` and `nodes` in the .heapsnapshot format Chrome DevTools
+// loads, with a synthetic root node referring to each of `roots` (indices
+// into `nodes`) first.
+pub(crate) fn write_snapshot(
+    roots: Vec<usize>,
+    nodes: &[SnapshotNode],
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    let node_field_count = 7;
+    let root = SnapshotNode {
+        name: "(GC roots)".to_string(),
+        is_string: false,
+        id: 1,
+        self_size: 0,
+        edges: roots,
+    };
+    let nodes: Vec<&SnapshotNode> = std::iter::once(&root).chain(nodes).collect();
+    let mut strings = StringTable::default();
+    let edge_count: usize = nodes.iter().map(|node| node.edges.len()).sum();
+
+    write!(
+        writer,
+        r#"{{"snapshot":{{"meta":{{"node_fields":{},"node_types":{},"edge_fields":{},"edge_types":{},"trace_function_info_fields":[],"trace_node_fields":[],"sample_fields":[],"location_fields":[]}},"node_count":{},"edge_count":{},"trace_function_count":0}},"#,
+        NODE_FIELDS,
+        NODE_TYPES,
+        EDGE_FIELDS,
+        EDGE_TYPES,
+        nodes.len(),
+        edge_count
+    )?;
+    writer.write_all(b"\n\"nodes\":[")?;
+    for (index, node) in nodes.iter().enumerate() {
+        let node_type = match index {
+            0 => NODE_SYNTHETIC,
+            _ if node.is_string => NODE_STRING,
+            _ => NODE_OBJECT,
+        };
+        let separator = if index == 0 { "" } else { ",\n" };
+        write!(
+            writer,
+            "{}{},{},{},{},{},0,0",
+            separator,
+            node_type,
+            strings.index_of(&node.name),
+            node.id,
+            node.self_size,
+            node.edges.len()
+        )?;
+    }
+    writer.write_all(b"],\n\"edges\":[")?;
+    let mut first = true;
+    for node in nodes.iter() {
+        for (position, to) in node.edges.iter().enumerate() {
+            let separator = if first { "" } else { ",\n" };
+            first = false;
+            write!(
+                writer,
+                "{}{},{},{}",
+                separator,
+                EDGE_ELEMENT,
+                position,
+                // Offsets into nodes, past the root.
+                (to + 1) * node_field_count
+            )?;
+        }
+    }
+    writer.write_all(
+        b"],\n\"trace_function_infos\":[],\n\"trace_tree\":[],\n\"samples\":[],\n\"locations\":[],\n\"strings\":[",
+    )?;
+    for (index, string) in strings.strings.iter().enumerate() {
+        if index > 0 {
+            writer.write_all(b",\n")?;
+        }
+        write_json_string(string, writer)?;
+    }
+    writer.write_all(b"]}\n")
+}
+
+// Node names, which snapshots list once and refer to by index.
+#[derive(Default)]
+struct StringTable {
+    strings: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl StringTable {
+    fn index_of(&mut self, string: &str) -> usize {
+        if let Some(index) = self.indices.get(string) {
+            return *index;
+        }
+        self.strings.push(string.to_string());
+        self.indices
+            .insert(string.to_string(), self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+}
+
+fn write_json_string(string: &str, writer: &mut dyn Write) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => writer.write_all(b"\\\"")?,
+            '\\' => writer.write_all(b"\\\\")?,
+            '\n' => writer.write_all(b"\\n")?,
+            '\r' => writer.write_all(b"\\r")?,
+            '\t' => writer.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => write!(writer, "{}", c)?,
+        }
+    }
+    writer.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heap::*;
+    use crate::object::*;
+
+    #[test]
+    fn snapshot_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list = scope.create::<List<String>>().unwrap();
+        {
+            let inner = HandleScope::new(&heap);
+            list.as_mut().push(inner.str("first").unwrap().into());
+            list.as_mut()
+                .push(inner.str("quote \" and\nnewline").unwrap().into());
+            inner.str("garbage").unwrap();
+        }
+        let mut out = vec![];
+        heap.write_snapshot(&mut out).unwrap();
+        let snapshot = String::from_utf8(out).unwrap();
+
+        // The root, the list and its two strings, the garbage collected
+        // first.
+        assert!(snapshot.contains(r#""node_count":4,"edge_count":3,"#));
+        assert!(snapshot.contains(r#""quote \" and\nnewline""#));
+        assert!(!snapshot.contains("garbage"));
+        let list_name = format!("{:?}", std::any::type_name::<List<String>>());
+        assert!(snapshot.contains(&list_name));
+        // Root to list, then list to both strings, by node offset.
+        assert!(snapshot.contains("\"edges\":[1,0,7,\n1,0,14,\n1,1,21]"));
+
+        // Nothing is left marked.
+        assert!(!list.ptr_for_test().header().unwrap().flags().is_marked());
+        heap.collect().unwrap();
+        assert_eq!(list.as_ref()[0].as_ref(), "first");
+    }
+}
@@ -0,0 +1,241 @@
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+
+use crate::display::ValueFormatter;
+use crate::heap::{HandleScope, LocalHandle};
+use crate::object::*;
+use crate::types::GCError;
+
+/// A map which keeps its keys sorted by TaggedPtr::compare, for symbol
+/// tables with range queries or schedulers keyed by deadline.  Entries live
+/// in a sorted vector searched by bisection.  Every key must be ordered
+/// against the others, e.g. all numbers or all strings.
+#[derive(Default)]
+pub struct SortedMap<K, V> {
+    entries: Vec<(HeapHandle<K>, HeapHandle<V>)>,
+}
+
+impl<K: 'static, V: 'static> HostObject for SortedMap<K, V> {}
+
+impl<K: 'static, V: 'static> Traceable for SortedMap<K, V> {
+    // Collection moves keys but doesn't change how they compare, so the
+    // entries stay sorted.
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        for (key, value) in self.entries.iter() {
+            key.trace(visitor);
+            value.trace(visitor);
+        }
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_map(self.iter())
+    }
+}
+
+// An entry rooted in a scope, as returned by SortedMap::range.
+type LocalEntry<'a, K, V> = (LocalHandle<'a, K>, LocalHandle<'a, V>);
+
+impl<K, V> SortedMap<K, V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&HeapHandle<K>, &HeapHandle<V>)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &HeapHandle<K>> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &HeapHandle<V>> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    pub fn first(&self) -> Option<(&HeapHandle<K>, &HeapHandle<V>)> {
+        self.entries.first().map(|(key, value)| (key, value))
+    }
+
+    pub fn last(&self) -> Option<(&HeapHandle<K>, &HeapHandle<V>)> {
+        self.entries.last().map(|(key, value)| (key, value))
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // Ok with the position of `key`, or Err with where it would be inserted.
+    // Fails if `key` is unordered against an entry it's compared with.
+    fn search(&self, key: &HeapHandle<K>) -> Result<Result<usize, usize>, GCError> {
+        let mut low = 0;
+        let mut high = self.entries.len();
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.entries[middle].0.compare(key) {
+                Some(Ordering::Less) => low = middle + 1,
+                Some(Ordering::Greater) => high = middle,
+                Some(Ordering::Equal) => return Ok(Ok(middle)),
+                None => return Err(GCError::TypeError),
+            }
+        }
+        Ok(Err(low))
+    }
+
+    // The first position not before `bound`, counting from the start.
+    fn lower_position(&self, bound: Bound<&HeapHandle<K>>) -> Result<usize, GCError> {
+        Ok(match bound {
+            Bound::Unbounded => 0,
+            Bound::Included(key) => self.search(key)?.unwrap_or_else(|position| position),
+            Bound::Excluded(key) => match self.search(key)? {
+                Ok(position) => position + 1,
+                Err(position) => position,
+            },
+        })
+    }
+
+    // The first position after `bound`.
+    fn upper_position(&self, bound: Bound<&HeapHandle<K>>) -> Result<usize, GCError> {
+        Ok(match bound {
+            Bound::Unbounded => self.entries.len(),
+            Bound::Included(key) => match self.search(key)? {
+                Ok(position) => position + 1,
+                Err(position) => position,
+            },
+            Bound::Excluded(key) => self.search(key)?.unwrap_or_else(|position| position),
+        })
+    }
+
+    /// Returns the previous value for `key`, if any.  Fails with TypeError if
+    /// `key` isn't ordered against the keys already in the map.
+    pub fn insert(
+        &mut self,
+        key: HeapHandle<K>,
+        value: HeapHandle<V>,
+    ) -> Result<Option<HeapHandle<V>>, GCError> {
+        Ok(match self.search(&key)? {
+            Ok(position) => Some(std::mem::replace(&mut self.entries[position].1, value)),
+            Err(position) => {
+                self.entries.insert(position, (key, value));
+                None
+            }
+        })
+    }
+
+    /// Keys unordered against the map's keys are never found.
+    pub fn get(&self, key: &HeapHandle<K>) -> Option<&HeapHandle<V>> {
+        let position = self.search(key).ok()?.ok()?;
+        Some(&self.entries[position].1)
+    }
+
+    pub fn contains_key(&self, key: &HeapHandle<K>) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn remove(&mut self, key: &HeapHandle<K>) -> Option<HeapHandle<V>> {
+        let position = self.search(key).ok()?.ok()?;
+        Some(self.entries.remove(position).1)
+    }
+
+    /// The entries with keys in `range`, in key order, rooted in `scope` so
+    /// the caller can allocate while walking them.  Fails with TypeError if
+    /// a bound isn't ordered against the map's keys.
+    pub fn range<'a>(
+        &self,
+        scope: &'a HandleScope,
+        range: impl RangeBounds<HeapHandle<K>>,
+    ) -> Result<Vec<LocalEntry<'a, K, V>>, GCError> {
+        let start = self.lower_position(range.start_bound())?;
+        let end = self.upper_position(range.end_bound())?.max(start);
+        Ok(self.entries[start..end]
+            .iter()
+            .map(|(key, value)| (scope.from_heap(key), scope.from_heap(value)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::Heap;
+
+    #[test]
+    fn sorted_order_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let map = scope.create::<SortedMap<String, f64>>().unwrap();
+        for (index, name) in ["pear", "apple", "fig", "kiwi"].iter().enumerate() {
+            let key = scope.str(name).unwrap().into();
+            map.as_mut().insert(key, (index as f64).into()).unwrap();
+        }
+        heap.collect().unwrap();
+        assert_eq!(
+            map.display(&scope),
+            "{\"apple\": 1, \"fig\": 2, \"kiwi\": 3, \"pear\": 0}"
+        );
+
+        let fig: HeapHandle<String> = scope.str("fig").unwrap().into();
+        let old = map.as_mut().insert(fig.clone(), 5.0.into()).unwrap();
+        assert_eq!(old.map(|value| value.into()), Some(2.0));
+        assert_eq!(map.as_ref().len(), 4);
+
+        // Keys must be ordered against each other.
+        let number: HeapHandle<String> = HeapHandle::new(1.0.into());
+        assert!(matches!(
+            map.as_mut().insert(number.clone(), 0.0.into()),
+            Err(GCError::TypeError)
+        ));
+        assert!(map.as_ref().get(&number).is_none());
+
+        assert_eq!(
+            map.as_mut().remove(&fig).map(|value| value.into()),
+            Some(5.0)
+        );
+        assert!(!map.as_ref().contains_key(&fig));
+    }
+
+    #[test]
+    fn range_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let map = scope.create::<SortedMap<f64, f64>>().unwrap();
+        for deadline in [30.0, 10.0, 50.0, 20.0, 40.0] {
+            map.as_mut()
+                .insert(deadline.into(), (deadline * 2.0).into())
+                .unwrap();
+        }
+        let keys = |entries: Vec<LocalEntry<f64, f64>>| -> Vec<f64> {
+            entries
+                .iter()
+                .map(|(key, _)| key.try_from_heap(&scope).unwrap())
+                .collect()
+        };
+        let low: HeapHandle<f64> = 20.0.into();
+        let high: HeapHandle<f64> = 40.0.into();
+        let between = map
+            .as_ref()
+            .range(&scope, low.clone()..high.clone())
+            .unwrap();
+        assert_eq!(keys(between), vec![20.0, 30.0]);
+        let through = map
+            .as_ref()
+            .range(&scope, low.clone()..=high.clone())
+            .unwrap();
+        assert_eq!(keys(through), vec![20.0, 30.0, 40.0]);
+        let after: HeapHandle<f64> = 25.0.into();
+        assert_eq!(
+            keys(map.as_ref().range(&scope, after..).unwrap()),
+            vec![30.0, 40.0, 50.0]
+        );
+        assert_eq!(keys(map.as_ref().range(&scope, ..low).unwrap()), vec![10.0]);
+        assert!(map
+            .as_ref()
+            .range(&scope, high..HeapHandle::new(15.0.into()))
+            .unwrap()
+            .is_empty());
+    }
+}
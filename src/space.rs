@@ -1,13 +1,195 @@
 use std::alloc::{alloc, dealloc, Layout};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
 
+#[cfg(feature = "jit")]
+use crate::collector::BumpPointer;
 use crate::types::*;
 
+/// The bytes of a space covered by each card of its CardTable.
+pub const CARD_SIZE: usize = 512;
+
+/// A dirty flag for each CARD_SIZE bytes of a space.  Mutable access to an
+/// object through a handle (as_mut or borrow_mut) dirties the card holding
+/// its header, so a minor collection only needs to trace the old objects in
+/// dirty cards for pointers into the young generation.  The card is dirtied
+/// again when the ObjectMut is dropped, and collections leave every card
+/// dirty while any ObjectMut on the heap is alive (see WriteBarrier), so
+/// stores through one are seen even across collections.  Stores through
+/// interior mutability on a shared reference aren't seen.
+#[derive(Debug)]
+pub struct CardTable {
+    base: *mut u8,
+    cards: Box<[Cell<bool>]>,
+}
+
+impl CardTable {
+    fn new(base: *mut u8, size_in_bytes: usize) -> CardTable {
+        CardTable {
+            base,
+            cards: vec![Cell::new(false); size_in_bytes.div_ceil(CARD_SIZE)].into_boxed_slice(),
+        }
+    }
+
+    fn card_of(&self, addr: *mut u8) -> Option<&Cell<bool>> {
+        let offset = (addr as usize).checked_sub(self.base as usize)?;
+        self.cards.get(offset / CARD_SIZE)
+    }
+
+    /// Whether the card holding `addr` has been written to since the last
+    /// clear.  Addresses outside the space are never dirty.
+    pub fn is_dirty(&self, addr: *mut u8) -> bool {
+        self.card_of(addr).is_some_and(|card| card.get())
+    }
+
+    pub fn dirty_cards(&self) -> usize {
+        self.cards.iter().filter(|card| card.get()).count()
+    }
+
+    pub fn clear(&self) {
+        for card in self.cards.iter() {
+            card.set(false);
+        }
+    }
+
+    pub fn dirty_all(&self) {
+        for card in self.cards.iter() {
+            card.set(true);
+        }
+    }
+}
+
+/// Counts the ObjectMuts alive on objects of one heap, and during an
+/// incremental collection logs the objects they're taken to.  A collector
+/// shares one between the spaces it creates with Space::with_barrier, so
+/// that as_mut finds it from the object's address.
+#[derive(Debug, Default)]
+pub struct WriteBarrier {
+    open_writes: Cell<usize>,
+    // The headers of objects ObjectMuts were taken to since start_logging.
+    written: RefCell<Option<Vec<*mut u8>>>,
+}
+
+impl WriteBarrier {
+    /// Whether stores may still be made through an ObjectMut taken before
+    /// now.  A collection finishing while one is alive can't know which
+    /// objects it refers to, or where they've moved, so must leave every
+    /// card dirty.
+    pub fn has_open_writes(&self) -> bool {
+        self.open_writes.get() > 0
+    }
+
+    // Starts logging the objects ObjectMuts are taken to, for an incremental
+    // collection to trace again.  Returns false, not logging, if any are
+    // open, as stores through them wouldn't be seen.
+    pub(crate) fn start_logging(&self) -> bool {
+        if self.has_open_writes() {
+            return false;
+        }
+        *self.written.borrow_mut() = Some(vec![]);
+        true
+    }
+
+    // Stops logging, returning the headers logged since start_logging.
+    pub(crate) fn take_written(&self) -> Option<Vec<*mut u8>> {
+        self.written.borrow_mut().take()
+    }
+}
+
+// A space registered with Space::with_barrier.
+struct TrackedSpace {
+    base: *mut u8,
+    limit: *mut u8,
+    barrier: Rc<WriteBarrier>,
+    card_table: Option<Rc<CardTable>>,
+}
+
+thread_local! {
+    // This thread's tracked spaces, found by address as handles don't know
+    // which heap they belong to.
+    static TRACKED_SPACES: RefCell<Vec<TrackedSpace>> = const { RefCell::new(Vec::new()) };
+}
+
+fn with_tracked_space(addr: *mut u8, f: impl FnOnce(&TrackedSpace)) {
+    TRACKED_SPACES.with(|spaces| {
+        let spaces = spaces.borrow();
+        if let Some(space) = spaces
+            .iter()
+            .find(|space| space.base <= addr && addr < space.limit)
+        {
+            f(space);
+        }
+    });
+}
+
+// Called as an ObjectMut is made for the object whose header is at `addr`.
+// Dirties its card, logs it if logging, and returns the barrier counting
+// it, if its space is tracked.  It's logged as it's taken, rather than
+// dropped, as by then the collection may have finished and freed `addr`.
+pub(crate) fn open_write(addr: *mut u8) -> Option<Rc<WriteBarrier>> {
+    let mut result = None;
+    with_tracked_space(addr, |space| {
+        if let Some(card) = space
+            .card_table
+            .as_ref()
+            .and_then(|table| table.card_of(addr))
+        {
+            card.set(true);
+        }
+        let barrier = &space.barrier;
+        barrier.open_writes.set(barrier.open_writes.get() + 1);
+        if let Some(written) = barrier.written.borrow_mut().as_mut() {
+            // Repeated as_mut of one object is common, e.g. in a loop.
+            if written.last() != Some(&addr) {
+                written.push(addr);
+            }
+        }
+        result = Some(space.barrier.clone());
+    });
+    result
+}
+
+// Called as an ObjectMut is dropped, after dirtying its object's card.
+pub(crate) fn close_write(barrier: &WriteBarrier) {
+    barrier.open_writes.set(barrier.open_writes.get() - 1);
+}
+
+/// Dirties the card holding `addr`, if it is in a space with a card table.
+#[inline]
+pub(crate) fn write_barrier(addr: *mut u8) {
+    with_tracked_space(addr, |space| {
+        if let Some(card) = space
+            .card_table
+            .as_ref()
+            .and_then(|table| table.card_of(addr))
+        {
+            card.set(true);
+        }
+    });
+}
+
 #[derive(Debug)]
 pub struct Space {
     layout: Layout,
     base: *mut u8,
     pub size_in_bytes: usize,
     next: *mut u8,
+    // One past the last usable byte, kept in memory for Space::bump_pointer.
+    limit: *mut u8,
+    // Chunks below `next` given back by Space::free or Space::sweep, reused
+    // once the bump region is full.
+    free_list: Vec<(*mut u8, usize)>,
+    // Includes the garbage not yet swept.
+    free_list_bytes: usize,
+    // While sweeping, the survivors not yet swept past in address order,
+    // where the next gap starts, and where the swept region ends.
+    unswept: VecDeque<(*mut u8, usize)>,
+    sweep_cursor: *mut u8,
+    sweep_end: Option<*mut u8>,
+    card_table: Option<Rc<CardTable>>,
+    // Set once registered with a WriteBarrier.
+    tracked: bool,
 }
 
 impl Space {
@@ -27,36 +209,228 @@ impl Space {
             base: ptr,
             size_in_bytes,
             next: ptr,
+            limit: unsafe { ptr.add(size_in_bytes) },
+            free_list: vec![],
+            free_list_bytes: 0,
+            unswept: VecDeque::new(),
+            sweep_cursor: ptr,
+            sweep_end: None,
+            card_table: None,
+            tracked: false,
         })
     }
 
+    /// A space whose objects' ObjectMuts are counted by `barrier`.
+    pub fn with_barrier(
+        size_in_bytes: usize,
+        barrier: &Rc<WriteBarrier>,
+    ) -> Result<Space, GCError> {
+        let mut space = Space::new(size_in_bytes)?;
+        space.track(barrier);
+        Ok(space)
+    }
+
+    /// Like with_barrier, also tracking writes in a CardTable, e.g. for an
+    /// old generation.
+    pub fn with_card_table(
+        size_in_bytes: usize,
+        barrier: &Rc<WriteBarrier>,
+    ) -> Result<Space, GCError> {
+        let mut space = Space::new(size_in_bytes)?;
+        space.card_table = Some(Rc::new(CardTable::new(space.base, size_in_bytes)));
+        space.track(barrier);
+        Ok(space)
+    }
+
+    fn track(&mut self, barrier: &Rc<WriteBarrier>) {
+        let tracked = TrackedSpace {
+            base: self.base,
+            limit: self.limit,
+            barrier: barrier.clone(),
+            card_table: self.card_table.clone(),
+        };
+        TRACKED_SPACES.with(|spaces| spaces.borrow_mut().push(tracked));
+        self.tracked = true;
+    }
+
+    pub fn card_table(&self) -> Option<&CardTable> {
+        self.card_table.as_deref()
+    }
+
     // TODO: The client should be able to specify the alignment.
     pub fn alloc(&mut self, size: usize) -> Result<*mut u8, GCError> {
-        let allocated = self.used_bytes();
-        if allocated.checked_add(size).ok_or(GCError::NoSpace)? > self.size_in_bytes {
-            return Err(GCError::NoSpace);
-        }
-        let result = self.next;
+        let result = self
+            .bump(size)
+            .or_else(|| self.alloc_from_free_list(size))
+            .ok_or(GCError::NoSpace)?;
         unsafe {
-            self.next = result.add(size);
             result.write_bytes(0, size);
         }
         Ok(result)
     }
 
+    fn bump(&mut self, size: usize) -> Option<*mut u8> {
+        if size > self.bump_bytes() {
+            return None;
+        }
+        let result = self.next;
+        self.next = unsafe { result.add(size) };
+        Some(result)
+    }
+
+    // First fit, sweeping more of the space if nothing fits yet.
+    fn alloc_from_free_list(&mut self, size: usize) -> Option<*mut u8> {
+        let fits = |(_, chunk_size): &(*mut u8, usize)| *chunk_size >= size;
+        if let Some(index) = self.free_list.iter().position(fits) {
+            return Some(self.take_chunk(index, size));
+        }
+        while self.sweep_next_gap() {
+            if self.free_list.last().is_some_and(fits) {
+                return Some(self.take_chunk(self.free_list.len() - 1, size));
+            }
+        }
+        // Sweeping may have given the end of the space back to bump.
+        self.bump(size)
+    }
+
+    // Allocates `size` from the start of a chunk, splitting it if larger.
+    fn take_chunk(&mut self, index: usize, size: usize) -> *mut u8 {
+        let (addr, chunk_size) = self.free_list[index];
+        if chunk_size == size {
+            self.free_list.swap_remove(index);
+        } else {
+            self.free_list[index] = (unsafe { addr.add(size) }, chunk_size - size);
+        }
+        self.free_list_bytes -= size;
+        addr
+    }
+
+    /// Gives back an allocation for reuse, wherever it is in the space.
+    pub fn free(&mut self, addr: *mut u8, size: usize) {
+        if !self.unalloc(addr, size) {
+            self.free_list.push((addr, size));
+            self.free_list_bytes += size;
+        }
+    }
+
+    /// Frees everything except `live`, the (address, size) of each
+    /// allocation still in use sorted by address, for collectors which
+    /// don't move objects.  Sweeping is lazy: the freed bytes count as free
+    /// straight away, but the gaps between survivors only go on the free
+    /// list as allocations need them.  Replaces any sweep in progress.
+    pub fn sweep(&mut self, live: Vec<(*mut u8, usize)>) {
+        let live_bytes: usize = live.iter().map(|(_, size)| size).sum();
+        let allocated_bytes = unsafe { self.next.offset_from(self.base) as usize };
+        self.free_list.clear();
+        self.free_list_bytes = allocated_bytes - live_bytes;
+        self.unswept = live.into();
+        self.sweep_cursor = self.base;
+        self.sweep_end = Some(self.next);
+    }
+
+    pub fn is_sweeping(&self) -> bool {
+        self.sweep_end.is_some()
+    }
+
+    /// Puts every remaining gap on the free list.
+    pub fn finish_sweep(&mut self) {
+        while self.sweep_next_gap() {}
+    }
+
+    // Adds the gap before the next survivor to the free list.  Returns
+    // false if the sweep is already done.
+    fn sweep_next_gap(&mut self) -> bool {
+        let end = match self.sweep_end {
+            Some(end) => end,
+            None => return false,
+        };
+        let (gap_end, next_cursor) = match self.unswept.pop_front() {
+            Some((addr, size)) => (addr, unsafe { addr.add(size) }),
+            None => {
+                self.sweep_end = None;
+                (end, end)
+            }
+        };
+        if gap_end > self.sweep_cursor {
+            let gap = unsafe { gap_end.offset_from(self.sweep_cursor) as usize };
+            if gap_end == self.next {
+                // Nothing was bumped since the collection, so the gap after
+                // the last survivor can be bumped into again.
+                self.next = self.sweep_cursor;
+                self.free_list_bytes -= gap;
+            } else {
+                self.free_list.push((self.sweep_cursor, gap));
+            }
+        }
+        self.sweep_cursor = next_cursor;
+        true
+    }
+
+    // Returns the most recent allocation to the space, if `addr` is it.
+    pub fn unalloc(&mut self, addr: *mut u8, size: usize) -> bool {
+        if addr.wrapping_add(size) != self.next {
+            return false;
+        }
+        self.next = addr;
+        true
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn bump_pointer(&mut self) -> BumpPointer {
+        BumpPointer {
+            top: &mut self.next as *mut *mut u8 as *mut usize,
+            limit: &self.limit as *const *mut u8 as *const usize,
+        }
+    }
+
+    /// Empties the space, once nothing in it is referenced.
+    pub fn reset(&mut self) {
+        unsafe {
+            self.base
+                .write_bytes(0, self.next.offset_from(self.base) as usize);
+        }
+        self.next = self.base;
+        self.free_list.clear();
+        self.free_list_bytes = 0;
+        self.unswept.clear();
+        self.sweep_end = None;
+    }
+
+    /// The address of the first allocation in the space.
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    pub fn contains(&self, addr: *mut u8) -> bool {
+        self.base <= addr && addr < self.limit
+    }
+
     pub fn used_bytes(&self) -> usize {
-        unsafe { self.next.offset_from(self.base) as usize }
+        unsafe { self.next.offset_from(self.base) as usize - self.free_list_bytes }
     }
 
+    /// Includes the free list, which may be too fragmented for a large
+    /// allocation.
     pub fn free_bytes(&self) -> usize {
-        self.size_in_bytes - self.used_bytes()
+        self.bump_bytes() + self.free_list_bytes
+    }
+
+    // The bytes left to bump allocate from.
+    fn bump_bytes(&self) -> usize {
+        unsafe { self.limit.offset_from(self.next) as usize }
     }
 }
 
 impl Drop for Space {
     fn drop(&mut self) {
+        if self.tracked {
+            // The registry may already be gone if the thread is exiting.
+            let _ = TRACKED_SPACES
+                .try_with(|spaces| spaces.borrow_mut().retain(|space| space.base != self.base));
+        }
         unsafe {
-            self.base.write_bytes(0, self.used_bytes());
+            self.base
+                .write_bytes(0, self.next.offset_from(self.base) as usize);
             dealloc(self.base, self.layout);
         }
     }
@@ -0,0 +1,188 @@
+use crate::heap::{HandleScope, LocalHandle};
+use crate::object::*;
+use crate::types::GCError;
+
+/// A call frame on a ValueStack, covering the values from its base up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    base: usize,
+    depth: usize,
+}
+
+impl Frame {
+    /// The index of the frame's first value (its first argument).
+    pub fn base(&self) -> usize {
+        self.base
+    }
+}
+
+/// An interpreter value stack split into call frames.  Keep it in the heap
+/// (e.g. behind a GlobalHandle) so everything on it is rooted, then native
+/// functions can borrow their arguments in place with args_window.
+#[derive(Default)]
+pub struct ValueStack {
+    values: Vec<HeapHandle<()>>,
+    // The base of every open frame, innermost last.
+    frames: Vec<usize>,
+}
+
+impl HostObject for ValueStack {}
+
+impl Traceable for ValueStack {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        visitor.trace_handles(&self.values);
+    }
+}
+
+impl ValueStack {
+    pub fn push<T>(&mut self, handle: HeapHandle<T>) {
+        self.values.push(handle.erase_type());
+    }
+
+    pub fn pop<'a>(&mut self, scope: &'a HandleScope) -> Option<LocalHandle<'a, ()>> {
+        if self.values.len() <= self.floor() {
+            return None;
+        }
+        self.values.pop().map(|handle| scope.from_heap(&handle))
+    }
+
+    /// The top value, left on the stack.  Like pop, sees nothing below the
+    /// innermost frame's base.
+    pub fn peek<'a>(&self, scope: &'a HandleScope) -> Option<LocalHandle<'a, ()>> {
+        if self.values.len() <= self.floor() {
+            return None;
+        }
+        self.values.last().map(|handle| scope.from_heap(handle))
+    }
+
+    /// Drops values from the top until `len` are left, but never below the
+    /// innermost frame's base.
+    pub fn truncate(&mut self, len: usize) {
+        self.values.truncate(len.max(self.floor()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // The lowest index the innermost frame may touch.
+    fn floor(&self) -> usize {
+        self.frames.last().copied().unwrap_or(0)
+    }
+
+    /// Opens a frame over the top `arg_count` values, which become its
+    /// arguments.
+    pub fn push_frame(&mut self, arg_count: usize) -> Result<Frame, GCError> {
+        if arg_count > self.values.len() - self.floor() {
+            return Err(GCError::TypeError);
+        }
+        let base = self.values.len() - arg_count;
+        self.frames.push(base);
+        Ok(Frame {
+            base,
+            depth: self.frames.len(),
+        })
+    }
+
+    /// Closes `frame`, which must be the innermost one, discarding its
+    /// values.
+    pub fn pop_frame(&mut self, frame: Frame) {
+        assert_eq!(self.frames.len(), frame.depth, "frames popped out of order");
+        self.frames.pop();
+        self.values.truncate(frame.base);
+    }
+
+    /// The values of `frame`, starting with its arguments.  The slice points
+    /// into the stack itself, so it stays rooted without copying.
+    pub fn args_window(&self, frame: Frame) -> &[HeapHandle<()>] {
+        let end = self
+            .frames
+            .get(frame.depth)
+            .copied()
+            .unwrap_or(self.values.len());
+        &self.values[frame.base..end]
+    }
+
+    /// Calls a native function with the top `arg_count` values as its
+    /// arguments, replacing them with the result it writes to `out`.  The
+    /// arguments are consumed even if the function fails.
+    pub fn call<F>(&mut self, arg_count: usize, function: F) -> Result<(), GCError>
+    where
+        F: FnOnce(&[HeapHandle<()>], &mut HeapHandle<()>) -> Result<(), GCError>,
+    {
+        let frame = self.push_frame(arg_count)?;
+        let mut result = HeapHandle::<()>::default();
+        let status = function(self.args_window(frame), &mut result);
+        self.pop_frame(frame);
+        status?;
+        self.values.push(result);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+    use std::convert::TryInto;
+
+    fn num_add(args: &[HeapHandle<()>], out: &mut HeapHandle<()>) -> Result<(), GCError> {
+        let lhs: f64 = args[0].ptr().try_into()?;
+        let rhs: f64 = args[1].ptr().try_into()?;
+        *out = HeapHandle::new((lhs + rhs).into());
+        Ok(())
+    }
+
+    #[test]
+    fn frames_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let stack = scope.create::<ValueStack>().unwrap();
+        stack.as_mut().push(scope.str("caller").unwrap().into());
+        stack.as_mut().push(scope.create_num(1.0).into());
+        stack.as_mut().push(scope.str("arg").unwrap().into());
+        let outer = stack.as_mut().push_frame(2).unwrap();
+        stack.as_mut().push(scope.create_num(2.0).into());
+        let inner = stack.as_mut().push_frame(1).unwrap();
+        heap.collect().unwrap();
+
+        // The outer window stops where the inner frame begins.
+        assert_eq!(stack.as_ref().args_window(outer).len(), 2);
+        let arg = &stack.as_ref().args_window(outer)[1];
+        assert_eq!(arg.try_as_ref::<String>().unwrap(), "arg");
+        assert_eq!(stack.as_ref().args_window(inner).len(), 1);
+        let top: f64 = stack.as_ref().peek(&scope).unwrap().try_into().unwrap();
+        assert_eq!(top, 2.0);
+        stack.as_mut().truncate(0);
+        assert_eq!(stack.as_ref().len(), 3);
+        assert!(stack.as_ref().peek(&scope).is_none());
+
+        // Popping a frame discards its values, and values below the
+        // innermost frame's base can't be popped.
+        stack.as_mut().pop_frame(inner);
+        assert_eq!(stack.as_ref().len(), 3);
+        assert!(stack.as_mut().pop(&scope).is_some());
+        assert!(stack.as_mut().pop(&scope).is_some());
+        assert!(stack.as_mut().pop(&scope).is_none());
+        stack.as_mut().pop_frame(outer);
+        assert_eq!(stack.as_ref().len(), 1);
+    }
+
+    #[test]
+    fn call_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let stack = scope.create::<ValueStack>().unwrap();
+        stack.as_mut().push(scope.create_num(1.0).into());
+        stack.as_mut().push(scope.create_num(2.0).into());
+        stack.as_mut().call(2, num_add).unwrap();
+        let result: f64 = stack.as_mut().pop(&scope).unwrap().try_into().unwrap();
+        assert_eq!(result, 3.0);
+        assert!(stack.as_ref().is_empty());
+        assert!(stack.as_mut().call(1, num_add).is_err());
+    }
+}
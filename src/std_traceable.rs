@@ -0,0 +1,184 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+use crate::display::ValueFormatter;
+use crate::object::*;
+use crate::pointer::*;
+
+// Traceable for handles and the std containers commonly holding them, so a
+// host object built from std types can trace each field with one call
+// rather than looping over its handles.
+
+impl<T: 'static> Traceable for HeapHandle<T> {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        HeapHandle::trace(self, visitor);
+    }
+}
+
+impl<T: Traceable> Traceable for Option<T> {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        if let Some(value) = self {
+            value.trace(visitor);
+        }
+    }
+}
+
+impl<T: Traceable> Traceable for Vec<T> {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        for value in self.iter_mut() {
+            value.trace(visitor);
+        }
+    }
+}
+
+// Behaves as the boxed object, so e.g. a Box<dyn Traceable> can be
+// allocated like any other host object.
+impl<T: Traceable + ?Sized> HostObject for Box<T> {}
+
+impl<T: Traceable + ?Sized> Traceable for Box<T> {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        (**self).trace(visitor);
+    }
+
+    fn object_hash(&self, ptr: ObjectPtr, state: &mut dyn Hasher) {
+        (**self).object_hash(ptr, state);
+    }
+
+    fn object_eq(&self, lhs: ObjectPtr, rhs: ObjectPtr) -> bool {
+        (**self).object_eq(lhs, rhs)
+    }
+
+    fn object_cmp(&self, lhs: ObjectPtr, rhs: ObjectPtr) -> Option<Ordering> {
+        (**self).object_cmp(lhs, rhs)
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        (**self).object_fmt(formatter)
+    }
+}
+
+// Keys are listed rather than generic, which would overlap Map, whose keys
+// are handles and must be traced too.
+macro_rules! impl_traceable_for_hash_map {
+    ($($key:ty),*) => {
+        $(
+            impl<V: Traceable, S: 'static> Traceable for HashMap<$key, V, S> {
+                fn trace(&mut self, visitor: &mut ObjectVisitor) {
+                    for value in self.values_mut() {
+                        value.trace(visitor);
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_traceable_for_hash_map!(
+    String,
+    &'static str,
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    isize
+);
+
+macro_rules! impl_traceable_for_tuple {
+    ($(($($name:ident: $index:tt),*)),*) => {
+        $(
+            impl<$($name: Traceable),*> Traceable for ($($name,)*) {
+                fn trace(&mut self, visitor: &mut ObjectVisitor) {
+                    $(self.$index.trace(visitor);)*
+                }
+            }
+        )*
+    };
+}
+
+impl_traceable_for_tuple!(
+    (A: 0),
+    (A: 0, B: 1),
+    (A: 0, B: 1, C: 2),
+    (A: 0, B: 1, C: 2, D: 3)
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+
+    struct Node {
+        parent: Option<HeapHandle<String>>,
+        children: Vec<HeapHandle<String>>,
+        by_name: HashMap<String, HeapHandle<String>>,
+        pair: (HeapHandle<String>, HeapHandle<()>),
+        boxed: Box<Vec<Option<HeapHandle<String>>>>,
+    }
+
+    impl HostObject for Node {}
+
+    impl Traceable for Node {
+        fn trace(&mut self, visitor: &mut ObjectVisitor) {
+            self.parent.trace(visitor);
+            self.children.trace(visitor);
+            self.by_name.trace(visitor);
+            self.pair.trace(visitor);
+            self.boxed.trace(visitor);
+        }
+    }
+
+    #[test]
+    fn std_containers_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let str = |text: &str| -> HeapHandle<String> { scope.str(text).unwrap().into() };
+        let node = scope
+            .take(Node {
+                parent: Some(str("parent")),
+                children: vec![str("first"), str("second")],
+                by_name: vec![("key".to_string(), str("value"))]
+                    .into_iter()
+                    .collect(),
+                pair: (str("left"), str("right").erase_type()),
+                boxed: Box::new(vec![None, Some(str("boxed"))]),
+            })
+            .unwrap();
+        let used_bytes = heap.used_bytes();
+        heap.collect().unwrap();
+        heap.collect().unwrap();
+        assert_eq!(heap.used_bytes(), used_bytes);
+
+        let node = node.as_ref();
+        assert_eq!(node.parent.as_ref().unwrap().as_ref(), "parent");
+        assert_eq!(node.children[1].as_ref(), "second");
+        assert_eq!(node.by_name["key"].as_ref(), "value");
+        assert_eq!(node.pair.0.as_ref(), "left");
+        assert_eq!(node.pair.1.try_as_ref::<String>().unwrap(), "right");
+        assert_eq!(node.boxed[1].as_ref().unwrap().as_ref(), "boxed");
+    }
+
+    #[test]
+    fn boxed_object_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let list: HeapHandle<List<String>> = scope.create::<List<String>>().unwrap().into();
+        list.as_mut().push(scope.str("item").unwrap().into());
+        let boxed: Box<dyn Traceable> = Box::new(Some(list));
+        let boxed = scope.take(boxed).unwrap();
+        heap.collect().unwrap();
+        let object: &dyn Traceable = &**boxed.as_ref();
+        let inner = object
+            .as_any()
+            .downcast_ref::<Option<HeapHandle<List<String>>>>();
+        let list = inner.unwrap().as_ref().unwrap();
+        assert_eq!(list.as_ref()[0].as_ref(), "item");
+    }
+}
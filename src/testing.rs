@@ -0,0 +1,132 @@
+//! Helpers for testing code built on vmgc: counting objects, checking that
+//! objects are collected, and counting finalizers.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::heap::{Heap, LocalHandle, PatchSiteId};
+use crate::object::*;
+use crate::types::GCError;
+
+/// Shared count of DropObjects finalized.
+#[derive(Default, Clone, Debug)]
+pub struct DropCounter(Rc<Cell<usize>>);
+
+impl DropCounter {
+    pub fn new() -> DropCounter {
+        DropCounter::default()
+    }
+
+    /// A DropObject which bumps this counter when finalized.
+    pub fn object(&self) -> DropObject {
+        DropObject {
+            counter: self.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.get()
+    }
+}
+
+/// A host object which counts its finalization, see DropCounter.
+#[derive(Default, Debug)]
+pub struct DropObject {
+    counter: DropCounter,
+}
+
+impl HostObject for DropObject {}
+
+impl Traceable for DropObject {
+    fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+}
+
+impl Drop for DropObject {
+    fn drop(&mut self) {
+        let counter = &self.counter.0;
+        counter.set(counter.get() + 1);
+    }
+}
+
+/// An object watched by HeapTester::watch.
+#[derive(Debug)]
+pub struct Watched(PatchSiteId);
+
+/// Assertions about a heap's objects, for tests.
+pub struct HeapTester<'h> {
+    heap: &'h Heap,
+}
+
+impl<'h> HeapTester<'h> {
+    pub fn new(heap: &'h Heap) -> HeapTester<'h> {
+        HeapTester { heap }
+    }
+
+    /// Collects `times` times, e.g. to let objects age or to check a
+    /// collection leaves nothing behind for the next.
+    pub fn collect_n(&self, times: usize) -> Result<(), GCError> {
+        for _ in 0..times {
+            self.heap.collect()?;
+        }
+        Ok(())
+    }
+
+    /// Collects and returns the number of objects of type T still alive.
+    pub fn live_objects<T: HostObject>(&self) -> Result<usize, GCError> {
+        self.heap.collect()?;
+        Ok(self.heap.count_objects::<T>())
+    }
+
+    /// Starts watching `handle`'s object, without keeping it alive.
+    pub fn watch<T>(&self, handle: &LocalHandle<'_, T>) -> Watched {
+        let handle: HeapHandle<()> = handle.erase_type().into();
+        Watched(self.heap.register_patch_site(&handle, |_, _| {}))
+    }
+
+    /// Whether the watched object survived the collections so far.
+    pub fn is_live(&self, watched: &Watched) -> bool {
+        self.heap.has_patch_site(&watched.0)
+    }
+
+    /// Collects and panics unless the watched object was collected.
+    pub fn assert_dead(&self, watched: &Watched) {
+        self.heap.collect().unwrap();
+        assert!(!self.is_live(watched), "watched object is still alive");
+    }
+
+    /// Collects and panics if the watched object was collected.
+    pub fn assert_live(&self, watched: &Watched) {
+        self.heap.collect().unwrap();
+        assert!(self.is_live(watched), "watched object was collected");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::HandleScope;
+
+    #[test]
+    fn heap_tester_test() {
+        let heap = Heap::new(10000).unwrap();
+        let tester = HeapTester::new(&heap);
+        let counter = DropCounter::new();
+        let scope = HandleScope::new(&heap);
+        let kept = scope.take(counter.object()).unwrap();
+        let (dropped, watched) = {
+            let inner = HandleScope::new(&heap);
+            let dropped = inner.take(counter.object()).unwrap();
+            (tester.watch(&dropped), tester.watch(&kept))
+        };
+        assert_eq!(tester.live_objects::<DropObject>().unwrap(), 1);
+        assert_eq!(counter.count(), 1);
+        tester.assert_dead(&dropped);
+        tester.assert_live(&watched);
+
+        std::mem::drop(kept);
+        std::mem::drop(scope);
+        tester.collect_n(2).unwrap();
+        assert!(!tester.is_live(&watched));
+        assert_eq!(counter.count(), 2);
+    }
+}
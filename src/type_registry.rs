@@ -0,0 +1,81 @@
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+
+use crate::pointer::ObjectType;
+
+// Every HostObject type seen so far, indexed by ObjectType.  Shared by all
+// heaps on all threads, so an ObjectType means the same type everywhere.
+static TYPES: Mutex<Vec<(TypeId, &'static str)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    // Saves taking the lock on every type check, see ObjectType::of.
+    static CACHE: RefCell<HashMap<TypeId, ObjectType>> = RefCell::new(HashMap::new());
+}
+
+// The ObjectType for T, assigning the next one the first time T is seen.
+pub(crate) fn type_of<T: 'static>() -> ObjectType {
+    let type_id = TypeId::of::<T>();
+    if let Some(object_type) = CACHE.with(|cache| cache.borrow().get(&type_id).copied()) {
+        return object_type;
+    }
+    let mut types = TYPES.lock().unwrap();
+    let index = match types.iter().position(|(id, _)| *id == type_id) {
+        Some(index) => index,
+        None => {
+            types.push((type_id, std::any::type_name::<T>()));
+            types.len() - 1
+        }
+    };
+    let object_type = ObjectType::new(u16::try_from(index).expect("too many host object types"));
+    CACHE.with(|cache| cache.borrow_mut().insert(type_id, object_type));
+    object_type
+}
+
+pub(crate) fn name_of(object_type: ObjectType) -> &'static str {
+    TYPES.lock().unwrap()[object_type.id() as usize].1
+}
+
+// Whether `object_type` was assigned by type_of, rather than read from a
+// corrupt header.
+pub(crate) fn is_registered(object_type: ObjectType) -> bool {
+    (object_type.id() as usize) < TYPES.lock().unwrap().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+    use crate::object::*;
+    use crate::pointer::ObjectPtr;
+    use crate::shared_str::SharedStr;
+    use std::convert::TryInto;
+
+    #[test]
+    fn type_registry_test() {
+        let string = ObjectType::of::<String>();
+        assert_eq!(string, ObjectType::of::<String>());
+        assert_ne!(string, ObjectType::of::<List<String>>());
+        assert_ne!(
+            ObjectType::of::<List<String>>(),
+            ObjectType::of::<List<f64>>()
+        );
+        assert_eq!(string.name(), "alloc::string::String");
+
+        // Ids are shared between threads.
+        let other_thread = std::thread::spawn(ObjectType::of::<String>).join();
+        assert_eq!(other_thread.unwrap(), string);
+
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let value = scope.str("value").unwrap();
+        let object_ptr: ObjectPtr = value.ptr_for_test().try_into().unwrap();
+        assert!(object_ptr.is_type(string));
+        assert!(!object_ptr.is_type(ObjectType::of::<SharedStr>()));
+        let erased = value.erase_type();
+        assert!(erased.try_as_ref::<SharedStr>().is_none());
+        assert!(erased.try_as_ref::<String>().is_some());
+    }
+}
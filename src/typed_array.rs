@@ -0,0 +1,271 @@
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::ops::{Index, IndexMut, Range};
+
+use crate::display::ValueFormatter;
+use crate::object::*;
+use crate::pointer::ObjectPtr;
+
+/// The numeric types a TypedArray can hold.
+pub trait ArrayElement: Copy + Default + Display + 'static {}
+
+impl ArrayElement for u8 {}
+impl ArrayElement for i32 {}
+impl ArrayElement for u32 {}
+impl ArrayElement for f32 {}
+impl ArrayElement for f64 {}
+
+pub(crate) fn write_elements<E: ArrayElement>(
+    formatter: &mut ValueFormatter<'_>,
+    elements: &[E],
+) -> std::fmt::Result {
+    formatter.write_str("[")?;
+    for (index, element) in elements.iter().enumerate() {
+        if index > 0 {
+            formatter.write_str(", ")?;
+        }
+        formatter.write_str(&element.to_string())?;
+    }
+    formatter.write_str("]")
+}
+
+/// A fixed length buffer of numbers, stored unboxed.  The length never
+/// changes, so TypedArrayViews into it stay in bounds.
+#[derive(Default)]
+pub struct TypedArray<E> {
+    elements: Box<[E]>,
+}
+
+impl<E: ArrayElement> TypedArray<E> {
+    /// An array of `len` zeroes.
+    pub fn new(len: usize) -> TypedArray<E> {
+        TypedArray {
+            elements: vec![E::default(); len].into_boxed_slice(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[E] {
+        &self.elements
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [E] {
+        &mut self.elements
+    }
+
+    /// Bounds checked access, unlike indexing which panics.
+    pub fn get(&self, index: usize) -> Option<E> {
+        self.elements.get(index).copied()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut E> {
+        self.elements.get_mut(index)
+    }
+}
+
+impl<E> From<Vec<E>> for TypedArray<E> {
+    fn from(elements: Vec<E>) -> Self {
+        TypedArray {
+            elements: elements.into_boxed_slice(),
+        }
+    }
+}
+
+impl<E: ArrayElement> HostObject for TypedArray<E> {}
+
+impl<E: ArrayElement> Traceable for TypedArray<E> {
+    fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        write_elements(formatter, self.as_slice())
+    }
+}
+
+pub type Uint8Array = TypedArray<u8>;
+pub type Int32Array = TypedArray<i32>;
+pub type Uint32Array = TypedArray<u32>;
+pub type Float32Array = TypedArray<f32>;
+pub type Float64Array = TypedArray<f64>;
+
+impl<E: ArrayElement> Index<usize> for TypedArray<E> {
+    type Output = E;
+
+    fn index(&self, index: usize) -> &E {
+        &self.elements[index]
+    }
+}
+
+impl<E: ArrayElement> IndexMut<usize> for TypedArray<E> {
+    fn index_mut(&mut self, index: usize) -> &mut E {
+        &mut self.elements[index]
+    }
+}
+
+/// A window onto part of a TypedArray, made by HandleScope::array_view,
+/// e.g. for a hosted language's `array.subarray(a, b)`.  Reads and writes go
+/// to the parent's elements, nothing is copied.  The view holds its parent
+/// alive.
+pub struct TypedArrayView<E> {
+    buffer: HeapHandle<TypedArray<E>>,
+    offset: usize,
+    len: usize,
+}
+
+impl<E: ArrayElement> TypedArrayView<E> {
+    /// Panics if `range` is out of bounds of `buffer`.
+    pub(crate) fn new(buffer: HeapHandle<TypedArray<E>>, range: Range<usize>) -> Self {
+        // Slicing checks the bounds.
+        let _ = &buffer.as_ref().as_slice()[range.clone()];
+        TypedArrayView {
+            buffer,
+            offset: range.start,
+            len: range.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The position of the view's first element in its parent.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn buffer(&self) -> &HeapHandle<TypedArray<E>> {
+        &self.buffer
+    }
+
+    pub fn as_slice(&self) -> &[E] {
+        &self.buffer.as_ref().as_slice()[self.offset..self.offset + self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [E] {
+        // Elements are never handles, so there's no card to dirty once the
+        // slice is done with, see ObjectMut.
+        let object_ptr: ObjectPtr = self.buffer.ptr().try_into().unwrap();
+        let buffer = unsafe { &mut *TraceableObject::downcast_mut::<TypedArray<E>>(object_ptr) };
+        &mut buffer.as_mut_slice()[self.offset..self.offset + self.len]
+    }
+
+    /// Bounds checked against the view, not just its parent.
+    pub fn get(&self, index: usize) -> Option<E> {
+        self.as_slice().get(index).copied()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut E> {
+        self.as_mut_slice().get_mut(index)
+    }
+
+    /// A view of `range` within this one, over the same parent.  Panics if
+    /// `range` is out of bounds.
+    pub fn subarray(&self, range: Range<usize>) -> TypedArrayView<E> {
+        // Slicing checks the bounds.
+        let _ = &self.as_slice()[range.clone()];
+        TypedArrayView {
+            buffer: self.buffer.clone(),
+            offset: self.offset + range.start,
+            len: range.len(),
+        }
+    }
+}
+
+impl<E: ArrayElement> Index<usize> for TypedArrayView<E> {
+    type Output = E;
+
+    fn index(&self, index: usize) -> &E {
+        &self.as_slice()[index]
+    }
+}
+
+impl<E: ArrayElement> IndexMut<usize> for TypedArrayView<E> {
+    fn index_mut(&mut self, index: usize) -> &mut E {
+        &mut self.as_mut_slice()[index]
+    }
+}
+
+impl<E: ArrayElement> HostObject for TypedArrayView<E> {}
+
+impl<E: ArrayElement> Traceable for TypedArrayView<E> {
+    fn trace(&mut self, visitor: &mut ObjectVisitor) {
+        self.buffer.trace(visitor);
+    }
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        write_elements(formatter, self.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::*;
+
+    #[test]
+    fn indexing_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let matrix = scope.take(Float64Array::new(4)).unwrap();
+        matrix.as_mut()[3] = 1.5;
+        *matrix.as_mut().get_mut(0).unwrap() = -2.0;
+        assert_eq!(matrix.as_mut().get_mut(4), None);
+        heap.collect().unwrap();
+        assert_eq!(matrix.as_ref()[3], 1.5);
+        assert_eq!(matrix.as_ref().get(0), Some(-2.0));
+        assert_eq!(matrix.as_ref().get(4), None);
+
+        let samples = scope.take(Int32Array::from(vec![1, 2, 3, 4])).unwrap();
+        let view = scope.array_view(&samples, 1..3).unwrap();
+        view.as_mut()[0] = 20;
+        assert_eq!(view.as_ref().get(1), Some(3));
+        // In bounds of the parent, but not of the view.
+        assert_eq!(view.as_ref().get(2), None);
+        assert_eq!(samples.as_ref().as_slice(), &[1, 20, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_out_of_bounds_test() {
+        let heap = Heap::new(1000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let array = scope.take(Uint8Array::new(2)).unwrap();
+        array.as_ref()[2];
+    }
+
+    #[test]
+    fn view_test() {
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let view = {
+            let inner = HandleScope::new(&heap);
+            let array = inner
+                .take(TypedArray::from(vec![0.0, 1.0, 2.0, 3.0, 4.0]))
+                .unwrap();
+            let view = inner.array_view(&array, 1..4).unwrap();
+            scope.from_local(&view)
+        };
+        // Only the view roots the parent.
+        heap.collect().unwrap();
+        assert_eq!(view.as_ref().as_slice(), &[1.0, 2.0, 3.0]);
+
+        let sub = scope.take(view.as_ref().subarray(1..3)).unwrap();
+        assert_eq!(sub.as_ref().offset(), 2);
+        sub.as_mut().as_mut_slice()[0] = 20.0;
+        assert_eq!(view.display(&scope), "[1, 20, 3]");
+        assert_eq!(
+            view.as_ref().buffer().as_ref().as_slice(),
+            &[0.0, 1.0, 20.0, 3.0, 4.0]
+        );
+    }
+}
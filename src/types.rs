@@ -11,6 +11,13 @@ pub enum GCError {
     // collecting dead objects.
     // HeapFull,
     TypeError,
+
+    // The HeapConfig is inconsistent, or asks for a size outside its bounds.
+    InvalidConfig,
+
+    // A Traceable::trace or a host object's Drop panicked during collection.
+    // The collection still completed.
+    Panicked,
 }
 
 impl fmt::Display for GCError {
@@ -19,6 +26,8 @@ impl fmt::Display for GCError {
             GCError::OSOutOfMemory => "OS failed to provide memory",
             GCError::NoSpace => "No memory left in space",
             GCError::TypeError => "Type coercion failed",
+            GCError::InvalidConfig => "Invalid heap configuration",
+            GCError::Panicked => "Object panicked during collection",
         };
         write!(f, "{}", string)
     }
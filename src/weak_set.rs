@@ -0,0 +1,164 @@
+use std::convert::TryInto;
+
+use crate::display::ValueFormatter;
+use crate::heap::forwarded_handle;
+use crate::object::*;
+use crate::pointer::*;
+
+/// A set of objects which doesn't keep them alive, e.g. for tracking every
+/// live fiber or module without leaking them.  Made by
+/// HandleScope::create_weak_set, entries are dropped by the first
+/// collection which finds nothing else referring to their object.  Objects
+/// are compared by identity, other values by value (and never dropped).
+pub struct WeakSet<T> {
+    // Not traced, see prune.
+    entries: Vec<HeapHandle<T>>,
+}
+
+// Lets the heap prune WeakSets of any element type.
+pub(crate) trait WeakEntries {
+    // Drops the entries whose objects the collection in progress didn't
+    // find live, and points the rest at where they're being moved to.
+    fn prune(&mut self);
+}
+
+impl<T> WeakSet<T> {
+    pub(crate) fn new() -> WeakSet<T> {
+        WeakSet { entries: vec![] }
+    }
+
+    /// Returns false if the set already holds `value`.
+    pub fn insert(&mut self, value: HeapHandle<T>) -> bool {
+        if self.contains(&value) {
+            return false;
+        }
+        self.entries.push(value);
+        true
+    }
+
+    pub fn contains(&self, value: &HeapHandle<T>) -> bool {
+        self.position(value).is_some()
+    }
+
+    /// Returns false if the set didn't hold `value`.
+    pub fn remove(&mut self, value: &HeapHandle<T>) -> bool {
+        match self.position(value) {
+            Some(index) => {
+                self.entries.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The live entries, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &HeapHandle<T>> {
+        self.entries.iter()
+    }
+
+    fn position(&self, value: &HeapHandle<T>) -> Option<usize> {
+        let object_ptr: Option<ObjectPtr> = value.ptr().try_into().ok();
+        self.entries.iter().position(|entry| {
+            let entry_ptr: Option<ObjectPtr> = entry.ptr().try_into().ok();
+            match (entry_ptr, object_ptr) {
+                // Mid collection, handles may disagree about the address.
+                (Some(entry_ptr), Some(object_ptr)) => {
+                    forwarded(entry_ptr).addr() == forwarded(object_ptr).addr()
+                }
+                (None, None) => entry.ptr() == value.ptr(),
+                _ => false,
+            }
+        })
+    }
+}
+
+impl<T> WeakEntries for WeakSet<T> {
+    fn prune(&mut self) {
+        let entries = std::mem::take(&mut self.entries);
+        self.entries = entries.iter().filter_map(forwarded_handle).collect();
+    }
+}
+
+impl<T: 'static> HostObject for WeakSet<T> {}
+
+impl<T: 'static> Traceable for WeakSet<T> {
+    fn trace(&mut self, _visitor: &mut ObjectVisitor) {}
+
+    fn object_fmt(&self, formatter: &mut ValueFormatter<'_>) -> std::fmt::Result {
+        formatter.write_list(self.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{CollectorKind, HeapConfig};
+    use crate::heap::*;
+
+    #[test]
+    fn weak_set_test() {
+        let collectors = [
+            CollectorKind::SemiSpace,
+            CollectorKind::Immix,
+            CollectorKind::Generational,
+            CollectorKind::MarkSweep,
+        ];
+        for collector in collectors {
+            let heap = Heap::with_config(HeapConfig {
+                collector,
+                ..HeapConfig::fixed(1 << 16)
+            })
+            .unwrap();
+            let scope = HandleScope::new(&heap);
+            let set = scope.create_weak_set::<String>().unwrap();
+            let kept = scope.str("kept").unwrap();
+            assert!(set.as_mut().insert(kept.clone().into()));
+            assert!(!set.as_mut().insert(kept.clone().into()));
+            {
+                let inner = HandleScope::new_region(&heap);
+                for name in ["a", "b", "c"] {
+                    set.as_mut().insert(inner.str(name).unwrap().into());
+                }
+                // Equal contents, but a different object.
+                let other = inner.str("kept").unwrap().into();
+                assert!(!set.as_ref().contains(&other));
+                assert!(set.as_mut().insert(other));
+            }
+            assert_eq!(set.as_ref().len(), 5);
+            heap.collect().unwrap();
+            heap.collect_minor().unwrap();
+            heap.compact().unwrap();
+            assert_eq!(set.as_ref().len(), 1);
+            assert!(set.as_ref().contains(&kept.clone().into()));
+            assert_eq!(set.display(&scope), "[\"kept\"]");
+
+            assert!(set.as_mut().remove(&kept.clone().into()));
+            assert!(set.as_ref().is_empty());
+        }
+    }
+
+    #[test]
+    fn weak_set_collected_test() {
+        // A dead set is forgotten rather than pruned.
+        let heap = Heap::new(10000).unwrap();
+        let scope = HandleScope::new(&heap);
+        let kept = scope.str("kept").unwrap();
+        let used_bytes = heap.used_bytes();
+        {
+            let inner = HandleScope::new(&heap);
+            let set = inner.create_weak_set::<String>().unwrap();
+            set.as_mut().insert(kept.clone().into());
+        }
+        heap.collect().unwrap();
+        heap.compact().unwrap();
+        assert_eq!(kept.as_ref(), "kept");
+        assert_eq!(heap.used_bytes(), used_bytes);
+    }
+}